@@ -0,0 +1,1053 @@
+use std::{collections::HashMap, fmt, time::Instant};
+
+use crate::bytecode::{
+    build_bytecode, build_instruction, Bytecode, Constant, Instruction, LocalVar, LuaInstruction,
+    LuaInt, LuaVersion, OpCode, OpMode, Proto, BITRK, MAXARG_C, MAX_STACK_SIZE,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmErrorKind {
+    UnknownDirective(String),
+    UnknownMnemonic(String),
+    OperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    InvalidOperand {
+        operand: String,
+        expected: &'static str,
+    },
+    OperandOutOfRange {
+        mnemonic: String,
+    },
+    ConstantOutOfRange {
+        index: LuaInt,
+        count: usize,
+    },
+    JumpOutOfRange {
+        target: LuaInt,
+        count: usize,
+    },
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    UnknownProc(String),
+    DuplicateProc(String),
+    UnterminatedString,
+    UnmatchedEnd,
+    UnclosedProc(String),
+    StackOverflow(LuaInt),
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsmErrorKind::UnknownDirective(directive) => {
+                write!(f, "unknown directive .{}", directive)
+            }
+            AsmErrorKind::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic {}", mnemonic),
+            AsmErrorKind::OperandCount {
+                mnemonic,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} takes {} operand(s), found {}",
+                mnemonic, expected, found
+            ),
+            AsmErrorKind::InvalidOperand { operand, expected } => {
+                write!(f, "expected {}, found '{}'", expected, operand)
+            }
+            AsmErrorKind::OperandOutOfRange { mnemonic } => {
+                write!(f, "an operand of {} is out of range", mnemonic)
+            }
+            AsmErrorKind::ConstantOutOfRange { index, count } => write!(
+                f,
+                "constant k{} is out of range, the proc has {} constant(s)",
+                index, count
+            ),
+            AsmErrorKind::JumpOutOfRange { target, count } => write!(
+                f,
+                "jump to pc {} is out of range, the proc has {} instruction(s)",
+                target, count
+            ),
+            AsmErrorKind::UnknownLabel(label) => write!(f, "undefined label {}", label),
+            AsmErrorKind::DuplicateLabel(label) => write!(f, "label {} is already defined", label),
+            AsmErrorKind::UnknownProc(proc) => write!(f, "undefined proc {}", proc),
+            AsmErrorKind::DuplicateProc(proc) => write!(f, "proc {} is already defined", proc),
+            AsmErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            AsmErrorKind::UnmatchedEnd => write!(f, ".end without a .proc"),
+            AsmErrorKind::UnclosedProc(proc) => write!(f, "proc {} is missing its .end", proc),
+            AsmErrorKind::StackOverflow(size) => write!(
+                f,
+                "needs {} registers, more than the {} a function can have",
+                size, MAX_STACK_SIZE
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub kind: AsmErrorKind,
+    pub line: usize,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to assemble line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub type AsmResult<T> = Result<T, AsmError>;
+
+// the instruction field an operand goes into
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    A,
+    B,
+    C,
+    Bx,
+    SBx,
+}
+
+// how an operand is written: a register, a constant, either (RK), an upvalue, a child
+// proc, a plain integer or a jump target
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Arg {
+    R,
+    K,
+    RK,
+    U,
+    P,
+    N,
+    J,
+}
+
+type Operands = &'static [(Field, Arg)];
+pub(crate) type BuildOp = fn(OpMode) -> OpCode;
+
+// operands are the fields luac -l prints for each opcode, unused ones are left out
+fn lookup_51(mnemonic: &str) -> Option<(BuildOp, Operands)> {
+    use self::{Arg::*, Field::*};
+
+    const BINARY: Operands = &[(A, R), (B, RK), (C, RK)];
+    const UNARY: Operands = &[(A, R), (B, R)];
+    const CONDITION: Operands = &[(A, N), (B, RK), (C, RK)];
+    const MNEMONICS: [(&str, BuildOp, Operands); 38] = [
+        ("MOVE", OpCode::OpMove, &[(A, R), (B, R)]),
+        ("LOADK", OpCode::OpLoadK, &[(A, R), (Bx, K)]),
+        ("LOADBOOL", OpCode::OpLoadBool, &[(A, R), (B, N), (C, N)]),
+        ("LOADNIL", OpCode::OpLoadNil, &[(A, R), (B, R)]),
+        ("GETUPVAL", OpCode::OpGetUpval, &[(A, R), (B, U)]),
+        ("GETGLOBAL", OpCode::OpGetGlobal, &[(A, R), (Bx, K)]),
+        ("GETTABLE", OpCode::OpGetTable, &[(A, R), (B, R), (C, RK)]),
+        ("SETGLOBAL", OpCode::OpSetGlobal, &[(A, R), (Bx, K)]),
+        ("SETUPVAL", OpCode::OpSetUpval, &[(A, R), (B, U)]),
+        ("SETTABLE", OpCode::OpSetTable, &[(A, R), (B, RK), (C, RK)]),
+        ("NEWTABLE", OpCode::OpNewTable, &[(A, R), (B, N), (C, N)]),
+        ("SELF", OpCode::OpSelf, &[(A, R), (B, R), (C, RK)]),
+        ("ADD", OpCode::OpAdd, BINARY),
+        ("SUB", OpCode::OpSub, BINARY),
+        ("MUL", OpCode::OpMul, BINARY),
+        ("DIV", OpCode::OpDiv, BINARY),
+        ("MOD", OpCode::OpMod, BINARY),
+        ("POW", OpCode::OpPow, BINARY),
+        ("UNM", OpCode::OpUnm, UNARY),
+        ("NOT", OpCode::OpNot, UNARY),
+        ("LEN", OpCode::OpLen, UNARY),
+        ("CONCAT", OpCode::OpConcat, &[(A, R), (B, R), (C, R)]),
+        ("JMP", OpCode::OpJmp, &[(SBx, J)]),
+        ("EQ", OpCode::OpEq, CONDITION),
+        ("LT", OpCode::OpLt, CONDITION),
+        ("LE", OpCode::OpLe, CONDITION),
+        ("TEST", OpCode::OpTest, &[(A, R), (C, N)]),
+        ("TESTSET", OpCode::OpTestSet, &[(A, R), (B, R), (C, N)]),
+        ("CALL", OpCode::OpCall, &[(A, R), (B, N), (C, N)]),
+        ("TAILCALL", OpCode::OpTailCall, &[(A, R), (B, N), (C, N)]),
+        ("RETURN", OpCode::OpReturn, &[(A, R), (B, N)]),
+        ("FORLOOP", OpCode::OpForLoop, &[(A, R), (SBx, J)]),
+        ("FORPREP", OpCode::OpForPrep, &[(A, R), (SBx, J)]),
+        ("TFORLOOP", OpCode::OpTForLoop, &[(A, R), (C, N)]),
+        ("SETLIST", OpCode::OpSetList, &[(A, R), (B, N), (C, N)]),
+        ("CLOSE", OpCode::OpClose, &[(A, R)]),
+        ("CLOSURE", OpCode::OpClosure, &[(A, R), (Bx, P)]),
+        ("VARARG", OpCode::OpVararg, &[(A, R), (B, N)]),
+    ];
+
+    MNEMONICS
+        .iter()
+        .find(|(name, _, _)| name.eq_ignore_ascii_case(mnemonic))
+        .map(|(_, build, operands)| (*build, *operands))
+}
+
+// just the opcode, for JSON that names it the way the listing does
+pub(crate) fn lookup_op_51(mnemonic: &str) -> Option<BuildOp> {
+    lookup_51(mnemonic).map(|(build, _)| build)
+}
+
+// registers an instruction uses past its register operands, for the default .maxstack
+fn range_top(op: &OpCode) -> LuaInt {
+    match op {
+        OpCode::OpSelf(OpMode::ABC(a, _, _)) => a + 2,
+        OpCode::OpCall(OpMode::ABC(a, b, c)) | OpCode::OpTailCall(OpMode::ABC(a, b, c)) => {
+            a + (*b).max(c - 1)
+        }
+        OpCode::OpReturn(OpMode::ABC(a, b, _)) | OpCode::OpVararg(OpMode::ABC(a, b, _)) => {
+            a + b - 1
+        }
+        OpCode::OpForPrep(OpMode::ASBX(a, _)) | OpCode::OpForLoop(OpMode::ASBX(a, _)) => a + 4,
+        OpCode::OpTForLoop(OpMode::ABC(a, _, c)) => a + 3 + c,
+        OpCode::OpSetList(OpMode::ABC(a, b, _)) => a + b + 1,
+        _ => 0,
+    }
+}
+
+// encodes op the way a 5.1 chunk stores it and reads it back, so assembled instructions
+// look exactly like loaded ones; aux asks for a SETLIST to keep its C in the next word
+pub(crate) fn encode_instruction(op: OpCode, aux: Option<LuaInstruction>) -> Option<Instruction> {
+    let words: Vec<LuaInstruction> = Instruction { raw: 0, aux, op }.encode_51(32, LuaInt::MAX)?;
+    let mut inst: Instruction = build_instruction(words[0], LuaVersion::Lua51, 32, LuaInt::MAX);
+    if let Some(aux) = words.get(1) {
+        inst.handle_aux(*aux);
+    }
+    Some(inst)
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+// rN, kN, uN and pN; registers and upvalues may also be written as a bare integer
+fn parse_prefixed(text: &str, prefix: char) -> Option<LuaInt> {
+    let digits: &str = text.strip_prefix(prefix)?;
+    match digits.bytes().all(|b| b.is_ascii_digit()) {
+        true => digits.parse().ok(),
+        false => None,
+    }
+}
+
+fn parse_string(text: &str) -> Option<Vec<u8>> {
+    let inner: &str = text.strip_prefix('"')?.strip_suffix('"')?;
+    let bytes: &[u8] = inner.as_bytes();
+    let mut result: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i: usize = 0;
+    while i < bytes.len() {
+        let b: u8 = bytes[i];
+        i += 1;
+        if b == b'"' {
+            return None;
+        }
+        if b != b'\\' {
+            result.push(b);
+            continue;
+        }
+
+        let escape: u8 = *bytes.get(i)?;
+        i += 1;
+        let value: u8 = match escape {
+            b'a' => b'\x07',
+            b'b' => b'\x08',
+            // Constant::format writes form feeds as \c
+            b'f' | b'c' => b'\x0C',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'v' => b'\x0B',
+            b'\\' | b'"' | b'\'' => escape,
+            b'x' => {
+                let hex: &str = inner.get(i..i + 2)?;
+                i += 2;
+                u8::from_str_radix(hex, 16).ok()?
+            }
+            // up to three decimal digits, like Lua's \ddd
+            b'0'..=b'9' => {
+                let start: usize = i - 1;
+                while i < bytes.len() && i - start < 3 && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                inner[start..i].parse().ok()?
+            }
+            _ => return None,
+        };
+        result.push(value);
+    }
+
+    Some(result)
+}
+
+fn parse_constant(text: &str) -> Option<Constant> {
+    match text {
+        "nil" => Some(Constant::Nil),
+        "true" => Some(Constant::Boolean(true)),
+        "false" => Some(Constant::Boolean(false)),
+        _ if text.starts_with('"') => parse_string(text).map(Constant::String),
+        _ => text.parse().ok().map(Constant::Number),
+    }
+}
+
+fn same_constant(a: &Constant, b: &Constant) -> bool {
+    match (a, b) {
+        (Constant::Nil, Constant::Nil) => true,
+        (Constant::Boolean(a), Constant::Boolean(b)) => a == b,
+        // by bits, so 0 and -0 stay apart
+        (Constant::Number(a), Constant::Number(b)) => a.to_bits() == b.to_bits(),
+        (Constant::String(a), Constant::String(b)) => a == b,
+        _ => false,
+    }
+}
+
+// cuts the line at a ; comment outside of strings
+fn strip_comment(text: &str) -> Result<&str, AsmErrorKind> {
+    let mut in_string: bool = false;
+    let mut escaped: bool = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ';' {
+            return Ok(&text[..i]);
+        }
+    }
+    match in_string {
+        true => Err(AsmErrorKind::UnterminatedString),
+        false => Ok(text),
+    }
+}
+
+// splits on commas outside of strings, the text has already been through strip_comment
+fn split_operands(text: &str) -> Vec<String> {
+    let text: &str = text.trim();
+    if text.is_empty() {
+        return Vec::new();
+    }
+
+    let mut result: Vec<String> = Vec::new();
+    let mut start: usize = 0;
+    let mut in_string: bool = false;
+    let mut escaped: bool = false;
+    for (i, c) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c == ',' {
+            result.push(text[start..i].trim().to_string());
+            start = i + 1;
+        }
+    }
+    result.push(text[start..].trim().to_string());
+    result
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], &text[i..]),
+        None => (text, ""),
+    }
+}
+
+struct PendingInstruction {
+    line: usize,
+    pc: usize,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+struct PendingLocal {
+    line: usize,
+    name: Vec<u8>,
+    start: String,
+    end: String,
+}
+
+// a proc being assembled, jumps, closures and locals are resolved once its .end is reached
+struct ProcState {
+    name: String,
+    line: usize,
+    proto: Proto,
+    max_stack_size: Option<u8>,
+    upvalue_count: Option<u8>,
+    instructions: Vec<PendingInstruction>,
+    locals: Vec<PendingLocal>,
+    labels: HashMap<String, usize>,
+    child_names: HashMap<String, usize>,
+    // pc of the next instruction, a SETLIST with an aux word takes up two
+    word_count: usize,
+    // lineinfo is only kept when the proc used .line
+    current_line: Option<LuaInt>,
+}
+
+fn build_proc_state(name: &str, line: usize, is_main: bool) -> ProcState {
+    ProcState {
+        name: name.to_string(),
+        line,
+        proto: Proto {
+            is_main,
+            id: 0,
+
+            source: Vec::new(),
+            line_defined: 0,
+            last_line_defined: 0,
+            upvalue_count: 0,
+            param_count: 0,
+            // the main chunk is always vararg
+            is_vararg: is_main,
+            vararg_flags: match is_main {
+                true => 2,
+                false => 0,
+            },
+            max_stack_size: 0,
+            code: Vec::new(),
+            constants: Vec::new(),
+            protos: Vec::new(),
+            upvalues: Vec::new(),
+            param_types: Vec::new(),
+            number_constants: Vec::new(),
+            two_slot_frame: false,
+
+            lineinfo: Vec::new(),
+            locals: Vec::new(),
+            upvalue_names: Vec::new(),
+            debug_name: Vec::new(),
+        },
+        max_stack_size: None,
+        upvalue_count: None,
+        instructions: Vec::new(),
+        locals: Vec::new(),
+        labels: HashMap::new(),
+        child_names: HashMap::new(),
+        word_count: 0,
+        current_line: None,
+    }
+}
+
+// assembles a textual listing into a Lua 5.1 chunk with the header of a stock x86_64 luac
+pub struct Assembler<'a> {
+    text: &'a str,
+    line: usize,
+    procs: Vec<ProcState>,
+}
+
+impl<'a> Assembler<'a> {
+    fn error(&self, kind: AsmErrorKind) -> AsmError {
+        AsmError {
+            kind,
+            line: self.line,
+        }
+    }
+    fn invalid_operand(&self, operand: &str, expected: &'static str) -> AsmError {
+        self.error(AsmErrorKind::InvalidOperand {
+            operand: operand.to_string(),
+            expected,
+        })
+    }
+    fn expect_operands(&self, name: &str, operands: &[String], expected: usize) -> AsmResult<()> {
+        match operands.len() == expected {
+            true => Ok(()),
+            false => Err(self.error(AsmErrorKind::OperandCount {
+                mnemonic: name.to_string(),
+                expected,
+                found: operands.len(),
+            })),
+        }
+    }
+    fn state(&mut self) -> &mut ProcState {
+        self.procs
+            .last_mut()
+            .expect("the main proc is never popped while assembling")
+    }
+
+    fn parse_int(&self, text: &str) -> AsmResult<LuaInt> {
+        text.parse()
+            .map_err(|_| self.invalid_operand(text, "an integer"))
+    }
+    fn parse_u8(&self, text: &str) -> AsmResult<u8> {
+        text.parse()
+            .map_err(|_| self.invalid_operand(text, "an integer from 0 to 255"))
+    }
+
+    pub fn assemble(&mut self) -> AsmResult<Bytecode> {
+        let start_instant: Instant = Instant::now();
+
+        self.procs = vec![build_proc_state("main", 0, true)];
+        let text: &'a str = self.text;
+        for (index, line) in text.lines().enumerate() {
+            self.line = index + 1;
+            self.assemble_line(line)?;
+        }
+
+        let state: ProcState = self.procs.pop().expect("the main proc is pushed above");
+        if !self.procs.is_empty() {
+            self.line = state.line;
+            return Err(self.error(AsmErrorKind::UnclosedProc(state.name)));
+        }
+        let main_proto: Proto = self.finish_proc(state)?;
+
+        Ok(build_bytecode(
+            LuaVersion::Lua51,
+            0,
+            true,
+            4,
+            8,
+            4,
+            8,
+            8,
+            false,
+            0,
+            0,
+            0,
+            0,
+            main_proto,
+            start_instant.elapsed(),
+        ))
+    }
+
+    fn assemble_line(&mut self, line: &str) -> AsmResult<()> {
+        let mut code: &str = strip_comment(line).map_err(|kind| self.error(kind))?.trim();
+
+        // any number of label: prefixes
+        while let Some((label, rest)) = code.split_once(':') {
+            if !is_identifier(label) {
+                break;
+            }
+            let state: &mut ProcState = self.state();
+            let pc: usize = state.word_count;
+            if state.labels.insert(label.to_string(), pc).is_some() {
+                return Err(self.error(AsmErrorKind::DuplicateLabel(label.to_string())));
+            }
+            code = rest.trim();
+        }
+        if code.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(directive) = code.strip_prefix('.') {
+            let (name, rest) = split_word(directive);
+            return self.assemble_directive(name, &split_operands(rest));
+        }
+
+        let (mnemonic, rest) = split_word(code);
+        let mnemonic: String = mnemonic.to_ascii_uppercase();
+        let operands: Vec<String> = split_operands(rest);
+        let (_, fields) = match lookup_51(&mnemonic) {
+            Some(entry) => entry,
+            None => return Err(self.error(AsmErrorKind::UnknownMnemonic(mnemonic))),
+        };
+        self.expect_operands(&mnemonic, &operands, fields.len())?;
+
+        // a C too big for its field goes into the word after the SETLIST, 0 would say it did
+        let mut size: usize = 1;
+        if mnemonic == "SETLIST" {
+            match self.parse_int(&operands[2])? {
+                0 => return Err(self.error(AsmErrorKind::OperandOutOfRange { mnemonic })),
+                c if c > MAXARG_C => size = 2,
+                _ => {}
+            }
+        }
+
+        let line: usize = self.line;
+        let state: &mut ProcState = self.state();
+        state.instructions.push(PendingInstruction {
+            line,
+            pc: state.word_count,
+            mnemonic,
+            operands,
+        });
+        state.word_count += size;
+        let current_line: LuaInt = state.current_line.unwrap_or(0);
+        state
+            .proto
+            .lineinfo
+            .extend(std::iter::repeat_n(current_line, size));
+        Ok(())
+    }
+
+    fn assemble_directive(&mut self, name: &str, operands: &[String]) -> AsmResult<()> {
+        let directive: String = format!(".{}", name);
+        match name {
+            "proc" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let proc: &String = &operands[0];
+                if !is_identifier(proc) {
+                    return Err(self.invalid_operand(proc, "a name"));
+                }
+                if self.state().child_names.contains_key(proc) {
+                    return Err(self.error(AsmErrorKind::DuplicateProc(proc.clone())));
+                }
+                let state: ProcState = build_proc_state(proc, self.line, false);
+                self.procs.push(state);
+            }
+            "end" => {
+                self.expect_operands(&directive, operands, 0)?;
+                if self.procs.len() < 2 {
+                    return Err(self.error(AsmErrorKind::UnmatchedEnd));
+                }
+                let state: ProcState = self.procs.pop().expect("checked above");
+                let name: String = state.name.clone();
+                let end_line: usize = self.line;
+                let mut proto: Proto = self.finish_proc(state)?;
+                self.line = end_line;
+
+                let parent: &mut ProcState = self.state();
+                let index: usize = parent.proto.protos.len();
+                proto.id = index as LuaInt;
+                parent.proto.protos.push(proto);
+                parent.child_names.insert(name, index);
+            }
+            "source" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let source: Vec<u8> = match parse_string(&operands[0]) {
+                    Some(source) => source,
+                    None => return Err(self.invalid_operand(&operands[0], "a string")),
+                };
+                self.state().proto.source = source;
+            }
+            "linedefined" => {
+                self.expect_operands(&directive, operands, 2)?;
+                let line_defined: LuaInt = self.parse_int(&operands[0])?;
+                let last_line_defined: LuaInt = self.parse_int(&operands[1])?;
+                let state: &mut ProcState = self.state();
+                state.proto.line_defined = line_defined;
+                state.proto.last_line_defined = last_line_defined;
+            }
+            "params" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let param_count: u8 = self.parse_u8(&operands[0])?;
+                self.state().proto.param_count = param_count;
+            }
+            // on its own it means VARARG_ISVARARG, a number gives the raw 5.1 flags
+            "vararg" => {
+                let vararg_flags: u8 = match operands.len() {
+                    0 => 2,
+                    _ => {
+                        self.expect_operands(&directive, operands, 1)?;
+                        self.parse_u8(&operands[0])?
+                    }
+                };
+                let state: &mut ProcState = self.state();
+                state.proto.vararg_flags = vararg_flags;
+                state.proto.is_vararg = vararg_flags != 0;
+            }
+            "maxstack" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let max_stack_size: u8 = self.parse_u8(&operands[0])?;
+                self.state().max_stack_size = Some(max_stack_size);
+            }
+            "upvalues" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let upvalue_count: u8 = self.parse_u8(&operands[0])?;
+                self.state().upvalue_count = Some(upvalue_count);
+            }
+            "upvalue" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let upvalue: &String = &operands[0];
+                let name: Vec<u8> = match parse_string(upvalue) {
+                    Some(name) => name,
+                    None if is_identifier(upvalue) => upvalue.as_bytes().to_vec(),
+                    None => return Err(self.invalid_operand(upvalue, "a name")),
+                };
+                self.state().proto.upvalue_names.push(name);
+            }
+            "const" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let constant: Constant = match parse_constant(&operands[0]) {
+                    Some(constant) => constant,
+                    None => return Err(self.invalid_operand(&operands[0], "a constant")),
+                };
+                self.state().proto.constants.push(constant);
+            }
+            "local" => {
+                self.expect_operands(&directive, operands, 3)?;
+                let local: &String = &operands[0];
+                let name: Vec<u8> = match parse_string(local) {
+                    Some(name) => name,
+                    None if is_identifier(local) => local.as_bytes().to_vec(),
+                    None => return Err(self.invalid_operand(local, "a name")),
+                };
+                let line: usize = self.line;
+                self.state().locals.push(PendingLocal {
+                    line,
+                    name,
+                    start: operands[1].clone(),
+                    end: operands[2].clone(),
+                });
+            }
+            "line" => {
+                self.expect_operands(&directive, operands, 1)?;
+                let line: LuaInt = self.parse_int(&operands[0])?;
+                self.state().current_line = Some(line);
+            }
+            _ => return Err(self.error(AsmErrorKind::UnknownDirective(name.to_string()))),
+        }
+        Ok(())
+    }
+
+    // a label or a pc
+    fn resolve_pc(&self, state: &ProcState, text: &str) -> AsmResult<LuaInt> {
+        if let Some(pc) = state.labels.get(text) {
+            return Ok(*pc as LuaInt);
+        }
+        match is_identifier(text) {
+            true => Err(self.error(AsmErrorKind::UnknownLabel(text.to_string()))),
+            false => self.parse_int(text),
+        }
+    }
+
+    fn resolve_constant(&self, state: &mut ProcState, text: &str) -> AsmResult<LuaInt> {
+        if let Some(index) = parse_prefixed(text, 'k') {
+            return Ok(index);
+        }
+        let constant: Constant = match parse_constant(text) {
+            Some(constant) => constant,
+            None => return Err(self.invalid_operand(text, "a constant")),
+        };
+        let constants: &mut Vec<Constant> = &mut state.proto.constants;
+        let index: usize = match constants.iter().position(|k| same_constant(k, &constant)) {
+            Some(index) => index,
+            None => {
+                constants.push(constant);
+                constants.len() - 1
+            }
+        };
+        Ok(index as LuaInt)
+    }
+
+    fn resolve_operand(
+        &self,
+        state: &mut ProcState,
+        arg: Arg,
+        text: &str,
+        pc: usize,
+    ) -> AsmResult<LuaInt> {
+        match arg {
+            Arg::R => match parse_prefixed(text, 'r') {
+                Some(reg) => Ok(reg),
+                None => text
+                    .parse()
+                    .map_err(|_| self.invalid_operand(text, "a register")),
+            },
+            Arg::K => self.resolve_constant(state, text),
+            // a bare number here is a constant, registers have to be written as rN
+            Arg::RK => {
+                let (value, is_constant): (LuaInt, bool) = match parse_prefixed(text, 'r') {
+                    Some(reg) => (reg, false),
+                    None => (self.resolve_constant(state, text)?, true),
+                };
+                match (value < BITRK, is_constant) {
+                    (true, true) => Ok(value | BITRK),
+                    (true, false) => Ok(value),
+                    (false, _) => {
+                        Err(self.invalid_operand(text, "a register or constant below 256"))
+                    }
+                }
+            }
+            Arg::U => match parse_prefixed(text, 'u') {
+                Some(index) => Ok(index),
+                None => text
+                    .parse()
+                    .map_err(|_| self.invalid_operand(text, "an upvalue")),
+            },
+            Arg::P => {
+                let index: Option<usize> = match parse_prefixed(text, 'p') {
+                    Some(index) => Some(index as usize).filter(|i| *i < state.proto.protos.len()),
+                    None => state.child_names.get(text).copied(),
+                };
+                match index {
+                    Some(index) => Ok(index as LuaInt),
+                    None => Err(self.error(AsmErrorKind::UnknownProc(text.to_string()))),
+                }
+            }
+            Arg::N => self.parse_int(text),
+            // relative to the next instruction, like sBx itself
+            Arg::J => match is_identifier(text) {
+                true => Ok(self.resolve_pc(state, text)? - (pc as LuaInt + 1)),
+                false => self.parse_int(text.strip_prefix('+').unwrap_or(text)),
+            },
+        }
+    }
+
+    fn finish_proc(&mut self, mut state: ProcState) -> AsmResult<Proto> {
+        let instructions: Vec<PendingInstruction> = std::mem::take(&mut state.instructions);
+        let mut code: Vec<Instruction> = Vec::with_capacity(instructions.len() + 1);
+        let mut top: LuaInt = 0;
+        // checked once every .const and literal of the proc is known, with their lines
+        let mut constant_uses: Vec<(usize, LuaInt)> = Vec::new();
+        let mut jump_targets: Vec<(usize, LuaInt)> = Vec::new();
+
+        for pending in &instructions {
+            self.line = pending.line;
+            let (build, fields) = lookup_51(&pending.mnemonic).expect("checked when parsed");
+
+            // A, B, C, Bx and sBx
+            let mut values: [LuaInt; 5] = [0; 5];
+            for ((field, arg), text) in fields.iter().zip(&pending.operands) {
+                let value: LuaInt = self.resolve_operand(&mut state, *arg, text, pending.pc)?;
+                if *arg == Arg::R || (*arg == Arg::RK && value < BITRK) {
+                    top = top.max(value + 1);
+                }
+                match arg {
+                    Arg::K => constant_uses.push((pending.line, value)),
+                    Arg::RK if value >= BITRK => constant_uses.push((pending.line, value - BITRK)),
+                    Arg::J => jump_targets.push((pending.line, pending.pc as LuaInt + 1 + value)),
+                    _ => {}
+                }
+                values[*field as usize] = value;
+            }
+            let [a, b, c, bx, sbx] = values;
+            let mode: OpMode = match fields.last() {
+                Some((Field::Bx, _)) => OpMode::ABX(a, bx),
+                Some((Field::SBx, _)) => OpMode::ASBX(a, sbx),
+                _ => OpMode::ABC(a, b, c),
+            };
+
+            let op: OpCode = build(mode);
+            top = top.max(range_top(&op));
+            match encode_instruction(op, None) {
+                Some(inst) => code.push(inst),
+                None => {
+                    return Err(self.error(AsmErrorKind::OperandOutOfRange {
+                        mnemonic: pending.mnemonic.clone(),
+                    }));
+                }
+            }
+        }
+
+        // every function has to end in a RETURN, like the one luac always adds
+        if !matches!(code.last(), Some(inst) if matches!(inst.op, OpCode::OpReturn(_))) {
+            code.push(
+                encode_instruction(OpCode::OpReturn(OpMode::ABC(0, 1, 0)), None)
+                    .expect("RETURN 0 1 always fits"),
+            );
+            let current_line: LuaInt = state.current_line.unwrap_or(0);
+            state.proto.lineinfo.push(current_line);
+        }
+        if state.current_line.is_none() {
+            state.proto.lineinfo.clear();
+        }
+
+        let constant_count: usize = state.proto.constants.len();
+        for (line, index) in constant_uses {
+            if index < 0 || index as usize >= constant_count {
+                self.line = line;
+                return Err(self.error(AsmErrorKind::ConstantOutOfRange {
+                    index,
+                    count: constant_count,
+                }));
+            }
+        }
+        // targets are word pcs, past the aux word of a SETLIST too
+        let word_count: usize = code
+            .iter()
+            .map(|inst| 1 + inst.aux.is_some() as usize)
+            .sum();
+        for (line, target) in jump_targets {
+            if target < 0 || target as usize >= word_count {
+                self.line = line;
+                return Err(self.error(AsmErrorKind::JumpOutOfRange {
+                    target,
+                    count: word_count,
+                }));
+            }
+        }
+
+        for pending in &state.locals {
+            self.line = pending.line;
+            let local: LocalVar = LocalVar {
+                name: pending.name.clone(),
+                start_pc: self.resolve_pc(&state, &pending.start)?,
+                end_pc: self.resolve_pc(&state, &pending.end)?,
+                reg: None,
+            };
+            state.proto.locals.push(local);
+        }
+
+        state.proto.max_stack_size = match state.max_stack_size {
+            Some(max_stack_size) => max_stack_size,
+            None => {
+                // luac never goes below 2
+                let max_stack_size: LuaInt = top.max(2);
+                if max_stack_size > MAX_STACK_SIZE {
+                    self.line = state.line;
+                    return Err(self.error(AsmErrorKind::StackOverflow(max_stack_size)));
+                }
+                max_stack_size as u8
+            }
+        };
+        state.proto.upvalue_count = match state.upvalue_count {
+            Some(upvalue_count) => upvalue_count,
+            None => state.proto.upvalue_names.len() as u8,
+        };
+        state.proto.code = code;
+
+        Ok(state.proto)
+    }
+}
+
+pub fn build_assembler(text: &str) -> Assembler<'_> {
+    Assembler {
+        text,
+        line: 0,
+        procs: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::build_verifier;
+    use crate::vm::{build_vm, Value, Vm};
+
+    fn assemble(text: &str) -> Bytecode {
+        match build_assembler(text).assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn asm_error(text: &str) -> AsmError {
+        match build_assembler(text).assemble() {
+            Ok(_) => panic!("expected the listing to be refused"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn assembles_and_runs() {
+        let bytecode: Bytecode = assemble(
+            r#"
+            .source "@sum.lua"
+            .line 1
+                LOADK r0, 0
+                LOADK r1, 1
+                LOADK r2, 10
+                LOADK r3, 1
+                FORPREP r1, done
+            again:
+                CLOSURE r5, add
+                MOVE r0, r0 ; captured by add
+                MOVE r6, r4
+                CALL r5, 2, 2
+                MOVE r0, r5
+            done:
+                FORLOOP r1, again
+                RETURN r0, 2
+            .proc add
+                .params 1
+                .upvalue total
+                GETUPVAL r1, u0
+                ADD r1, r1, r0
+                RETURN r1, 2
+            .end
+            "#,
+        );
+        assert_eq!(build_verifier(&bytecode).verify(), []);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(main.max_stack_size, 7);
+        assert_eq!(main.protos[0].upvalue_count, 1);
+        assert_eq!(main.lineinfo, vec![1; main.code.len()]);
+
+        let mut vm: Vm = build_vm();
+        let results: Vec<Value> = match vm.load(bytecode).and_then(|main| vm.call(main, Vec::new()))
+        {
+            Ok(results) => results,
+            Err(err) => panic!("{}", err),
+        };
+        assert!(matches!(results.as_slice(), [Value::Number(n)] if *n == 55.0));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let err: AsmError = asm_error("    LOADK r0, 1\n    LAODK r0, 2\n");
+        assert_eq!(err.kind, AsmErrorKind::UnknownMnemonic("LAODK".to_string()));
+        assert_eq!(err.line, 2);
+        assert_eq!(
+            err.to_string(),
+            "Failed to assemble line 2: unknown mnemonic LAODK"
+        );
+        assert_eq!(
+            asm_error("    MOVE r0\n").kind,
+            AsmErrorKind::OperandCount {
+                mnemonic: "MOVE".to_string(),
+                expected: 2,
+                found: 1
+            }
+        );
+        assert_eq!(
+            asm_error("    JMP nowhere\n").kind,
+            AsmErrorKind::UnknownLabel("nowhere".to_string())
+        );
+        assert_eq!(
+            asm_error("    MOVE r300, r0\n").kind,
+            AsmErrorKind::OperandOutOfRange {
+                mnemonic: "MOVE".to_string()
+            }
+        );
+        assert_eq!(
+            asm_error(".proc f\n    RETURN r0, 1\n").kind,
+            AsmErrorKind::UnclosedProc("f".to_string())
+        );
+        assert_eq!(asm_error(".end\n").kind, AsmErrorKind::UnmatchedEnd);
+    }
+
+    #[test]
+    fn constants_and_jumps_stay_in_the_proc() {
+        let err: AsmError = asm_error("    MOVE r0, r1\n    LOADK r0, k5\n");
+        assert_eq!(
+            err.kind,
+            AsmErrorKind::ConstantOutOfRange { index: 5, count: 0 }
+        );
+        assert_eq!(err.line, 2);
+        assert_eq!(
+            asm_error("    ADD r0, r0, k1\n.const 1\n").kind,
+            AsmErrorKind::ConstantOutOfRange { index: 1, count: 1 }
+        );
+        // a .const after the instruction using it still counts
+        assemble("    LOADK r0, k1\n.const 1\n.const 2\n");
+
+        // one past the RETURN added after it
+        let err: AsmError = asm_error("    JMP 5\n");
+        assert_eq!(
+            err.kind,
+            AsmErrorKind::JumpOutOfRange {
+                target: 6,
+                count: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Failed to assemble line 1: jump to pc 6 is out of range, the proc has 2 instruction(s)"
+        );
+        assert_eq!(
+            asm_error("    MOVE r0, r1\n    JMP -3\n").kind,
+            AsmErrorKind::JumpOutOfRange {
+                target: -1,
+                count: 3
+            }
+        );
+        // a label after the last instruction lands on the added RETURN
+        assemble("    JMP done\ndone:\n");
+    }
+}
@@ -1,14 +1,26 @@
 use std::{collections::HashMap, str::from_utf8, time::Duration};
 
-use crate::util::util::format_time_taken;
+use crate::{
+    cfg::{build_cfg, Cfg},
+    color::{highlight, paint, Style},
+    decompile::{structure_proto, Block, Chain, Node, Shape},
+    liveness::{build_liveness, captured_registers, register_uses, Liveness},
+    util::{format_time_taken, is_identifier},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LuaVersion {
     Lua51,
+    Lua52,
+    Lua53,
+    Lua54,
+    Luau,
+    LuaJit,
 }
 
 pub type LuaInt = i32; // make sure this type's size matches size_luaint (or is bigger than it)
 pub type LuaUint = u32;
+pub type LuaInteger = i64; // make sure this type's size matches size_luainteger (or is bigger than it)
 pub type LuaNumber = f64; // make sure this type's size matches size_luanumber (or is bigger than it)
 pub type LuaInstruction = u32; // make sure this type's size matches size_instruction (or is bigger than it)
 
@@ -17,31 +29,88 @@ pub enum Constant {
     Nil,
     Boolean(bool),
     Number(LuaNumber),
+    Integer(LuaInteger),
     String(Vec<u8>),
+    // Luau only
+    Import(Vec<Vec<u8>>), // path of a global resolved at load time, e.g. math.floor
+    Table(Vec<Constant>), // keys of a table template
+    Closure(LuaInt),      // index of the child proto
+    Vector(f32, f32, f32, f32),
+    // LuaJIT only
+    Template(Vec<Constant>, Vec<(Constant, Constant)>), // array and hash part of a table template
+    Int64(i64),                                         // FFI cdata literals like 1LL, 1ULL and 1i
+    UInt64(u64),
+    Complex(f64, f64),
 }
 
 impl Constant {
+    // indexing a table with the constant, as .name when it's a name
+    pub fn format_index(&self) -> String {
+        match self {
+            Constant::String(bytes) if is_identifier(bytes) => {
+                format!(".{}", String::from_utf8_lossy(bytes))
+            }
+            _ => format!("[{}]", self.format()),
+        }
+    }
+
     pub fn format(&self) -> String {
         match self {
             Constant::Nil => "nil".to_string(),
             Constant::Boolean(bool) => bool.to_string(),
             Constant::Number(number) => number.to_string(),
+            Constant::Integer(integer) => integer.to_string(),
+            Constant::Import(path) => path
+                .iter()
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .collect::<Vec<String>>()
+                .join("."),
+            Constant::Table(keys) => format!(
+                "{{{}}}",
+                keys.iter()
+                    .map(|key| format!("[{}] = ...", key.format()))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Constant::Closure(index) => format!("proto_{}", index),
+            Constant::Vector(x, y, z, w) => match *w == 0.0 {
+                true => format!("vector.create({}, {}, {})", x, y, z),
+                false => format!("vector.create({}, {}, {}, {})", x, y, z, w),
+            },
+            Constant::Template(array, hash) => {
+                let mut fields: Vec<String> = Vec::new();
+                for (i, value) in array.iter().enumerate() {
+                    // the array part starts at index 0, which is only set by an explicit [0] key
+                    match (i, value) {
+                        (0, Constant::Nil) => {}
+                        (0, _) => fields.push(format!("[0] = {}", value.format())),
+                        _ => fields.push(value.format()),
+                    }
+                }
+                for (key, value) in hash {
+                    fields.push(format!("[{}] = {}", key.format(), value.format()));
+                }
+                format!("{{{}}}", fields.join(", "))
+            }
+            Constant::Int64(integer) => format!("{}LL", integer),
+            Constant::UInt64(integer) => format!("{}ULL", integer),
+            Constant::Complex(real, imaginary) => format!("{}{:+}i", real, imaginary),
             Constant::String(bytes) => {
                 // unparse string
                 let mut result: String = String::from('"');
                 let mut map: HashMap<u8, char> = HashMap::new();
 
-                map.insert('\u{0007}' as u8, 'a');
-                map.insert('\u{0008}' as u8, 'b');
-                map.insert('\u{000C}' as u8, 'c');
-                map.insert('\n' as u8, 'n');
-                map.insert('\r' as u8, 'r');
-                map.insert('\t' as u8, 't');
-                map.insert('\u{000B}' as u8, 'v');
-                map.insert('\\' as u8, '\\');
-                map.insert('\'' as u8, '\'');
-                map.insert('"' as u8, '"');
-                map.insert('\0' as u8, '0');
+                map.insert(b'\x07', 'a');
+                map.insert(b'\x08', 'b');
+                map.insert(b'\x0C', 'c');
+                map.insert(b'\n', 'n');
+                map.insert(b'\r', 'r');
+                map.insert(b'\t', 't');
+                map.insert(b'\x0B', 'v');
+                map.insert(b'\\', '\\');
+                map.insert(b'\'', '\'');
+                map.insert(b'"', '"');
+                map.insert(b'\0', '0');
                 // map.insert('\1', '1');
 
                 let mut i = 0;
@@ -49,7 +118,7 @@ impl Constant {
                     let b = bytes[i];
                     if let Some(replacement) = map.get(&b) {
                         result.push('\\');
-                        result.push(replacement.clone());
+                        result.push(*replacement);
                     } else if let Ok(c) = from_utf8(&bytes[i..i + 1]) {
                         result.push_str(c);
                     } else {
@@ -70,12 +139,21 @@ pub enum OpMode {
     ABC(LuaInt, LuaInt, LuaInt),
     ABX(LuaInt, LuaInt),
     ASBX(LuaInt, LuaInt),
+    AX(LuaInt),
+    // 5.4 only
+    ABCK(LuaInt, LuaInt, LuaInt, bool),
+    SJ(LuaInt),
+    // Luau and LuaJIT
+    AD(LuaInt, LuaInt),
+    // Luau only
+    E(LuaInt),
 }
 
 const SIZE_C: LuaInt = 9;
 const SIZE_B: LuaInt = 9;
 const SIZE_BX: LuaInt = SIZE_C + SIZE_B;
 const SIZE_A: LuaInt = 8;
+const SIZE_AX: LuaInt = SIZE_C + SIZE_B + SIZE_A;
 
 const SIZE_OP: LuaInt = 6;
 
@@ -84,6 +162,7 @@ const POS_A: LuaInt = POS_OP + SIZE_OP;
 const POS_C: LuaInt = POS_A + SIZE_A;
 const POS_B: LuaInt = POS_C + SIZE_C;
 const POS_BX: LuaInt = POS_C;
+const POS_AX: LuaInt = POS_A;
 
 macro_rules! MASK1 {
     ( $n:expr, $p:expr ) => {
@@ -116,8 +195,12 @@ macro_rules! GET_ARGBX {
         (($i >> POS_BX) & MASK1!(SIZE_BX, 0)) as LuaInt
     };
 }
+macro_rules! GET_ARGAX {
+    ( $i:expr ) => {
+        (($i >> POS_AX) & MASK1!(SIZE_AX, 0)) as LuaInt
+    };
+}
 
-/*
 macro_rules! MAXARG_BX {
     ( $num_bits_int:expr, $max_int:expr ) => {
         if SIZE_BX < ($num_bits_int - 1) {
@@ -127,7 +210,6 @@ macro_rules! MAXARG_BX {
         }
     };
 }
-*/
 macro_rules! MAXARG_SBX {
     ( $num_bits_int:expr, $max_int:expr ) => {
         if SIZE_BX < ($num_bits_int - 1) {
@@ -143,7 +225,65 @@ macro_rules! GET_ARGASBX {
     };
 }
 
-const BITRK: LuaInt = 1 << (SIZE_B - 1);
+pub const MAXARG_A: LuaInt = (1 << SIZE_A) - 1;
+pub const MAXARG_B: LuaInt = (1 << SIZE_B) - 1;
+pub const MAXARG_C: LuaInt = (1 << SIZE_C) - 1;
+// Bx and sBx limits with the 32 bit int every chunk inu reads or writes is decoded with
+pub const MAXARG_BX_32: LuaInt = MAXARG_BX!(32, LuaInt::MAX);
+pub const MAXARG_SBX_32: LuaInt = MAXARG_SBX!(32, LuaInt::MAX);
+// MAXSTACK in 5.1's llimits.h
+pub const MAX_STACK_SIZE: LuaInt = 250;
+// is_vararg bits of a 5.1 proto, from lobject.h
+pub const VARARG_HASARG: u8 = 1;
+pub const VARARG_ISVARARG: u8 = 2;
+pub const VARARG_NEEDSARG: u8 = 4;
+
+macro_rules! CREATE_ABC {
+    ( $o:expr, $a:expr, $b:expr, $c:expr ) => {
+        (($o as LuaInstruction) << POS_OP)
+            | (($a as LuaInstruction) << POS_A)
+            | (($b as LuaInstruction) << POS_B)
+            | (($c as LuaInstruction) << POS_C)
+    };
+}
+macro_rules! CREATE_ABX {
+    ( $o:expr, $a:expr, $bx:expr ) => {
+        (($o as LuaInstruction) << POS_OP)
+            | (($a as LuaInstruction) << POS_A)
+            | (($bx as LuaInstruction) << POS_BX)
+    };
+}
+
+// 5.4 widened the opcode to 7 bits, moved k between A and B and shrank B and C to 8 bits
+const SIZE_C_54: LuaInt = 8;
+const SIZE_B_54: LuaInt = 8;
+const SIZE_BX_54: LuaInt = SIZE_C_54 + SIZE_B_54 + 1;
+const SIZE_A_54: LuaInt = 8;
+const SIZE_AX_54: LuaInt = SIZE_BX_54 + SIZE_A_54;
+const SIZE_SJ_54: LuaInt = SIZE_BX_54 + SIZE_A_54;
+
+const SIZE_OP_54: LuaInt = 7;
+
+const POS_OP_54: LuaInt = 0;
+const POS_A_54: LuaInt = POS_OP_54 + SIZE_OP_54;
+const POS_K_54: LuaInt = POS_A_54 + SIZE_A_54;
+const POS_B_54: LuaInt = POS_K_54 + 1;
+const POS_C_54: LuaInt = POS_B_54 + SIZE_B_54;
+const POS_BX_54: LuaInt = POS_K_54;
+const POS_AX_54: LuaInt = POS_A_54;
+const POS_SJ_54: LuaInt = POS_A_54;
+
+const OFFSET_SBX_54: LuaInt = ((1 << SIZE_BX_54) - 1) >> 1;
+const OFFSET_SJ_54: LuaInt = ((1 << SIZE_SJ_54) - 1) >> 1;
+const OFFSET_SC_54: LuaInt = ((1 << SIZE_C_54) - 1) >> 1;
+
+macro_rules! GET_ARG_54 {
+    ( $i:expr, $pos:expr, $size:expr ) => {
+        (($i >> $pos) & MASK1!($size, 0)) as LuaInt
+    };
+}
+
+pub const BITRK: LuaInt = 1 << (SIZE_B - 1);
 
 macro_rules! IS_RK {
     ( $x:expr ) => {
@@ -159,29 +299,65 @@ macro_rules! INDEXK {
 #[derive(Debug, Clone)]
 pub enum OpCode {
     OpMove(OpMode),
+    OpLoadI(OpMode),
+    OpLoadF(OpMode),
     OpLoadK(OpMode),
+    OpLoadKx(OpMode),
     OpLoadBool(OpMode),
+    OpLoadFalse(OpMode),
+    OpLFalseSkip(OpMode),
+    OpLoadTrue(OpMode),
     OpLoadNil(OpMode),
     OpGetUpval(OpMode),
 
     OpGetGlobal(OpMode),
+    OpGetTabUp(OpMode),
     OpGetTable(OpMode),
+    OpGetI(OpMode),
+    OpGetField(OpMode),
 
     OpSetGlobal(OpMode),
+    OpSetTabUp(OpMode),
     OpSetUpval(OpMode),
     OpSetTable(OpMode),
+    OpSetI(OpMode),
+    OpSetField(OpMode),
 
     OpNewTable(OpMode),
 
     OpSelf(OpMode),
 
+    OpAddI(OpMode),
+    OpAddK(OpMode),
+    OpSubK(OpMode),
+    OpMulK(OpMode),
+    OpModK(OpMode),
+    OpPowK(OpMode),
+    OpDivK(OpMode),
+    OpIdivK(OpMode),
+    OpBandK(OpMode),
+    OpBorK(OpMode),
+    OpBxorK(OpMode),
+    OpShrI(OpMode),
+    OpShlI(OpMode),
+
     OpAdd(OpMode),
     OpSub(OpMode),
     OpMul(OpMode),
     OpDiv(OpMode),
     OpMod(OpMode),
     OpPow(OpMode),
+    OpIdiv(OpMode),
+    OpBand(OpMode),
+    OpBor(OpMode),
+    OpBxor(OpMode),
+    OpShl(OpMode),
+    OpShr(OpMode),
+    OpMmBin(OpMode),
+    OpMmBinI(OpMode),
+    OpMmBinK(OpMode),
     OpUnm(OpMode),
+    OpBnot(OpMode),
     OpNot(OpMode),
     OpLen(OpMode),
 
@@ -192,6 +368,12 @@ pub enum OpCode {
     OpEq(OpMode),
     OpLt(OpMode),
     OpLe(OpMode),
+    OpEqK(OpMode),
+    OpEqI(OpMode),
+    OpLtI(OpMode),
+    OpLeI(OpMode),
+    OpGtI(OpMode),
+    OpGeI(OpMode),
 
     OpTest(OpMode),
     OpTestSet(OpMode),
@@ -199,66 +381,494 @@ pub enum OpCode {
     OpCall(OpMode),
     OpTailCall(OpMode),
     OpReturn(OpMode),
+    OpReturn0(OpMode),
+    OpReturn1(OpMode),
 
     OpForLoop(OpMode),
     OpForPrep(OpMode),
 
+    OpTForPrep(OpMode),
+    OpTForCall(OpMode),
     OpTForLoop(OpMode),
     OpSetList(OpMode),
 
     OpClose(OpMode),
+    OpTbc(OpMode),
     OpClosure(OpMode),
 
     OpVararg(OpMode),
+    OpVarargPrep(OpMode),
+
+    OpExtraArg(OpMode),
+
+    // Luau only
+    OpNop(OpMode),
+    OpBreak(OpMode),
+    OpLoadB(OpMode),
+    OpLoadN(OpMode),
+    OpCloseUpvals(OpMode),
+    OpGetImport(OpMode),
+    OpGetTableKs(OpMode),
+    OpSetTableKs(OpMode),
+    OpGetTableN(OpMode),
+    OpSetTableN(OpMode),
+    OpNamecall(OpMode),
+    OpJump(OpMode),
+    OpJumpBack(OpMode),
+    OpJumpIf(OpMode),
+    OpJumpIfNot(OpMode),
+    OpJumpIfEq(OpMode),
+    OpJumpIfLe(OpMode),
+    OpJumpIfLt(OpMode),
+    OpJumpIfNotEq(OpMode),
+    OpJumpIfNotLe(OpMode),
+    OpJumpIfNotLt(OpMode),
+    OpSubRk(OpMode),
+    OpDivRk(OpMode),
+    OpAnd(OpMode),
+    OpOr(OpMode),
+    OpAndK(OpMode),
+    OpOrK(OpMode),
+    OpDupTable(OpMode),
+    OpForNPrep(OpMode),
+    OpForNLoop(OpMode),
+    OpForGPrep(OpMode),
+    OpForGPrepINext(OpMode),
+    OpForGPrepNext(OpMode),
+    OpForGLoop(OpMode),
+    OpNativeCall(OpMode),
+    OpDupClosure(OpMode),
+    OpJumpX(OpMode),
+    OpFastCall(OpMode),
+    OpFastCall1(OpMode),
+    OpFastCall2(OpMode),
+    OpFastCall2K(OpMode),
+    OpFastCall3(OpMode),
+    OpCoverage(OpMode),
+    OpCapture(OpMode),
+    OpJumpXEqKNil(OpMode),
+    OpJumpXEqKB(OpMode),
+    OpJumpXEqKN(OpMode),
+    OpJumpXEqKS(OpMode),
+    // the AUX word following some Luau instructions, kept in place so pcs match jump offsets
+    OpAux(OpMode),
+    // LuaJIT only
+    OpIsLt(OpMode),
+    OpIsGe(OpMode),
+    OpIsLe(OpMode),
+    OpIsGt(OpMode),
+    OpIsEqV(OpMode),
+    OpIsNeV(OpMode),
+    OpIsEqS(OpMode),
+    OpIsNeS(OpMode),
+    OpIsEqN(OpMode),
+    OpIsNeN(OpMode),
+    OpIsEqP(OpMode),
+    OpIsNeP(OpMode),
+    OpIsTC(OpMode),
+    OpIsFC(OpMode),
+    OpIsT(OpMode),
+    OpIsF(OpMode),
+    OpIsType(OpMode),
+    OpIsNum(OpMode),
+    OpAddVN(OpMode),
+    OpSubVN(OpMode),
+    OpMulVN(OpMode),
+    OpDivVN(OpMode),
+    OpModVN(OpMode),
+    OpAddNV(OpMode),
+    OpSubNV(OpMode),
+    OpMulNV(OpMode),
+    OpDivNV(OpMode),
+    OpModNV(OpMode),
+    OpAddVV(OpMode),
+    OpSubVV(OpMode),
+    OpMulVV(OpMode),
+    OpDivVV(OpMode),
+    OpModVV(OpMode),
+    OpCat(OpMode),
+    OpKStr(OpMode),
+    OpKCData(OpMode),
+    OpKShort(OpMode),
+    OpKNum(OpMode),
+    OpKPri(OpMode),
+    OpKNil(OpMode),
+    OpUGet(OpMode),
+    OpUSetV(OpMode),
+    OpUSetS(OpMode),
+    OpUSetN(OpMode),
+    OpUSetP(OpMode),
+    OpUClo(OpMode),
+    OpFNew(OpMode),
+    OpTNew(OpMode),
+    OpTDup(OpMode),
+    OpGGet(OpMode),
+    OpGSet(OpMode),
+    OpTGetV(OpMode),
+    OpTGetS(OpMode),
+    OpTGetB(OpMode),
+    OpTGetR(OpMode),
+    OpTSetV(OpMode),
+    OpTSetS(OpMode),
+    OpTSetB(OpMode),
+    OpTSetM(OpMode),
+    OpTSetR(OpMode),
+    OpCallM(OpMode),
+    OpCallMT(OpMode),
+    OpCallT(OpMode),
+    OpIterC(OpMode),
+    OpIterN(OpMode),
+    OpVarg(OpMode),
+    OpIsNext(OpMode),
+    OpRetM(OpMode),
+    OpRet(OpMode),
+    OpRet0(OpMode),
+    OpRet1(OpMode),
+    OpForI(OpMode),
+    OpJForI(OpMode),
+    OpForL(OpMode),
+    OpIForL(OpMode),
+    OpJForL(OpMode),
+    OpIterL(OpMode),
+    OpIIterL(OpMode),
+    OpJIterL(OpMode),
+    OpLoop(OpMode),
+    OpILoop(OpMode),
+    OpJLoop(OpMode),
+    OpFuncF(OpMode),
+    OpIFuncF(OpMode),
+    OpJFuncF(OpMode),
+    OpFuncV(OpMode),
+    OpIFuncV(OpMode),
+    OpJFuncV(OpMode),
+    OpFuncC(OpMode),
+    OpFuncCW(OpMode),
 
     OpUnknown(u32),
 }
 
-macro_rules! GET_RAW_CONSTANT_AND_EXPECT_STRING {
-    ( $constants:expr, $index:expr, $instruction_name:expr ) => {
-        if let Constant::String(s) = $constants[$index].clone() {
-            s
-        } else {
-            panic!(
-                "Failed to format {} instruction: constant type was not String",
-                $instruction_name
-            );
+impl OpCode {
+    // the variant's name, e.g. OpGetGlobal, as the listing shows it
+    pub fn name(&self) -> String {
+        let text: String = format!("{:?}", self);
+        match text.split_once('(') {
+            Some((name, _)) => name.to_string(),
+            None => text,
         }
-    };
+    }
+
+    // None for an opcode the reader didn't know
+    pub fn mode(&self) -> Option<&OpMode> {
+        match self {
+            OpCode::OpMove(mode)
+            | OpCode::OpLoadI(mode)
+            | OpCode::OpLoadF(mode)
+            | OpCode::OpLoadK(mode)
+            | OpCode::OpLoadKx(mode)
+            | OpCode::OpLoadBool(mode)
+            | OpCode::OpLoadFalse(mode)
+            | OpCode::OpLFalseSkip(mode)
+            | OpCode::OpLoadTrue(mode)
+            | OpCode::OpLoadNil(mode)
+            | OpCode::OpGetUpval(mode)
+            | OpCode::OpGetGlobal(mode)
+            | OpCode::OpGetTabUp(mode)
+            | OpCode::OpGetTable(mode)
+            | OpCode::OpGetI(mode)
+            | OpCode::OpGetField(mode)
+            | OpCode::OpSetGlobal(mode)
+            | OpCode::OpSetTabUp(mode)
+            | OpCode::OpSetUpval(mode)
+            | OpCode::OpSetTable(mode)
+            | OpCode::OpSetI(mode)
+            | OpCode::OpSetField(mode)
+            | OpCode::OpNewTable(mode)
+            | OpCode::OpSelf(mode)
+            | OpCode::OpAddI(mode)
+            | OpCode::OpAddK(mode)
+            | OpCode::OpSubK(mode)
+            | OpCode::OpMulK(mode)
+            | OpCode::OpModK(mode)
+            | OpCode::OpPowK(mode)
+            | OpCode::OpDivK(mode)
+            | OpCode::OpIdivK(mode)
+            | OpCode::OpBandK(mode)
+            | OpCode::OpBorK(mode)
+            | OpCode::OpBxorK(mode)
+            | OpCode::OpShrI(mode)
+            | OpCode::OpShlI(mode)
+            | OpCode::OpAdd(mode)
+            | OpCode::OpSub(mode)
+            | OpCode::OpMul(mode)
+            | OpCode::OpDiv(mode)
+            | OpCode::OpMod(mode)
+            | OpCode::OpPow(mode)
+            | OpCode::OpIdiv(mode)
+            | OpCode::OpBand(mode)
+            | OpCode::OpBor(mode)
+            | OpCode::OpBxor(mode)
+            | OpCode::OpShl(mode)
+            | OpCode::OpShr(mode)
+            | OpCode::OpMmBin(mode)
+            | OpCode::OpMmBinI(mode)
+            | OpCode::OpMmBinK(mode)
+            | OpCode::OpUnm(mode)
+            | OpCode::OpBnot(mode)
+            | OpCode::OpNot(mode)
+            | OpCode::OpLen(mode)
+            | OpCode::OpConcat(mode)
+            | OpCode::OpJmp(mode)
+            | OpCode::OpEq(mode)
+            | OpCode::OpLt(mode)
+            | OpCode::OpLe(mode)
+            | OpCode::OpEqK(mode)
+            | OpCode::OpEqI(mode)
+            | OpCode::OpLtI(mode)
+            | OpCode::OpLeI(mode)
+            | OpCode::OpGtI(mode)
+            | OpCode::OpGeI(mode)
+            | OpCode::OpTest(mode)
+            | OpCode::OpTestSet(mode)
+            | OpCode::OpCall(mode)
+            | OpCode::OpTailCall(mode)
+            | OpCode::OpReturn(mode)
+            | OpCode::OpReturn0(mode)
+            | OpCode::OpReturn1(mode)
+            | OpCode::OpForLoop(mode)
+            | OpCode::OpForPrep(mode)
+            | OpCode::OpTForPrep(mode)
+            | OpCode::OpTForCall(mode)
+            | OpCode::OpTForLoop(mode)
+            | OpCode::OpSetList(mode)
+            | OpCode::OpClose(mode)
+            | OpCode::OpTbc(mode)
+            | OpCode::OpClosure(mode)
+            | OpCode::OpVararg(mode)
+            | OpCode::OpVarargPrep(mode)
+            | OpCode::OpExtraArg(mode)
+            | OpCode::OpNop(mode)
+            | OpCode::OpBreak(mode)
+            | OpCode::OpLoadB(mode)
+            | OpCode::OpLoadN(mode)
+            | OpCode::OpCloseUpvals(mode)
+            | OpCode::OpGetImport(mode)
+            | OpCode::OpGetTableKs(mode)
+            | OpCode::OpSetTableKs(mode)
+            | OpCode::OpGetTableN(mode)
+            | OpCode::OpSetTableN(mode)
+            | OpCode::OpNamecall(mode)
+            | OpCode::OpJump(mode)
+            | OpCode::OpJumpBack(mode)
+            | OpCode::OpJumpIf(mode)
+            | OpCode::OpJumpIfNot(mode)
+            | OpCode::OpJumpIfEq(mode)
+            | OpCode::OpJumpIfLe(mode)
+            | OpCode::OpJumpIfLt(mode)
+            | OpCode::OpJumpIfNotEq(mode)
+            | OpCode::OpJumpIfNotLe(mode)
+            | OpCode::OpJumpIfNotLt(mode)
+            | OpCode::OpSubRk(mode)
+            | OpCode::OpDivRk(mode)
+            | OpCode::OpAnd(mode)
+            | OpCode::OpOr(mode)
+            | OpCode::OpAndK(mode)
+            | OpCode::OpOrK(mode)
+            | OpCode::OpDupTable(mode)
+            | OpCode::OpForNPrep(mode)
+            | OpCode::OpForNLoop(mode)
+            | OpCode::OpForGPrep(mode)
+            | OpCode::OpForGPrepINext(mode)
+            | OpCode::OpForGPrepNext(mode)
+            | OpCode::OpForGLoop(mode)
+            | OpCode::OpNativeCall(mode)
+            | OpCode::OpDupClosure(mode)
+            | OpCode::OpJumpX(mode)
+            | OpCode::OpFastCall(mode)
+            | OpCode::OpFastCall1(mode)
+            | OpCode::OpFastCall2(mode)
+            | OpCode::OpFastCall2K(mode)
+            | OpCode::OpFastCall3(mode)
+            | OpCode::OpCoverage(mode)
+            | OpCode::OpCapture(mode)
+            | OpCode::OpJumpXEqKNil(mode)
+            | OpCode::OpJumpXEqKB(mode)
+            | OpCode::OpJumpXEqKN(mode)
+            | OpCode::OpJumpXEqKS(mode)
+            | OpCode::OpAux(mode)
+            | OpCode::OpIsLt(mode)
+            | OpCode::OpIsGe(mode)
+            | OpCode::OpIsLe(mode)
+            | OpCode::OpIsGt(mode)
+            | OpCode::OpIsEqV(mode)
+            | OpCode::OpIsNeV(mode)
+            | OpCode::OpIsEqS(mode)
+            | OpCode::OpIsNeS(mode)
+            | OpCode::OpIsEqN(mode)
+            | OpCode::OpIsNeN(mode)
+            | OpCode::OpIsEqP(mode)
+            | OpCode::OpIsNeP(mode)
+            | OpCode::OpIsTC(mode)
+            | OpCode::OpIsFC(mode)
+            | OpCode::OpIsT(mode)
+            | OpCode::OpIsF(mode)
+            | OpCode::OpIsType(mode)
+            | OpCode::OpIsNum(mode)
+            | OpCode::OpAddVN(mode)
+            | OpCode::OpSubVN(mode)
+            | OpCode::OpMulVN(mode)
+            | OpCode::OpDivVN(mode)
+            | OpCode::OpModVN(mode)
+            | OpCode::OpAddNV(mode)
+            | OpCode::OpSubNV(mode)
+            | OpCode::OpMulNV(mode)
+            | OpCode::OpDivNV(mode)
+            | OpCode::OpModNV(mode)
+            | OpCode::OpAddVV(mode)
+            | OpCode::OpSubVV(mode)
+            | OpCode::OpMulVV(mode)
+            | OpCode::OpDivVV(mode)
+            | OpCode::OpModVV(mode)
+            | OpCode::OpCat(mode)
+            | OpCode::OpKStr(mode)
+            | OpCode::OpKCData(mode)
+            | OpCode::OpKShort(mode)
+            | OpCode::OpKNum(mode)
+            | OpCode::OpKPri(mode)
+            | OpCode::OpKNil(mode)
+            | OpCode::OpUGet(mode)
+            | OpCode::OpUSetV(mode)
+            | OpCode::OpUSetS(mode)
+            | OpCode::OpUSetN(mode)
+            | OpCode::OpUSetP(mode)
+            | OpCode::OpUClo(mode)
+            | OpCode::OpFNew(mode)
+            | OpCode::OpTNew(mode)
+            | OpCode::OpTDup(mode)
+            | OpCode::OpGGet(mode)
+            | OpCode::OpGSet(mode)
+            | OpCode::OpTGetV(mode)
+            | OpCode::OpTGetS(mode)
+            | OpCode::OpTGetB(mode)
+            | OpCode::OpTGetR(mode)
+            | OpCode::OpTSetV(mode)
+            | OpCode::OpTSetS(mode)
+            | OpCode::OpTSetB(mode)
+            | OpCode::OpTSetM(mode)
+            | OpCode::OpTSetR(mode)
+            | OpCode::OpCallM(mode)
+            | OpCode::OpCallMT(mode)
+            | OpCode::OpCallT(mode)
+            | OpCode::OpIterC(mode)
+            | OpCode::OpIterN(mode)
+            | OpCode::OpVarg(mode)
+            | OpCode::OpIsNext(mode)
+            | OpCode::OpRetM(mode)
+            | OpCode::OpRet(mode)
+            | OpCode::OpRet0(mode)
+            | OpCode::OpRet1(mode)
+            | OpCode::OpForI(mode)
+            | OpCode::OpJForI(mode)
+            | OpCode::OpForL(mode)
+            | OpCode::OpIForL(mode)
+            | OpCode::OpJForL(mode)
+            | OpCode::OpIterL(mode)
+            | OpCode::OpIIterL(mode)
+            | OpCode::OpJIterL(mode)
+            | OpCode::OpLoop(mode)
+            | OpCode::OpILoop(mode)
+            | OpCode::OpJLoop(mode)
+            | OpCode::OpFuncF(mode)
+            | OpCode::OpIFuncF(mode)
+            | OpCode::OpJFuncF(mode)
+            | OpCode::OpFuncV(mode)
+            | OpCode::OpIFuncV(mode)
+            | OpCode::OpJFuncV(mode)
+            | OpCode::OpFuncC(mode)
+            | OpCode::OpFuncCW(mode) => Some(mode),
+            OpCode::OpUnknown(_) => None,
+        }
+    }
+}
+
+// operands of malformed chunks can point past the constants or at the wrong type
+fn invalid_constant(index: usize) -> String {
+    format!("[INVALID CONSTANT {}]", index)
+}
+
+fn format_constant(constants: &[Constant], index: usize) -> String {
+    match constants.get(index) {
+        Some(constant) => constant.format(),
+        None => invalid_constant(index),
+    }
+}
+
+fn format_constant_index(constants: &[Constant], index: usize) -> String {
+    match constants.get(index) {
+        Some(constant) => constant.format_index(),
+        None => format!("[{}]", invalid_constant(index)),
+    }
+}
+
+// the name of the global an instruction reads or writes
+fn global_constant(constants: &[Constant], index: usize) -> String {
+    match constants.get(index) {
+        Some(Constant::String(name)) => String::from_utf8_lossy(name).into_owned(),
+        Some(_) => String::from("[INVALID STRING]"),
+        None => invalid_constant(index),
+    }
 }
 
 macro_rules! REG_FMT {
-    ( $reg:expr, $proto:expr ) => {
-        match $reg < $proto.param_count as i32 {
-            true => format!("arg_{}", $reg),
-            false => format!("r_{}", $reg),
-        }
+    ( $reg:expr, $proto:expr, $pc:expr ) => {
+        $proto.register_name($reg, $pc)
+    };
+}
+
+// a register the instruction writes
+macro_rules! TARGET_FMT {
+    ( $reg:expr, $proto:expr, $pc:expr ) => {
+        $proto.target_name($reg, $pc)
     };
 }
 
 macro_rules! FORMAT_CONSTANT_RK {
-    ( $constants:expr, $value:expr, $proto:expr ) => {
+    ( $constants:expr, $value:expr, $proto:expr, $pc:expr ) => {
+        if IS_RK!($value) {
+            format_constant($constants, INDEXK!($value) as usize)
+        } else {
+            REG_FMT!($value, $proto, $pc)
+        }
+    };
+}
+// indexing with an RK operand, as .name when it's a name constant
+macro_rules! FORMAT_INDEX_RK {
+    ( $constants:expr, $value:expr, $proto:expr, $pc:expr ) => {
         if IS_RK!($value) {
-            $constants[INDEXK!($value) as usize].format()
+            format_constant_index($constants, INDEXK!($value) as usize)
         } else {
-            REG_FMT!($value, $proto)
+            format!("[{}]", REG_FMT!($value, $proto, $pc))
         }
     };
 }
 macro_rules! FORMAT_BINARY {
-    ( $op:expr, $constants:expr, $proto:expr, $a:expr, $b:expr, $c:expr ) => {
+    ( $op:expr, $constants:expr, $proto:expr, $a:expr, $b:expr, $c:expr, $pc:expr ) => {
         format!(
             "{} = {} {} {}",
-            REG_FMT!($a, $proto),
-            FORMAT_CONSTANT_RK!($constants, $b, $proto),
+            TARGET_FMT!($a, $proto, $pc),
+            FORMAT_CONSTANT_RK!($constants, $b, $proto, $pc),
             $op,
-            FORMAT_CONSTANT_RK!($constants, $c, $proto)
+            FORMAT_CONSTANT_RK!($constants, $c, $proto, $pc)
         )
     };
 }
 macro_rules! FORMAT_UNARY {
-    ( $op:expr, $proto:expr, $a:expr, $b:expr ) => {
-        format!("{} = {}{}", REG_FMT!($a, $proto), $op, REG_FMT!($b, $proto))
+    ( $op:expr, $proto:expr, $a:expr, $b:expr, $pc:expr ) => {
+        format!(
+            "{} = {}{}",
+            TARGET_FMT!($a, $proto, $pc),
+            $op,
+            REG_FMT!($b, $proto, $pc)
+        )
     };
 }
 
@@ -266,19 +876,35 @@ macro_rules! FORMAT_CONDITION {
     ( $yes:expr, $no:expr, $constants:expr, $proto:expr, $a:expr, $b:expr, $c:expr, $pc:expr ) => {
         format!(
             "if {} {} {} then goto {}",
-            FORMAT_CONSTANT_RK!($constants, $b, $proto),
+            FORMAT_CONSTANT_RK!($constants, $b, $proto, $pc),
             if $a == 1 { $no } else { $yes },
-            FORMAT_CONSTANT_RK!($constants, $c, $proto),
+            FORMAT_CONSTANT_RK!($constants, $c, $proto, $pc),
             $pc + 2
         )
     };
 }
 
 macro_rules! SIMPLE_REG_LIST {
-    ( $from:expr, $to:expr, $proto:expr ) => {
+    ( $from:expr, $to:expr, $proto:expr, $pc:expr ) => {
+        match $from == $to {
+            true => REG_FMT!($from, $proto, $pc),
+            false => format!(
+                "{} ... {}",
+                REG_FMT!($from, $proto, $pc),
+                REG_FMT!($to, $proto, $pc)
+            ),
+        }
+    };
+}
+macro_rules! TARGET_REG_LIST {
+    ( $from:expr, $to:expr, $proto:expr, $pc:expr ) => {
         match $from == $to {
-            true => REG_FMT!($from, $proto),
-            false => format!("r_{} ... r_{}", $from, $to),
+            true => TARGET_FMT!($from, $proto, $pc),
+            false => format!(
+                "{} ... {}",
+                TARGET_FMT!($from, $proto, $pc),
+                TARGET_FMT!($to, $proto, $pc)
+            ),
         }
     };
 }
@@ -286,23 +912,45 @@ macro_rules! SIMPLE_REG_LIST {
 impl OpCode {
     fn describe(
         &self,
-        constants: &Vec<Constant>,
+        constants: &[Constant],
         proto: &Proto,
-        protos: &Vec<Proto>,
+        protos: &[Proto],
         pc: isize,
+        version: LuaVersion,
     ) -> String {
-        return if let OpCode::OpMove(OpMode::ABC(a, b, _c)) = self {
-            format!("{} = {}", REG_FMT!(*a, proto), REG_FMT!(*b, proto))
+        match version {
+            LuaVersion::Lua54 => return self.describe_54(constants, proto, protos, pc),
+            LuaVersion::Luau => return self.describe_luau(constants, proto, protos, pc),
+            LuaVersion::LuaJit => return self.describe_luajit(constants, proto, protos, pc),
+            _ => {}
+        }
+
+        if let OpCode::OpMove(OpMode::ABC(a, b, _c)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc)
+            )
         } else if let OpCode::OpLoadK(OpMode::ABX(a, bx)) = self {
             format!(
                 "{} = {}",
-                REG_FMT!(*a, proto),
-                constants[*bx as usize].format()
+                TARGET_FMT!(*a, proto, pc),
+                format_constant(constants, *bx as usize)
+            )
+        } else if let OpCode::OpLoadKx(OpMode::ABX(a, _bx)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                match proto.code.get(pc as usize + 1).map(|inst| &inst.op) {
+                    Some(OpCode::OpExtraArg(OpMode::AX(ax))) =>
+                        format_constant(constants, *ax as usize),
+                    _ => String::from("[MISSING EXTRAARG]"),
+                }
             )
         } else if let OpCode::OpLoadBool(OpMode::ABC(a, b, c)) = self {
             format!(
                 "{} = {}{}",
-                REG_FMT!(*a, proto),
+                TARGET_FMT!(*a, proto, pc),
                 *b == 1,
                 if *c == 1 {
                     format!("; goto {}", pc + 2)
@@ -311,85 +959,140 @@ impl OpCode {
                 }
             )
         } else if let OpCode::OpLoadNil(OpMode::ABC(a, b, _c)) = self {
-            format!("{} = nil", SIMPLE_REG_LIST!(*a, *b, proto))
+            format!(
+                "{} = nil",
+                match version {
+                    LuaVersion::Lua51 => TARGET_REG_LIST!(*a, *b, proto, pc),
+                    // from 5.2 on, B is the count of extra registers instead of the last one
+                    _ => TARGET_REG_LIST!(*a, *a + *b, proto, pc),
+                }
+            )
         } else if let OpCode::OpGetUpval(OpMode::ABC(a, b, _c)) = self {
-            format!("{} = upvalue_{}", REG_FMT!(*a, proto), b)
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                proto.upvalue_name(*b)
+            )
         } else if let OpCode::OpGetGlobal(OpMode::ABX(a, bx)) = self {
             format!(
                 "{} = {}",
-                REG_FMT!(*a, proto),
-                String::from_utf8(GET_RAW_CONSTANT_AND_EXPECT_STRING!(
-                    constants,
-                    *bx as usize,
-                    "GETGLOBAL"
-                ))
-                .unwrap_or("[INVALID STRING]".to_string())
+                TARGET_FMT!(*a, proto, pc),
+                global_constant(constants, *bx as usize)
             )
+        } else if let OpCode::OpGetTabUp(OpMode::ABC(a, b, c)) = self {
+            match proto.global_name(*b, *c, constants, version) {
+                Some(name) => format!("{} = {}", TARGET_FMT!(*a, proto, pc), name),
+                None => format!(
+                    "{} = {}{}",
+                    TARGET_FMT!(*a, proto, pc),
+                    proto.upvalue_name(*b),
+                    FORMAT_INDEX_RK!(constants, *c, proto, pc)
+                ),
+            }
         } else if let OpCode::OpGetTable(OpMode::ABC(a, b, c)) = self {
             format!(
-                "{} = {}[{}]",
-                REG_FMT!(*a, proto),
-                REG_FMT!(*b, proto),
-                FORMAT_CONSTANT_RK!(constants, *c, proto)
+                "{} = {}{}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                FORMAT_INDEX_RK!(constants, *c, proto, pc)
             )
         } else if let OpCode::OpSetGlobal(OpMode::ABX(a, bx)) = self {
             format!(
                 "{} = {}",
-                String::from_utf8(GET_RAW_CONSTANT_AND_EXPECT_STRING!(
-                    constants,
-                    *bx as usize,
-                    "SETGLOBAL"
-                ))
-                .unwrap_or("[INVALID STRING]".to_string()),
-                REG_FMT!(*a, proto)
+                global_constant(constants, *bx as usize),
+                REG_FMT!(*a, proto, pc)
             )
+        } else if let OpCode::OpSetTabUp(OpMode::ABC(a, b, c)) = self {
+            match proto.global_name(*a, *b, constants, version) {
+                Some(name) => format!(
+                    "{} = {}",
+                    name,
+                    FORMAT_CONSTANT_RK!(constants, *c, proto, pc)
+                ),
+                None => format!(
+                    "{}{} = {}",
+                    proto.upvalue_name(*a),
+                    FORMAT_INDEX_RK!(constants, *b, proto, pc),
+                    FORMAT_CONSTANT_RK!(constants, *c, proto, pc)
+                ),
+            }
         } else if let OpCode::OpSetUpval(OpMode::ABC(a, b, _c)) = self {
-            format!("upvalue_{} = {}", b, REG_FMT!(*a, proto))
+            format!("{} = {}", proto.upvalue_name(*b), REG_FMT!(*a, proto, pc))
         } else if let OpCode::OpSetTable(OpMode::ABC(a, b, c)) = self {
             format!(
-                "{}[{}] = {}",
-                REG_FMT!(*a, proto),
-                FORMAT_CONSTANT_RK!(constants, *b, proto),
-                FORMAT_CONSTANT_RK!(constants, *c, proto)
+                "{}{} = {}",
+                REG_FMT!(*a, proto, pc),
+                FORMAT_INDEX_RK!(constants, *b, proto, pc),
+                FORMAT_CONSTANT_RK!(constants, *c, proto, pc)
             )
         } else if let OpCode::OpNewTable(OpMode::ABC(a, b, c)) = self {
-            format!("{} = {{}} -- {} list, {} record", REG_FMT!(*a, proto), b, c)
+            format!(
+                "{} = {{}} -- {} list, {} record",
+                TARGET_FMT!(*a, proto, pc),
+                b,
+                c
+            )
         } else if let OpCode::OpSelf(OpMode::ABC(a, b, c)) = self {
             format!(
-                "{} = {}; {} = {}[{}]",
-                REG_FMT!(a + 1, proto),
-                REG_FMT!(*b, proto),
-                REG_FMT!(*a, proto),
-                REG_FMT!(*b, proto),
-                FORMAT_CONSTANT_RK!(constants, *c, proto)
+                "{} = {}; {} = {}{}",
+                TARGET_FMT!(a + 1, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                FORMAT_INDEX_RK!(constants, *c, proto, pc)
             )
         } else if let OpCode::OpAdd(OpMode::ABC(a, b, c)) = self {
-            FORMAT_BINARY!('+', constants, proto, *a, *b, *c)
+            FORMAT_BINARY!('+', constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpSub(OpMode::ABC(a, b, c)) = self {
-            FORMAT_BINARY!('-', constants, proto, *a, *b, *c)
+            FORMAT_BINARY!('-', constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpMul(OpMode::ABC(a, b, c)) = self {
-            FORMAT_BINARY!('*', constants, proto, *a, *b, *c)
+            FORMAT_BINARY!('*', constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpDiv(OpMode::ABC(a, b, c)) = self {
-            FORMAT_BINARY!('/', constants, proto, *a, *b, *c)
+            FORMAT_BINARY!('/', constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpMod(OpMode::ABC(a, b, c)) = self {
-            FORMAT_BINARY!('%', constants, proto, *a, *b, *c)
+            FORMAT_BINARY!('%', constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpPow(OpMode::ABC(a, b, c)) = self {
-            FORMAT_BINARY!('^', constants, proto, *a, *b, *c)
+            FORMAT_BINARY!('^', constants, proto, *a, *b, *c, pc)
+        } else if let OpCode::OpIdiv(OpMode::ABC(a, b, c)) = self {
+            FORMAT_BINARY!("//", constants, proto, *a, *b, *c, pc)
+        } else if let OpCode::OpBand(OpMode::ABC(a, b, c)) = self {
+            FORMAT_BINARY!('&', constants, proto, *a, *b, *c, pc)
+        } else if let OpCode::OpBor(OpMode::ABC(a, b, c)) = self {
+            FORMAT_BINARY!('|', constants, proto, *a, *b, *c, pc)
+        } else if let OpCode::OpBxor(OpMode::ABC(a, b, c)) = self {
+            FORMAT_BINARY!('~', constants, proto, *a, *b, *c, pc)
+        } else if let OpCode::OpShl(OpMode::ABC(a, b, c)) = self {
+            FORMAT_BINARY!("<<", constants, proto, *a, *b, *c, pc)
+        } else if let OpCode::OpShr(OpMode::ABC(a, b, c)) = self {
+            FORMAT_BINARY!(">>", constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpUnm(OpMode::ABC(a, b, _c)) = self {
-            FORMAT_UNARY!('-', proto, *a, *b)
+            FORMAT_UNARY!('-', proto, *a, *b, pc)
+        } else if let OpCode::OpBnot(OpMode::ABC(a, b, _c)) = self {
+            FORMAT_UNARY!('~', proto, *a, *b, pc)
         } else if let OpCode::OpNot(OpMode::ABC(a, b, _c)) = self {
-            FORMAT_UNARY!("not ", proto, *a, *b)
+            FORMAT_UNARY!("not ", proto, *a, *b, pc)
         } else if let OpCode::OpLen(OpMode::ABC(a, b, _c)) = self {
-            FORMAT_UNARY!('#', proto, *a, *b)
+            FORMAT_UNARY!('#', proto, *a, *b, pc)
         } else if let OpCode::OpConcat(OpMode::ABC(a, b, c)) = self {
             format!(
                 "{} = {} .. ... .. {}",
-                REG_FMT!(*a, proto),
-                REG_FMT!(*b, proto),
-                REG_FMT!(*c, proto)
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc)
+            )
+        } else if let OpCode::OpJmp(OpMode::ASBX(a, sbx)) = self {
+            format!(
+                "goto {}{}",
+                pc + *sbx as isize + 1,
+                if *a > 0 {
+                    format!(
+                        "; close all variables in the stack up to {}",
+                        REG_FMT!(*a - 1, proto, pc)
+                    )
+                } else {
+                    String::new()
+                }
             )
-        } else if let OpCode::OpJmp(OpMode::ASBX(_a, sbx)) = self {
-            format!("goto {}", pc + *sbx as isize + 1)
         } else if let OpCode::OpEq(OpMode::ABC(a, b, c)) = self {
             FORMAT_CONDITION!("==", "~=", constants, proto, *a, *b, *c, pc)
         } else if let OpCode::OpLt(OpMode::ABC(a, b, c)) = self {
@@ -400,108 +1103,138 @@ impl OpCode {
             format!(
                 "if {}{} then goto {}",
                 if *c == 0 { "" } else { "not " },
-                REG_FMT!(*a, proto),
+                REG_FMT!(*a, proto, pc),
                 pc + 2
             )
         } else if let OpCode::OpTestSet(OpMode::ABC(a, b, c)) = self {
             format!(
                 "if {}{} then goto {} else {} = {}",
                 if *c == 0 { "" } else { "not " },
-                b,
-                REG_FMT!(pc as i32 + 2, proto),
-                REG_FMT!(*a, proto),
-                REG_FMT!(*b, proto)
+                REG_FMT!(*b, proto, pc),
+                pc + 2,
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc)
             )
         } else if let OpCode::OpCall(OpMode::ABC(a, b, c)) = self {
             format!(
-                "{}{}({})",
+                "{}{}",
                 if *c == 0 {
-                    String::from("top ... ??? = ")
+                    format!("{} ... = ", TARGET_FMT!(*a, proto, pc))
                 } else if *c == 1 {
                     String::new()
                 } else {
-                    format!("{} = ", SIMPLE_REG_LIST!(*a, *a + *c - 2, proto))
+                    format!("{} = ", TARGET_REG_LIST!(*a, *a + *c - 2, proto, pc))
                 },
-                REG_FMT!(*a, proto),
-                if *b == 0 {
-                    String::from("top ... ???")
-                } else if *b == 1 {
-                    String::new()
-                } else {
-                    SIMPLE_REG_LIST!(*a + 1, *a + *b - 1, proto)
-                }
+                describe_callee(constants, proto, pc, *a, *b, version)
             )
         } else if let OpCode::OpTailCall(OpMode::ABC(a, b, _c)) = self {
             format!(
-                "return {}({})",
-                REG_FMT!(*a, proto),
-                if *b == 0 {
-                    String::from("top ... ???")
-                } else if *b == 1 {
-                    String::new()
-                } else {
-                    SIMPLE_REG_LIST!(*a + 1, *a + *b - 1, proto)
-                }
+                "return {}",
+                describe_callee(constants, proto, pc, *a, *b, version)
             )
         } else if let OpCode::OpReturn(OpMode::ABC(a, b, _c)) = self {
             format!(
                 "return{}",
                 if *b == 0 {
-                    String::from(" top ... ???")
+                    format!(" {}", describe_top(constants, proto, pc, *a, version))
                 } else if *b == 1 {
                     String::new()
                 } else {
-                    format!(" {}", SIMPLE_REG_LIST!(*a, *a + *b - 2, proto))
+                    format!(" {}", SIMPLE_REG_LIST!(*a, *a + *b - 2, proto, pc))
                 }
             )
         } else if let OpCode::OpForLoop(OpMode::ASBX(a, sbx)) = self {
             format!(
                 "{} += {}; if {} <?= {} then {{ goto {}; {} = {} }}",
-                REG_FMT!(*a, proto),
-                REG_FMT!(*a + 2, proto),
-                REG_FMT!(*a, proto),
-                REG_FMT!(*a + 1, proto),
-                pc + *sbx as isize + 2,
-                REG_FMT!(*a + 3, proto),
-                REG_FMT!(*a, proto)
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                pc + *sbx as isize + 1,
+                TARGET_FMT!(*a + 3, proto, pc),
+                REG_FMT!(*a, proto, pc)
             )
         } else if let OpCode::OpForPrep(OpMode::ASBX(a, sbx)) = self {
             format!(
                 "{} -= {}; goto {}",
-                REG_FMT!(*a, proto),
-                REG_FMT!(*a + 2, proto),
-                pc + *sbx as isize + 2
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                pc + *sbx as isize + 1
             )
         } else if let OpCode::OpTForLoop(OpMode::ABC(a, _b, c)) = self {
             format!(
                 "{} = {}({}, {}); if {} ~= nil {{{} = {}}} else goto {}",
-                SIMPLE_REG_LIST!(*a + 3, *a + 2 + *c, proto),
-                *a,
-                REG_FMT!(*a + 1, proto),
-                REG_FMT!(*a + 2, proto),
-                REG_FMT!(*a + 3, proto),
-                REG_FMT!(*a + 2, proto),
-                REG_FMT!(*a + 3, proto),
+                TARGET_REG_LIST!(*a + 3, *a + 2 + *c, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a + 3, proto, pc),
+                TARGET_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a + 3, proto, pc),
                 pc + 2
             )
+        } else if let OpCode::OpTForCall(OpMode::ABC(a, _b, c)) = self {
+            format!(
+                "{} = {}({}, {})",
+                TARGET_REG_LIST!(*a + 3, *a + 2 + *c, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                REG_FMT!(*a + 2, proto, pc)
+            )
+        } else if let OpCode::OpTForLoop(OpMode::ASBX(a, sbx)) = self {
+            format!(
+                "if {} ~= nil then {{ {} = {}; goto {} }}",
+                REG_FMT!(*a + 1, proto, pc),
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                pc + *sbx as isize + 1
+            )
         } else if let OpCode::OpSetList(OpMode::ABC(a, b, c)) = self {
-            let offset = (*c - 1) * 50;
+            // from 5.2 on, a C of 0 means the real C is stored in the following EXTRAARG
+            let c: LuaInt = match (*c, proto.code.get(pc as usize + 1).map(|inst| &inst.op)) {
+                (0, Some(OpCode::OpExtraArg(OpMode::AX(ax)))) if version != LuaVersion::Lua51 => {
+                    *ax
+                }
+                _ => *c,
+            };
+            let offset = (c - 1) * 50;
+            if *b == 0 {
+                return format!(
+                    "{}[{} ...] = {}",
+                    REG_FMT!(*a, proto, pc),
+                    offset + 1,
+                    describe_top(constants, proto, pc, *a + 1, version)
+                );
+            }
             format!(
                 "{}[{} ... {}] = {} ... {}",
-                REG_FMT!(*a, proto),
+                REG_FMT!(*a, proto, pc),
                 offset + 1,
                 offset + *b,
-                REG_FMT!(*a + 1, proto),
-                REG_FMT!(*a + *b, proto)
+                REG_FMT!(*a + 1, proto, pc),
+                REG_FMT!(*a + *b, proto, pc)
             )
         } else if let OpCode::OpClose(OpMode::ABX(a, _bx)) = self {
             format!("close all variables in the stack up to r_{}", a)
         } else if let OpCode::OpClosure(OpMode::ABX(a, bx)) = self {
-            let upvalue_count: u8 = protos[*bx as usize].upvalue_count;
+            let child: &Proto = match protos.get(*bx as usize) {
+                Some(child) => child,
+                None => return format!("{} = [INVALID CLOSURE]", TARGET_FMT!(*a, proto, pc)),
+            };
+            if version != LuaVersion::Lua51 {
+                // upvalues are described by the child instead of pseudo-instructions
+                return format!(
+                    "{} = proto_{}{}",
+                    TARGET_FMT!(*a, proto, pc),
+                    bx,
+                    describe_upvalue_captures(child, proto, pc)
+                );
+            }
+            let upvalue_count: u8 = child.upvalue_count;
             let multiple: bool = upvalue_count > 1;
             format!(
                 "{} = proto_{}{}",
-                REG_FMT!(*a, proto),
+                TARGET_FMT!(*a, proto, pc),
                 bx,
                 if upvalue_count > 0 {
                     format!(
@@ -524,130 +1257,2784 @@ impl OpCode {
             format!(
                 "{} = vararg",
                 if *b == 0 {
-                    format!("{}, top ... ???", REG_FMT!(*a, proto))
+                    format!("{} ...", TARGET_FMT!(*a, proto, pc))
                 } else if *b == 1 {
-                    format!("{}", REG_FMT!(*a, proto))
+                    TARGET_FMT!(*a, proto, pc)
                 } else {
-                    format!("{}", SIMPLE_REG_LIST!(*a, *a + *b - 2, proto))
+                    TARGET_REG_LIST!(*a, *a + *b - 2, proto, pc)
                 }
             )
+        } else if let OpCode::OpExtraArg(OpMode::AX(ax)) = self {
+            format!("extra argument {}", ax)
         } else {
             format!("TODO: DESCRIBE {:?}", self)
-        };
+        }
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct Instruction {
-    // raw: LuaInstruction,
-    pub op: OpCode,
-}
 
-impl Instruction {
-    pub fn handle_aux(&mut self, aux: Option<&LuaInstruction>) -> bool {
-        if let OpCode::OpSetList(OpMode::ABC(a, b, c)) = &self.op {
-            if *c != 0 {
-                return false;
+    // what has to hold for the JMP after a 5.1 comparison or test to be taken, or with jump
+    // false for it to be skipped
+    fn describe_test(
+        &self,
+        constants: &[Constant],
+        proto: &Proto,
+        pc: isize,
+        jump: bool,
+    ) -> String {
+        match self {
+            OpCode::OpEq(OpMode::ABC(a, b, c)) => format!(
+                "{} {} {}",
+                FORMAT_CONSTANT_RK!(constants, *b, proto, pc),
+                match (*a != 0) == jump {
+                    true => "==",
+                    false => "~=",
+                },
+                FORMAT_CONSTANT_RK!(constants, *c, proto, pc)
+            ),
+            OpCode::OpLt(OpMode::ABC(a, b, c)) | OpCode::OpLe(OpMode::ABC(a, b, c)) => {
+                let compare: String = format!(
+                    "{} {} {}",
+                    FORMAT_CONSTANT_RK!(constants, *b, proto, pc),
+                    match self {
+                        OpCode::OpLt(_) => "<",
+                        _ => "<=",
+                    },
+                    FORMAT_CONSTANT_RK!(constants, *c, proto, pc)
+                );
+                match (*a != 0) == jump {
+                    true => compare,
+                    false => format!("not ({})", compare),
+                }
             }
-            self.op = OpCode::OpSetList(OpMode::ABC(
-                *a,
-                *b,
-                *aux.expect("failed to get aux for SetList with C of 0") as i32,
-            ));
-            return true;
+            // TESTSET also copies B into A when it jumps
+            OpCode::OpTest(OpMode::ABC(value, _, c))
+            | OpCode::OpTestSet(OpMode::ABC(_, value, c)) => match (*c != 0) == jump {
+                true => REG_FMT!(*value, proto, pc),
+                false => format!("not {}", REG_FMT!(*value, proto, pc)),
+            },
+            _ => String::from("[INVALID TEST]"),
         }
-        return false;
     }
 }
 
-pub fn build_instruction(
-    raw: LuaInstruction,
-    num_bits_int: LuaInt,
-    max_int: LuaInt,
-) -> Instruction {
-    let a: LuaInt = GET_ARGA!(raw);
-    let b: LuaInt = GET_ARGB!(raw);
-    let c: LuaInt = GET_ARGC!(raw);
-    let bx: LuaInt = GET_ARGBX!(raw);
-    let sbx: LuaInt = GET_ARGASBX!(raw, num_bits_int, max_int);
-    let op: OpCode = match GET_OPCODE!(raw) {
-        0 => OpCode::OpMove(OpMode::ABC(a, b, c)),
-        1 => OpCode::OpLoadK(OpMode::ABX(a, bx)),
-        2 => OpCode::OpLoadBool(OpMode::ABC(a, b, c)),
-        3 => OpCode::OpLoadNil(OpMode::ABC(a, b, c)),
-        4 => OpCode::OpGetUpval(OpMode::ABC(a, b, c)),
-
-        5 => OpCode::OpGetGlobal(OpMode::ABX(a, bx)),
-        6 => OpCode::OpGetTable(OpMode::ABC(a, b, c)),
-
-        7 => OpCode::OpSetGlobal(OpMode::ABX(a, bx)),
-        8 => OpCode::OpSetUpval(OpMode::ABC(a, b, c)),
-        9 => OpCode::OpSetTable(OpMode::ABC(a, b, c)),
-
-        10 => OpCode::OpNewTable(OpMode::ABC(a, b, c)),
-
-        11 => OpCode::OpSelf(OpMode::ABC(a, b, c)),
-
-        12 => OpCode::OpAdd(OpMode::ABC(a, b, c)),
-        13 => OpCode::OpSub(OpMode::ABC(a, b, c)),
-        14 => OpCode::OpMul(OpMode::ABC(a, b, c)),
-        15 => OpCode::OpDiv(OpMode::ABC(a, b, c)),
-        16 => OpCode::OpMod(OpMode::ABC(a, b, c)),
-        17 => OpCode::OpPow(OpMode::ABC(a, b, c)),
-        18 => OpCode::OpUnm(OpMode::ABC(a, b, c)),
-        19 => OpCode::OpNot(OpMode::ABC(a, b, c)),
-        20 => OpCode::OpLen(OpMode::ABC(a, b, c)),
-
-        21 => OpCode::OpConcat(OpMode::ABC(a, b, c)),
-
-        22 => OpCode::OpJmp(OpMode::ASBX(0, sbx)),
+// a call of the function in a with B counting its arguments plus one, as obj:name(...) when
+// a SELF set it up
+fn describe_callee(
+    constants: &[Constant],
+    proto: &Proto,
+    pc: isize,
+    a: LuaInt,
+    b: LuaInt,
+    version: LuaVersion,
+) -> String {
+    let (function, first): (String, LuaInt) = match method_call(constants, proto, pc, a) {
+        Some(method) => (method, a + 2),
+        None => (REG_FMT!(a, proto, pc), a + 1),
+    };
+    format!(
+        "{}({})",
+        function,
+        if b == 0 {
+            describe_top(constants, proto, pc, first, version)
+        } else if a + b - 1 < first {
+            String::new()
+        } else {
+            SIMPLE_REG_LIST!(first, a + b - 1, proto, pc)
+        }
+    )
+}
 
-        23 => OpCode::OpEq(OpMode::ABC(a, b, c)),
-        24 => OpCode::OpLt(OpMode::ABC(a, b, c)),
-        25 => OpCode::OpLe(OpMode::ABC(a, b, c)),
+// the values from first up to top as the instruction at pc reads them, ending in the call or
+// vararg that left them open
+fn describe_top(
+    constants: &[Constant],
+    proto: &Proto,
+    pc: isize,
+    first: LuaInt,
+    version: LuaVersion,
+) -> String {
+    let (index, producer): (usize, LuaInt) = match top_producer(proto, pc, version) {
+        Some((index, producer)) if producer >= first => (index, producer),
+        _ => return String::from("top ... ???"),
+    };
+    let value: String = match &proto.code[index].op {
+        OpCode::OpCall(OpMode::ABC(a, b, _))
+        | OpCode::OpCall(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpTailCall(OpMode::ABC(a, b, _))
+        | OpCode::OpTailCall(OpMode::ABCK(a, b, _, _)) => {
+            describe_callee(constants, proto, proto.pcs()[index], *a, *b, version)
+        }
+        _ => String::from("..."),
+    };
+    match producer > first {
+        true => format!(
+            "{}, {}",
+            SIMPLE_REG_LIST!(first, producer - 1, proto, pc),
+            value
+        ),
+        false => value,
+    }
+}
 
-        26 => OpCode::OpTest(OpMode::ABC(a, b, c)),
-        27 => OpCode::OpTestSet(OpMode::ABC(a, b, c)),
+// what the instruction at index does with top: sets it as the values from a register
+// on, reads it, or neither
+enum TopUse {
+    Produces(LuaInt),
+    Consumes,
+    None,
+}
 
-        28 => OpCode::OpCall(OpMode::ABC(a, b, c)),
-        29 => OpCode::OpTailCall(OpMode::ABC(a, b, c)),
-        30 => OpCode::OpReturn(OpMode::ABC(a, b, c)),
+fn top_use(op: &OpCode) -> TopUse {
+    match op {
+        // a multret CALL reads the top before it as well, but what it leaves is what counts;
+        // a TAILCALL of a C function runs on to the RETURN after it with its results
+        OpCode::OpCall(OpMode::ABC(a, _, 0))
+        | OpCode::OpCall(OpMode::ABCK(a, _, 0, _))
+        | OpCode::OpTailCall(OpMode::ABC(a, _, 0))
+        | OpCode::OpTailCall(OpMode::ABCK(a, _, 0, _))
+        | OpCode::OpVararg(OpMode::ABC(a, 0, _))
+        | OpCode::OpVararg(OpMode::ABCK(a, _, 0, _)) => TopUse::Produces(*a),
+        OpCode::OpCall(OpMode::ABC(_, 0, _))
+        | OpCode::OpCall(OpMode::ABCK(_, 0, _, _))
+        | OpCode::OpTailCall(OpMode::ABC(_, 0, _))
+        | OpCode::OpTailCall(OpMode::ABCK(_, 0, _, _))
+        | OpCode::OpReturn(OpMode::ABC(_, 0, _))
+        | OpCode::OpReturn(OpMode::ABCK(_, 0, _, _))
+        | OpCode::OpSetList(OpMode::ABC(_, 0, _))
+        | OpCode::OpSetList(OpMode::ABCK(_, 0, _, _)) => TopUse::Consumes,
+        _ => TopUse::None,
+    }
+}
 
-        31 => OpCode::OpForLoop(OpMode::ASBX(a, sbx)),
-        32 => OpCode::OpForPrep(OpMode::ASBX(a, sbx)),
+// the CALL, TAILCALL or VARARG that leaves top open for the instruction at pc, with the
+// register its values start at. Each is read by the next instruction that goes up to top,
+// so the search follows every path back into pc and stops at the first of either; all of
+// them have to reach the same one
+fn top_producer(proto: &Proto, pc: isize, version: LuaVersion) -> Option<(usize, LuaInt)> {
+    let cfg: Cfg = build_cfg(proto, version);
+    let mut visited: Vec<bool> = vec![false; cfg.blocks.len()];
+    let mut found: Option<(usize, LuaInt)> = None;
+    // blocks with the index to scan back from, exclusive. The one pc is in can start with
+    // it, then every path into it counts and not just the fallthrough
+    let index: usize = proto.index_at(pc).max(0) as usize;
+    let mut pending: Vec<(usize, usize)> = vec![(cfg.block_at(index)?, index)];
+    while let Some((block, end)) = pending.pop() {
+        let start: usize = cfg.blocks[block].start;
+        let mut producer: Option<(usize, LuaInt)> = None;
+        for index in (start..end).rev() {
+            match top_use(&proto.code[index].op) {
+                TopUse::Produces(a) => {
+                    producer = Some((index, a));
+                    break;
+                }
+                TopUse::Consumes => return None,
+                TopUse::None => {}
+            }
+        }
+        // a TAILCALL right before the block reaches it without being an edge
+        if let Some(index) = start.checked_sub(1).filter(|_| producer.is_none()) {
+            if let (OpCode::OpTailCall(_), TopUse::Produces(a)) =
+                (&proto.code[index].op, top_use(&proto.code[index].op))
+            {
+                producer = Some((index, a));
+            }
+        }
+        match producer {
+            Some(producer) => match found {
+                Some(found) if found != producer => return None,
+                _ => found = Some(producer),
+            },
+            None => {
+                let predecessors: &[usize] = &cfg.blocks[block].predecessors;
+                if predecessors.is_empty() {
+                    return None;
+                }
+                for predecessor in predecessors {
+                    if !visited[*predecessor] {
+                        visited[*predecessor] = true;
+                        pending.push((*predecessor, cfg.blocks[*predecessor].end));
+                    }
+                }
+            }
+        }
+    }
+    found
+}
 
-        33 => OpCode::OpTForLoop(OpMode::ABC(a, b, c)),
-        34 => OpCode::OpSetList(OpMode::ABC(a, b, c)),
+// obj:name for the SELF or Luau NAMECALL that set up the function and object of the call
+// at pc with an A of a. Each of those is used by exactly one call with the same A and the
+// arguments in between only use the registers above, so another call there ends the search
+fn method_call(constants: &[Constant], proto: &Proto, pc: isize, a: LuaInt) -> Option<String> {
+    for index in (0..proto.index_at(pc).max(0) as usize).rev() {
+        let (object, key): (LuaInt, Option<&Constant>) = match &proto.code[index].op {
+            OpCode::OpSelf(OpMode::ABC(self_a, b, c)) if *self_a == a => (
+                *b,
+                match IS_RK!(*c) {
+                    true => constants.get(INDEXK!(*c) as usize),
+                    false => None,
+                },
+            ),
+            OpCode::OpSelf(OpMode::ABCK(self_a, b, c, k)) if *self_a == a => (
+                *b,
+                match k {
+                    true => constants.get(*c as usize),
+                    false => None,
+                },
+            ),
+            OpCode::OpNamecall(OpMode::ABC(self_a, b, _)) if *self_a == a => (
+                *b,
+                match proto.code.get(index + 1).map(|inst| &inst.op) {
+                    Some(OpCode::OpAux(OpMode::AX(aux))) => constants.get(*aux as usize),
+                    _ => None,
+                },
+            ),
+            OpCode::OpCall(OpMode::ABC(call_a, _, _))
+            | OpCode::OpCall(OpMode::ABCK(call_a, _, _, _))
+            | OpCode::OpTailCall(OpMode::ABC(call_a, _, _))
+            | OpCode::OpTailCall(OpMode::ABCK(call_a, _, _, _))
+                if *call_a == a =>
+            {
+                return None
+            }
+            _ => continue,
+        };
+        return match key {
+            Some(Constant::String(name)) if is_identifier(name) => Some(format!(
+                "{}:{}",
+                REG_FMT!(object, proto, index as isize),
+                String::from_utf8_lossy(name)
+            )),
+            _ => None,
+        };
+    }
+    None
+}
 
-        35 => OpCode::OpClose(OpMode::ABX(a, bx)),
-        36 => OpCode::OpClosure(OpMode::ABX(a, bx)),
+fn describe_upvalue_captures(child: &Proto, proto: &Proto, pc: isize) -> String {
+    let upvalues: Vec<String> = child
+        .upvalues
+        .iter()
+        .enumerate()
+        .map(|(i, upvalue)| {
+            format!(
+                "{} = {}",
+                child.upvalue_name(i as LuaInt),
+                match upvalue.instack {
+                    true => REG_FMT!(upvalue.idx as LuaInt, proto, pc),
+                    false => proto.upvalue_name(upvalue.idx as LuaInt),
+                }
+            )
+        })
+        .collect();
+    match upvalues.is_empty() {
+        true => String::new(),
+        false => format!(" -- upvalues: {}", upvalues.join(", ")),
+    }
+}
 
-        37 => OpCode::OpVararg(OpMode::ABC(a, b, c)),
+// event names of MMBIN's C, in the order of 5.4's TMS enum
+const TM_NAMES_54: [&str; 25] = [
+    "__index",
+    "__newindex",
+    "__gc",
+    "__mode",
+    "__len",
+    "__eq",
+    "__add",
+    "__sub",
+    "__mul",
+    "__mod",
+    "__pow",
+    "__div",
+    "__idiv",
+    "__band",
+    "__bor",
+    "__bxor",
+    "__shl",
+    "__shr",
+    "__unm",
+    "__bnot",
+    "__lt",
+    "__le",
+    "__concat",
+    "__call",
+    "__close",
+];
 
-        op => {
-            // panic!("Failed to get opcode: {}", op);
-            OpCode::OpUnknown(op)
+macro_rules! K_FMT {
+    ( $constants:expr, $index:expr ) => {
+        format_constant(&$constants, $index as usize)
+    };
+}
+// 5.4 marks constant operands with the k bit instead of BITRK
+macro_rules! RK_FMT_54 {
+    ( $constants:expr, $value:expr, $k:expr, $proto:expr, $pc:expr ) => {
+        match $k {
+            true => K_FMT!($constants, $value),
+            false => REG_FMT!($value, $proto, $pc),
         }
     };
-    Instruction { /* raw, */ op }
+}
+macro_rules! FORMAT_BINARY_54 {
+    ( $op:expr, $a:expr, $b:expr, $c:expr, $proto:expr, $pc:expr ) => {
+        format!("{} = {} {} {}", TARGET_FMT!($a, $proto, $pc), $b, $op, $c)
+    };
+}
+macro_rules! FORMAT_CONDITION_54 {
+    ( $yes:expr, $no:expr, $lhs:expr, $rhs:expr, $k:expr, $pc:expr ) => {
+        format!(
+            "if {} {} {} then goto {}",
+            $lhs,
+            if $k { $no } else { $yes },
+            $rhs,
+            $pc + 2
+        )
+    };
 }
 
-#[derive(Debug, Clone)]
-pub struct Proto {
-    pub is_main: bool,
-    pub id: LuaInt,
+impl OpCode {
+    fn describe_54(
+        &self,
+        constants: &[Constant],
+        proto: &Proto,
+        protos: &[Proto],
+        pc: isize,
+    ) -> String {
+        // EXTRAARG following NEWTABLE, SETLIST and LOADKX
+        let extra_arg: Option<LuaInt> = match proto.code.get(pc as usize + 1).map(|inst| &inst.op) {
+            Some(OpCode::OpExtraArg(OpMode::AX(ax))) => Some(*ax),
+            _ => None,
+        };
 
-    pub source: Vec<u8>,
+        if let OpCode::OpMove(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc)
+            )
+        } else if let OpCode::OpLoadI(OpMode::ASBX(a, sbx)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), sbx)
+        } else if let OpCode::OpLoadF(OpMode::ASBX(a, sbx)) = self {
+            format!("{} = {:?}", TARGET_FMT!(*a, proto, pc), *sbx as LuaNumber)
+        } else if let OpCode::OpLoadK(OpMode::ABX(a, bx)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                K_FMT!(constants, *bx)
+            )
+        } else if let OpCode::OpLoadKx(OpMode::ABX(a, _bx)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                match extra_arg {
+                    Some(ax) => K_FMT!(constants, ax),
+                    None => String::from("[MISSING EXTRAARG]"),
+                }
+            )
+        } else if let OpCode::OpLoadFalse(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!("{} = false", TARGET_FMT!(*a, proto, pc))
+        } else if let OpCode::OpLFalseSkip(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!("{} = false; goto {}", TARGET_FMT!(*a, proto, pc), pc + 2)
+        } else if let OpCode::OpLoadTrue(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!("{} = true", TARGET_FMT!(*a, proto, pc))
+        } else if let OpCode::OpLoadNil(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!("{} = nil", TARGET_REG_LIST!(*a, *a + *b, proto, pc))
+        } else if let OpCode::OpGetUpval(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                proto.upvalue_name(*b)
+            )
+        } else if let OpCode::OpSetUpval(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!("{} = {}", proto.upvalue_name(*b), REG_FMT!(*a, proto, pc))
+        } else if let OpCode::OpGetTabUp(OpMode::ABCK(a, b, c, _k)) = self {
+            match proto.global_name(*b, *c | BITRK, constants, LuaVersion::Lua54) {
+                Some(name) => format!("{} = {}", TARGET_FMT!(*a, proto, pc), name),
+                None => format!(
+                    "{} = {}{}",
+                    TARGET_FMT!(*a, proto, pc),
+                    proto.upvalue_name(*b),
+                    format_constant_index(constants, *c as usize)
+                ),
+            }
+        } else if let OpCode::OpGetTable(OpMode::ABCK(a, b, c, _k)) = self {
+            format!(
+                "{} = {}[{}]",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc)
+            )
+        } else if let OpCode::OpGetI(OpMode::ABCK(a, b, c, _k)) = self {
+            format!(
+                "{} = {}[{}]",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                c
+            )
+        } else if let OpCode::OpGetField(OpMode::ABCK(a, b, c, _k)) = self {
+            format!(
+                "{} = {}{}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                format_constant_index(constants, *c as usize)
+            )
+        } else if let OpCode::OpSetTabUp(OpMode::ABCK(a, b, c, k)) = self {
+            match proto.global_name(*a, *b | BITRK, constants, LuaVersion::Lua54) {
+                Some(name) => format!("{} = {}", name, RK_FMT_54!(constants, *c, *k, proto, pc)),
+                None => format!(
+                    "{}{} = {}",
+                    proto.upvalue_name(*a),
+                    format_constant_index(constants, *b as usize),
+                    RK_FMT_54!(constants, *c, *k, proto, pc)
+                ),
+            }
+        } else if let OpCode::OpSetTable(OpMode::ABCK(a, b, c, k)) = self {
+            format!(
+                "{}[{}] = {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                RK_FMT_54!(constants, *c, *k, proto, pc)
+            )
+        } else if let OpCode::OpSetI(OpMode::ABCK(a, b, c, k)) = self {
+            format!(
+                "{}[{}] = {}",
+                REG_FMT!(*a, proto, pc),
+                b,
+                RK_FMT_54!(constants, *c, *k, proto, pc)
+            )
+        } else if let OpCode::OpSetField(OpMode::ABCK(a, b, c, k)) = self {
+            format!(
+                "{}{} = {}",
+                REG_FMT!(*a, proto, pc),
+                format_constant_index(constants, *b as usize),
+                RK_FMT_54!(constants, *c, *k, proto, pc)
+            )
+        } else if let OpCode::OpNewTable(OpMode::ABCK(a, b, c, k)) = self {
+            // B is log2 of the record size plus one, C spills into EXTRAARG when k is set
+            let list: LuaInt = match (*k, extra_arg) {
+                (true, Some(ax)) => *c + ax * (1 << SIZE_C_54),
+                _ => *c,
+            };
+            let record: LuaInt = if *b > 0 { 1 << (*b - 1) } else { 0 };
+            format!(
+                "{} = {{}} -- {} list, {} record",
+                TARGET_FMT!(*a, proto, pc),
+                list,
+                record
+            )
+        } else if let OpCode::OpSelf(OpMode::ABCK(a, b, c, k)) = self {
+            format!(
+                "{} = {}; {} = {}{}",
+                TARGET_FMT!(a + 1, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                match k {
+                    true => format_constant_index(constants, *c as usize),
+                    false => format!("[{}]", REG_FMT!(*c, proto, pc)),
+                }
+            )
+        } else if let OpCode::OpAddI(OpMode::ABCK(a, b, sc, _k)) = self {
+            FORMAT_BINARY_54!('+', *a, REG_FMT!(*b, proto, pc), sc, proto, pc)
+        } else if let OpCode::OpAddK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '+',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpSubK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '-',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpMulK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '*',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpModK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '%',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpPowK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '^',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpDivK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '/',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpIdivK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                "//",
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpBandK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '&',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpBorK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '|',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpBxorK(OpMode::ABCK(a, b, c, _k)) = self {
+            FORMAT_BINARY_54!(
+                '~',
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpShrI(OpMode::ABCK(a, b, sc, _k)) = self {
+            FORMAT_BINARY_54!(">>", *a, REG_FMT!(*b, proto, pc), sc, proto, pc)
+        } else if let OpCode::OpShlI(OpMode::ABCK(a, b, sc, _k)) = self {
+            // the immediate is the left operand
+            FORMAT_BINARY_54!("<<", *a, sc, REG_FMT!(*b, proto, pc), proto, pc)
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpAdd(OpMode::ABCK(a, b, c, _k)) => Some(("+", a, b, c)),
+            OpCode::OpSub(OpMode::ABCK(a, b, c, _k)) => Some(("-", a, b, c)),
+            OpCode::OpMul(OpMode::ABCK(a, b, c, _k)) => Some(("*", a, b, c)),
+            OpCode::OpMod(OpMode::ABCK(a, b, c, _k)) => Some(("%", a, b, c)),
+            OpCode::OpPow(OpMode::ABCK(a, b, c, _k)) => Some(("^", a, b, c)),
+            OpCode::OpDiv(OpMode::ABCK(a, b, c, _k)) => Some(("/", a, b, c)),
+            OpCode::OpIdiv(OpMode::ABCK(a, b, c, _k)) => Some(("//", a, b, c)),
+            OpCode::OpBand(OpMode::ABCK(a, b, c, _k)) => Some(("&", a, b, c)),
+            OpCode::OpBor(OpMode::ABCK(a, b, c, _k)) => Some(("|", a, b, c)),
+            OpCode::OpBxor(OpMode::ABCK(a, b, c, _k)) => Some(("~", a, b, c)),
+            OpCode::OpShl(OpMode::ABCK(a, b, c, _k)) => Some(("<<", a, b, c)),
+            OpCode::OpShr(OpMode::ABCK(a, b, c, _k)) => Some((">>", a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpMmBin(OpMode::ABCK(a, b, c, _k)) = self {
+            format!(
+                "metamethod {} fallback over {} and {}",
+                TM_NAMES_54.get(*c as usize).unwrap_or(&"[INVALID EVENT]"),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc)
+            )
+        } else if let OpCode::OpMmBinI(OpMode::ABCK(a, sb, c, k)) = self {
+            let (lhs, rhs) = (REG_FMT!(*a, proto, pc), sb.to_string());
+            // k means the operands were flipped, as in 1 - x
+            let (lhs, rhs) = if *k { (rhs, lhs) } else { (lhs, rhs) };
+            format!(
+                "metamethod {} fallback over {} and {}",
+                TM_NAMES_54.get(*c as usize).unwrap_or(&"[INVALID EVENT]"),
+                lhs,
+                rhs
+            )
+        } else if let OpCode::OpMmBinK(OpMode::ABCK(a, b, c, k)) = self {
+            let (lhs, rhs) = (REG_FMT!(*a, proto, pc), K_FMT!(constants, *b));
+            let (lhs, rhs) = if *k { (rhs, lhs) } else { (lhs, rhs) };
+            format!(
+                "metamethod {} fallback over {} and {}",
+                TM_NAMES_54.get(*c as usize).unwrap_or(&"[INVALID EVENT]"),
+                lhs,
+                rhs
+            )
+        } else if let OpCode::OpUnm(OpMode::ABCK(a, b, _c, _k)) = self {
+            FORMAT_UNARY!('-', proto, *a, *b, pc)
+        } else if let OpCode::OpBnot(OpMode::ABCK(a, b, _c, _k)) = self {
+            FORMAT_UNARY!('~', proto, *a, *b, pc)
+        } else if let OpCode::OpNot(OpMode::ABCK(a, b, _c, _k)) = self {
+            FORMAT_UNARY!("not ", proto, *a, *b, pc)
+        } else if let OpCode::OpLen(OpMode::ABCK(a, b, _c, _k)) = self {
+            FORMAT_UNARY!('#', proto, *a, *b, pc)
+        } else if let OpCode::OpConcat(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!(
+                "{} = {} .. ... .. {}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + *b - 1, proto, pc)
+            )
+        } else if let OpCode::OpClose(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!(
+                "close all variables in the stack up to {}",
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpTbc(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!("mark {} to be closed", REG_FMT!(*a, proto, pc))
+        } else if let OpCode::OpJmp(OpMode::SJ(sj)) = self {
+            format!("goto {}", pc + *sj as isize + 1)
+        } else if let OpCode::OpEq(OpMode::ABCK(a, b, _c, k)) = self {
+            FORMAT_CONDITION_54!(
+                "==",
+                "~=",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                *k,
+                pc
+            )
+        } else if let OpCode::OpLt(OpMode::ABCK(a, b, _c, k)) = self {
+            FORMAT_CONDITION_54!(
+                "<",
+                ">=",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                *k,
+                pc
+            )
+        } else if let OpCode::OpLe(OpMode::ABCK(a, b, _c, k)) = self {
+            FORMAT_CONDITION_54!(
+                "<=",
+                ">",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                *k,
+                pc
+            )
+        } else if let OpCode::OpEqK(OpMode::ABCK(a, b, _c, k)) = self {
+            FORMAT_CONDITION_54!(
+                "==",
+                "~=",
+                REG_FMT!(*a, proto, pc),
+                K_FMT!(constants, *b),
+                *k,
+                pc
+            )
+        } else if let OpCode::OpEqI(OpMode::ABCK(a, sb, _c, k)) = self {
+            FORMAT_CONDITION_54!("==", "~=", REG_FMT!(*a, proto, pc), sb, *k, pc)
+        } else if let OpCode::OpLtI(OpMode::ABCK(a, sb, _c, k)) = self {
+            FORMAT_CONDITION_54!("<", ">=", REG_FMT!(*a, proto, pc), sb, *k, pc)
+        } else if let OpCode::OpLeI(OpMode::ABCK(a, sb, _c, k)) = self {
+            FORMAT_CONDITION_54!("<=", ">", REG_FMT!(*a, proto, pc), sb, *k, pc)
+        } else if let OpCode::OpGtI(OpMode::ABCK(a, sb, _c, k)) = self {
+            FORMAT_CONDITION_54!(">", "<=", REG_FMT!(*a, proto, pc), sb, *k, pc)
+        } else if let OpCode::OpGeI(OpMode::ABCK(a, sb, _c, k)) = self {
+            FORMAT_CONDITION_54!(">=", "<", REG_FMT!(*a, proto, pc), sb, *k, pc)
+        } else if let OpCode::OpTest(OpMode::ABCK(a, _b, _c, k)) = self {
+            format!(
+                "if {}{} then goto {}",
+                if *k { "not " } else { "" },
+                REG_FMT!(*a, proto, pc),
+                pc + 2
+            )
+        } else if let OpCode::OpTestSet(OpMode::ABCK(a, b, _c, k)) = self {
+            format!(
+                "if {}{} then goto {} else {} = {}",
+                if *k { "not " } else { "" },
+                REG_FMT!(*b, proto, pc),
+                pc + 2,
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc)
+            )
+        } else if let OpCode::OpCall(OpMode::ABCK(a, b, c, _k)) = self {
+            format!(
+                "{}{}",
+                if *c == 0 {
+                    format!("{} ... = ", TARGET_FMT!(*a, proto, pc))
+                } else if *c == 1 {
+                    String::new()
+                } else {
+                    format!("{} = ", TARGET_REG_LIST!(*a, *a + *c - 2, proto, pc))
+                },
+                describe_callee(constants, proto, pc, *a, *b, LuaVersion::Lua54)
+            )
+        } else if let OpCode::OpTailCall(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!(
+                "return {}",
+                describe_callee(constants, proto, pc, *a, *b, LuaVersion::Lua54)
+            )
+        } else if let OpCode::OpReturn(OpMode::ABCK(a, b, _c, _k)) = self {
+            format!(
+                "return{}",
+                if *b == 0 {
+                    format!(
+                        " {}",
+                        describe_top(constants, proto, pc, *a, LuaVersion::Lua54)
+                    )
+                } else if *b == 1 {
+                    String::new()
+                } else {
+                    format!(" {}", SIMPLE_REG_LIST!(*a, *a + *b - 2, proto, pc))
+                }
+            )
+        } else if let OpCode::OpReturn0(OpMode::ABCK(_a, _b, _c, _k)) = self {
+            String::from("return")
+        } else if let OpCode::OpReturn1(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!("return {}", REG_FMT!(*a, proto, pc))
+        } else if let OpCode::OpForLoop(OpMode::ABX(a, bx)) = self {
+            format!(
+                "{} += {}; if {} <?= {} then {{ {} = {}; goto {} }}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                TARGET_FMT!(*a + 3, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                pc + 1 - *bx as isize
+            )
+        } else if let OpCode::OpForPrep(OpMode::ABX(a, bx)) = self {
+            format!(
+                "prepare {} ... {}; if the loop doesn't run then goto {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                pc + *bx as isize + 2
+            )
+        } else if let OpCode::OpTForPrep(OpMode::ABX(a, bx)) = self {
+            format!(
+                "mark {} to be closed; goto {}",
+                REG_FMT!(*a + 3, proto, pc),
+                pc + *bx as isize + 1
+            )
+        } else if let OpCode::OpTForCall(OpMode::ABCK(a, _b, c, _k)) = self {
+            format!(
+                "{} = {}({}, {})",
+                TARGET_REG_LIST!(*a + 4, *a + 3 + *c, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                REG_FMT!(*a + 2, proto, pc)
+            )
+        } else if let OpCode::OpTForLoop(OpMode::ABX(a, bx)) = self {
+            format!(
+                "if {} ~= nil then {{ {} = {}; goto {} }}",
+                REG_FMT!(*a + 4, proto, pc),
+                TARGET_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a + 4, proto, pc),
+                pc + 1 - *bx as isize
+            )
+        } else if let OpCode::OpSetList(OpMode::ABCK(a, b, c, k)) = self {
+            // C is the last index already stored, not a batch number
+            let offset: LuaInt = match (*k, extra_arg) {
+                (true, Some(ax)) => *c + ax * (1 << SIZE_C_54),
+                _ => *c,
+            };
+            if *b == 0 {
+                format!(
+                    "{}[{} ...] = {}",
+                    REG_FMT!(*a, proto, pc),
+                    offset + 1,
+                    describe_top(constants, proto, pc, *a + 1, LuaVersion::Lua54)
+                )
+            } else {
+                format!(
+                    "{}[{} ... {}] = {} ... {}",
+                    REG_FMT!(*a, proto, pc),
+                    offset + 1,
+                    offset + *b,
+                    REG_FMT!(*a + 1, proto, pc),
+                    REG_FMT!(*a + *b, proto, pc)
+                )
+            }
+        } else if let OpCode::OpClosure(OpMode::ABX(a, bx)) = self {
+            match protos.get(*bx as usize) {
+                Some(child) => format!(
+                    "{} = proto_{}{}",
+                    TARGET_FMT!(*a, proto, pc),
+                    bx,
+                    describe_upvalue_captures(child, proto, pc)
+                ),
+                None => format!("{} = [INVALID CLOSURE]", TARGET_FMT!(*a, proto, pc)),
+            }
+        } else if let OpCode::OpVararg(OpMode::ABCK(a, _b, c, _k)) = self {
+            format!(
+                "{} = vararg",
+                if *c == 0 {
+                    format!("{} ...", TARGET_FMT!(*a, proto, pc))
+                } else if *c == 1 {
+                    TARGET_FMT!(*a, proto, pc)
+                } else {
+                    TARGET_REG_LIST!(*a, *a + *c - 2, proto, pc)
+                }
+            )
+        } else if let OpCode::OpVarargPrep(OpMode::ABCK(a, _b, _c, _k)) = self {
+            format!("adjust vararg parameters after the first {}", a)
+        } else if let OpCode::OpExtraArg(OpMode::AX(ax)) = self {
+            format!("extra argument {}", ax)
+        } else {
+            format!("TODO: DESCRIBE {:?}", self)
+        }
+    }
+}
+
+// functions FASTCALL can run inline, in the order of LuauBuiltinFunction
+const BUILTIN_NAMES_LUAU: [&str; 78] = [
+    "none",
+    "assert",
+    "math.abs",
+    "math.acos",
+    "math.asin",
+    "math.atan2",
+    "math.atan",
+    "math.ceil",
+    "math.cosh",
+    "math.cos",
+    "math.deg",
+    "math.exp",
+    "math.floor",
+    "math.fmod",
+    "math.frexp",
+    "math.ldexp",
+    "math.log10",
+    "math.log",
+    "math.max",
+    "math.min",
+    "math.modf",
+    "math.pow",
+    "math.rad",
+    "math.sinh",
+    "math.sin",
+    "math.sqrt",
+    "math.tanh",
+    "math.tan",
+    "bit32.arshift",
+    "bit32.band",
+    "bit32.bnot",
+    "bit32.bor",
+    "bit32.bxor",
+    "bit32.btest",
+    "bit32.extract",
+    "bit32.lrotate",
+    "bit32.lshift",
+    "bit32.replace",
+    "bit32.rrotate",
+    "bit32.rshift",
+    "type",
+    "string.byte",
+    "string.char",
+    "string.len",
+    "typeof",
+    "string.sub",
+    "math.clamp",
+    "math.sign",
+    "math.round",
+    "rawset",
+    "rawget",
+    "rawequal",
+    "table.insert",
+    "table.unpack",
+    "vector",
+    "bit32.countlz",
+    "bit32.countrz",
+    "select",
+    "rawlen",
+    "bit32.extract",
+    "getmetatable",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "bit32.byteswap",
+    "buffer.readi8",
+    "buffer.readu8",
+    "buffer.writeu8",
+    "buffer.readi16",
+    "buffer.readu16",
+    "buffer.writeu16",
+    "buffer.readi32",
+    "buffer.readu32",
+    "buffer.writeu32",
+    "buffer.readf32",
+    "buffer.writef32",
+    "buffer.readf64",
+    "buffer.writef64",
+];
+
+// Luau jumps are relative to the next word, AUX words included
+macro_rules! JUMP_TARGET_LUAU {
+    ( $pc:expr, $offset:expr ) => {
+        $pc + 1 + $offset as isize
+    };
+}
+macro_rules! FORMAT_JUMP_IF_LUAU {
+    ( $condition:expr, $lhs:expr, $rhs:expr, $pc:expr, $offset:expr ) => {
+        format!(
+            "if {} then goto {}",
+            format!($condition, $lhs, $rhs),
+            JUMP_TARGET_LUAU!($pc, $offset)
+        )
+    };
+}
+
+impl OpCode {
+    fn describe_luau(
+        &self,
+        constants: &[Constant],
+        proto: &Proto,
+        protos: &[Proto],
+        pc: isize,
+    ) -> String {
+        // the reader always places the AUX word right after the instruction using it
+        let aux: LuaInt = match proto.code.get(pc as usize + 1).map(|inst| &inst.op) {
+            Some(OpCode::OpAux(OpMode::AX(aux))) => *aux,
+            _ => 0,
+        };
+        // comparisons against constants keep a NOT flag in the high bit of AUX
+        let aux_not: bool = (aux as u32) >> 31 != 0;
+
+        if let OpCode::OpNop(_) = self {
+            String::from("no operation")
+        } else if let OpCode::OpBreak(_) = self {
+            String::from("debugger break")
+        } else if let OpCode::OpLoadNil(OpMode::ABC(a, _b, _c)) = self {
+            format!("{} = nil", TARGET_FMT!(*a, proto, pc))
+        } else if let OpCode::OpLoadB(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{} = {}{}",
+                TARGET_FMT!(*a, proto, pc),
+                *b != 0,
+                if *c != 0 {
+                    format!("; goto {}", JUMP_TARGET_LUAU!(pc, *c))
+                } else {
+                    String::new()
+                }
+            )
+        } else if let OpCode::OpLoadN(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), d)
+        } else if let OpCode::OpLoadK(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), K_FMT!(constants, *d))
+        } else if let OpCode::OpLoadKx(OpMode::AD(a, _d)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                K_FMT!(constants, aux)
+            )
+        } else if let OpCode::OpGetGlobal(OpMode::ABC(a, _b, _c)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                global_constant(constants, aux as usize)
+            )
+        } else if let OpCode::OpSetGlobal(OpMode::ABC(a, _b, _c)) = self {
+            format!(
+                "{} = {}",
+                global_constant(constants, aux as usize),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpCloseUpvals(OpMode::ABC(a, _b, _c)) = self {
+            format!(
+                "close all upvalues in the stack up to {}",
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpGetImport(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), K_FMT!(constants, *d))
+        } else if let OpCode::OpSetTable(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{}[{}] = {}",
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpGetTableKs(OpMode::ABC(a, b, _c)) = self {
+            format!(
+                "{} = {}{}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                format_constant_index(constants, aux as usize)
+            )
+        } else if let OpCode::OpSetTableKs(OpMode::ABC(a, b, _c)) = self {
+            format!(
+                "{}{} = {}",
+                REG_FMT!(*b, proto, pc),
+                format_constant_index(constants, aux as usize),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpGetTableN(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{} = {}[{}]",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                c + 1
+            )
+        } else if let OpCode::OpSetTableN(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{}[{}] = {}",
+                REG_FMT!(*b, proto, pc),
+                c + 1,
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpClosure(OpMode::AD(a, d)) = self {
+            format!("{} = proto_{}", TARGET_FMT!(*a, proto, pc), d)
+        } else if let OpCode::OpDupClosure(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), K_FMT!(constants, *d))
+        } else if let OpCode::OpCapture(OpMode::ABC(a, b, _c)) = self {
+            // CAPTUREs directly follow their closure, one per upvalue in order
+            let mut closure_pc: isize = pc - 1;
+            while let Some(OpCode::OpCapture(_)) =
+                proto.code.get(closure_pc as usize).map(|inst| &inst.op)
+            {
+                closure_pc -= 1;
+            }
+            let child: Option<&Proto> =
+                match proto.code.get(closure_pc as usize).map(|inst| &inst.op) {
+                    Some(OpCode::OpClosure(OpMode::AD(_, d))) => protos.get(*d as usize),
+                    Some(OpCode::OpDupClosure(OpMode::AD(_, d))) => {
+                        match constants.get(*d as usize) {
+                            Some(Constant::Closure(index)) => protos.get(*index as usize),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+            let index: LuaInt = (pc - closure_pc - 1) as LuaInt;
+            let name: String = match child {
+                Some(child) => child.upvalue_name(index),
+                None => format!("upvalue_{}", index),
+            };
+            match a {
+                0 => format!("capture {} = {} by value", name, REG_FMT!(*b, proto, pc)),
+                1 => format!(
+                    "capture {} = {} by reference",
+                    name,
+                    REG_FMT!(*b, proto, pc)
+                ),
+                _ => format!("capture {} = {}", name, proto.upvalue_name(*b)),
+            }
+        } else if let OpCode::OpNamecall(OpMode::ABC(a, b, _c)) = self {
+            format!(
+                "{} = {}; {} = {}{}",
+                TARGET_FMT!(a + 1, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                format_constant_index(constants, aux as usize)
+            )
+        } else if let OpCode::OpJump(OpMode::AD(_a, d)) | OpCode::OpJumpBack(OpMode::AD(_a, d)) =
+            self
+        {
+            format!("goto {}", JUMP_TARGET_LUAU!(pc, *d))
+        } else if let OpCode::OpJumpX(OpMode::E(e)) = self {
+            format!("goto {}", JUMP_TARGET_LUAU!(pc, *e))
+        } else if let OpCode::OpJumpIf(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!("{}{}", REG_FMT!(*a, proto, pc), "", pc, *d)
+        } else if let OpCode::OpJumpIfNot(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!("{}{}", "not ", REG_FMT!(*a, proto, pc), pc, *d)
+        } else if let OpCode::OpJumpIfEq(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} == {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(aux, proto, pc),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpIfLe(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} <= {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(aux, proto, pc),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpIfLt(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} < {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(aux, proto, pc),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpIfNotEq(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} ~= {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(aux, proto, pc),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpIfNotLe(OpMode::AD(a, d)) = self {
+            // not the same as > once NaN is involved
+            FORMAT_JUMP_IF_LUAU!(
+                "not ({} <= {})",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(aux, proto, pc),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpIfNotLt(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "not ({} < {})",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(aux, proto, pc),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpXEqKNil(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} {} nil",
+                REG_FMT!(*a, proto, pc),
+                if aux_not { "~=" } else { "==" },
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpXEqKB(OpMode::AD(a, d)) = self {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} {}",
+                REG_FMT!(*a, proto, pc),
+                format!("{} {}", if aux_not { "~=" } else { "==" }, aux & 1 != 0),
+                pc,
+                *d
+            )
+        } else if let OpCode::OpJumpXEqKN(OpMode::AD(a, d))
+        | OpCode::OpJumpXEqKS(OpMode::AD(a, d)) = self
+        {
+            FORMAT_JUMP_IF_LUAU!(
+                "{} {}",
+                REG_FMT!(*a, proto, pc),
+                format!(
+                    "{} {}",
+                    if aux_not { "~=" } else { "==" },
+                    K_FMT!(constants, aux & 0xFFFFFF)
+                ),
+                pc,
+                *d
+            )
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpAddK(OpMode::ABC(a, b, c)) => Some(("+", a, b, c)),
+            OpCode::OpSubK(OpMode::ABC(a, b, c)) => Some(("-", a, b, c)),
+            OpCode::OpMulK(OpMode::ABC(a, b, c)) => Some(("*", a, b, c)),
+            OpCode::OpDivK(OpMode::ABC(a, b, c)) => Some(("/", a, b, c)),
+            OpCode::OpModK(OpMode::ABC(a, b, c)) => Some(("%", a, b, c)),
+            OpCode::OpPowK(OpMode::ABC(a, b, c)) => Some(("^", a, b, c)),
+            OpCode::OpIdivK(OpMode::ABC(a, b, c)) => Some(("//", a, b, c)),
+            OpCode::OpAndK(OpMode::ABC(a, b, c)) => Some(("and", a, b, c)),
+            OpCode::OpOrK(OpMode::ABC(a, b, c)) => Some(("or", a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(constants, *c),
+                proto,
+                pc
+            )
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpSubRk(OpMode::ABC(a, b, c)) => Some(("-", a, b, c)),
+            OpCode::OpDivRk(OpMode::ABC(a, b, c)) => Some(("/", a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                K_FMT!(constants, *b),
+                REG_FMT!(*c, proto, pc),
+                proto,
+                pc
+            )
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpAnd(OpMode::ABC(a, b, c)) => Some(("and", a, b, c)),
+            OpCode::OpOr(OpMode::ABC(a, b, c)) => Some(("or", a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpConcat(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                (*b..=*c)
+                    .map(|reg| REG_FMT!(reg, proto, pc))
+                    .collect::<Vec<String>>()
+                    .join(" .. ")
+            )
+        } else if let OpCode::OpNewTable(OpMode::ABC(a, b, _c)) = self {
+            let record: LuaInt = if *b > 0 { 1 << (*b - 1) } else { 0 };
+            format!(
+                "{} = {{}} -- {} list, {} record",
+                TARGET_FMT!(*a, proto, pc),
+                aux,
+                record
+            )
+        } else if let OpCode::OpDupTable(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), K_FMT!(constants, *d))
+        } else if let OpCode::OpSetList(OpMode::ABC(a, b, c)) = self {
+            // AUX is the first index to store at
+            if *c == 0 {
+                format!(
+                    "{}[{} ...] = {}",
+                    REG_FMT!(*a, proto, pc),
+                    aux,
+                    describe_top(constants, proto, pc, *b, LuaVersion::Luau)
+                )
+            } else {
+                format!(
+                    "{}[{} ... {}] = {}",
+                    REG_FMT!(*a, proto, pc),
+                    aux,
+                    aux + *c - 2,
+                    SIMPLE_REG_LIST!(*b, *b + *c - 2, proto, pc)
+                )
+            }
+        } else if let OpCode::OpForNPrep(OpMode::AD(a, d)) = self {
+            format!(
+                "prepare {} ... {}; if the loop doesn't run then goto {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                JUMP_TARGET_LUAU!(pc, *d)
+            )
+        } else if let OpCode::OpForNLoop(OpMode::AD(a, d)) = self {
+            format!(
+                "{} += {}; if {} <?= {} then goto {}",
+                TARGET_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                JUMP_TARGET_LUAU!(pc, *d)
+            )
+        } else if let OpCode::OpForGPrep(OpMode::AD(a, d)) = self {
+            format!(
+                "prepare {} ... {}; goto {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                JUMP_TARGET_LUAU!(pc, *d)
+            )
+        } else if let OpCode::OpForGPrepINext(OpMode::AD(a, d)) = self {
+            format!(
+                "prepare ipairs iteration over {}; goto {}",
+                REG_FMT!(*a + 1, proto, pc),
+                JUMP_TARGET_LUAU!(pc, *d)
+            )
+        } else if let OpCode::OpForGPrepNext(OpMode::AD(a, d)) = self {
+            format!(
+                "prepare pairs iteration over {}; goto {}",
+                REG_FMT!(*a + 1, proto, pc),
+                JUMP_TARGET_LUAU!(pc, *d)
+            )
+        } else if let OpCode::OpForGLoop(OpMode::AD(a, d)) = self {
+            // the low byte of AUX is the variable count
+            let count: LuaInt = aux & 0xFF;
+            format!(
+                "{} = {}({}, {}); if {} ~= nil then {{ {} = {}; goto {} }}",
+                TARGET_REG_LIST!(*a + 3, *a + 2 + count.max(1), proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a + 3, proto, pc),
+                TARGET_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a + 3, proto, pc),
+                JUMP_TARGET_LUAU!(pc, *d)
+            )
+        } else if let Some((a, c, arguments)) = match self {
+            OpCode::OpFastCall(OpMode::ABC(a, _b, c)) => Some((a, c, None)),
+            OpCode::OpFastCall1(OpMode::ABC(a, b, c)) => {
+                Some((a, c, Some(REG_FMT!(*b, proto, pc))))
+            }
+            OpCode::OpFastCall2(OpMode::ABC(a, b, c)) => Some((
+                a,
+                c,
+                Some(format!(
+                    "{}, {}",
+                    REG_FMT!(*b, proto, pc),
+                    REG_FMT!(aux & 0xFF, proto, pc)
+                )),
+            )),
+            OpCode::OpFastCall2K(OpMode::ABC(a, b, c)) => Some((
+                a,
+                c,
+                Some(format!(
+                    "{}, {}",
+                    REG_FMT!(*b, proto, pc),
+                    K_FMT!(constants, aux)
+                )),
+            )),
+            OpCode::OpFastCall3(OpMode::ABC(a, b, c)) => Some((
+                a,
+                c,
+                Some(format!(
+                    "{}, {}, {}",
+                    REG_FMT!(*b, proto, pc),
+                    REG_FMT!(aux & 0xFF, proto, pc),
+                    REG_FMT!((aux >> 8) & 0xFF, proto, pc)
+                )),
+            )),
+            _ => None,
+        } {
+            // C counts from the next word to the CALL, which is skipped as well on success
+            format!(
+                "try {}({}) inline; goto {} if it succeeds",
+                BUILTIN_NAMES_LUAU
+                    .get(*a as usize)
+                    .unwrap_or(&"[INVALID BUILTIN]"),
+                arguments.unwrap_or(String::from("arguments of the next CALL")),
+                JUMP_TARGET_LUAU!(pc, *c + 1)
+            )
+        } else if let OpCode::OpVarargPrep(OpMode::ABC(a, _b, _c)) = self {
+            format!("adjust vararg parameters after the first {}", a)
+        } else if let OpCode::OpNativeCall(_) = self {
+            String::from("enter native code")
+        } else if let OpCode::OpCoverage(OpMode::E(e)) = self {
+            format!("coverage hit count {}", e)
+        } else if let OpCode::OpAux(OpMode::AX(ax)) = self {
+            format!("aux word 0x{:08x}", ax)
+        } else if matches!(
+            self,
+            OpCode::OpMove(_)
+                | OpCode::OpGetUpval(_)
+                | OpCode::OpSetUpval(_)
+                | OpCode::OpGetTable(_)
+                | OpCode::OpAdd(_)
+                | OpCode::OpSub(_)
+                | OpCode::OpMul(_)
+                | OpCode::OpDiv(_)
+                | OpCode::OpMod(_)
+                | OpCode::OpPow(_)
+                | OpCode::OpIdiv(_)
+                | OpCode::OpNot(_)
+                | OpCode::OpUnm(_)
+                | OpCode::OpLen(_)
+                | OpCode::OpCall(_)
+                | OpCode::OpReturn(_)
+                | OpCode::OpVararg(_)
+        ) {
+            // same operands and meaning as in 5.3, and registers never reach BITRK
+            self.describe(constants, proto, protos, pc, LuaVersion::Lua53)
+        } else {
+            format!("TODO: DESCRIBE {:?}", self)
+        }
+    }
+}
+
+// LuaJIT stores jump offsets in D with this bias added
+const BCBIAS_J_LUAJIT: LuaInt = 0x8000;
+
+// LuaJIT jumps are relative to the next instruction
+macro_rules! JUMP_TARGET_LUAJIT {
+    ( $pc:expr, $offset:expr ) => {
+        $pc + 1 + $offset as isize
+    };
+}
+// comparisons run the JMP after them when they hold and skip it otherwise
+macro_rules! FORMAT_CONDITION_LUAJIT {
+    ( $skip:expr, $lhs:expr, $rhs:expr, $pc:expr ) => {
+        format!("if {} {} {} then goto {}", $lhs, $skip, $rhs, $pc + 2)
+    };
+}
+macro_rules! PRI_FMT_LUAJIT {
+    ( $d:expr ) => {
+        match $d {
+            0 => "nil",
+            1 => "false",
+            _ => "true",
+        }
+    };
+}
+// count registers from the first one, with an open end when the rest is only known at runtime
+macro_rules! MULTRES_REG_LIST_LUAJIT {
+    ( $from:expr, $count:expr, $multres:expr, $proto:expr, $pc:expr ) => {
+        match ($count > 0, $multres) {
+            (true, true) => format!(
+                "{}, top ... ???",
+                SIMPLE_REG_LIST!($from, $from + $count - 1, $proto, $pc)
+            ),
+            (true, false) => SIMPLE_REG_LIST!($from, $from + $count - 1, $proto, $pc),
+            (false, true) => String::from("top ... ???"),
+            (false, false) => String::new(),
+        }
+    };
+}
+
+impl OpCode {
+    fn describe_luajit(
+        &self,
+        constants: &[Constant],
+        proto: &Proto,
+        protos: &[Proto],
+        pc: isize,
+    ) -> String {
+        // with FR2 the arguments of a call start one slot further
+        let frame: LuaInt = proto.two_slot_frame as LuaInt;
+
+        if let Some((skip, a, d)) = match self {
+            OpCode::OpIsLt(OpMode::AD(a, d)) => Some((">=", a, REG_FMT!(*d, proto, pc))),
+            OpCode::OpIsGe(OpMode::AD(a, d)) => Some(("<", a, REG_FMT!(*d, proto, pc))),
+            OpCode::OpIsLe(OpMode::AD(a, d)) => Some((">", a, REG_FMT!(*d, proto, pc))),
+            OpCode::OpIsGt(OpMode::AD(a, d)) => Some(("<=", a, REG_FMT!(*d, proto, pc))),
+            OpCode::OpIsEqV(OpMode::AD(a, d)) => Some(("~=", a, REG_FMT!(*d, proto, pc))),
+            OpCode::OpIsNeV(OpMode::AD(a, d)) => Some(("==", a, REG_FMT!(*d, proto, pc))),
+            OpCode::OpIsEqS(OpMode::AD(a, d)) => Some(("~=", a, K_FMT!(constants, *d))),
+            OpCode::OpIsNeS(OpMode::AD(a, d)) => Some(("==", a, K_FMT!(constants, *d))),
+            OpCode::OpIsEqN(OpMode::AD(a, d)) => {
+                Some(("~=", a, K_FMT!(proto.number_constants, *d)))
+            }
+            OpCode::OpIsNeN(OpMode::AD(a, d)) => {
+                Some(("==", a, K_FMT!(proto.number_constants, *d)))
+            }
+            OpCode::OpIsEqP(OpMode::AD(a, d)) => Some(("~=", a, PRI_FMT_LUAJIT!(*d).to_string())),
+            OpCode::OpIsNeP(OpMode::AD(a, d)) => Some(("==", a, PRI_FMT_LUAJIT!(*d).to_string())),
+            _ => None,
+        } {
+            FORMAT_CONDITION_LUAJIT!(skip, REG_FMT!(*a, proto, pc), d, pc)
+        } else if let OpCode::OpIsTC(OpMode::AD(a, d)) = self {
+            format!(
+                "if not {} then goto {} else {} = {}",
+                REG_FMT!(*d, proto, pc),
+                pc + 2,
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*d, proto, pc)
+            )
+        } else if let OpCode::OpIsFC(OpMode::AD(a, d)) = self {
+            format!(
+                "if {} then goto {} else {} = {}",
+                REG_FMT!(*d, proto, pc),
+                pc + 2,
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*d, proto, pc)
+            )
+        } else if let OpCode::OpIsT(OpMode::AD(_a, d)) = self {
+            format!("if not {} then goto {}", REG_FMT!(*d, proto, pc), pc + 2)
+        } else if let OpCode::OpIsF(OpMode::AD(_a, d)) = self {
+            format!("if {} then goto {}", REG_FMT!(*d, proto, pc), pc + 2)
+        } else if let OpCode::OpIsType(OpMode::AD(a, d)) = self {
+            format!("assert {} has type tag {}", REG_FMT!(*a, proto, pc), d)
+        } else if let OpCode::OpIsNum(OpMode::AD(a, _d)) = self {
+            format!("assert {} is a number", REG_FMT!(*a, proto, pc))
+        } else if let OpCode::OpMove(OpMode::AD(a, d)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*d, proto, pc)
+            )
+        } else if let OpCode::OpNot(OpMode::AD(a, d)) = self {
+            FORMAT_UNARY!("not ", proto, *a, *d, pc)
+        } else if let OpCode::OpUnm(OpMode::AD(a, d)) = self {
+            FORMAT_UNARY!('-', proto, *a, *d, pc)
+        } else if let OpCode::OpLen(OpMode::AD(a, d)) = self {
+            FORMAT_UNARY!('#', proto, *a, *d, pc)
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpAddVN(OpMode::ABC(a, b, c)) => Some(('+', a, b, c)),
+            OpCode::OpSubVN(OpMode::ABC(a, b, c)) => Some(('-', a, b, c)),
+            OpCode::OpMulVN(OpMode::ABC(a, b, c)) => Some(('*', a, b, c)),
+            OpCode::OpDivVN(OpMode::ABC(a, b, c)) => Some(('/', a, b, c)),
+            OpCode::OpModVN(OpMode::ABC(a, b, c)) => Some(('%', a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                REG_FMT!(*b, proto, pc),
+                K_FMT!(proto.number_constants, *c),
+                proto,
+                pc
+            )
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpAddNV(OpMode::ABC(a, b, c)) => Some(('+', a, b, c)),
+            OpCode::OpSubNV(OpMode::ABC(a, b, c)) => Some(('-', a, b, c)),
+            OpCode::OpMulNV(OpMode::ABC(a, b, c)) => Some(('*', a, b, c)),
+            OpCode::OpDivNV(OpMode::ABC(a, b, c)) => Some(('/', a, b, c)),
+            OpCode::OpModNV(OpMode::ABC(a, b, c)) => Some(('%', a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                K_FMT!(proto.number_constants, *c),
+                REG_FMT!(*b, proto, pc),
+                proto,
+                pc
+            )
+        } else if let Some((op, a, b, c)) = match self {
+            OpCode::OpAddVV(OpMode::ABC(a, b, c)) => Some(('+', a, b, c)),
+            OpCode::OpSubVV(OpMode::ABC(a, b, c)) => Some(('-', a, b, c)),
+            OpCode::OpMulVV(OpMode::ABC(a, b, c)) => Some(('*', a, b, c)),
+            OpCode::OpDivVV(OpMode::ABC(a, b, c)) => Some(('/', a, b, c)),
+            OpCode::OpModVV(OpMode::ABC(a, b, c)) => Some(('%', a, b, c)),
+            OpCode::OpPow(OpMode::ABC(a, b, c)) => Some(('^', a, b, c)),
+            _ => None,
+        } {
+            FORMAT_BINARY_54!(
+                op,
+                *a,
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc),
+                proto,
+                pc
+            )
+        } else if let OpCode::OpCat(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{} = {} .. ... .. {}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc)
+            )
+        } else if let OpCode::OpKStr(OpMode::AD(a, d)) | OpCode::OpKCData(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), K_FMT!(constants, *d))
+        } else if let OpCode::OpKShort(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), d)
+        } else if let OpCode::OpKNum(OpMode::AD(a, d)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                K_FMT!(proto.number_constants, *d)
+            )
+        } else if let OpCode::OpKPri(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), PRI_FMT_LUAJIT!(*d))
+        } else if let OpCode::OpKNil(OpMode::AD(a, d)) = self {
+            format!("{} = nil", TARGET_REG_LIST!(*a, *d, proto, pc))
+        } else if let OpCode::OpUGet(OpMode::AD(a, d)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                proto.upvalue_name(*d)
+            )
+        } else if let OpCode::OpUSetV(OpMode::AD(a, d)) = self {
+            format!("{} = {}", proto.upvalue_name(*a), REG_FMT!(*d, proto, pc))
+        } else if let OpCode::OpUSetS(OpMode::AD(a, d)) = self {
+            format!("{} = {}", proto.upvalue_name(*a), K_FMT!(constants, *d))
+        } else if let OpCode::OpUSetN(OpMode::AD(a, d)) = self {
+            format!(
+                "{} = {}",
+                proto.upvalue_name(*a),
+                K_FMT!(proto.number_constants, *d)
+            )
+        } else if let OpCode::OpUSetP(OpMode::AD(a, d)) = self {
+            format!("{} = {}", proto.upvalue_name(*a), PRI_FMT_LUAJIT!(*d))
+        } else if let OpCode::OpUClo(OpMode::AD(a, d)) = self {
+            format!(
+                "close all upvalues from {} on; goto {}",
+                REG_FMT!(*a, proto, pc),
+                JUMP_TARGET_LUAJIT!(pc, *d)
+            )
+        } else if let OpCode::OpFNew(OpMode::AD(a, d)) = self {
+            let child: Option<(&LuaInt, &Proto)> = match constants.get(*d as usize) {
+                Some(Constant::Closure(index)) => {
+                    protos.get(*index as usize).map(|child| (index, child))
+                }
+                _ => None,
+            };
+            match child {
+                Some((index, child)) => format!(
+                    "{} = proto_{}{}",
+                    TARGET_FMT!(*a, proto, pc),
+                    index,
+                    describe_upvalue_captures(child, proto, pc)
+                ),
+                None => format!("{} = [INVALID CLOSURE]", TARGET_FMT!(*a, proto, pc)),
+            }
+        } else if let OpCode::OpTNew(OpMode::AD(a, d)) = self {
+            // the array size is in the low 11 bits, the log2 of the hash size in the rest
+            format!(
+                "{} = {{}} -- {} list, {} record",
+                TARGET_FMT!(*a, proto, pc),
+                d & 0x7FF,
+                match d >> 11 {
+                    0 => 0,
+                    bits => 1 << bits,
+                }
+            )
+        } else if let OpCode::OpTDup(OpMode::AD(a, d)) = self {
+            format!("{} = {}", TARGET_FMT!(*a, proto, pc), K_FMT!(constants, *d))
+        } else if let OpCode::OpGGet(OpMode::AD(a, d)) = self {
+            format!(
+                "{} = {}",
+                TARGET_FMT!(*a, proto, pc),
+                global_constant(constants, *d as usize)
+            )
+        } else if let OpCode::OpGSet(OpMode::AD(a, d)) = self {
+            format!(
+                "{} = {}",
+                global_constant(constants, *d as usize),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let Some((a, b, key)) = match self {
+            OpCode::OpTGetV(OpMode::ABC(a, b, c)) => {
+                Some((a, b, format!("[{}]", REG_FMT!(*c, proto, pc))))
+            }
+            OpCode::OpTGetS(OpMode::ABC(a, b, c)) => {
+                Some((a, b, format_constant_index(constants, *c as usize)))
+            }
+            OpCode::OpTGetB(OpMode::ABC(a, b, c)) => Some((a, b, format!("[{}]", c))),
+            _ => None,
+        } {
+            format!(
+                "{} = {}{}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                key
+            )
+        } else if let Some((a, b, key)) = match self {
+            OpCode::OpTSetV(OpMode::ABC(a, b, c)) => {
+                Some((a, b, format!("[{}]", REG_FMT!(*c, proto, pc))))
+            }
+            OpCode::OpTSetS(OpMode::ABC(a, b, c)) => {
+                Some((a, b, format_constant_index(constants, *c as usize)))
+            }
+            OpCode::OpTSetB(OpMode::ABC(a, b, c)) => Some((a, b, format!("[{}]", c))),
+            _ => None,
+        } {
+            format!(
+                "{}{} = {}",
+                REG_FMT!(*b, proto, pc),
+                key,
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpTGetR(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "{} = rawget({}, {})",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc)
+            )
+        } else if let OpCode::OpTSetR(OpMode::ABC(a, b, c)) = self {
+            format!(
+                "rawset({}, {}, {})",
+                REG_FMT!(*b, proto, pc),
+                REG_FMT!(*c, proto, pc),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpTSetM(OpMode::AD(a, d)) = self {
+            // the first index is kept in the low word of a number constant
+            let start: String = match proto.number_constants.get(*d as usize) {
+                Some(Constant::Number(number)) => (number.to_bits() as u32).to_string(),
+                _ => String::from("???"),
+            };
+            format!(
+                "{}[{} ...] = {}, top ... ???",
+                REG_FMT!(*a - 1, proto, pc),
+                start,
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let Some((a, b, arguments)) = match self {
+            OpCode::OpCall(OpMode::ABC(a, b, c)) => Some((
+                a,
+                b,
+                MULTRES_REG_LIST_LUAJIT!(*a + 1 + frame, *c - 1, false, proto, pc),
+            )),
+            // C only counts the fixed arguments
+            OpCode::OpCallM(OpMode::ABC(a, b, c)) => Some((
+                a,
+                b,
+                MULTRES_REG_LIST_LUAJIT!(*a + 1 + frame, *c, true, proto, pc),
+            )),
+            _ => None,
+        } {
+            format!(
+                "{}{}({})",
+                match *b {
+                    0 => String::from("top ... ??? = "),
+                    1 => String::new(),
+                    _ => format!("{} = ", TARGET_REG_LIST!(*a, *a + *b - 2, proto, pc)),
+                },
+                REG_FMT!(*a, proto, pc),
+                arguments
+            )
+        } else if let OpCode::OpCallT(OpMode::AD(a, d)) = self {
+            format!(
+                "return {}({})",
+                REG_FMT!(*a, proto, pc),
+                MULTRES_REG_LIST_LUAJIT!(*a + 1 + frame, *d - 1, false, proto, pc)
+            )
+        } else if let OpCode::OpCallMT(OpMode::AD(a, d)) = self {
+            format!(
+                "return {}({})",
+                REG_FMT!(*a, proto, pc),
+                MULTRES_REG_LIST_LUAJIT!(*a + 1 + frame, *d, true, proto, pc)
+            )
+        } else if let OpCode::OpIterC(OpMode::ABC(a, b, _c))
+        | OpCode::OpIterN(OpMode::ABC(a, b, _c)) = self
+        {
+            format!(
+                "{} = {}({}, {})",
+                TARGET_REG_LIST!(*a, *a + *b - 2, proto, pc),
+                REG_FMT!(*a - 3, proto, pc),
+                REG_FMT!(*a - 2, proto, pc),
+                REG_FMT!(*a - 1, proto, pc)
+            )
+        } else if let OpCode::OpIsNext(OpMode::AD(a, d)) = self {
+            format!(
+                "goto {} -- iterates with next if {} is next",
+                JUMP_TARGET_LUAJIT!(pc, *d),
+                REG_FMT!(*a - 3, proto, pc)
+            )
+        } else if let OpCode::OpVarg(OpMode::ABC(a, b, _c)) = self {
+            format!(
+                "{} = vararg",
+                match *b {
+                    0 => format!("{}, top ... ???", TARGET_FMT!(*a, proto, pc)),
+                    _ => TARGET_REG_LIST!(*a, *a + *b - 2, proto, pc),
+                }
+            )
+        } else if let OpCode::OpRetM(OpMode::AD(a, d)) = self {
+            format!(
+                "return {}",
+                MULTRES_REG_LIST_LUAJIT!(*a, *d, true, proto, pc)
+            )
+        } else if let OpCode::OpRet(OpMode::AD(a, d)) = self {
+            match *d {
+                1 => String::from("return"),
+                _ => format!("return {}", SIMPLE_REG_LIST!(*a, *a + *d - 2, proto, pc)),
+            }
+        } else if let OpCode::OpRet0(_) = self {
+            String::from("return")
+        } else if let OpCode::OpRet1(OpMode::AD(a, _d)) = self {
+            format!("return {}", REG_FMT!(*a, proto, pc))
+        } else if let OpCode::OpForI(OpMode::AD(a, d)) | OpCode::OpJForI(OpMode::AD(a, d)) = self {
+            format!(
+                "if not ({} <?= {}) then goto {} else {} = {}",
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                JUMP_TARGET_LUAJIT!(pc, *d),
+                TARGET_FMT!(*a + 3, proto, pc),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpForL(OpMode::AD(a, d)) | OpCode::OpIForL(OpMode::AD(a, d)) = self {
+            format!(
+                "{} += {}; if {} <?= {} then {{ goto {}; {} = {} }}",
+                TARGET_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 2, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                REG_FMT!(*a + 1, proto, pc),
+                JUMP_TARGET_LUAJIT!(pc, *d),
+                TARGET_FMT!(*a + 3, proto, pc),
+                REG_FMT!(*a, proto, pc)
+            )
+        } else if let OpCode::OpIterL(OpMode::AD(a, d)) | OpCode::OpIIterL(OpMode::AD(a, d)) = self
+        {
+            format!(
+                "if {} ~= nil then {{ {} = {}; goto {} }}",
+                REG_FMT!(*a, proto, pc),
+                TARGET_FMT!(*a - 1, proto, pc),
+                REG_FMT!(*a, proto, pc),
+                JUMP_TARGET_LUAJIT!(pc, *d)
+            )
+        } else if let OpCode::OpLoop(OpMode::AD(_a, d)) | OpCode::OpILoop(OpMode::AD(_a, d)) = self
+        {
+            format!("loop start, ends at {}", JUMP_TARGET_LUAJIT!(pc, *d))
+        } else if let OpCode::OpJForL(OpMode::AD(_a, d))
+        | OpCode::OpJIterL(OpMode::AD(_a, d))
+        | OpCode::OpJLoop(OpMode::AD(_a, d)) = self
+        {
+            format!("run trace {}", d)
+        } else if let OpCode::OpJmp(OpMode::AD(_a, d)) = self {
+            format!("goto {}", JUMP_TARGET_LUAJIT!(pc, *d))
+        } else if let OpCode::OpFuncF(_)
+        | OpCode::OpIFuncF(_)
+        | OpCode::OpJFuncF(_)
+        | OpCode::OpFuncV(_)
+        | OpCode::OpIFuncV(_)
+        | OpCode::OpJFuncV(_)
+        | OpCode::OpFuncC(_)
+        | OpCode::OpFuncCW(_) = self
+        {
+            String::from("function header")
+        } else {
+            format!("TODO: DESCRIBE {:?}", self)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Instruction {
+    pub raw: LuaInstruction,
+    // 5.1 only, the word after a SETLIST with a C of 0, already folded into op
+    pub aux: Option<LuaInstruction>,
+    pub op: OpCode,
+}
+
+impl Instruction {
+    // SETLIST with a C of 0 stores its real C in the next instruction word
+    pub fn needs_aux(&self) -> bool {
+        matches!(self.op, OpCode::OpSetList(OpMode::ABC(_, _, 0)))
+    }
+    // Luau instructions that are followed by an AUX word
+    pub fn has_aux_luau(&self) -> bool {
+        matches!(
+            self.op,
+            OpCode::OpGetGlobal(_)
+                | OpCode::OpSetGlobal(_)
+                | OpCode::OpGetImport(_)
+                | OpCode::OpGetTableKs(_)
+                | OpCode::OpSetTableKs(_)
+                | OpCode::OpNamecall(_)
+                | OpCode::OpJumpIfEq(_)
+                | OpCode::OpJumpIfLe(_)
+                | OpCode::OpJumpIfLt(_)
+                | OpCode::OpJumpIfNotEq(_)
+                | OpCode::OpJumpIfNotLe(_)
+                | OpCode::OpJumpIfNotLt(_)
+                | OpCode::OpNewTable(_)
+                | OpCode::OpSetList(_)
+                | OpCode::OpForGLoop(_)
+                | OpCode::OpLoadKx(_)
+                | OpCode::OpFastCall2(_)
+                | OpCode::OpFastCall2K(_)
+                | OpCode::OpFastCall3(_)
+                | OpCode::OpJumpXEqKNil(_)
+                | OpCode::OpJumpXEqKB(_)
+                | OpCode::OpJumpXEqKN(_)
+                | OpCode::OpJumpXEqKS(_)
+        )
+    }
+    pub fn handle_aux(&mut self, aux: LuaInstruction) {
+        if let OpCode::OpSetList(OpMode::ABC(a, b, _c)) = &self.op {
+            self.op = OpCode::OpSetList(OpMode::ABC(*a, *b, aux as i32));
+            self.aux = Some(aux);
+        }
+    }
+
+    // encodes op back into 5.1 words, so patched instructions can be written out; JMP's
+    // unused A is kept from raw, and SETLIST gets its aux word back when it had one or
+    // its C no longer fits. None when op isn't a 5.1 instruction, an operand overflows or
+    // a JMP was given an A, which 5.1 doesn't have
+    pub fn encode_51(&self, num_bits_int: LuaInt, max_int: LuaInt) -> Option<Vec<LuaInstruction>> {
+        let (opcode, mode): (LuaInstruction, &OpMode) = match &self.op {
+            OpCode::OpMove(mode) => (0, mode),
+            OpCode::OpLoadK(mode) => (1, mode),
+            OpCode::OpLoadBool(mode) => (2, mode),
+            OpCode::OpLoadNil(mode) => (3, mode),
+            OpCode::OpGetUpval(mode) => (4, mode),
+
+            OpCode::OpGetGlobal(mode) => (5, mode),
+            OpCode::OpGetTable(mode) => (6, mode),
+
+            OpCode::OpSetGlobal(mode) => (7, mode),
+            OpCode::OpSetUpval(mode) => (8, mode),
+            OpCode::OpSetTable(mode) => (9, mode),
+
+            OpCode::OpNewTable(mode) => (10, mode),
+
+            OpCode::OpSelf(mode) => (11, mode),
+
+            OpCode::OpAdd(mode) => (12, mode),
+            OpCode::OpSub(mode) => (13, mode),
+            OpCode::OpMul(mode) => (14, mode),
+            OpCode::OpDiv(mode) => (15, mode),
+            OpCode::OpMod(mode) => (16, mode),
+            OpCode::OpPow(mode) => (17, mode),
+            OpCode::OpUnm(mode) => (18, mode),
+            OpCode::OpNot(mode) => (19, mode),
+            OpCode::OpLen(mode) => (20, mode),
+
+            OpCode::OpConcat(mode) => (21, mode),
+
+            OpCode::OpJmp(mode) => (22, mode),
+
+            OpCode::OpEq(mode) => (23, mode),
+            OpCode::OpLt(mode) => (24, mode),
+            OpCode::OpLe(mode) => (25, mode),
+
+            OpCode::OpTest(mode) => (26, mode),
+            OpCode::OpTestSet(mode) => (27, mode),
+
+            OpCode::OpCall(mode) => (28, mode),
+            OpCode::OpTailCall(mode) => (29, mode),
+            OpCode::OpReturn(mode) => (30, mode),
+
+            OpCode::OpForLoop(mode) => (31, mode),
+            OpCode::OpForPrep(mode) => (32, mode),
+
+            OpCode::OpTForLoop(mode) => (33, mode),
+            OpCode::OpSetList(mode) => (34, mode),
+
+            OpCode::OpClose(mode) => (35, mode),
+            OpCode::OpClosure(mode) => (36, mode),
+
+            OpCode::OpVararg(mode) => (37, mode),
+
+            OpCode::OpUnknown(_) => return Some(vec![self.raw]),
+            _ => return None,
+        };
+        let fits = |value: LuaInt, max: LuaInt| (0..=max).contains(&value);
+        match *mode {
+            OpMode::ABC(a, b, c) if opcode == 34 && (self.aux.is_some() || c > MAXARG_C) => {
+                match fits(a, MAXARG_A) && fits(b, MAXARG_B) && c >= 0 {
+                    true => Some(vec![CREATE_ABC!(opcode, a, b, 0), c as LuaInstruction]),
+                    false => None,
+                }
+            }
+            OpMode::ABC(a, b, c) => {
+                match fits(a, MAXARG_A) && fits(b, MAXARG_B) && fits(c, MAXARG_C) {
+                    true => Some(vec![CREATE_ABC!(opcode, a, b, c)]),
+                    false => None,
+                }
+            }
+            OpMode::ABX(a, bx) => {
+                match fits(a, MAXARG_A) && fits(bx, MAXARG_BX!(num_bits_int, max_int)) {
+                    true => Some(vec![CREATE_ABX!(opcode, a, bx)]),
+                    false => None,
+                }
+            }
+            OpMode::ASBX(a, sbx) => {
+                let a: LuaInt = match (opcode, a) {
+                    (22, 0) => GET_ARGA!(self.raw),
+                    (22, _) => return None,
+                    _ => a,
+                };
+                let bx: LuaInt = sbx.checked_add(MAXARG_SBX!(num_bits_int, max_int))?;
+                match fits(a, MAXARG_A) && fits(bx, MAXARG_BX!(num_bits_int, max_int)) {
+                    true => Some(vec![CREATE_ABX!(opcode, a, bx)]),
+                    false => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn build_instruction(
+    raw: LuaInstruction,
+    version: LuaVersion,
+    num_bits_int: LuaInt,
+    max_int: LuaInt,
+) -> Instruction {
+    let a: LuaInt = GET_ARGA!(raw);
+    let b: LuaInt = GET_ARGB!(raw);
+    let c: LuaInt = GET_ARGC!(raw);
+    let bx: LuaInt = GET_ARGBX!(raw);
+    let sbx: LuaInt = GET_ARGASBX!(raw, num_bits_int, max_int);
+    let op: OpCode = match version {
+        LuaVersion::Lua51 => build_op_51(raw, a, b, c, bx, sbx),
+        LuaVersion::Lua52 => build_op_52(raw, a, b, c, bx, sbx),
+        LuaVersion::Lua53 => build_op_53(raw, a, b, c, bx, sbx),
+        LuaVersion::Lua54 => return build_instruction_54(raw),
+        LuaVersion::Luau => return build_instruction_luau(raw),
+        LuaVersion::LuaJit => return build_instruction_luajit(raw),
+    };
+    Instruction { raw, aux: None, op }
+}
+
+fn build_op_51(
+    raw: LuaInstruction,
+    a: LuaInt,
+    b: LuaInt,
+    c: LuaInt,
+    bx: LuaInt,
+    sbx: LuaInt,
+) -> OpCode {
+    match GET_OPCODE!(raw) {
+        0 => OpCode::OpMove(OpMode::ABC(a, b, c)),
+        1 => OpCode::OpLoadK(OpMode::ABX(a, bx)),
+        2 => OpCode::OpLoadBool(OpMode::ABC(a, b, c)),
+        3 => OpCode::OpLoadNil(OpMode::ABC(a, b, c)),
+        4 => OpCode::OpGetUpval(OpMode::ABC(a, b, c)),
+
+        5 => OpCode::OpGetGlobal(OpMode::ABX(a, bx)),
+        6 => OpCode::OpGetTable(OpMode::ABC(a, b, c)),
+
+        7 => OpCode::OpSetGlobal(OpMode::ABX(a, bx)),
+        8 => OpCode::OpSetUpval(OpMode::ABC(a, b, c)),
+        9 => OpCode::OpSetTable(OpMode::ABC(a, b, c)),
+
+        10 => OpCode::OpNewTable(OpMode::ABC(a, b, c)),
+
+        11 => OpCode::OpSelf(OpMode::ABC(a, b, c)),
+
+        12 => OpCode::OpAdd(OpMode::ABC(a, b, c)),
+        13 => OpCode::OpSub(OpMode::ABC(a, b, c)),
+        14 => OpCode::OpMul(OpMode::ABC(a, b, c)),
+        15 => OpCode::OpDiv(OpMode::ABC(a, b, c)),
+        16 => OpCode::OpMod(OpMode::ABC(a, b, c)),
+        17 => OpCode::OpPow(OpMode::ABC(a, b, c)),
+        18 => OpCode::OpUnm(OpMode::ABC(a, b, c)),
+        19 => OpCode::OpNot(OpMode::ABC(a, b, c)),
+        20 => OpCode::OpLen(OpMode::ABC(a, b, c)),
+
+        21 => OpCode::OpConcat(OpMode::ABC(a, b, c)),
+
+        22 => OpCode::OpJmp(OpMode::ASBX(0, sbx)),
+
+        23 => OpCode::OpEq(OpMode::ABC(a, b, c)),
+        24 => OpCode::OpLt(OpMode::ABC(a, b, c)),
+        25 => OpCode::OpLe(OpMode::ABC(a, b, c)),
+
+        26 => OpCode::OpTest(OpMode::ABC(a, b, c)),
+        27 => OpCode::OpTestSet(OpMode::ABC(a, b, c)),
+
+        28 => OpCode::OpCall(OpMode::ABC(a, b, c)),
+        29 => OpCode::OpTailCall(OpMode::ABC(a, b, c)),
+        30 => OpCode::OpReturn(OpMode::ABC(a, b, c)),
+
+        31 => OpCode::OpForLoop(OpMode::ASBX(a, sbx)),
+        32 => OpCode::OpForPrep(OpMode::ASBX(a, sbx)),
+
+        33 => OpCode::OpTForLoop(OpMode::ABC(a, b, c)),
+        34 => OpCode::OpSetList(OpMode::ABC(a, b, c)),
+
+        35 => OpCode::OpClose(OpMode::ABX(a, bx)),
+        36 => OpCode::OpClosure(OpMode::ABX(a, bx)),
+
+        37 => OpCode::OpVararg(OpMode::ABC(a, b, c)),
+
+        op => {
+            // panic!("Failed to get opcode: {}", op);
+            OpCode::OpUnknown(op)
+        }
+    }
+}
+
+fn build_op_52(
+    raw: LuaInstruction,
+    a: LuaInt,
+    b: LuaInt,
+    c: LuaInt,
+    bx: LuaInt,
+    sbx: LuaInt,
+) -> OpCode {
+    match GET_OPCODE!(raw) {
+        0 => OpCode::OpMove(OpMode::ABC(a, b, c)),
+        1 => OpCode::OpLoadK(OpMode::ABX(a, bx)),
+        2 => OpCode::OpLoadKx(OpMode::ABX(a, bx)),
+        3 => OpCode::OpLoadBool(OpMode::ABC(a, b, c)),
+        4 => OpCode::OpLoadNil(OpMode::ABC(a, b, c)),
+        5 => OpCode::OpGetUpval(OpMode::ABC(a, b, c)),
+
+        6 => OpCode::OpGetTabUp(OpMode::ABC(a, b, c)),
+        7 => OpCode::OpGetTable(OpMode::ABC(a, b, c)),
+
+        8 => OpCode::OpSetTabUp(OpMode::ABC(a, b, c)),
+        9 => OpCode::OpSetUpval(OpMode::ABC(a, b, c)),
+        10 => OpCode::OpSetTable(OpMode::ABC(a, b, c)),
+
+        11 => OpCode::OpNewTable(OpMode::ABC(a, b, c)),
+
+        12 => OpCode::OpSelf(OpMode::ABC(a, b, c)),
+
+        13 => OpCode::OpAdd(OpMode::ABC(a, b, c)),
+        14 => OpCode::OpSub(OpMode::ABC(a, b, c)),
+        15 => OpCode::OpMul(OpMode::ABC(a, b, c)),
+        16 => OpCode::OpDiv(OpMode::ABC(a, b, c)),
+        17 => OpCode::OpMod(OpMode::ABC(a, b, c)),
+        18 => OpCode::OpPow(OpMode::ABC(a, b, c)),
+        19 => OpCode::OpUnm(OpMode::ABC(a, b, c)),
+        20 => OpCode::OpNot(OpMode::ABC(a, b, c)),
+        21 => OpCode::OpLen(OpMode::ABC(a, b, c)),
+
+        22 => OpCode::OpConcat(OpMode::ABC(a, b, c)),
+
+        23 => OpCode::OpJmp(OpMode::ASBX(a, sbx)),
+
+        24 => OpCode::OpEq(OpMode::ABC(a, b, c)),
+        25 => OpCode::OpLt(OpMode::ABC(a, b, c)),
+        26 => OpCode::OpLe(OpMode::ABC(a, b, c)),
+
+        27 => OpCode::OpTest(OpMode::ABC(a, b, c)),
+        28 => OpCode::OpTestSet(OpMode::ABC(a, b, c)),
+
+        29 => OpCode::OpCall(OpMode::ABC(a, b, c)),
+        30 => OpCode::OpTailCall(OpMode::ABC(a, b, c)),
+        31 => OpCode::OpReturn(OpMode::ABC(a, b, c)),
+
+        32 => OpCode::OpForLoop(OpMode::ASBX(a, sbx)),
+        33 => OpCode::OpForPrep(OpMode::ASBX(a, sbx)),
+
+        34 => OpCode::OpTForCall(OpMode::ABC(a, b, c)),
+        35 => OpCode::OpTForLoop(OpMode::ASBX(a, sbx)),
+        36 => OpCode::OpSetList(OpMode::ABC(a, b, c)),
+
+        37 => OpCode::OpClosure(OpMode::ABX(a, bx)),
+
+        38 => OpCode::OpVararg(OpMode::ABC(a, b, c)),
+
+        39 => OpCode::OpExtraArg(OpMode::AX(GET_ARGAX!(raw))),
+
+        op => OpCode::OpUnknown(op),
+    }
+}
+
+fn build_op_53(
+    raw: LuaInstruction,
+    a: LuaInt,
+    b: LuaInt,
+    c: LuaInt,
+    bx: LuaInt,
+    sbx: LuaInt,
+) -> OpCode {
+    match GET_OPCODE!(raw) {
+        0 => OpCode::OpMove(OpMode::ABC(a, b, c)),
+        1 => OpCode::OpLoadK(OpMode::ABX(a, bx)),
+        2 => OpCode::OpLoadKx(OpMode::ABX(a, bx)),
+        3 => OpCode::OpLoadBool(OpMode::ABC(a, b, c)),
+        4 => OpCode::OpLoadNil(OpMode::ABC(a, b, c)),
+        5 => OpCode::OpGetUpval(OpMode::ABC(a, b, c)),
+
+        6 => OpCode::OpGetTabUp(OpMode::ABC(a, b, c)),
+        7 => OpCode::OpGetTable(OpMode::ABC(a, b, c)),
+
+        8 => OpCode::OpSetTabUp(OpMode::ABC(a, b, c)),
+        9 => OpCode::OpSetUpval(OpMode::ABC(a, b, c)),
+        10 => OpCode::OpSetTable(OpMode::ABC(a, b, c)),
+
+        11 => OpCode::OpNewTable(OpMode::ABC(a, b, c)),
+
+        12 => OpCode::OpSelf(OpMode::ABC(a, b, c)),
+
+        13 => OpCode::OpAdd(OpMode::ABC(a, b, c)),
+        14 => OpCode::OpSub(OpMode::ABC(a, b, c)),
+        15 => OpCode::OpMul(OpMode::ABC(a, b, c)),
+        16 => OpCode::OpMod(OpMode::ABC(a, b, c)),
+        17 => OpCode::OpPow(OpMode::ABC(a, b, c)),
+        18 => OpCode::OpDiv(OpMode::ABC(a, b, c)),
+        19 => OpCode::OpIdiv(OpMode::ABC(a, b, c)),
+        20 => OpCode::OpBand(OpMode::ABC(a, b, c)),
+        21 => OpCode::OpBor(OpMode::ABC(a, b, c)),
+        22 => OpCode::OpBxor(OpMode::ABC(a, b, c)),
+        23 => OpCode::OpShl(OpMode::ABC(a, b, c)),
+        24 => OpCode::OpShr(OpMode::ABC(a, b, c)),
+        25 => OpCode::OpUnm(OpMode::ABC(a, b, c)),
+        26 => OpCode::OpBnot(OpMode::ABC(a, b, c)),
+        27 => OpCode::OpNot(OpMode::ABC(a, b, c)),
+        28 => OpCode::OpLen(OpMode::ABC(a, b, c)),
+
+        29 => OpCode::OpConcat(OpMode::ABC(a, b, c)),
+
+        30 => OpCode::OpJmp(OpMode::ASBX(a, sbx)),
+
+        31 => OpCode::OpEq(OpMode::ABC(a, b, c)),
+        32 => OpCode::OpLt(OpMode::ABC(a, b, c)),
+        33 => OpCode::OpLe(OpMode::ABC(a, b, c)),
+
+        34 => OpCode::OpTest(OpMode::ABC(a, b, c)),
+        35 => OpCode::OpTestSet(OpMode::ABC(a, b, c)),
+
+        36 => OpCode::OpCall(OpMode::ABC(a, b, c)),
+        37 => OpCode::OpTailCall(OpMode::ABC(a, b, c)),
+        38 => OpCode::OpReturn(OpMode::ABC(a, b, c)),
+
+        39 => OpCode::OpForLoop(OpMode::ASBX(a, sbx)),
+        40 => OpCode::OpForPrep(OpMode::ASBX(a, sbx)),
+
+        41 => OpCode::OpTForCall(OpMode::ABC(a, b, c)),
+        42 => OpCode::OpTForLoop(OpMode::ASBX(a, sbx)),
+        43 => OpCode::OpSetList(OpMode::ABC(a, b, c)),
+
+        44 => OpCode::OpClosure(OpMode::ABX(a, bx)),
+
+        45 => OpCode::OpVararg(OpMode::ABC(a, b, c)),
+
+        46 => OpCode::OpExtraArg(OpMode::AX(GET_ARGAX!(raw))),
+
+        op => OpCode::OpUnknown(op),
+    }
+}
+
+pub fn build_instruction_54(raw: LuaInstruction) -> Instruction {
+    let a: LuaInt = GET_ARG_54!(raw, POS_A_54, SIZE_A_54);
+    let b: LuaInt = GET_ARG_54!(raw, POS_B_54, SIZE_B_54);
+    let c: LuaInt = GET_ARG_54!(raw, POS_C_54, SIZE_C_54);
+    let k: bool = GET_ARG_54!(raw, POS_K_54, 1) != 0;
+    let bx: LuaInt = GET_ARG_54!(raw, POS_BX_54, SIZE_BX_54);
+    let sbx: LuaInt = bx - OFFSET_SBX_54;
+    // sB and sC share the excess-K encoding
+    let sb: LuaInt = b - OFFSET_SC_54;
+    let sc: LuaInt = c - OFFSET_SC_54;
+    let abck: OpMode = OpMode::ABCK(a, b, c, k);
+    let op: OpCode = match GET_ARG_54!(raw, POS_OP_54, SIZE_OP_54) {
+        0 => OpCode::OpMove(abck),
+        1 => OpCode::OpLoadI(OpMode::ASBX(a, sbx)),
+        2 => OpCode::OpLoadF(OpMode::ASBX(a, sbx)),
+        3 => OpCode::OpLoadK(OpMode::ABX(a, bx)),
+        4 => OpCode::OpLoadKx(OpMode::ABX(a, bx)),
+        5 => OpCode::OpLoadFalse(abck),
+        6 => OpCode::OpLFalseSkip(abck),
+        7 => OpCode::OpLoadTrue(abck),
+        8 => OpCode::OpLoadNil(abck),
+        9 => OpCode::OpGetUpval(abck),
+        10 => OpCode::OpSetUpval(abck),
+
+        11 => OpCode::OpGetTabUp(abck),
+        12 => OpCode::OpGetTable(abck),
+        13 => OpCode::OpGetI(abck),
+        14 => OpCode::OpGetField(abck),
+
+        15 => OpCode::OpSetTabUp(abck),
+        16 => OpCode::OpSetTable(abck),
+        17 => OpCode::OpSetI(abck),
+        18 => OpCode::OpSetField(abck),
+
+        19 => OpCode::OpNewTable(abck),
+
+        20 => OpCode::OpSelf(abck),
+
+        21 => OpCode::OpAddI(OpMode::ABCK(a, b, sc, k)),
+        22 => OpCode::OpAddK(abck),
+        23 => OpCode::OpSubK(abck),
+        24 => OpCode::OpMulK(abck),
+        25 => OpCode::OpModK(abck),
+        26 => OpCode::OpPowK(abck),
+        27 => OpCode::OpDivK(abck),
+        28 => OpCode::OpIdivK(abck),
+        29 => OpCode::OpBandK(abck),
+        30 => OpCode::OpBorK(abck),
+        31 => OpCode::OpBxorK(abck),
+        32 => OpCode::OpShrI(OpMode::ABCK(a, b, sc, k)),
+        33 => OpCode::OpShlI(OpMode::ABCK(a, b, sc, k)),
+
+        34 => OpCode::OpAdd(abck),
+        35 => OpCode::OpSub(abck),
+        36 => OpCode::OpMul(abck),
+        37 => OpCode::OpMod(abck),
+        38 => OpCode::OpPow(abck),
+        39 => OpCode::OpDiv(abck),
+        40 => OpCode::OpIdiv(abck),
+        41 => OpCode::OpBand(abck),
+        42 => OpCode::OpBor(abck),
+        43 => OpCode::OpBxor(abck),
+        44 => OpCode::OpShl(abck),
+        45 => OpCode::OpShr(abck),
+
+        46 => OpCode::OpMmBin(abck),
+        47 => OpCode::OpMmBinI(OpMode::ABCK(a, sb, c, k)),
+        48 => OpCode::OpMmBinK(abck),
+
+        49 => OpCode::OpUnm(abck),
+        50 => OpCode::OpBnot(abck),
+        51 => OpCode::OpNot(abck),
+        52 => OpCode::OpLen(abck),
+
+        53 => OpCode::OpConcat(abck),
+
+        54 => OpCode::OpClose(abck),
+        55 => OpCode::OpTbc(abck),
+        56 => OpCode::OpJmp(OpMode::SJ(
+            GET_ARG_54!(raw, POS_SJ_54, SIZE_SJ_54) - OFFSET_SJ_54,
+        )),
+
+        57 => OpCode::OpEq(abck),
+        58 => OpCode::OpLt(abck),
+        59 => OpCode::OpLe(abck),
+        60 => OpCode::OpEqK(abck),
+        61 => OpCode::OpEqI(OpMode::ABCK(a, sb, c, k)),
+        62 => OpCode::OpLtI(OpMode::ABCK(a, sb, c, k)),
+        63 => OpCode::OpLeI(OpMode::ABCK(a, sb, c, k)),
+        64 => OpCode::OpGtI(OpMode::ABCK(a, sb, c, k)),
+        65 => OpCode::OpGeI(OpMode::ABCK(a, sb, c, k)),
+
+        66 => OpCode::OpTest(abck),
+        67 => OpCode::OpTestSet(abck),
+
+        68 => OpCode::OpCall(abck),
+        69 => OpCode::OpTailCall(abck),
+        70 => OpCode::OpReturn(abck),
+        71 => OpCode::OpReturn0(abck),
+        72 => OpCode::OpReturn1(abck),
+
+        73 => OpCode::OpForLoop(OpMode::ABX(a, bx)),
+        74 => OpCode::OpForPrep(OpMode::ABX(a, bx)),
+
+        75 => OpCode::OpTForPrep(OpMode::ABX(a, bx)),
+        76 => OpCode::OpTForCall(abck),
+        77 => OpCode::OpTForLoop(OpMode::ABX(a, bx)),
+        78 => OpCode::OpSetList(abck),
+
+        79 => OpCode::OpClosure(OpMode::ABX(a, bx)),
+
+        80 => OpCode::OpVararg(abck),
+        81 => OpCode::OpVarargPrep(abck),
+
+        82 => OpCode::OpExtraArg(OpMode::AX(GET_ARG_54!(raw, POS_AX_54, SIZE_AX_54))),
+
+        op => OpCode::OpUnknown(op as u32),
+    };
+    Instruction { raw, aux: None, op }
+}
+
+pub fn build_instruction_luau(raw: LuaInstruction) -> Instruction {
+    // the opcode is the low byte, followed by A, B and C
+    let a: LuaInt = ((raw >> 8) & 0xFF) as LuaInt;
+    let b: LuaInt = ((raw >> 16) & 0xFF) as LuaInt;
+    let c: LuaInt = ((raw >> 24) & 0xFF) as LuaInt;
+    // D and E are the signed upper 16 and 24 bits
+    let d: LuaInt = (raw as LuaInt) >> 16;
+    let e: LuaInt = (raw as LuaInt) >> 8;
+    let abc: OpMode = OpMode::ABC(a, b, c);
+    let ad: OpMode = OpMode::AD(a, d);
+    let op: OpCode = match raw & 0xFF {
+        0 => OpCode::OpNop(abc),
+        1 => OpCode::OpBreak(abc),
+        2 => OpCode::OpLoadNil(abc),
+        3 => OpCode::OpLoadB(abc),
+        4 => OpCode::OpLoadN(ad),
+        5 => OpCode::OpLoadK(ad),
+        6 => OpCode::OpMove(abc),
+        7 => OpCode::OpGetGlobal(abc),
+        8 => OpCode::OpSetGlobal(abc),
+        9 => OpCode::OpGetUpval(abc),
+        10 => OpCode::OpSetUpval(abc),
+        11 => OpCode::OpCloseUpvals(abc),
+        12 => OpCode::OpGetImport(ad),
+
+        13 => OpCode::OpGetTable(abc),
+        14 => OpCode::OpSetTable(abc),
+        15 => OpCode::OpGetTableKs(abc),
+        16 => OpCode::OpSetTableKs(abc),
+        17 => OpCode::OpGetTableN(abc),
+        18 => OpCode::OpSetTableN(abc),
+
+        19 => OpCode::OpClosure(ad),
+        20 => OpCode::OpNamecall(abc),
+        21 => OpCode::OpCall(abc),
+        22 => OpCode::OpReturn(abc),
+
+        23 => OpCode::OpJump(ad),
+        24 => OpCode::OpJumpBack(ad),
+        25 => OpCode::OpJumpIf(ad),
+        26 => OpCode::OpJumpIfNot(ad),
+        27 => OpCode::OpJumpIfEq(ad),
+        28 => OpCode::OpJumpIfLe(ad),
+        29 => OpCode::OpJumpIfLt(ad),
+        30 => OpCode::OpJumpIfNotEq(ad),
+        31 => OpCode::OpJumpIfNotLe(ad),
+        32 => OpCode::OpJumpIfNotLt(ad),
+
+        33 => OpCode::OpAdd(abc),
+        34 => OpCode::OpSub(abc),
+        35 => OpCode::OpMul(abc),
+        36 => OpCode::OpDiv(abc),
+        37 => OpCode::OpMod(abc),
+        38 => OpCode::OpPow(abc),
+        39 => OpCode::OpAddK(abc),
+        40 => OpCode::OpSubK(abc),
+        41 => OpCode::OpMulK(abc),
+        42 => OpCode::OpDivK(abc),
+        43 => OpCode::OpModK(abc),
+        44 => OpCode::OpPowK(abc),
+        45 => OpCode::OpAnd(abc),
+        46 => OpCode::OpOr(abc),
+        47 => OpCode::OpAndK(abc),
+        48 => OpCode::OpOrK(abc),
+
+        49 => OpCode::OpConcat(abc),
+        50 => OpCode::OpNot(abc),
+        51 => OpCode::OpUnm(abc),
+        52 => OpCode::OpLen(abc),
+
+        53 => OpCode::OpNewTable(abc),
+        54 => OpCode::OpDupTable(ad),
+        55 => OpCode::OpSetList(abc),
+
+        56 => OpCode::OpForNPrep(ad),
+        57 => OpCode::OpForNLoop(ad),
+        58 => OpCode::OpForGLoop(ad),
+        59 => OpCode::OpForGPrepINext(ad),
+        60 => OpCode::OpFastCall3(abc),
+        61 => OpCode::OpForGPrepNext(ad),
+        62 => OpCode::OpNativeCall(abc),
+        63 => OpCode::OpVararg(abc),
+        64 => OpCode::OpDupClosure(ad),
+        65 => OpCode::OpVarargPrep(abc),
+        66 => OpCode::OpLoadKx(ad),
+        67 => OpCode::OpJumpX(OpMode::E(e)),
+        68 => OpCode::OpFastCall(abc),
+        69 => OpCode::OpCoverage(OpMode::E(e)),
+        70 => OpCode::OpCapture(abc),
+        71 => OpCode::OpSubRk(abc),
+        72 => OpCode::OpDivRk(abc),
+        73 => OpCode::OpFastCall1(abc),
+        74 => OpCode::OpFastCall2(abc),
+        75 => OpCode::OpFastCall2K(abc),
+        76 => OpCode::OpForGPrep(ad),
+        77 => OpCode::OpJumpXEqKNil(ad),
+        78 => OpCode::OpJumpXEqKB(ad),
+        79 => OpCode::OpJumpXEqKN(ad),
+        80 => OpCode::OpJumpXEqKS(ad),
+        81 => OpCode::OpIdiv(abc),
+        82 => OpCode::OpIdivK(abc),
+
+        op => OpCode::OpUnknown(op),
+    };
+    Instruction { raw, aux: None, op }
+}
+
+pub fn build_instruction_luajit(raw: LuaInstruction) -> Instruction {
+    // the opcode is the low byte, followed by A, C and B, with D overlapping B and C
+    let a: LuaInt = ((raw >> 8) & 0xFF) as LuaInt;
+    let c: LuaInt = ((raw >> 16) & 0xFF) as LuaInt;
+    let b: LuaInt = ((raw >> 24) & 0xFF) as LuaInt;
+    let d: LuaInt = ((raw >> 16) & 0xFFFF) as LuaInt;
+    let abc: OpMode = OpMode::ABC(a, b, c);
+    let ad: OpMode = OpMode::AD(a, d);
+    // jump offsets are biased to fit D, KSHORT's literal is signed
+    let jump: OpMode = OpMode::AD(a, d - BCBIAS_J_LUAJIT);
+    let lits: OpMode = OpMode::AD(a, d as i16 as LuaInt);
+    let op: OpCode = match raw & 0xFF {
+        0 => OpCode::OpIsLt(ad),
+        1 => OpCode::OpIsGe(ad),
+        2 => OpCode::OpIsLe(ad),
+        3 => OpCode::OpIsGt(ad),
+        4 => OpCode::OpIsEqV(ad),
+        5 => OpCode::OpIsNeV(ad),
+        6 => OpCode::OpIsEqS(ad),
+        7 => OpCode::OpIsNeS(ad),
+        8 => OpCode::OpIsEqN(ad),
+        9 => OpCode::OpIsNeN(ad),
+        10 => OpCode::OpIsEqP(ad),
+        11 => OpCode::OpIsNeP(ad),
+
+        12 => OpCode::OpIsTC(ad),
+        13 => OpCode::OpIsFC(ad),
+        14 => OpCode::OpIsT(ad),
+        15 => OpCode::OpIsF(ad),
+        16 => OpCode::OpIsType(ad),
+        17 => OpCode::OpIsNum(ad),
+
+        18 => OpCode::OpMove(ad),
+        19 => OpCode::OpNot(ad),
+        20 => OpCode::OpUnm(ad),
+        21 => OpCode::OpLen(ad),
+
+        22 => OpCode::OpAddVN(abc),
+        23 => OpCode::OpSubVN(abc),
+        24 => OpCode::OpMulVN(abc),
+        25 => OpCode::OpDivVN(abc),
+        26 => OpCode::OpModVN(abc),
+        27 => OpCode::OpAddNV(abc),
+        28 => OpCode::OpSubNV(abc),
+        29 => OpCode::OpMulNV(abc),
+        30 => OpCode::OpDivNV(abc),
+        31 => OpCode::OpModNV(abc),
+        32 => OpCode::OpAddVV(abc),
+        33 => OpCode::OpSubVV(abc),
+        34 => OpCode::OpMulVV(abc),
+        35 => OpCode::OpDivVV(abc),
+        36 => OpCode::OpModVV(abc),
+        37 => OpCode::OpPow(abc),
+        38 => OpCode::OpCat(abc),
+
+        39 => OpCode::OpKStr(ad),
+        40 => OpCode::OpKCData(ad),
+        41 => OpCode::OpKShort(lits),
+        42 => OpCode::OpKNum(ad),
+        43 => OpCode::OpKPri(ad),
+        44 => OpCode::OpKNil(ad),
+
+        45 => OpCode::OpUGet(ad),
+        46 => OpCode::OpUSetV(ad),
+        47 => OpCode::OpUSetS(ad),
+        48 => OpCode::OpUSetN(ad),
+        49 => OpCode::OpUSetP(ad),
+        50 => OpCode::OpUClo(jump),
+        51 => OpCode::OpFNew(ad),
+
+        52 => OpCode::OpTNew(ad),
+        53 => OpCode::OpTDup(ad),
+        54 => OpCode::OpGGet(ad),
+        55 => OpCode::OpGSet(ad),
+        56 => OpCode::OpTGetV(abc),
+        57 => OpCode::OpTGetS(abc),
+        58 => OpCode::OpTGetB(abc),
+        59 => OpCode::OpTGetR(abc),
+        60 => OpCode::OpTSetV(abc),
+        61 => OpCode::OpTSetS(abc),
+        62 => OpCode::OpTSetB(abc),
+        63 => OpCode::OpTSetM(ad),
+        64 => OpCode::OpTSetR(abc),
+
+        65 => OpCode::OpCallM(abc),
+        66 => OpCode::OpCall(abc),
+        67 => OpCode::OpCallMT(ad),
+        68 => OpCode::OpCallT(ad),
+        69 => OpCode::OpIterC(abc),
+        70 => OpCode::OpIterN(abc),
+        71 => OpCode::OpVarg(abc),
+        72 => OpCode::OpIsNext(jump),
+
+        73 => OpCode::OpRetM(ad),
+        74 => OpCode::OpRet(ad),
+        75 => OpCode::OpRet0(ad),
+        76 => OpCode::OpRet1(ad),
+
+        77 => OpCode::OpForI(jump),
+        78 => OpCode::OpJForI(jump),
+        79 => OpCode::OpForL(jump),
+        80 => OpCode::OpIForL(jump),
+        81 => OpCode::OpJForL(ad),
+        82 => OpCode::OpIterL(jump),
+        83 => OpCode::OpIIterL(jump),
+        84 => OpCode::OpJIterL(ad),
+        85 => OpCode::OpLoop(jump),
+        86 => OpCode::OpILoop(jump),
+        87 => OpCode::OpJLoop(ad),
+        88 => OpCode::OpJmp(jump),
+
+        89 => OpCode::OpFuncF(ad),
+        90 => OpCode::OpIFuncF(ad),
+        91 => OpCode::OpJFuncF(ad),
+        92 => OpCode::OpFuncV(ad),
+        93 => OpCode::OpIFuncV(ad),
+        94 => OpCode::OpJFuncV(ad),
+        95 => OpCode::OpFuncC(ad),
+        96 => OpCode::OpFuncCW(ad),
+
+        op => OpCode::OpUnknown(op),
+    };
+    Instruction { raw, aux: None, op }
+}
+
+#[derive(Debug, Clone)]
+pub struct Proto {
+    pub is_main: bool,
+    pub id: LuaInt,
+
+    pub source: Vec<u8>,
     pub line_defined: LuaInt,
     pub last_line_defined: LuaInt,
     pub upvalue_count: u8,
     pub param_count: u8,
     pub is_vararg: bool,
+    pub vararg_flags: u8, // 5.1 only, VARARG_HASARG, VARARG_ISVARARG and VARARG_NEEDSARG as stored
     pub max_stack_size: u8,
 
     pub code: Vec<Instruction>,
     pub constants: Vec<Constant>,
     pub protos: Vec<Proto>,
+    // upvalue descriptors, only present from 5.2 on
+    pub upvalues: Vec<UpvalueDesc>,
+    // Luau only, type tags of the parameters when compiled with type info
+    pub param_types: Vec<u8>,
+    // LuaJIT only, numbers live in their own table indexed separately from the other constants
+    pub number_constants: Vec<Constant>,
+    // LuaJIT only, FR2 dumps keep the frame link in the slot after a called function
+    pub two_slot_frame: bool,
+
+    // debug, all empty when the chunk was stripped
+    pub lineinfo: Vec<LuaInt>,
+    pub locals: Vec<LocalVar>,
+    pub upvalue_names: Vec<Vec<u8>>,
+    pub debug_name: Vec<u8>, // Luau only, the name the function was declared with
+}
+
+impl Proto {
+    // the pc of each instruction, jumps, lineinfo and locals count words and a 5.1 SETLIST
+    // folds its aux word into one instruction
+    pub fn pcs(&self) -> Vec<isize> {
+        let mut pcs: Vec<isize> = Vec::with_capacity(self.code.len());
+        let mut pc: isize = 0;
+        for inst in &self.code {
+            pcs.push(pc);
+            pc += match inst.aux.is_some() {
+                true => 2,
+                false => 1,
+            };
+        }
+        pcs
+    }
+
+    // the index of the instruction at pc, or of the first one after it when pc is an aux word
+    pub fn index_at(&self, pc: isize) -> isize {
+        let mut word: isize = 0;
+        for (index, inst) in self.code.iter().enumerate() {
+            if word >= pc {
+                return index as isize;
+            }
+            word += match inst.aux.is_some() {
+                true => 2,
+                false => 1,
+            };
+        }
+        self.code.len() as isize + (pc - word).max(0)
+    }
+
+    // mirrors luaF_getlocalname: the register is the n-th local still alive at pc
+    pub fn local_at(&self, reg: LuaInt, pc: isize) -> Option<&LocalVar> {
+        // Luau records the register of every local instead
+        if self.locals.iter().any(|local| local.reg.is_some()) {
+            return self.locals.iter().find(|local| {
+                local.reg == Some(reg as u8)
+                    && local.start_pc as isize <= pc
+                    && pc < local.end_pc as isize
+            });
+        }
+
+        let mut remaining: LuaInt = reg + 1;
+        for local in &self.locals {
+            if local.start_pc as isize > pc {
+                break;
+            }
+            if pc < local.end_pc as isize {
+                remaining -= 1;
+                if remaining == 0 {
+                    return Some(local);
+                }
+            }
+        }
+        None
+    }
+
+    // the name of a register an instruction at pc reads
+    pub fn register_name(&self, reg: LuaInt, pc: isize) -> String {
+        self.local_name(self.local_at(reg, pc), reg)
+    }
+
+    // the name of a register an instruction at pc writes; a local only becomes active after
+    // the instructions that initialize it, all of them when several are assigned at once, so
+    // look ahead to the next pc a local starts at unless the register is written again first
+    pub fn target_name(&self, reg: LuaInt, pc: isize) -> String {
+        if self.local_at(reg, pc).is_some() {
+            return self.register_name(reg, pc);
+        }
+        let next: Option<isize> = self
+            .locals
+            .iter()
+            .map(|local| local.start_pc as isize)
+            .filter(|start_pc| *start_pc > pc)
+            .min();
+        let local: Option<&LocalVar> = match next {
+            Some(next) => {
+                let between: &[Instruction] = match self
+                    .code
+                    .get((self.index_at(pc) + 1) as usize..self.index_at(next) as usize)
+                {
+                    Some(between) => between,
+                    None => &[],
+                };
+                // any instruction in between with this register as its A might overwrite it
+                let rewritten: bool = between.iter().any(|inst| {
+                    matches!(
+                        inst.op.mode(),
+                        Some(
+                            OpMode::ABC(a, _, _)
+                                | OpMode::ABX(a, _)
+                                | OpMode::ASBX(a, _)
+                                | OpMode::ABCK(a, _, _, _)
+                                | OpMode::AD(a, _)
+                        ) if *a == reg
+                    )
+                });
+                match rewritten {
+                    true => None,
+                    false => self
+                        .local_at(reg, next)
+                        .filter(|local| local.start_pc as isize == next),
+                }
+            }
+            None => None,
+        };
+        self.local_name(local, reg)
+    }
+
+    // every name local_name can give a local, for coloring them like registers
+    pub fn local_names(&self) -> Vec<String> {
+        self.locals
+            .iter()
+            .filter(|local| !local.name.starts_with(b"("))
+            .map(|local| String::from_utf8_lossy(&local.name).into_owned())
+            .collect()
+    }
+
+    fn local_name(&self, local: Option<&LocalVar>, reg: LuaInt) -> String {
+        match local {
+            // internal locals such as (for index) aren't valid names
+            Some(local) if !local.name.starts_with(b"(") => {
+                String::from_utf8_lossy(&local.name).into_owned()
+            }
+            _ => match reg < self.param_count as LuaInt {
+                true => format!("arg_{}", reg),
+                false => format!("r_{}", reg),
+            },
+        }
+    }
+
+    // from 5.2 on globals are fields of the _ENV upvalue
+    pub fn global_name(
+        &self,
+        upvalue: LuaInt,
+        key: LuaInt,
+        constants: &[Constant],
+        version: LuaVersion,
+    ) -> Option<String> {
+        if version == LuaVersion::Lua51 || !IS_RK!(key) {
+            return None;
+        }
+        let is_env: bool = match self.upvalue_names.get(upvalue as usize) {
+            Some(name) => name == b"_ENV",
+            // stripped, but the main proto's only upvalue is always _ENV
+            None => self.is_main && upvalue == 0,
+        };
+        match constants.get(INDEXK!(key) as usize) {
+            Some(Constant::String(name)) if is_env => {
+                Some(String::from_utf8_lossy(name).into_owned())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn upvalue_name(&self, index: LuaInt) -> String {
+        match self.upvalue_names.get(index as usize) {
+            Some(name) if !name.is_empty() => String::from_utf8_lossy(name).into_owned(),
+            _ => format!("upvalue_{}", index),
+        }
+    }
+
+    // Luau annotation of a parameter, left out for any
+    pub fn param_type(&self, index: usize) -> Option<String> {
+        let tag: u8 = *self.param_types.get(index)?;
+        let name: &str = match tag & 0x7F {
+            0 => "nil",
+            1 => "boolean",
+            2 => "number",
+            3 => "string",
+            4 => "table",
+            5 => "function",
+            6 => "thread",
+            7 => "userdata",
+            8 => "vector",
+            9 => "buffer",
+            // host defined userdata types
+            64..=95 => "userdata",
+            _ => return None,
+        };
+        // the high bit marks optional types
+        match tag & 0x80 != 0 {
+            true => Some(format!("{}?", name)),
+            false => Some(name.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpvalueDesc {
+    pub instack: bool, // whether idx is a register of the enclosing function or one of its upvalues
+    pub idx: u8,
+    pub kind: u8, // 5.4 only: regular, <const>, <close> or compile time constant
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalVar {
+    pub name: Vec<u8>,
+    pub start_pc: LuaInt, // first pc where the local is active
+    pub end_pc: LuaInt,   // first pc where the local is dead
+    pub reg: Option<u8>,  // Luau only
 }
 
 pub struct Bytecode {
@@ -658,17 +4045,27 @@ pub struct Bytecode {
     pub size_int: u8,
     pub size_sizet: u8,
     pub size_instruction: u8,
+    pub size_luainteger: u8, // only present from 5.3 on
     pub size_luanumber: u8,
-    pub luanumber_integral: bool,
+    pub luanumber_integral: bool, // only present up to 5.2
+    pub luau_version: u8,         // Luau only, like luau_types_version
+    pub luau_types_version: u8,
+    pub luajit_version: u8, // LuaJIT only, like luajit_flags
+    pub luajit_flags: u8,
 
     // function
     pub main_proto: Proto,
 
     // printing
     pub time_taken: Duration,
+    pub print_blocks: bool, // head each basic block with its edges
+    pub print_live: bool,   // follow each instruction with the registers live after it
+    pub color: bool,        // ANSI colors in the text listing
     indent: u8,
+    output: String,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_bytecode(
     version: LuaVersion,
     format: u8,
@@ -676,8 +4073,13 @@ pub fn build_bytecode(
     size_int: u8,
     size_sizet: u8,
     size_instruction: u8,
+    size_luainteger: u8,
     size_luanumber: u8,
     luanumber_integral: bool,
+    luau_version: u8,
+    luau_types_version: u8,
+    luajit_version: u8,
+    luajit_flags: u8,
     main_proto: Proto,
     time_taken: Duration,
 ) -> Bytecode {
@@ -688,56 +4090,196 @@ pub fn build_bytecode(
         size_int,
         size_sizet,
         size_instruction,
+        size_luainteger,
         size_luanumber,
         luanumber_integral,
+        luau_version,
+        luau_types_version,
+        luajit_version,
+        luajit_flags,
 
         main_proto,
 
         time_taken,
+        print_blocks: false,
+        print_live: false,
+        color: false,
         indent: 0,
+        output: String::new(),
     }
 }
 
 impl Bytecode {
     pub fn print_disassembly(&mut self, just_describes: bool) {
+        print!("{}", self.disassembly(just_describes));
+    }
+
+    // the text listing print_disassembly prints
+    pub fn disassembly(&mut self, just_describes: bool) -> String {
         let time_taken = self.time_taken;
-        println!(
-            "-- disassembled by inu in {}\n",
-            format_time_taken!(time_taken)
+        let header: String = self.paint(
+            Style::Comment,
+            &format!(
+                "-- disassembled by inu in {}",
+                format_time_taken!(time_taken)
+            ),
         );
+        self.output = format!("{}\n\n", header);
 
         self.print_proto(self.main_proto.clone(), just_describes);
+        std::mem::take(&mut self.output)
+    }
+
+    // one cluster per proto, nested like the protos are, with a node per basic block
+    pub fn print_dot(&mut self) {
+        print!("{}", self.dot());
+    }
+
+    // the DOT graph print_dot prints
+    pub fn dot(&mut self) -> String {
+        self.output = String::new();
+        self.print_text_str("digraph main {");
+        self.indent += 1;
+        self.print_text_str("node [shape=box, fontname=\"monospace\"];");
+        // main's children already take proto_0 and up
+        self.print_text_str("subgraph cluster_main {");
+        self.indent += 1;
+        self.print_text_str("label=\"main\";");
+        self.print_proto_dot(&self.main_proto.clone(), "main");
+        self.indent -= 1;
+        self.print_text_str("}");
+        self.indent -= 1;
+        self.print_text_str("}");
+        std::mem::take(&mut self.output)
     }
 
-    fn print_text(&self, text: String) {
-        println!("{}{}", ("    ").repeat(self.indent.into()), text);
+    fn print_proto_dot(&mut self, proto: &Proto, name: &str) {
+        let cfg: Cfg = build_cfg(proto, self.version);
+        let pcs: Vec<isize> = proto.pcs();
+
+        for (i, block) in cfg.blocks.iter().enumerate() {
+            let mut label: String = String::new();
+            for (inst, pc) in proto.code[block.start..block.end]
+                .iter()
+                .zip(&pcs[block.start..block.end])
+            {
+                let describe: String =
+                    inst.op
+                        .describe(&proto.constants, proto, &proto.protos, *pc, self.version);
+                // \l ends a left aligned line
+                label.push_str(
+                    &format!("{}  {}", pc, describe)
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\""),
+                );
+                label.push_str("\\l");
+            }
+            self.print_text(format!("{}_b{} [label=\"{}\"];", name, i, label));
+        }
+        for (i, block) in cfg.blocks.iter().enumerate() {
+            let mut edges: Vec<(usize, &str)> = Vec::new();
+            if let Some(jump) = block.jump {
+                edges.push((
+                    jump,
+                    if cfg.blocks[jump].start <= block.start {
+                        "loop-back"
+                    } else if block.fallthrough.is_some() {
+                        "true"
+                    } else {
+                        "jump"
+                    },
+                ));
+            }
+            if let Some(fallthrough) = block.fallthrough {
+                edges.push((
+                    fallthrough,
+                    match block.jump.is_some() {
+                        true => "false",
+                        false => "fallthrough",
+                    },
+                ));
+            }
+            for (successor, label) in edges {
+                self.print_text(format!(
+                    "{}_b{} -> {}_b{} [label=\"{}\"];",
+                    name, i, name, successor, label
+                ));
+            }
+        }
+
+        for (i, child) in proto.protos.iter().enumerate() {
+            let child_name: String = match proto.is_main {
+                true => format!("proto_{}", i),
+                false => format!("{}_{}", name, i),
+            };
+            self.print_text(format!("subgraph cluster_{} {{", child_name));
+            self.indent += 1;
+            self.print_text(format!("label=\"{}\";", child_name));
+            self.print_proto_dot(child, &child_name);
+            self.indent -= 1;
+            self.print_text_str("}");
+        }
+    }
+
+    fn print_text(&mut self, text: String) {
+        let indent: String = ("    ").repeat(self.indent.into());
+        self.output.push_str(&indent);
+        self.output.push_str(&text);
+        self.output.push('\n');
     }
-    fn print_text_str(&self, text: &str) {
+    fn print_text_str(&mut self, text: &str) {
         self.print_text(text.to_string());
     }
+    // text unchanged when the listing isn't colored
+    fn paint(&self, style: Style, text: &str) -> String {
+        match self.color {
+            true => paint(style, text),
+            false => text.to_string(),
+        }
+    }
+    fn highlight(&self, text: &str, names: &[String]) -> String {
+        match self.color {
+            true => highlight(text, names),
+            false => text.to_string(),
+        }
+    }
 
     fn print_proto(&mut self, proto: Proto, just_describes: bool) {
+        let local_names: Vec<String> = proto.local_names();
         if !proto.is_main {
-            self.print_text(format!("local function proto_{}({})", proto.id, {
-                let count = proto.param_count;
-                let vararg = proto.is_vararg;
-                let mut arg_str: String = String::new();
+            let header: String = format!(
+                "local function proto_{}({}){}",
+                proto.id,
+                {
+                    let count = proto.param_count;
+                    let vararg = proto.is_vararg;
+                    let mut arg_str: String = String::new();
 
-                for i in 0..count {
-                    arg_str.push_str(format!("arg_{}, ", i).as_str());
-                }
-                if vararg {
-                    arg_str += "...";
-                } else if count > 0 {
-                    arg_str.truncate(arg_str.len() - 2);
-                }
+                    for i in 0..count {
+                        arg_str.push_str(&proto.register_name(i as LuaInt, 0));
+                        if let Some(param_type) = proto.param_type(i as usize) {
+                            arg_str.push_str(&format!(": {}", param_type));
+                        }
+                        arg_str.push_str(", ");
+                    }
+                    if vararg {
+                        arg_str += "...";
+                    } else if count > 0 {
+                        arg_str.truncate(arg_str.len() - 2);
+                    }
 
-                arg_str
-            }));
+                    arg_str
+                },
+                match proto.debug_name.is_empty() {
+                    true => String::new(),
+                    false => format!(" -- {}", String::from_utf8_lossy(&proto.debug_name)),
+                }
+            );
+            self.print_text(self.highlight(&header, &local_names));
             self.indent += 1;
         }
 
-        self.print_text_str("--[[ constants:");
+        self.print_text(self.paint(Style::Comment, "--[[ constants:"));
         self.indent += 1;
         for i in 0..proto.constants.len() {
             let kst: &Constant = &proto.constants[i];
@@ -745,69 +4287,189 @@ impl Bytecode {
             let mut i_str = i.to_string();
             i_str.push_str(" - ");
 
-            let mut str = kst.format();
+            let mut str = self.highlight(&kst.format(), &[]);
             str.insert_str(0, i_str.as_str());
             self.print_text(str);
         }
         self.indent -= 1;
-        self.print_text_str("]]");
+        self.print_text(self.paint(Style::Comment, "]]"));
+        if !proto.number_constants.is_empty() {
+            self.print_text(self.paint(Style::Comment, "--[[ number constants:"));
+            self.indent += 1;
+            for (i, kst) in proto.number_constants.iter().enumerate() {
+                self.print_text(format!("{} - {}", i, self.highlight(&kst.format(), &[])));
+            }
+            self.indent -= 1;
+            self.print_text(self.paint(Style::Comment, "]]"));
+        }
 
-        let mut was_proto_printed_map: Vec<bool> = vec![false; proto.code.len()];
+        let mut was_proto_printed_map: Vec<bool> = vec![false; proto.protos.len()];
 
         let code_len: usize = proto.code.len();
         let mut code_op_strings: Vec<String> = Vec::with_capacity(code_len);
         let mut code_op_describes: Vec<String> = Vec::with_capacity(code_len);
+        // jumps, lineinfo and locals count words, past a 5.1 SETLIST aux word they're ahead
+        // of the index
+        let pcs: Vec<isize> = proto.pcs();
 
-        for i in 0..code_len {
-            let inst: &Instruction = &proto.code[i];
-
+        for (inst, pc) in proto.code.iter().zip(&pcs) {
             code_op_strings.push(format!("{:?}", inst.op));
             code_op_describes.push(inst.op.describe(
                 &proto.constants,
                 &proto,
                 &proto.protos,
-                i as isize,
+                *pc,
+                self.version,
             ));
         }
 
-        let max_index_width = if code_len == 0 {
-            1
-        } else {
-            (code_len as f64).log(10.0).floor() as usize + 1
+        let max_index_width = match pcs.last() {
+            Some(pc) => ((pc + 1) as f64).log(10.0).floor() as usize + 1,
+            None => 1,
         } + 2;
         let max_op_strings_width = code_op_strings
             .iter()
             .map(|string| string.len())
             .max()
             .unwrap_or(0);
+        // source line column, left out entirely for stripped chunks
+        let code_lines: Vec<String> = (0..code_len)
+            .map(|i| match proto.lineinfo.get(pcs[i] as usize) {
+                Some(line) => format!("[{}]", line),
+                None => String::new(),
+            })
+            .collect();
+        let max_line_width = match code_lines.iter().map(|line| line.len()).max() {
+            Some(0) | None => 0,
+            Some(width) => width + 2,
+        };
+
+        let cfg: Option<Cfg> = match self.print_blocks || self.print_live {
+            true => Some(build_cfg(&proto, self.version)),
+            false => None,
+        };
+        let liveness: Option<Liveness> = match (&cfg, self.print_live) {
+            (Some(cfg), true) => Some(build_liveness(
+                cfg,
+                register_uses(&proto, cfg, self.version),
+            )),
+            _ => None,
+        };
+        let format_regs = |live: &[bool]| -> String {
+            let regs: Vec<String> = (0..live.len())
+                .filter(|reg| live[*reg])
+                .map(|reg| format!("r_{}", reg))
+                .collect();
+            match regs.is_empty() {
+                true => String::from("none"),
+                false => regs.join(" "),
+            }
+        };
+        // what's live after each instruction, with the stores nothing reads back
+        let mut live_notes: Vec<String> = vec![String::new(); code_len];
+        if let (Some(cfg), Some(liveness)) = (&cfg, &liveness) {
+            let live_after: Vec<Vec<bool>> = liveness.live_after(cfg);
+            for (i, live) in live_after.iter().enumerate() {
+                live_notes[i] = format!("  ; live: {}", format_regs(live));
+            }
+            let captured: Vec<bool> = captured_registers(&proto, self.version);
+            for (i, reg) in liveness.dead_stores(cfg, &captured) {
+                live_notes[i].push_str(&format!("  ; dead store: r_{}", reg));
+            }
+        }
 
+        // what each instruction prints, after the child proto a closure makes
+        let mut code_text: Vec<Vec<String>> = vec![Vec::new(); code_len];
         for i in 0..code_len {
-            let inst: &Instruction = &proto.code[i];
-            if let OpCode::OpClosure(OpMode::ABX(_, bx)) = inst.op.clone() {
-                let index: usize = bx as usize;
-                if !was_proto_printed_map[index] {
-                    was_proto_printed_map[index] = true;
-                    self.print_proto(proto.protos[index].clone(), just_describes);
+            if let Some(cfg) = &cfg {
+                let block: usize = cfg.block_at(i).expect("every instruction is in a block");
+                if cfg.blocks[block].start == i && self.print_blocks {
+                    let format_edges = |edges: &[usize]| match edges.is_empty() {
+                        true => String::from("none"),
+                        false => edges
+                            .iter()
+                            .map(|edge| edge.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", "),
+                    };
+                    let live: String = match &liveness {
+                        Some(liveness) => format!(
+                            "; live in: {}; live out: {}",
+                            format_regs(&liveness.live_in[block]),
+                            format_regs(&liveness.live_out[block])
+                        ),
+                        None => String::new(),
+                    };
+                    let header: String = format!(
+                        "-- block {} (predecessors: {}; successors: {}{})",
+                        block,
+                        format_edges(&cfg.blocks[block].predecessors),
+                        format_edges(&cfg.blocks[block].successors),
+                        live
+                    );
+                    code_text[i].push(self.paint(Style::Comment, &header));
                 }
             }
+            let describe: String = self.highlight(&code_op_describes[i], &local_names);
             if just_describes {
-                self.print_text(code_op_describes[i].clone());
+                code_text[i].push(match live_notes[i].is_empty() {
+                    true => describe,
+                    false => format!(
+                        "{}  {}",
+                        describe,
+                        self.paint(Style::Comment, &format!("--{}", live_notes[i]))
+                    ),
+                });
             } else {
-                self.print_text(format!(
-                    "{:<width_index$}{:<width_strings$}  --  {}",
-                    i,
+                // padded before painting, escape codes would count towards the width
+                let op_string: String = format!(
+                    "{:<width_strings$}",
                     code_op_strings[i],
-                    code_op_describes[i],
+                    width_strings = max_op_strings_width
+                );
+                let mnemonic_end: usize = op_string.find('(').unwrap_or(op_string.len());
+                code_text[i].push(format!(
+                    "{:<width_index$}{:<width_line$}{}{}  {}  {}{}",
+                    pcs[i],
+                    code_lines[i],
+                    self.paint(Style::Mnemonic, &op_string[..mnemonic_end]),
+                    &op_string[mnemonic_end..],
+                    self.paint(Style::Comment, "--"),
+                    describe,
+                    self.paint(Style::Comment, &live_notes[i]),
                     width_index = max_index_width,
-                    width_strings = max_op_strings_width,
-                ))
+                    width_line = max_line_width,
+                ));
+            }
+        }
+
+        // pseudo code of a 5.1 proto nests its instructions in the statements the decompiler
+        // recovers, and keeps its gotos when the jumps don't fit together
+        let structure: Option<Block> =
+            match just_describes && !self.print_blocks && self.version == LuaVersion::Lua51 {
+                true => structure_proto(&proto),
+                false => None,
+            };
+        let mut listing: Listing = Listing {
+            proto: &proto,
+            describes: &code_op_describes,
+            code_text: &code_text,
+            local_names: &local_names,
+            was_proto_printed_map: &mut was_proto_printed_map,
+            just_describes,
+        };
+        match structure {
+            Some(block) => self.print_nodes(&mut listing, &block.nodes),
+            None => {
+                for i in 0..code_len {
+                    self.print_instruction(&mut listing, i);
+                }
             }
         }
 
-        for i in 0..proto.protos.len() {
-            let proto: Proto = proto.protos[i].clone();
-            if !was_proto_printed_map[i] {
-                self.print_proto(proto, just_describes);
+        for (i, was_printed) in was_proto_printed_map.iter().enumerate() {
+            if !was_printed {
+                self.print_proto(proto.protos[i].clone(), just_describes);
             }
         }
 
@@ -816,4 +4478,644 @@ impl Bytecode {
             self.print_text_str("end")
         }
     }
+
+    fn print_instruction(&mut self, listing: &mut Listing, i: usize) {
+        let proto: &Proto = listing.proto;
+        let closure_index: Option<LuaInt> = match &proto.code[i].op {
+            OpCode::OpClosure(OpMode::ABX(_, index) | OpMode::AD(_, index)) => Some(*index),
+            // Luau shares closures without upvalues through the constant table,
+            // LuaJIT keeps every child there
+            OpCode::OpDupClosure(OpMode::AD(_, d)) | OpCode::OpFNew(OpMode::AD(_, d)) => {
+                match proto.constants.get(*d as usize) {
+                    Some(Constant::Closure(index)) => Some(*index),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(index) = closure_index {
+            let index: usize = index as usize;
+            // a malformed chunk can name a child that isn't there
+            if index < listing.was_proto_printed_map.len() && !listing.was_proto_printed_map[index]
+            {
+                listing.was_proto_printed_map[index] = true;
+                self.print_proto(proto.protos[index].clone(), listing.just_describes);
+            }
+        }
+        for text in &listing.code_text[i] {
+            self.print_text(text.clone());
+        }
+    }
+
+    fn print_block(&mut self, listing: &mut Listing, nodes: &[Node]) {
+        self.indent += 1;
+        self.print_nodes(listing, nodes);
+        self.indent -= 1;
+    }
+
+    fn print_nodes(&mut self, listing: &mut Listing, nodes: &[Node]) {
+        let proto: &Proto = listing.proto;
+        let describes: &[String] = listing.describes;
+        let names: &[String] = listing.local_names;
+        for node in nodes {
+            match node {
+                Node::Code(start, end) => {
+                    for i in *start..*end {
+                        self.print_instruction(listing, i);
+                    }
+                }
+                Node::Value(chain) => {
+                    let text: String = format_value(proto, describes, chain);
+                    self.print_text(self.highlight(&text, names));
+                }
+                Node::If {
+                    condition,
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    let text: String =
+                        format!("if {} then", format_condition(proto, describes, condition));
+                    self.print_text(self.highlight(&text, names));
+                    self.print_block(listing, &then_block.nodes);
+                    let mut else_block: &Option<Block> = else_block;
+                    while let Some(block) = else_block {
+                        // an else holding nothing but another if, with the code of its first
+                        // operand, comes out as elseif
+                        let (code, nested): (&[Node], &Node) = match block.nodes.as_slice() {
+                            [nested @ Node::If { .. }] => (&[], nested),
+                            [code @ Node::Code(_, _), nested @ Node::If { .. }] => {
+                                (std::slice::from_ref(code), nested)
+                            }
+                            _ => {
+                                self.print_text_str("else");
+                                self.print_block(listing, &block.nodes);
+                                break;
+                            }
+                        };
+                        if let Node::If {
+                            condition,
+                            then_block,
+                            else_block: next,
+                            ..
+                        } = nested
+                        {
+                            let text: String = format!(
+                                "elseif {} then",
+                                with_operand_code(
+                                    operand_code(proto, describes, code),
+                                    format_condition(proto, describes, condition)
+                                )
+                            );
+                            self.print_text(self.highlight(&text, names));
+                            self.print_block(listing, &then_block.nodes);
+                            else_block = next;
+                        }
+                    }
+                    self.print_text_str("end");
+                }
+                Node::While {
+                    condition, body, ..
+                } => match condition {
+                    Some((before, condition)) if before.nodes.is_empty() => {
+                        let text: String =
+                            format!("while {} do", format_condition(proto, describes, condition));
+                        self.print_text(self.highlight(&text, names));
+                        self.print_block(listing, &body.nodes);
+                        self.print_text_str("end");
+                    }
+                    // the code of the first operand runs before every check
+                    Some((before, condition)) => {
+                        self.print_text_str("while true do");
+                        self.print_block(listing, &before.nodes);
+                        self.indent += 1;
+                        let text: String = format!(
+                            "if not ({}) then break end",
+                            format_condition(proto, describes, condition)
+                        );
+                        self.print_text(self.highlight(&text, names));
+                        self.indent -= 1;
+                        self.print_block(listing, &body.nodes);
+                        self.print_text_str("end");
+                    }
+                    None => {
+                        self.print_text_str("while true do");
+                        self.print_block(listing, &body.nodes);
+                        self.print_text_str("end");
+                    }
+                },
+                Node::Repeat {
+                    body, condition, ..
+                } => {
+                    self.print_text_str("repeat");
+                    self.print_block(listing, &body.nodes);
+                    let text: String =
+                        format!("until {}", format_condition(proto, describes, condition));
+                    self.print_text(self.highlight(&text, names));
+                }
+                Node::NumericFor { prep, body, .. } => {
+                    let text: String = match &proto.code[*prep].op {
+                        OpCode::OpForPrep(OpMode::ASBX(a, _)) => {
+                            let pc: isize = proto.pcs()[*prep];
+                            format!(
+                                "for {} = {}, {}, {} do",
+                                REG_FMT!(a + 3, proto, pc + 1),
+                                REG_FMT!(*a, proto, pc),
+                                REG_FMT!(a + 1, proto, pc),
+                                REG_FMT!(a + 2, proto, pc)
+                            )
+                        }
+                        _ => String::from("for [INVALID FORPREP] do"),
+                    };
+                    self.print_text(self.highlight(&text, names));
+                    self.print_block(listing, &body.nodes);
+                    self.print_text_str("end");
+                }
+                Node::GenericFor {
+                    prep, body, call, ..
+                } => {
+                    let text: String = match &proto.code[*call].op {
+                        OpCode::OpTForLoop(OpMode::ABC(a, _, c)) => {
+                            let pc: isize = proto.pcs()[*prep];
+                            format!(
+                                "for {} in {}, {}, {} do",
+                                SIMPLE_REG_LIST!(a + 3, a + 2 + c.max(&1), proto, pc + 1),
+                                REG_FMT!(*a, proto, pc),
+                                REG_FMT!(a + 1, proto, pc),
+                                REG_FMT!(a + 2, proto, pc)
+                            )
+                        }
+                        _ => String::from("for [INVALID TFORLOOP] do"),
+                    };
+                    self.print_text(self.highlight(&text, names));
+                    self.print_block(listing, &body.nodes);
+                    self.print_text_str("end");
+                }
+                Node::Break(_) => self.print_text_str("break"),
+            }
+        }
+    }
+}
+
+// what print_proto has worked out about a proto while it prints its code
+struct Listing<'a> {
+    proto: &'a Proto,
+    describes: &'a [String],
+    code_text: &'a [Vec<String>],
+    local_names: &'a [String],
+    was_proto_printed_map: &'a mut Vec<bool>,
+    just_describes: bool,
+}
+
+// the describes of the code of an operand other than the first, which runs as part of the
+// condition
+fn operand_code(proto: &Proto, describes: &[String], nodes: &[Node]) -> Vec<String> {
+    let mut code: Vec<String> = Vec::new();
+    for node in nodes {
+        match node {
+            Node::Code(start, end) => code.extend(describes[*start..*end].iter().cloned()),
+            Node::Value(chain) => code.push(format_value(proto, describes, chain)),
+            _ => code.push(String::from("[INVALID OPERAND]")),
+        }
+    }
+    code
+}
+
+fn with_operand_code(code: Vec<String>, value: String) -> String {
+    match code.is_empty() {
+        true => value,
+        false => format!("({}; {})", code.join("; "), value),
+    }
+}
+
+fn format_condition(proto: &Proto, describes: &[String], chain: &Chain) -> String {
+    format_shape(proto, describes, chain, &chain.shape)
+}
+
+// a value made with and/or as the register it's left in being assigned the whole of it
+fn format_value(proto: &Proto, describes: &[String], chain: &Chain) -> String {
+    let target: String = match chain.reg {
+        Some(reg) => proto.target_name(reg as LuaInt, proto.pcs()[chain.end - 1]),
+        None => String::from("[INVALID VALUE]"),
+    };
+    format!(
+        "{} = {}",
+        target,
+        format_shape(proto, describes, chain, &chain.shape)
+    )
+}
+
+fn format_shape(proto: &Proto, describes: &[String], chain: &Chain, shape: &Shape) -> String {
+    match shape {
+        Shape::Test(index, jump) => {
+            let (block, test): &(Block, usize) = &chain.tests[*index];
+            let condition: String = proto.code[*test].op.describe_test(
+                &proto.constants,
+                proto,
+                proto.pcs()[*test],
+                *jump,
+            );
+            // the code of the first operand is left to the statement before
+            match index {
+                0 => condition,
+                _ => with_operand_code(operand_code(proto, describes, &block.nodes), condition),
+            }
+        }
+        Shape::Tail => match (&chain.tail, chain.reg) {
+            (Some(block), Some(reg)) => {
+                let name: String = proto.target_name(reg as LuaInt, proto.pcs()[block.end - 1]);
+                let mut code: Vec<String> = operand_code(proto, describes, &block.nodes);
+                // a last operand computed by a single instruction is just what that assigns
+                let assigned: Option<String> = match code.as_slice() {
+                    [describe] => describe
+                        .strip_prefix(&format!("{} = ", name))
+                        .map(|value| value.to_string()),
+                    _ => None,
+                };
+                match assigned {
+                    Some(value) => value,
+                    None => {
+                        code.push(name);
+                        format!("({})", code.join("; "))
+                    }
+                }
+            }
+            _ => String::from("[INVALID VALUE]"),
+        },
+        Shape::And(left, right) | Shape::Or(left, right) => {
+            let is_and: bool = matches!(shape, Shape::And(_, _));
+            // and binds tighter than or, and both are left associative
+            let left_text: String = format_shape(proto, describes, chain, left);
+            let left_text: String = match (is_and, left.as_ref()) {
+                (true, Shape::Or(_, _)) => format!("({})", left_text),
+                _ => left_text,
+            };
+            let right_text: String = format_shape(proto, describes, chain, right);
+            let right_text: String = match (is_and, right.as_ref()) {
+                (_, Shape::Or(_, _)) | (true, Shape::And(_, _)) => format!("({})", right_text),
+                _ => right_text,
+            };
+            format!(
+                "{} {} {}",
+                left_text,
+                match is_and {
+                    true => "and",
+                    false => "or",
+                },
+                right_text
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::read::build_reader;
+
+    // each compiled from the .lua file of the same name in tests/fixtures
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.luac");
+    const LUA52: &[u8] = include_bytes!("../tests/fixtures/lua52.luac");
+    const LUA53: &[u8] = include_bytes!("../tests/fixtures/lua53.luac");
+    const LUA54: &[u8] = include_bytes!("../tests/fixtures/lua54.luac");
+    const LUAU: &[u8] = include_bytes!("../tests/fixtures/luau.luauc");
+    const LUAJIT: &[u8] = include_bytes!("../tests/fixtures/luajit.ljbc");
+    const EXPRS: &[u8] = include_bytes!("../tests/fixtures/exprs.luac");
+
+    fn read(bytes: &[u8]) -> Bytecode {
+        match build_reader(&bytes.to_vec()).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn describe_at(bytecode: &Bytecode, proto: &Proto, pc: usize) -> String {
+        proto.code[pc].op.describe(
+            &proto.constants,
+            proto,
+            &proto.protos,
+            pc as isize,
+            bytecode.version,
+        )
+    }
+
+    #[test]
+    fn describes_lua52() {
+        let bytecode: Bytecode = read(LUA52);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(describe_at(&bytecode, main, 0), "greeting = \"hello\"");
+        assert_eq!(
+            describe_at(&bytecode, main, 1),
+            "shout = proto_0 -- upvalues: greeting = greeting"
+        );
+        assert_eq!(describe_at(&bytecode, main, 12), "t[i] = r_7");
+        assert_eq!(describe_at(&bytecode, main, 14), "goto 18");
+        // _ENV is the only upvalue of main, so globals go through it
+        assert_eq!(describe_at(&bytecode, main, 15), "r_3 = print");
+        assert_eq!(describe_at(&bytecode, &main.protos[0], 0), "r_1 = greeting");
+    }
+
+    #[test]
+    fn describes_lua53() {
+        let bytecode: Bytecode = read(LUA53);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(describe_at(&bytecode, main, 3), "r_3 = a ~ b");
+        assert_eq!(describe_at(&bytecode, main, 4), "r_3 = r_3 << 2");
+        assert_eq!(describe_at(&bytecode, main, 7), "half = r_3 + 0.25");
+        assert_eq!(describe_at(&bytecode, main, 8), "whole = a // b");
+        assert_eq!(describe_at(&bytecode, main, 9), "big = 9007199254740993");
+        assert_eq!(describe_at(&bytecode, main, 14), "r_10 = ~a");
+    }
+
+    #[test]
+    fn describes_lua54() {
+        let bytecode: Bytecode = read(LUA54);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(
+            describe_at(&bytecode, main, 7),
+            "bump = proto_0 -- upvalues: count = count, names = names"
+        );
+        assert_eq!(describe_at(&bytecode, main, 9), "mark guard to be closed");
+        assert_eq!(
+            describe_at(&bytecode, main, 13),
+            "close all variables in the stack up to r_3"
+        );
+        assert_eq!(describe_at(&bytecode, main, 16), "value = r_3(r_4)");
+        let bump: &Proto = &main.protos[0];
+        assert_eq!(describe_at(&bytecode, bump, 3), "r_1 = r_1 + 10");
+        assert_eq!(
+            describe_at(&bytecode, bump, 4),
+            "metamethod __add fallback over r_1 and 10"
+        );
+    }
+
+    #[test]
+    fn describes_luau() {
+        let bytecode: Bytecode = read(LUAU);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(describe_at(&bytecode, main, 1), "area = proto_0");
+        assert_eq!(describe_at(&bytecode, main, 3), "r_2 = print");
+        assert_eq!(describe_at(&bytecode, main, 4), "aux word 0x40200000");
+        assert_eq!(
+            describe_at(&bytecode, main, 11),
+            "r_5 = r_4; r_4 = r_4.format"
+        );
+        assert_eq!(
+            describe_at(&bytecode, main, 13),
+            "r_4 = r_4:format(r_6 ... r_7)"
+        );
+        let area: &Proto = &main.protos[0];
+        assert_eq!(
+            describe_at(&bytecode, area, 1),
+            "try math.floor(r_3) inline; goto 5 if it succeeds"
+        );
+        assert_eq!(describe_at(&bytecode, area, 2), "r_2 = math.floor");
+    }
+
+    #[test]
+    fn describes_luajit() {
+        let bytecode: Bytecode = read(LUAJIT);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(
+            describe_at(&bytecode, main, 0),
+            "point = {\"first\", [\"x\"] = 1, [\"y\"] = 2}"
+        );
+        assert_eq!(describe_at(&bytecode, main, 2), "big = 3000000000");
+        assert_eq!(describe_at(&bytecode, main, 3), "mid = 100000 - big");
+        assert_eq!(
+            describe_at(&bytecode, main, 4),
+            "make = proto_0 -- upvalues: scale = scale, big = big"
+        );
+        let inner: &Proto = &main.protos[0].protos[0];
+        assert_eq!(describe_at(&bytecode, inner, 1), "r_1 = scale");
+        assert_eq!(describe_at(&bytecode, inner, 2), "r_0 = r_0 * r_1");
+        assert_eq!(describe_at(&bytecode, inner, 5), "return r_0");
+    }
+
+    #[test]
+    fn name_keys_print_as_fields() {
+        let pseudo: String = read(SAMPLE).disassembly(true);
+        let lines: Vec<&str> = pseudo.lines().map(|line| line.trim()).collect();
+        for line in [
+            "format = r_0.format",
+            "r_3[10] = \"ten\"",
+            "r_3.key = \"value\"",
+            "r_4.name = \"obj\\0\\n\\\"quoted\\\"\"",
+            "name = self.name",
+            "obj.describe = r_5",
+            "r_6 = obj; r_5 = obj.describe",
+        ] {
+            assert!(lines.contains(&line), "{} missing from\n{}", line, pseudo);
+        }
+        assert!(!pseudo.contains("[\"name\"]"));
+    }
+
+    #[test]
+    fn pcs_count_setlist_aux_words() {
+        // a C over 9 bits goes in the word after the SETLIST, so every pc after it is one
+        // ahead of the index
+        let mut bytecode: Bytecode = match build_assembler(
+            r#"
+            .line 1
+                NEWTABLE r0, 0, 0
+            .line 2
+                SETLIST r0, 1, 600
+            .line 3
+                MOVE r1, r0
+            .line 4
+                JMP done
+            .line 5
+                LOADK r1, 5
+            done:
+            .line 6
+                RETURN r1, 2
+            .local t, 3, 7
+            "#,
+        )
+        .assemble()
+        {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(main.code[1].aux, Some(600));
+        assert_eq!(main.pcs(), [0, 1, 3, 4, 5, 6]);
+        assert_eq!(main.index_at(2), 2);
+        assert_eq!(main.index_at(4), 3);
+
+        let listing: String = bytecode.disassembly(false);
+        let lines: Vec<&str> = listing.lines().map(|line| line.trim()).collect();
+        for (start, end) in [
+            ("3  [3]  OpMove", "r_1 = t"),
+            ("4  [4]  OpJmp", "goto 6"),
+            ("6  [6]  OpReturn", "return r_1"),
+        ] {
+            assert!(
+                lines
+                    .iter()
+                    .any(|line| line.starts_with(start) && line.ends_with(end)),
+                "{} ... {} missing from\n{}",
+                start,
+                end,
+                listing
+            );
+        }
+    }
+
+    #[test]
+    fn dot_has_a_cluster_per_proto() {
+        let dot: String = read(SAMPLE).dot();
+        let lines: Vec<&str> = dot.lines().map(|line| line.trim()).collect();
+        assert_eq!(lines.first(), Some(&"digraph main {"));
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.starts_with("digraph"))
+                .count(),
+            1
+        );
+        for cluster in ["main", "proto_0", "proto_1", "proto_1_0"] {
+            let header: String = format!("subgraph cluster_{} {{", cluster);
+            assert!(
+                lines.contains(&header.as_str()),
+                "{} missing from\n{}",
+                header,
+                dot
+            );
+        }
+        // every block is a node listing what describe gives its instructions
+        assert!(lines
+            .iter()
+            .any(|line| line
+                .starts_with("main_b0 [label=\"0  r_0 = string\\l1  format = r_0.format\\l")));
+        let closure: &str = match lines.iter().find(|line| line.starts_with("proto_1_0_b0 ")) {
+            Some(line) => line,
+            None => panic!("proto_1_0_b0 missing from\n{}", dot),
+        };
+        assert!(closure.contains("\\l1  r_1 = \\\"%s %s\\\"\\l2  r_2 = prefix\\l"));
+        assert!(closure.ends_with("\\l7  return r_0 ... r_1\\l\"];"));
+        for edge in [
+            "main_b0 -> main_b9 [label=\"jump\"];",
+            "main_b1 -> main_b3 [label=\"true\"];",
+            "main_b1 -> main_b2 [label=\"false\"];",
+            "main_b11 -> main_b12 [label=\"fallthrough\"];",
+            "main_b9 -> main_b1 [label=\"loop-back\"];",
+        ] {
+            assert!(lines.contains(&edge), "{} missing from\n{}", edge, dot);
+        }
+        // edges stay inside their proto
+        for line in lines.iter().filter(|line| line.contains(" -> ")) {
+            let (from, to): (&str, &str) = line.split_once(" -> ").unwrap();
+            let proto: &str = &from[..from.rfind("_b").unwrap()];
+            assert!(to.starts_with(&format!("{}_b", proto)), "{}", line);
+        }
+    }
+
+    fn assemble(text: &str) -> Bytecode {
+        match build_assembler(text).assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    #[test]
+    fn open_tops_name_their_producer() {
+        let pseudo: String = read(EXPRS).disassembly(true);
+        let lines: Vec<&str> = pseudo.lines().map(|line| line.trim()).collect();
+        for line in [
+            "return r_7 ... r_9, ...",
+            "r_1[1 ...] = ...",
+            "r_2[1 ...] = r_3(...)",
+            "r_3 = r_3(r_4 ... r_6, r_7(r_8 ... r_9))",
+        ] {
+            assert!(lines.contains(&line), "{} missing from\n{}", line, pseudo);
+        }
+        assert!(!pseudo.contains("???"), "{}", pseudo);
+
+        // the same VARARG whichever way the TEST goes
+        let bytecode: Bytecode = assemble(
+            "    VARARG r1, 0\n    TEST r0, 0\n    JMP skip\n    MOVE r0, r0\nskip:\n    RETURN r1, 0\n",
+        );
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(top_producer(main, 4, bytecode.version), Some((0, 1)));
+        assert_eq!(describe_at(&bytecode, main, 4), "return ...");
+
+        // a different one on each path
+        let bytecode: Bytecode = assemble(
+            "    TEST r0, 0\n    JMP other\n    VARARG r1, 0\n    JMP done\nother:\n    CALL r1, 1, 0\ndone:\n    RETURN r1, 0\n",
+        );
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(top_producer(main, 5, bytecode.version), None);
+        assert_eq!(describe_at(&bytecode, main, 5), "return top ... ???");
+
+        // the SETLIST already took what the VARARG left
+        let bytecode: Bytecode =
+            assemble("    VARARG r1, 0\n    SETLIST r0, 0, 1\n    RETURN r1, 0\n");
+        assert_eq!(
+            top_producer(&bytecode.main_proto, 2, bytecode.version),
+            None
+        );
+
+        // a TAILCALL of a C function runs on to the RETURN with its results
+        let bytecode: Bytecode = assemble("    TAILCALL r0, 1, 0\n    RETURN r0, 0\n");
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(top_producer(main, 1, bytecode.version), Some((0, 0)));
+        assert_eq!(describe_at(&bytecode, main, 1), "return r_0()");
+    }
+
+    #[test]
+    fn psuedo_nests_control_flow() {
+        let pseudo: String = read(SAMPLE).disassembly(true);
+        let lines: Vec<&str> = pseudo.lines().collect();
+        let contains = |expected: &[&str]| {
+            assert!(
+                lines
+                    .windows(expected.len())
+                    .any(|window| window == expected),
+                "{:?} missing from\n{}",
+                expected,
+                pseudo
+            );
+        };
+        // the elseif and break in the numeric for
+        contains(&[
+            "for i = r_4, r_5, r_6 do",
+            "    r_8 = type",
+            "    r_9 = list[i]",
+            "    r_8 = r_8(r_9)",
+            "    if r_8 == \"number\" and (r_8 = list[i]; 1 < r_8) then",
+            "        r_8 = list[i]",
+            "        r_8 = r_8 * 2",
+            "        r_8 = r_8 - 0.25",
+            "        list[i] = r_8",
+            "    elseif (r_8 = list[i]; not r_8) then",
+            "        break",
+            "    end",
+            "end",
+        ]);
+        contains(&["for k ... v in r_4, r_5, r_6 do"]);
+        contains(&[
+            "while counter < 100 do",
+            "    r_5 = bump",
+            "    r_6 = 10 < counter and (r_6 = 7; r_6) or 3",
+        ]);
+        contains(&[
+            "    if counter == 50 or counter <= 20 then",
+            "        counter = counter + 1",
+            "    end",
+            "end",
+        ]);
+        contains(&["repeat", "    done = 0 <= counter", "until done"]);
+        assert!(!pseudo.contains("goto"), "{}", pseudo);
+    }
+
+    #[test]
+    fn psuedo_blocks_stay_flat() {
+        let mut bytecode: Bytecode = read(SAMPLE);
+        bytecode.print_blocks = true;
+        let pseudo: String = bytecode.disassembly(true);
+        assert!(pseudo.contains("goto"), "{}", pseudo);
+        assert!(!pseudo.contains("repeat"), "{}", pseudo);
+    }
 }
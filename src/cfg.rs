@@ -0,0 +1,338 @@
+use crate::bytecode::{Instruction, LuaVersion, OpCode, OpMode, Proto};
+
+// how control leaves an instruction, targets are pcs like the ones jumps count in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Next,
+    Jump(isize),
+    // either falls through or goes to the target
+    Branch(isize),
+    Exit,
+}
+
+pub fn instruction_flow(op: &OpCode, pc: isize, version: LuaVersion) -> Flow {
+    match version {
+        LuaVersion::Luau => instruction_flow_luau(op, pc),
+        LuaVersion::LuaJit => instruction_flow_luajit(op, pc),
+        _ => instruction_flow_lua(op, pc),
+    }
+}
+
+// 5.1 to 5.4, the modes tell apart the opcodes that changed meaning between versions
+fn instruction_flow_lua(op: &OpCode, pc: isize) -> Flow {
+    match op {
+        OpCode::OpJmp(OpMode::ASBX(_, sbx)) => Flow::Jump(pc + 1 + *sbx as isize),
+        OpCode::OpJmp(OpMode::SJ(sj)) => Flow::Jump(pc + 1 + *sj as isize),
+        // comparisons and tests skip the JMP after them when they don't hold
+        OpCode::OpEq(_)
+        | OpCode::OpLt(_)
+        | OpCode::OpLe(_)
+        | OpCode::OpEqK(_)
+        | OpCode::OpEqI(_)
+        | OpCode::OpLtI(_)
+        | OpCode::OpLeI(_)
+        | OpCode::OpGtI(_)
+        | OpCode::OpGeI(_)
+        | OpCode::OpTest(_)
+        | OpCode::OpTestSet(_) => Flow::Branch(pc + 2),
+        OpCode::OpLoadBool(OpMode::ABC(_, _, c)) if *c != 0 => Flow::Jump(pc + 2),
+        OpCode::OpLFalseSkip(_) => Flow::Jump(pc + 2),
+        OpCode::OpForLoop(OpMode::ASBX(_, sbx)) => Flow::Branch(pc + 1 + *sbx as isize),
+        OpCode::OpForPrep(OpMode::ASBX(_, sbx)) => Flow::Jump(pc + 1 + *sbx as isize),
+        // 5.4 counts loop jumps with an unsigned Bx
+        OpCode::OpForLoop(OpMode::ABX(_, bx)) => Flow::Branch(pc + 1 - *bx as isize),
+        OpCode::OpForPrep(OpMode::ABX(_, bx)) => Flow::Branch(pc + 2 + *bx as isize),
+        OpCode::OpTForPrep(OpMode::ABX(_, bx)) => Flow::Jump(pc + 1 + *bx as isize),
+        // 5.1 skips the JMP back once the iterator returns nil
+        OpCode::OpTForLoop(OpMode::ABC(_, _, _)) => Flow::Branch(pc + 2),
+        OpCode::OpTForLoop(OpMode::ASBX(_, sbx)) => Flow::Branch(pc + 1 + *sbx as isize),
+        OpCode::OpTForLoop(OpMode::ABX(_, bx)) => Flow::Branch(pc + 1 - *bx as isize),
+        OpCode::OpReturn(_)
+        | OpCode::OpReturn0(_)
+        | OpCode::OpReturn1(_)
+        | OpCode::OpTailCall(_) => Flow::Exit,
+        _ => Flow::Next,
+    }
+}
+
+fn instruction_flow_luau(op: &OpCode, pc: isize) -> Flow {
+    match op {
+        OpCode::OpJump(OpMode::AD(_, d)) | OpCode::OpJumpBack(OpMode::AD(_, d)) => {
+            Flow::Jump(pc + 1 + *d as isize)
+        }
+        OpCode::OpJumpX(OpMode::E(e)) => Flow::Jump(pc + 1 + *e as isize),
+        OpCode::OpJumpIf(OpMode::AD(_, d))
+        | OpCode::OpJumpIfNot(OpMode::AD(_, d))
+        | OpCode::OpJumpIfEq(OpMode::AD(_, d))
+        | OpCode::OpJumpIfLe(OpMode::AD(_, d))
+        | OpCode::OpJumpIfLt(OpMode::AD(_, d))
+        | OpCode::OpJumpIfNotEq(OpMode::AD(_, d))
+        | OpCode::OpJumpIfNotLe(OpMode::AD(_, d))
+        | OpCode::OpJumpIfNotLt(OpMode::AD(_, d))
+        | OpCode::OpJumpXEqKNil(OpMode::AD(_, d))
+        | OpCode::OpJumpXEqKB(OpMode::AD(_, d))
+        | OpCode::OpJumpXEqKN(OpMode::AD(_, d))
+        | OpCode::OpJumpXEqKS(OpMode::AD(_, d))
+        | OpCode::OpForNPrep(OpMode::AD(_, d))
+        | OpCode::OpForNLoop(OpMode::AD(_, d))
+        | OpCode::OpForGLoop(OpMode::AD(_, d)) => Flow::Branch(pc + 1 + *d as isize),
+        OpCode::OpForGPrep(OpMode::AD(_, d))
+        | OpCode::OpForGPrepINext(OpMode::AD(_, d))
+        | OpCode::OpForGPrepNext(OpMode::AD(_, d)) => Flow::Jump(pc + 1 + *d as isize),
+        OpCode::OpLoadB(OpMode::ABC(_, _, c)) if *c != 0 => Flow::Jump(pc + 1 + *c as isize),
+        // a builtin that succeeds skips ahead past the CALL it stands in for
+        OpCode::OpFastCall(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall1(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall2(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall2K(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall3(OpMode::ABC(_, _, c)) => Flow::Branch(pc + 2 + *c as isize),
+        OpCode::OpReturn(_) => Flow::Exit,
+        _ => Flow::Next,
+    }
+}
+
+fn instruction_flow_luajit(op: &OpCode, pc: isize) -> Flow {
+    match op {
+        // comparisons run the JMP after them when they hold and skip it otherwise
+        OpCode::OpIsLt(_)
+        | OpCode::OpIsGe(_)
+        | OpCode::OpIsLe(_)
+        | OpCode::OpIsGt(_)
+        | OpCode::OpIsEqV(_)
+        | OpCode::OpIsNeV(_)
+        | OpCode::OpIsEqS(_)
+        | OpCode::OpIsNeS(_)
+        | OpCode::OpIsEqN(_)
+        | OpCode::OpIsNeN(_)
+        | OpCode::OpIsEqP(_)
+        | OpCode::OpIsNeP(_)
+        | OpCode::OpIsTC(_)
+        | OpCode::OpIsFC(_)
+        | OpCode::OpIsT(_)
+        | OpCode::OpIsF(_) => Flow::Branch(pc + 2),
+        OpCode::OpJmp(OpMode::AD(_, d))
+        | OpCode::OpUClo(OpMode::AD(_, d))
+        | OpCode::OpIsNext(OpMode::AD(_, d)) => Flow::Jump(pc + 1 + *d as isize),
+        OpCode::OpForI(OpMode::AD(_, d))
+        | OpCode::OpJForI(OpMode::AD(_, d))
+        | OpCode::OpForL(OpMode::AD(_, d))
+        | OpCode::OpIForL(OpMode::AD(_, d))
+        | OpCode::OpIterL(OpMode::AD(_, d))
+        | OpCode::OpIIterL(OpMode::AD(_, d)) => Flow::Branch(pc + 1 + *d as isize),
+        OpCode::OpRet(_)
+        | OpCode::OpRetM(_)
+        | OpCode::OpRet0(_)
+        | OpCode::OpRet1(_)
+        | OpCode::OpCallT(_)
+        | OpCode::OpCallMT(_) => Flow::Exit,
+        // LOOP only marks where the JIT may start recording, the interpreter runs through it
+        _ => Flow::Next,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: usize, // index into Proto.code of the first instruction
+    pub end: usize,   // index just past the last one
+    // where the block goes when its last instruction runs on, and where it jumps to
+    pub fallthrough: Option<usize>,
+    pub jump: Option<usize>,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    // in code order, the entry block is the first one
+    pub blocks: Vec<BasicBlock>,
+    block_of: Vec<usize>,
+}
+
+impl Cfg {
+    pub fn block_at(&self, index: usize) -> Option<usize> {
+        self.block_of.get(index).copied()
+    }
+}
+
+// the AUX words Luau keeps as instructions belong to the instruction before them
+fn is_aux(inst: &Instruction) -> bool {
+    matches!(inst.op, OpCode::OpAux(_))
+}
+
+pub fn build_cfg(proto: &Proto, version: LuaVersion) -> Cfg {
+    let code: &[Instruction] = &proto.code;
+    let code_len: usize = code.len();
+
+    // jumps count words, and a 5.1 SETLIST folds its aux word into one instruction
+    let mut pcs: Vec<isize> = Vec::with_capacity(code_len);
+    let mut pc: isize = 0;
+    for inst in code {
+        pcs.push(pc);
+        pc += match version == LuaVersion::Lua51 && inst.aux.is_some() {
+            true => 2,
+            false => 1,
+        };
+    }
+    let index_of = |target: isize| -> Option<usize> { pcs.binary_search(&target).ok() };
+    // past the AUX words of the instruction at index
+    let next_of = |index: usize| -> usize {
+        let mut next: usize = index + 1;
+        while next < code_len && is_aux(&code[next]) {
+            next += 1;
+        }
+        next
+    };
+
+    let flows: Vec<Flow> = code
+        .iter()
+        .zip(&pcs)
+        .map(|(inst, pc)| instruction_flow(&inst.op, *pc, version))
+        .collect();
+
+    let mut is_leader: Vec<bool> = vec![false; code_len];
+    if let Some(first) = is_leader.first_mut() {
+        *first = true;
+    }
+    for (index, flow) in flows.iter().enumerate() {
+        let target: Option<isize> = match flow {
+            Flow::Next => continue,
+            Flow::Jump(target) | Flow::Branch(target) => Some(*target),
+            Flow::Exit => None,
+        };
+        // jumps out of the function are left for the verifier to report
+        if let Some(target) = target.and_then(index_of) {
+            is_leader[target] = true;
+        }
+        if let Some(next) = is_leader.get_mut(next_of(index)) {
+            *next = true;
+        }
+    }
+    // AUX words can't start a block, whatever points at them
+    for (index, inst) in code.iter().enumerate() {
+        if is_aux(inst) && index > 0 {
+            is_leader[index] = false;
+        }
+    }
+
+    let mut blocks: Vec<BasicBlock> = Vec::new();
+    let mut block_of: Vec<usize> = Vec::with_capacity(code_len);
+    for (index, leader) in is_leader.iter().enumerate() {
+        if *leader {
+            blocks.push(BasicBlock {
+                start: index,
+                end: index,
+                fallthrough: None,
+                jump: None,
+                successors: Vec::new(),
+                predecessors: Vec::new(),
+            });
+        }
+        let block: usize = blocks.len() - 1;
+        blocks[block].end = index + 1;
+        block_of.push(block);
+    }
+
+    for block in 0..blocks.len() {
+        let end: usize = blocks[block].end;
+        let last: usize = match (blocks[block].start..end)
+            .rev()
+            .find(|i| !is_aux(&code[*i]))
+        {
+            Some(last) => last,
+            None => continue,
+        };
+        let next: Option<usize> = block_of.get(end).copied();
+        let target_block =
+            |target: isize| -> Option<usize> { index_of(target).map(|index| block_of[index]) };
+        let (fallthrough, jump): (Option<usize>, Option<usize>) = match flows[last] {
+            Flow::Next => (next, None),
+            Flow::Jump(target) => (None, target_block(target)),
+            Flow::Branch(target) => (next, target_block(target)),
+            Flow::Exit => (None, None),
+        };
+        blocks[block].fallthrough = fallthrough;
+        blocks[block].jump = jump;
+        for successor in [fallthrough, jump].into_iter().flatten() {
+            if !blocks[block].successors.contains(&successor) {
+                blocks[block].successors.push(successor);
+                blocks[successor].predecessors.push(block);
+            }
+        }
+    }
+
+    Cfg { blocks, block_of }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::bytecode::Bytecode;
+
+    fn cfg_of(text: &str) -> Cfg {
+        let bytecode: Bytecode = match build_assembler(text).assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        build_cfg(&bytecode.main_proto, LuaVersion::Lua51)
+    }
+
+    fn edges(cfg: &Cfg) -> Vec<(usize, usize, Vec<usize>)> {
+        cfg.blocks
+            .iter()
+            .map(|block| (block.start, block.end, block.successors.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn splits_a_loop() {
+        let cfg: Cfg = cfg_of(
+            r#"
+                LOADK r0, 1
+                LOADK r1, 3
+                LOADK r2, 1
+                FORPREP r0, done
+            again:
+                MOVE r4, r3
+            done:
+                FORLOOP r0, again
+                RETURN r0, 1
+            "#,
+        );
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, 4, vec![2]),
+                (4, 5, vec![2]),
+                (5, 6, vec![3, 1]),
+                (6, 7, vec![]),
+            ]
+        );
+        assert_eq!(cfg.blocks[1].predecessors, [2]);
+        assert_eq!(cfg.block_at(5), Some(2));
+    }
+
+    // jumps count the count word of a SETLIST, the blocks index instructions
+    #[test]
+    fn jumps_over_a_setlist_count() {
+        let cfg: Cfg = cfg_of(
+            r#"
+                SETLIST r0, 1, 600
+                EQ 0, r0, r1
+                JMP skip
+                LOADK r0, 1
+            skip:
+                RETURN r0, 1
+            "#,
+        );
+        assert_eq!(
+            edges(&cfg),
+            [
+                (0, 2, vec![1, 2]),
+                (2, 3, vec![3]),
+                (3, 4, vec![3]),
+                (4, 5, vec![]),
+            ]
+        );
+    }
+}
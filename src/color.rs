@@ -0,0 +1,235 @@
+// --color, ANSI colors for the text listing
+
+use std::io::{stdout, IsTerminal};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    // only when stdout is a terminal
+    Auto,
+}
+
+impl ColorChoice {
+    pub fn parse(text: &str) -> Option<ColorChoice> {
+        match text {
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            "auto" => Some(ColorChoice::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled_on(stdout().is_terminal())
+    }
+
+    // whether to color a listing written to a terminal or not
+    pub fn enabled_on(&self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => terminal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Mnemonic,
+    Register,
+    String,
+    Number,
+    Boolean,
+    Nil,
+    Jump,
+    Comment,
+}
+
+impl Style {
+    fn code(&self) -> &'static str {
+        match self {
+            Style::Mnemonic => "1;36",
+            Style::Register => "33",
+            Style::String => "32",
+            Style::Number => "35",
+            Style::Boolean => "34",
+            Style::Nil => "31",
+            Style::Jump => "4;36",
+            Style::Comment => "90",
+        }
+    }
+}
+
+pub fn paint(style: Style, text: &str) -> String {
+    match text.is_empty() {
+        true => String::new(),
+        false => format!("\x1b[{}m{}\x1b[0m", style.code(), text),
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// r_N and arg_N, the names describe gives registers without a local
+fn is_register(word: &str) -> bool {
+    let digits: Option<&str> = word
+        .strip_prefix("r_")
+        .or_else(|| word.strip_prefix("arg_"));
+    match digits {
+        Some(digits) => !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()),
+        None => false,
+    }
+}
+
+// colors the Lua-like text describe writes: registers, by their local name from names or
+// r_N and arg_N, literals by type, the pc after a goto and -- comments
+pub fn highlight(text: &str, names: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out: String = String::new();
+    let mut after_goto: bool = false;
+    let mut i: usize = 0;
+    while i < chars.len() {
+        let c: char = chars[i];
+        let start: usize = i;
+        let previous: Option<char> = match i {
+            0 => None,
+            _ => Some(chars[i - 1]),
+        };
+        let style: Option<Style> = if c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                // skips what's escaped, a quote included
+                i += match chars[i] {
+                    '\\' => 2,
+                    _ => 1,
+                };
+            }
+            i = (i + 1).min(chars.len());
+            Some(Style::String)
+        } else if c == '-' && chars.get(i + 1) == Some(&'-') {
+            i = chars.len();
+            Some(Style::Comment)
+        } else if (c.is_ascii_digit()
+            || (c == '-' && chars.get(i + 1).is_some_and(|next| next.is_ascii_digit())))
+            && !previous.is_some_and(|previous| is_word(previous) || previous == ')')
+        {
+            i += 1;
+            while i < chars.len() && (is_word(chars[i]) || chars[i] == '.') {
+                i += 1;
+            }
+            match after_goto {
+                true => Some(Style::Jump),
+                false => Some(Style::Number),
+            }
+        } else if is_word(c) {
+            while i < chars.len() && is_word(chars[i]) {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            match word.as_str() {
+                "true" | "false" => Some(Style::Boolean),
+                "nil" => Some(Style::Nil),
+                _ if is_register(&word) => Some(Style::Register),
+                // a name after . or : is a field, not the local
+                _ if names.contains(&word) && !matches!(previous, Some('.' | ':')) => {
+                    Some(Style::Register)
+                }
+                _ => None,
+            }
+        } else {
+            i += 1;
+            None
+        };
+        let token: String = chars[start..i].iter().collect();
+        if !token.trim().is_empty() {
+            after_goto = token == "goto";
+        }
+        match style {
+            Some(style) => out.push_str(&paint(style, &token)),
+            None => out.push_str(&token),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Bytecode;
+    use crate::read::build_reader;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.luac");
+
+    // the sample listed as it would be with choice on something that isn't a terminal
+    fn listings(choice: ColorChoice) -> Vec<String> {
+        let mut bytecode: Bytecode = match build_reader(&SAMPLE.to_vec()).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        bytecode.color = choice.enabled_on(false);
+        vec![bytecode.disassembly(false), bytecode.disassembly(true)]
+    }
+
+    #[test]
+    fn never_and_auto_off_a_terminal_stay_plain() {
+        for choice in [ColorChoice::Never, ColorChoice::Auto] {
+            for listing in listings(choice) {
+                assert!(
+                    !listing.contains('\x1b'),
+                    "{:?} colored\n{}",
+                    choice,
+                    listing
+                );
+            }
+        }
+        assert!(!ColorChoice::Never.enabled_on(true));
+        assert!(ColorChoice::Auto.enabled_on(true));
+    }
+
+    #[test]
+    fn always_paints_each_token_class() {
+        let listings: Vec<String> = listings(ColorChoice::Always);
+        for (style, token) in [
+            (Style::Mnemonic, "OpForPrep"),
+            (Style::Register, "r_4"),
+            (Style::Register, "counter"),
+            (Style::String, "\"number\""),
+            (Style::Number, "100"),
+            (Style::Comment, "--[[ constants:"),
+        ] {
+            assert!(
+                listings[0].contains(&paint(style, token)),
+                "{:?} {} not painted in\n{}",
+                style,
+                token,
+                listings[0]
+            );
+        }
+        assert!(listings[1].contains(&paint(Style::Register, "i")));
+
+        let names: Vec<String> = vec![String::from("name"), String::from("self")];
+        assert_eq!(
+            highlight("name = self.name; goto 12 -- 1 open", &names),
+            format!(
+                "{} = {}.name; goto {} {}",
+                paint(Style::Register, "name"),
+                paint(Style::Register, "self"),
+                paint(Style::Jump, "12"),
+                paint(Style::Comment, "-- 1 open")
+            )
+        );
+        assert_eq!(
+            highlight("arg_0 = {true, false, nil, -2.5}", &[]),
+            format!(
+                "{} = {{{}, {}, {}, {}}}",
+                paint(Style::Register, "arg_0"),
+                paint(Style::Boolean, "true"),
+                paint(Style::Boolean, "false"),
+                paint(Style::Nil, "nil"),
+                paint(Style::Number, "-2.5")
+            )
+        );
+    }
+}
@@ -0,0 +1,3308 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use crate::{
+    bytecode::{Bytecode, Constant, Instruction, LuaInt, LuaVersion, OpCode, OpMode, Proto, BITRK},
+    cfg::{build_cfg, Cfg},
+    liveness::{build_liveness, register_uses, Liveness, NUM_REGS},
+    util::{format_time_taken, is_identifier},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecompileErrorKind {
+    UnsupportedVersion(LuaVersion),
+    InvalidOperand { pc: usize },
+    UnexpectedInstruction { pc: usize },
+}
+
+impl fmt::Display for DecompileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecompileErrorKind::UnsupportedVersion(version) => {
+                write!(f, "decompiling {:?} chunks is not supported", version)
+            }
+            DecompileErrorKind::InvalidOperand { pc } => {
+                write!(f, "instruction {} has an operand out of range", pc)
+            }
+            DecompileErrorKind::UnexpectedInstruction { pc } => {
+                write!(f, "instruction {} can't appear in a Lua 5.1 function", pc)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecompileError {
+    pub kind: DecompileErrorKind,
+    // path of the proto being decompiled, e.g. main/3/1
+    pub proto_path: String,
+}
+
+impl fmt::Display for DecompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to decompile")?;
+        if !self.proto_path.is_empty() {
+            write!(f, " {}", self.proto_path)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for DecompileError {}
+
+pub type DecompileResult<T> = Result<T, DecompileError>;
+
+// 5.1 only knows decimal escapes, which take up to three digits
+fn quote_string(bytes: &[u8]) -> String {
+    let mut result: String = String::from('"');
+    for chunk in bytes.utf8_chunks() {
+        let mut chars = chunk.valid().chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x07' => result.push_str("\\a"),
+                '\x08' => result.push_str("\\b"),
+                '\x0C' => result.push_str("\\f"),
+                '\n' => result.push_str("\\n"),
+                '\r' => result.push_str("\\r"),
+                '\t' => result.push_str("\\t"),
+                '\x0B' => result.push_str("\\v"),
+                '\\' => result.push_str("\\\\"),
+                '"' => result.push_str("\\\""),
+                _ if c.is_control() => {
+                    // pad when a digit follows so it isn't read as part of the escape
+                    match chars.peek().is_some_and(|next| next.is_ascii_digit()) {
+                        true => result.push_str(&format!("\\{:03}", c as u32)),
+                        false => result.push_str(&format!("\\{}", c as u32)),
+                    }
+                }
+                _ => result.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            result.push_str(&format!("\\{:03}", b));
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn format_number(number: f64) -> String {
+    if number.is_nan() {
+        "0/0".to_string()
+    } else if number.is_infinite() {
+        match number > 0.0 {
+            true => "1/0".to_string(),
+            false => "-1/0".to_string(),
+        }
+    } else if number.fract() == 0.0 && number.abs() < 1e16 {
+        number.to_string()
+    } else {
+        // exponents keep very large and very small numbers short
+        format!("{:?}", number)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Concat,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Or => "or",
+            BinaryOp::And => "and",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "~=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Concat => "..",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "^",
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le => 3,
+            BinaryOp::Concat => 4,
+            BinaryOp::Add | BinaryOp::Sub => 5,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 6,
+            BinaryOp::Pow => 8,
+        }
+    }
+
+    fn is_right_associative(&self) -> bool {
+        matches!(self, BinaryOp::Concat | BinaryOp::Pow)
+    }
+}
+
+const UNARY_PRECEDENCE: u8 = 7;
+const ATOM_PRECEDENCE: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UnaryOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+    // whether every value is kept, a single one is wrapped in parentheses at the end of a list
+    Vararg(bool),
+    Name(String),
+    Global(Vec<u8>),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>, bool),
+    // object:name as the function of a call, the object is Extra while it's still in the
+    // register after the one SELF put the method in
+    Method(Box<Expr>, Vec<u8>),
+    Function(Box<Function>),
+    // fields in the order they're evaluated, one without a key is the next item of the list
+    Table(Vec<(Option<Expr>, Expr)>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    // one of the extra results of the call or vararg before it in a list
+    Extra,
+}
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, _, _) => op.precedence(),
+            Expr::Unary(_, _) => UNARY_PRECEDENCE,
+            // written as a division
+            Expr::Number(number) if number.is_nan() || number.is_infinite() => 6,
+            Expr::Number(number) if number.is_sign_negative() => UNARY_PRECEDENCE,
+            _ => ATOM_PRECEDENCE,
+        }
+    }
+
+    // can be called or indexed without parentheses
+    fn is_prefix(&self) -> bool {
+        matches!(
+            self,
+            Expr::Name(_)
+                | Expr::Global(_)
+                | Expr::Index(_, _)
+                | Expr::Call(_, _, _)
+                | Expr::Method(_, _)
+        )
+    }
+
+    // cheap to repeat and can't change between two reads
+    fn is_simple(&self) -> bool {
+        matches!(
+            self,
+            Expr::Nil | Expr::Boolean(_) | Expr::Number(_) | Expr::String(_) | Expr::Name(_)
+        )
+    }
+
+    fn format(&self, indent: usize) -> String {
+        match self {
+            Expr::Nil | Expr::Extra => "nil".to_string(),
+            Expr::Boolean(bool) => bool.to_string(),
+            Expr::Number(number) => format_number(*number),
+            Expr::String(bytes) => quote_string(bytes),
+            Expr::Vararg(_) => "...".to_string(),
+            Expr::Name(name) => name.clone(),
+            Expr::Global(name) => match is_identifier(name) {
+                true => String::from_utf8_lossy(name).into_owned(),
+                false => format!("_G[{}]", quote_string(name)),
+            },
+            Expr::Index(table, key) => {
+                let table: String = table.format_prefix(indent);
+                match key.as_ref() {
+                    Expr::String(name) if is_identifier(name) => {
+                        format!("{}.{}", table, String::from_utf8_lossy(name))
+                    }
+                    _ => format!("{}[{}]", table, key.format(indent)),
+                }
+            }
+            Expr::Call(function, args, _) => format!(
+                "{}({})",
+                function.format_prefix(indent),
+                format_list(args, true, indent)
+            ),
+            Expr::Method(object, name) => format!(
+                "{}:{}",
+                object.format_prefix(indent),
+                String::from_utf8_lossy(name)
+            ),
+            Expr::Function(function) => format!("function{}", function.format(indent)),
+            Expr::Table(fields) => {
+                let fields: Vec<&(Option<Expr>, Expr)> = fields
+                    .iter()
+                    .filter(|(key, value)| key.is_some() || !matches!(value, Expr::Extra))
+                    .collect();
+                let mut parts: Vec<String> = Vec::with_capacity(fields.len());
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    let is_last: bool = i + 1 == fields.len();
+                    parts.push(match key {
+                        Some(Expr::String(name)) if is_identifier(name) => format!(
+                            "{} = {}",
+                            String::from_utf8_lossy(name),
+                            value.format(indent)
+                        ),
+                        Some(key) => format!("[{}] = {}", key.format(indent), value.format(indent)),
+                        // only the last item of the list keeps every value
+                        None => match value {
+                            Expr::Call(_, _, false) | Expr::Vararg(false) if is_last => {
+                                format!("({})", value.format(indent))
+                            }
+                            _ => value.format(indent),
+                        },
+                    });
+                }
+                format!("{{{}}}", parts.join(", "))
+            }
+            Expr::Binary(op, left, right) => {
+                let precedence: u8 = op.precedence();
+                let (left_min, right_min): (u8, u8) = match op.is_right_associative() {
+                    true => (precedence + 1, precedence),
+                    false => (precedence, precedence + 1),
+                };
+                format!(
+                    "{} {} {}",
+                    left.format_operand(left_min, indent),
+                    op.symbol(),
+                    right.format_operand(right_min, indent)
+                )
+            }
+            Expr::Unary(op, operand) => {
+                let operand: String = operand.format_operand(UNARY_PRECEDENCE, indent);
+                match op {
+                    // --x would start a comment
+                    UnaryOp::Neg if operand.starts_with('-') => format!("- {}", operand),
+                    UnaryOp::Neg => format!("-{}", operand),
+                    UnaryOp::Not => format!("not {}", operand),
+                    UnaryOp::Len => format!("#{}", operand),
+                }
+            }
+        }
+    }
+
+    fn format_operand(&self, min_precedence: u8, indent: usize) -> String {
+        match self.precedence() < min_precedence {
+            true => format!("({})", self.format(indent)),
+            false => self.format(indent),
+        }
+    }
+
+    fn format_prefix(&self, indent: usize) -> String {
+        match self.is_prefix() {
+            true => self.format(indent),
+            false => format!("({})", self.format(indent)),
+        }
+    }
+}
+
+// the extra results are left to the call or vararg before them, truncate is for lists
+// that take every value of the last one like arguments and returns do
+fn format_list(exprs: &[Expr], truncate: bool, indent: usize) -> String {
+    let exprs: Vec<&Expr> = exprs
+        .iter()
+        .filter(|expr| !matches!(expr, Expr::Extra))
+        .collect();
+    let mut parts: Vec<String> = Vec::with_capacity(exprs.len());
+    for (i, expr) in exprs.iter().enumerate() {
+        let is_last: bool = i + 1 == exprs.len();
+        match expr {
+            // a single value at the end of a list has to be truncated by hand
+            Expr::Call(_, _, false) | Expr::Vararg(false) if is_last && truncate => {
+                parts.push(format!("({})", expr.format(indent)))
+            }
+            _ => parts.push(expr.format(indent)),
+        }
+    }
+    parts.join(", ")
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    params: Vec<String>,
+    is_vararg: bool,
+    body: Vec<Stmt>,
+}
+
+impl Function {
+    // everything after the function keyword and name
+    fn format(&self, indent: usize) -> String {
+        self.format_from(0, indent)
+    }
+
+    // leaving out the params before first, like the self of a method
+    fn format_from(&self, first: usize, indent: usize) -> String {
+        let mut params: Vec<String> = self.params[first.min(self.params.len())..].to_vec();
+        if self.is_vararg {
+            params.push("...".to_string());
+        }
+        let mut result: String = format!("({})\n", params.join(", "));
+        format_block(&self.body, indent + 1, &mut result);
+        result.push_str(&format!("{}end", "    ".repeat(indent)));
+        result
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Local(Vec<String>, Vec<Expr>),
+    LocalFunction(String, Box<Function>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Return(Vec<Expr>),
+    // the else block holding a single if is printed as elseif
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Repeat(Vec<Stmt>, Expr),
+    // the start, the limit and a step that isn't 1
+    NumericFor(String, Vec<Expr>, Vec<Stmt>),
+    GenericFor(Vec<String>, Vec<Expr>, Vec<Stmt>),
+    Break,
+    Comment(String),
+}
+
+// the name in function a.b.c() when assigning a function to target can use that form
+fn function_name(target: &Expr) -> Option<String> {
+    match target {
+        Expr::Name(name) => Some(name.clone()),
+        Expr::Global(name) if is_identifier(name) => {
+            Some(String::from_utf8_lossy(name).into_owned())
+        }
+        Expr::Index(table, key) => match key.as_ref() {
+            Expr::String(name) if is_identifier(name) => Some(format!(
+                "{}.{}",
+                function_name(table)?,
+                String::from_utf8_lossy(name)
+            )),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl Stmt {
+    fn format(&self, indent: usize) -> String {
+        match self {
+            Stmt::Local(names, values) if values.is_empty() => {
+                format!("local {}", names.join(", "))
+            }
+            Stmt::Local(names, values) => {
+                if let ([name], [Expr::Function(function)]) = (&names[..], &values[..]) {
+                    return format!("local function {}{}", name, function.format(indent));
+                }
+                format!(
+                    "local {} = {}",
+                    names.join(", "),
+                    format_list(values, false, indent)
+                )
+            }
+            Stmt::LocalFunction(name, function) => {
+                format!("local function {}{}", name, function.format(indent))
+            }
+            Stmt::Assign(targets, values) => {
+                if let ([target], [Expr::Function(function)]) = (&targets[..], &values[..]) {
+                    // a first param named self is what function a:name() declares
+                    if let Expr::Index(table, key) = target {
+                        match (function_name(table), key.as_ref()) {
+                            (Some(table), Expr::String(name))
+                                if is_identifier(name)
+                                    && function.params.first().is_some_and(|p| p == "self") =>
+                            {
+                                return format!(
+                                    "function {}:{}{}",
+                                    table,
+                                    String::from_utf8_lossy(name),
+                                    function.format_from(1, indent)
+                                );
+                            }
+                            _ => {}
+                        }
+                    }
+                    if let Some(name) = function_name(target) {
+                        return format!("function {}{}", name, function.format(indent));
+                    }
+                }
+                let targets: Vec<String> = targets.iter().map(|t| t.format(indent)).collect();
+                format!(
+                    "{} = {}",
+                    targets.join(", "),
+                    format_list(values, false, indent)
+                )
+            }
+            Stmt::Call(call) => call.format(indent),
+            Stmt::Return(values) if values.is_empty() => "return".to_string(),
+            Stmt::Return(values) => format!("return {}", format_list(values, true, indent)),
+            Stmt::If(condition, then_block, else_block) => {
+                let prefix: String = "    ".repeat(indent);
+                let mut result: String = format!("if {} then\n", condition.format(indent));
+                format_block(then_block, indent + 1, &mut result);
+                let mut else_block: &[Stmt] = else_block;
+                while let [Stmt::If(condition, then_block, next)] = else_block {
+                    result.push_str(&format!(
+                        "{}elseif {} then\n",
+                        prefix,
+                        condition.format(indent)
+                    ));
+                    format_block(then_block, indent + 1, &mut result);
+                    else_block = next;
+                }
+                if !else_block.is_empty() {
+                    result.push_str(&format!("{}else\n", prefix));
+                    format_block(else_block, indent + 1, &mut result);
+                }
+                result.push_str(&format!("{}end", prefix));
+                result
+            }
+            Stmt::While(condition, body) => {
+                let mut result: String = format!("while {} do\n", condition.format(indent));
+                format_block(body, indent + 1, &mut result);
+                result.push_str(&format!("{}end", "    ".repeat(indent)));
+                result
+            }
+            Stmt::Repeat(body, condition) => {
+                let mut result: String = "repeat\n".to_string();
+                format_block(body, indent + 1, &mut result);
+                result.push_str(&format!(
+                    "{}until {}",
+                    "    ".repeat(indent),
+                    condition.format(indent)
+                ));
+                result
+            }
+            Stmt::NumericFor(name, range, body) => {
+                let mut result: String =
+                    format!("for {} = {} do\n", name, format_list(range, false, indent));
+                format_block(body, indent + 1, &mut result);
+                result.push_str(&format!("{}end", "    ".repeat(indent)));
+                result
+            }
+            Stmt::GenericFor(names, values, body) => {
+                let mut result: String = format!(
+                    "for {} in {} do\n",
+                    names.join(", "),
+                    format_list(values, false, indent)
+                );
+                format_block(body, indent + 1, &mut result);
+                result.push_str(&format!("{}end", "    ".repeat(indent)));
+                result
+            }
+            Stmt::Break => "break".to_string(),
+            Stmt::Comment(text) => format!("-- {}", text),
+        }
+    }
+}
+
+fn format_block(stmts: &[Stmt], indent: usize, out: &mut String) {
+    let prefix: String = "    ".repeat(indent);
+    for (i, stmt) in stmts.iter().enumerate() {
+        let text: String = match stmt {
+            // return and break have to be the last statement of a block
+            Stmt::Return(_) | Stmt::Break if i + 1 < stmts.len() => {
+                format!("do {} end", stmt.format(indent))
+            }
+            // every line of a comment needs its own marker
+            Stmt::Comment(text) => {
+                format!("-- {}", text.replace('\n', &format!("\n{}-- ", prefix)))
+            }
+            _ => stmt.format(indent),
+        };
+        // a line starting with ( would be read as arguments to a call on the line before
+        let separator: &str = match text.starts_with('(') {
+            true => ";",
+            false => "",
+        };
+        out.push_str(&format!("{}{}{}\n", prefix, separator, text));
+    }
+}
+
+// what a register holds while its function is decompiled
+#[derive(Debug, Clone)]
+enum Reg {
+    Free,
+    // a temporary, inlined where it's read
+    Pending(Expr),
+    Extra,
+    // a declared local, writing to it assigns it
+    Local(String),
+    // a temporary that had to be given a name, writing to it starts a new one
+    Temp(String),
+}
+
+#[derive(Debug, Clone)]
+struct LocalInfo {
+    name: String,
+    reg: usize,
+    start_pc: usize,
+    end_pc: usize,
+    // the state a for loop keeps in (for index) and the like, which the loop itself declares
+    hidden: bool,
+}
+
+// locals are numbered like luaF_getlocalname does, by how many are still alive where they start
+fn local_infos(proto: &Proto) -> Vec<LocalInfo> {
+    let mut infos: Vec<LocalInfo> = Vec::with_capacity(proto.locals.len());
+    for (i, local) in proto.locals.iter().enumerate() {
+        let reg: usize = proto.locals[..i]
+            .iter()
+            .filter(|other| other.start_pc <= local.start_pc && other.end_pc > local.start_pc)
+            .count();
+        // internal locals such as (for index) aren't valid names
+        let name: String = match is_identifier(&local.name) {
+            true => String::from_utf8_lossy(&local.name).into_owned(),
+            false => format!("r_{}", reg),
+        };
+        infos.push(LocalInfo {
+            name,
+            reg,
+            start_pc: local.start_pc.max(0) as usize,
+            end_pc: local.end_pc.max(0) as usize,
+            hidden: local.name.starts_with(b"(for "),
+        });
+    }
+    infos
+}
+
+const FIELDS_PER_FLUSH: usize = 50;
+
+// the registers read and written by every instruction as the statements see them. A for
+// loop reads its start values once where it's entered, the FORLOOP or TFORLOOP only sets
+// the variables and the control variable of a generic for is left to the loop
+fn code_register_use(proto: &Proto, cfg: &Cfg, pcs: &[usize]) -> Vec<(Vec<usize>, Vec<usize>)> {
+    let mut uses: Vec<(Vec<usize>, Vec<usize>)> = register_uses(proto, cfg, LuaVersion::Lua51);
+    for (index, inst) in proto.code.iter().enumerate() {
+        match inst.op {
+            OpCode::OpForLoop(OpMode::ASBX(a, _)) => {
+                uses[index].0.clear();
+                uses[index].1.retain(|reg| *reg as LuaInt == a + 3);
+            }
+            OpCode::OpTForLoop(OpMode::ABC(a, _, _)) => {
+                uses[index].0.clear();
+                uses[index].1.retain(|reg| *reg as LuaInt > a + 2);
+            }
+            OpCode::OpJmp(_) => {
+                if let Some(a) = generic_for_base(proto, pcs, index) {
+                    uses[index]
+                        .0
+                        .extend((a..a + 3).filter(|reg| *reg < NUM_REGS));
+                }
+            }
+            _ => {}
+        }
+    }
+    uses
+}
+
+// index of the instruction a jump at index by offset lands on
+fn jump_index(pcs: &[usize], index: usize, offset: LuaInt) -> Option<usize> {
+    let target: usize = usize::try_from(pcs[index] as i64 + 1 + offset as i64).ok()?;
+    pcs.binary_search(&target).ok()
+}
+
+// the base register of the generic for a JMP at index enters, which goes to a TFORLOOP
+// followed by a JMP back to just after it
+fn generic_for_base(proto: &Proto, pcs: &[usize], index: usize) -> Option<usize> {
+    let target: usize = match proto.code[index].op {
+        OpCode::OpJmp(OpMode::ASBX(_, sbx)) => jump_index(pcs, index, sbx)?,
+        _ => return None,
+    };
+    let a: LuaInt = match proto.code.get(target).map(|inst| &inst.op) {
+        Some(OpCode::OpTForLoop(OpMode::ABC(a, _, _))) if target > index => *a,
+        _ => return None,
+    };
+    match proto.code.get(target + 1).map(|inst| &inst.op) {
+        Some(OpCode::OpJmp(OpMode::ASBX(_, sbx)))
+            if jump_index(pcs, target + 1, *sbx) == Some(index + 1) =>
+        {
+            usize::try_from(a).ok()
+        }
+        _ => None,
+    }
+}
+
+// a table size as NEWTABLE keeps it, in a byte with 3 bits of mantissa
+pub(crate) fn float_byte(value: LuaInt) -> usize {
+    let value: usize = usize::try_from(value).unwrap_or(0);
+    match (value >> 3) & 31 {
+        0 => value,
+        exponent => ((value & 7) + 8) << (exponent - 1),
+    }
+}
+
+// the SETTABLEs and SETLISTs that fill in the table of a constructor, with its register.
+// Only the expressions of its fields and the jumps of and/or can come in between, and none
+// of those jumps skips a member
+fn constructor_members(
+    proto: &Proto,
+    pcs: &[usize],
+    uses: &[(Vec<usize>, Vec<usize>)],
+) -> HashMap<usize, usize> {
+    let code: &[Instruction] = &proto.code;
+    let mut members: HashMap<usize, usize> = HashMap::new();
+    // a constructor nested in a field is done first, its members are part of the field
+    for (start, inst) in code.iter().enumerate().rev() {
+        let (table, records): (usize, usize) = match inst.op {
+            OpCode::OpNewTable(OpMode::ABC(a, _, c)) => match usize::try_from(a) {
+                Ok(a) => (a, float_byte(c)),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        let mut furthest: usize = start + 1;
+        let mut record_count: usize = 0;
+        for index in start + 1..code.len() {
+            let starts_local: bool = proto
+                .locals
+                .iter()
+                .any(|local| local.start_pc.max(0) as usize == pcs[index]);
+            if starts_local {
+                break;
+            }
+            let op: &OpCode = &code[index].op;
+            let is_record: bool = match op {
+                OpCode::OpSetTable(OpMode::ABC(a, _, _)) => usize::try_from(*a) == Ok(table),
+                _ => false,
+            };
+            let is_list: bool = match op {
+                OpCode::OpSetList(OpMode::ABC(a, _, _)) => usize::try_from(*a) == Ok(table),
+                _ => false,
+            };
+            if is_record || is_list {
+                if furthest > index || (is_record && record_count == records) {
+                    break;
+                }
+                if is_record {
+                    record_count += 1;
+                }
+                members.insert(index, table);
+                continue;
+            }
+            let (reads, writes): &(Vec<usize>, Vec<usize>) = &uses[index];
+            if reads.contains(&table) || writes.contains(&table) {
+                break;
+            }
+            if is_expression(op) || members.contains_key(&index) {
+                continue;
+            }
+            let target: Option<usize> = match op {
+                OpCode::OpJmp(OpMode::ASBX(_, sbx)) => jump_index(pcs, index, *sbx),
+                OpCode::OpLoadBool(_) => Some(index + 2),
+                _ if is_test(op) => Some(index + 2),
+                _ => None,
+            };
+            match target {
+                Some(target) if target > index => furthest = furthest.max(target),
+                _ => break,
+            }
+        }
+    }
+    members
+}
+
+// the writes whose value can't just be inlined into the one instruction that reads it,
+// as (index, register)
+fn shared_writes(
+    cfg: &Cfg,
+    uses: &[(Vec<usize>, Vec<usize>)],
+    live_out: &[Vec<bool>],
+) -> HashSet<(usize, usize)> {
+    let mut shared: HashSet<(usize, usize)> = HashSet::new();
+    for (block_index, block) in cfg.blocks.iter().enumerate() {
+        // the write each register holds and how often it was read since
+        let mut current: HashMap<usize, (usize, usize)> = HashMap::new();
+        for (index, (reads, writes)) in uses.iter().enumerate().take(block.end).skip(block.start) {
+            for reg in reads {
+                if let Some((_, count)) = current.get_mut(reg) {
+                    *count += 1;
+                }
+            }
+            for reg in writes {
+                if let Some((write, count)) = current.insert(*reg, (index, 0)) {
+                    if count != 1 {
+                        shared.insert((write, *reg));
+                    }
+                }
+            }
+        }
+        for (reg, (write, count)) in current {
+            if count != 1 || live_out[block_index][reg] {
+                shared.insert((write, reg));
+            }
+        }
+    }
+    shared
+}
+
+// the statements of a function as recovered from its jumps, indices are into Proto.code
+#[derive(Debug, Clone)]
+pub(crate) enum Node {
+    // instructions without control flow, from start up to end
+    Code(usize, usize),
+    // a value computed with and/or into a register
+    Value(Chain),
+    If {
+        condition: Chain,
+        then_block: Block,
+        else_block: Option<Block>,
+        end: usize,
+    },
+    // a condition that's None is a while true, the block is the code of its first operand
+    While {
+        head: usize,
+        condition: Option<(Block, Chain)>,
+        body: Block,
+        end: usize,
+    },
+    Repeat {
+        head: usize,
+        body: Block,
+        condition: Chain,
+        end: usize,
+    },
+    NumericFor {
+        prep: usize,
+        body: Block,
+        end: usize,
+    },
+    GenericFor {
+        prep: usize,
+        body: Block,
+        call: usize,
+        end: usize,
+    },
+    Break(usize),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Block {
+    pub(crate) nodes: Vec<Node>,
+    // where control goes once the last node is done
+    pub(crate) end: usize,
+}
+
+// tests joined by and/or into a condition or a value, the code of the first operand is left
+// to the statement before it
+#[derive(Debug, Clone)]
+pub(crate) struct Chain {
+    // every test with the code of its operand since the JMP of the one before
+    pub(crate) tests: Vec<(Block, usize)>,
+    // the last operand of a value, which isn't tested
+    pub(crate) tail: Option<Block>,
+    pub(crate) shape: Shape,
+    // the register a value is left in
+    pub(crate) reg: Option<usize>,
+    // where a condition goes on when it holds, or where a value is done
+    pub(crate) end: usize,
+    // where a condition goes when it doesn't hold
+    pub(crate) jump: usize,
+}
+
+// how the operands of a chain are joined
+#[derive(Debug, Clone)]
+pub(crate) enum Shape {
+    // a test as the condition to take its JMP, or with false to skip it
+    Test(usize, bool),
+    Tail,
+    And(Box<Shape>, Box<Shape>),
+    Or(Box<Shape>, Box<Shape>),
+}
+
+// where a test of a chain goes, a start of another operand or out of the chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Exit {
+    At(usize),
+    True,
+    False,
+}
+
+// what a single position of a block turned out to be
+enum Step {
+    Straight,
+    Node(Node, usize),
+    // instructions up to the index that do nothing, such as a jump to where the block ends
+    Skip(usize),
+}
+
+struct FunctionDecompiler<'a> {
+    proto: &'a Proto,
+    proto_path: String,
+    upvalue_names: Vec<String>,
+
+    // word pc of every instruction, a SETLIST with an aux word takes up two
+    pcs: Vec<usize>,
+    cfg: Cfg,
+    uses: Vec<(Vec<usize>, Vec<usize>)>,
+    live_out: Vec<Vec<bool>>,
+    // the JMPs going back to each instruction
+    back_jumps: Vec<Vec<usize>>,
+    locals: Vec<LocalInfo>,
+    next_local: usize,
+    active_locals: Vec<usize>,
+    stripped: bool,
+    shared: HashSet<(usize, usize)>,
+    // the SETTABLEs and SETLISTs of table constructors, with the register of their table
+    members: HashMap<usize, usize>,
+    // registers a closure sees, which have to keep being assigned as locals
+    captured: Vec<bool>,
+    // registers whose r_N name is declared already, reusing it keeps under the limit of locals
+    declared: Vec<bool>,
+    // the registers each open block declared an r_N for
+    scopes: Vec<Vec<usize>>,
+    // where a break goes for each loop around the current statement
+    break_targets: Vec<usize>,
+    // whether control flow is recovered, cleared when an expression needs statements after all
+    structured: bool,
+    // the register of the value chain being decompiled, its operands don't assign the local
+    value_reg: Option<usize>,
+    // ends of the value chains read once by the code after them, which go into that code
+    inline_values: HashSet<usize>,
+
+    regs: Vec<Reg>,
+    // index of the instruction that last wrote each register
+    writes: Vec<usize>,
+    // the register holding a call or vararg that keeps all of its results
+    top: Option<usize>,
+    // assignments of a multiple assignment, collected until every value they use is stored
+    stores: Vec<(Expr, Expr)>,
+    stmts: Vec<Stmt>,
+    index: usize,
+    pc: usize,
+    block_end_pc: usize,
+}
+
+impl<'a> FunctionDecompiler<'a> {
+    fn error(&self, kind: DecompileErrorKind) -> DecompileError {
+        DecompileError {
+            kind,
+            proto_path: self.proto_path.clone(),
+        }
+    }
+    fn invalid_operand(&self) -> DecompileError {
+        self.error(DecompileErrorKind::InvalidOperand { pc: self.pc })
+    }
+
+    fn reg(&self, reg: LuaInt) -> DecompileResult<usize> {
+        match usize::try_from(reg) {
+            Ok(reg) if reg < NUM_REGS => Ok(reg),
+            _ => Err(self.invalid_operand()),
+        }
+    }
+
+    fn constant(&self, index: LuaInt) -> DecompileResult<Expr> {
+        let constant: Option<&Constant> = usize::try_from(index)
+            .ok()
+            .and_then(|index| self.proto.constants.get(index));
+        match constant {
+            Some(Constant::Nil) => Ok(Expr::Nil),
+            Some(Constant::Boolean(bool)) => Ok(Expr::Boolean(*bool)),
+            Some(Constant::Number(number)) => Ok(Expr::Number(*number)),
+            Some(Constant::String(bytes)) => Ok(Expr::String(bytes.clone())),
+            _ => Err(self.invalid_operand()),
+        }
+    }
+
+    fn global(&self, index: LuaInt) -> DecompileResult<Expr> {
+        match self.constant(index)? {
+            Expr::String(name) => Ok(Expr::Global(name)),
+            _ => Err(self.invalid_operand()),
+        }
+    }
+
+    fn upvalue(&self, index: LuaInt) -> DecompileResult<Expr> {
+        match usize::try_from(index)
+            .ok()
+            .and_then(|index| self.upvalue_names.get(index))
+        {
+            Some(name) => Ok(Expr::Name(name.clone())),
+            None => Err(self.invalid_operand()),
+        }
+    }
+
+    fn name_of(&self, reg: usize) -> String {
+        match &self.regs[reg] {
+            Reg::Local(name) | Reg::Temp(name) => name.clone(),
+            _ => format!("r_{}", reg),
+        }
+    }
+
+    fn has_pending(&self) -> bool {
+        self.regs
+            .iter()
+            .any(|reg| matches!(reg, Reg::Pending(_) | Reg::Extra))
+    }
+
+    fn read(&mut self, reg: usize) -> Expr {
+        // only a local or an assignment can take exactly some of the results of a call
+        let is_grouped: bool = match &self.regs[reg] {
+            Reg::Pending(_) => matches!(self.regs.get(reg + 1), Some(Reg::Extra)),
+            Reg::Extra => true,
+            _ => false,
+        };
+        let is_shared: bool = matches!(self.regs[reg], Reg::Pending(_))
+            && self.shared.contains(&(self.writes[reg], reg));
+        if is_grouped || is_shared {
+            self.flush_pending_through(reg);
+        }
+        self.take(reg)
+    }
+
+    fn take(&mut self, reg: usize) -> Expr {
+        match &self.regs[reg] {
+            Reg::Pending(_) | Reg::Extra => {
+                match std::mem::replace(&mut self.regs[reg], Reg::Free) {
+                    Reg::Pending(expr) => expr,
+                    _ => Expr::Extra,
+                }
+            }
+            _ => Expr::Name(self.name_of(reg)),
+        }
+    }
+
+    // a value used twice is named after the register it was read from, unless it's simple
+    fn shared_value(&mut self, reg: usize, value: Expr) -> Expr {
+        if value.is_simple() {
+            return value;
+        }
+        if matches!(self.regs[reg], Reg::Pending(_) | Reg::Extra) {
+            self.flush_pending();
+        }
+        self.regs[reg] = Reg::Pending(value);
+        self.flush_pending();
+        Expr::Name(self.name_of(reg))
+    }
+
+    fn rk(&mut self, value: LuaInt) -> DecompileResult<Expr> {
+        match value & BITRK != 0 {
+            true => self.constant(value & !BITRK),
+            false => {
+                let reg: usize = self.reg(value)?;
+                Ok(self.read(reg))
+            }
+        }
+    }
+
+    fn read_range(&mut self, from: usize, to: usize) -> Vec<Expr> {
+        (from..=to).map(|reg| self.read(reg)).collect()
+    }
+
+    // the last register of a B or C that counts values plus one, 0 meaning up to top
+    fn range_end(&mut self, first: usize, count: LuaInt) -> DecompileResult<Option<usize>> {
+        match count {
+            0 => match self.top.take() {
+                Some(top) if top + 1 >= first => Ok(Some(top)),
+                _ => Err(self.invalid_operand()),
+            },
+            1 => Ok(None),
+            count => Ok(Some(self.reg(first as LuaInt + count - 2)?)),
+        }
+    }
+
+    // whether the value written to reg by the current instruction has to be named
+    fn is_shared(&self, reg: usize) -> bool {
+        if !self.shared.contains(&(self.index, reg)) {
+            return false;
+        }
+        // the value a local starts out with is named by the local
+        !self.locals[self.next_local..]
+            .iter()
+            .any(|local| local.reg == reg && local.start_pc <= self.block_end_pc)
+    }
+
+    fn write(&mut self, reg: usize, value: Expr) {
+        match &self.regs[reg] {
+            // a stripped chunk reuses registers for temporaries once their locals are gone
+            Reg::Local(name)
+                if self.value_reg != Some(reg)
+                    && (!self.stripped
+                        || self.captured[reg]
+                        || self.shared.contains(&(self.index, reg))) =>
+            {
+                let target: Expr = Expr::Name(name.clone());
+                self.store(target, value);
+            }
+            // a value nothing read was a local after all
+            Reg::Pending(_) | Reg::Extra => {
+                self.flush_pending();
+                self.write(reg, value);
+            }
+            _ => {
+                self.regs[reg] = Reg::Pending(value);
+                self.writes[reg] = self.index;
+                if self.is_shared(reg) {
+                    self.flush_pending();
+                }
+            }
+        }
+    }
+
+    // a call or vararg with several results, in the registers from first to last
+    fn write_multiple(&mut self, first: usize, last: usize, value: Expr) {
+        let keeps_local: bool = (first..=last).any(|reg| {
+            matches!(self.regs[reg], Reg::Local(_))
+                && (!self.stripped
+                    || self.captured[reg]
+                    || self.shared.contains(&(self.index, reg)))
+        });
+        if keeps_local {
+            self.close_stores();
+            self.flush_pending();
+            let mut targets: Vec<Expr> = Vec::with_capacity(last - first + 1);
+            for reg in first..=last {
+                if !matches!(self.regs[reg], Reg::Local(_)) {
+                    let name: String = format!("r_{}", reg);
+                    if !self.declared[reg] {
+                        self.stmts.push(Stmt::Local(vec![name.clone()], Vec::new()));
+                        self.declare(reg);
+                    }
+                    self.regs[reg] = Reg::Local(name);
+                }
+                targets.push(Expr::Name(self.name_of(reg)));
+            }
+            self.stmts.push(Stmt::Assign(targets, vec![value]));
+            return;
+        }
+        for reg in first..=last {
+            if matches!(self.regs[reg], Reg::Pending(_) | Reg::Extra) {
+                self.flush_pending();
+            }
+        }
+        self.regs[first] = Reg::Pending(value);
+        for reg in first + 1..=last {
+            self.regs[reg] = Reg::Extra;
+        }
+        for reg in first..=last {
+            self.writes[reg] = self.index;
+        }
+        if (first..=last).any(|reg| self.is_shared(reg)) {
+            self.flush_pending();
+        }
+    }
+
+    // the fields of the constructor filling in the table at reg, while it's still pending
+    fn open_table(&mut self, reg: usize) -> Option<Vec<(Option<Expr>, Expr)>> {
+        if !matches!(self.regs[reg], Reg::Pending(Expr::Table(_))) {
+            return None;
+        }
+        match std::mem::replace(&mut self.regs[reg], Reg::Free) {
+            Reg::Pending(Expr::Table(fields)) => Some(fields),
+            _ => None,
+        }
+    }
+
+    // the register of the first item of the list not in the table yet, the ones before
+    // went in with a field
+    fn list_start(&self, table: usize) -> usize {
+        let mut reg: usize = table + 1;
+        while reg < NUM_REGS && matches!(self.regs[reg], Reg::Free) {
+            reg += 1;
+        }
+        reg
+    }
+
+    fn store(&mut self, target: Expr, value: Expr) {
+        self.stores.push((target, value));
+        if !self.has_pending() {
+            self.close_stores();
+        }
+    }
+
+    // a multiple assignment evaluates all of its values and then stores them last to first
+    fn close_stores(&mut self) {
+        if self.stores.is_empty() {
+            return;
+        }
+        let stores: Vec<(Expr, Expr)> = std::mem::take(&mut self.stores);
+        self.flush_pending();
+        let (targets, values): (Vec<Expr>, Vec<Expr>) = stores.into_iter().rev().unzip();
+        self.stmts.push(Stmt::Assign(targets, values));
+    }
+
+    // temporaries nothing read are locals of a stripped chunk, or have to outlive their block
+    fn flush_pending(&mut self) {
+        self.flush_pending_through(NUM_REGS - 1);
+    }
+
+    // registers above last were written later and can stay pending, like the rest of a list
+    fn flush_pending_through(&mut self, last: usize) {
+        let mut reg: usize = 0;
+        while reg <= last {
+            let value: Expr = match &self.regs[reg] {
+                // the object SELF left in the next register is named first
+                Reg::Pending(Expr::Method(object, name)) if matches!(**object, Expr::Extra) => {
+                    let name: Vec<u8> = name.clone();
+                    self.regs[reg] = Reg::Free;
+                    self.flush_pending_through(reg + 1);
+                    Expr::Index(
+                        Box::new(Expr::Name(self.name_of(reg + 1))),
+                        Box::new(Expr::String(name)),
+                    )
+                }
+                Reg::Pending(value) => value.clone(),
+                Reg::Extra => Expr::Nil,
+                _ => {
+                    reg += 1;
+                    continue;
+                }
+            };
+            let mut names: Vec<String> = vec![format!("r_{}", reg)];
+            let mut next: usize = reg + 1;
+            while next < NUM_REGS && matches!(self.regs[next], Reg::Extra) {
+                names.push(format!("r_{}", next));
+                next += 1;
+            }
+            for (offset, name) in names.iter().enumerate() {
+                self.regs[reg + offset] = match self.stripped {
+                    true => Reg::Local(name.clone()),
+                    false => Reg::Temp(name.clone()),
+                };
+            }
+            if self.top.is_some_and(|top| top >= reg && top < next) {
+                self.top = None;
+            }
+            if self.declared[reg..next].iter().all(|declared| *declared) {
+                let targets: Vec<Expr> = names.into_iter().map(Expr::Name).collect();
+                self.stmts.push(Stmt::Assign(targets, vec![value]));
+            } else {
+                (reg..next).for_each(|reg| self.declare(reg));
+                self.stmts.push(Stmt::Local(names, vec![value]));
+            }
+            reg = next;
+        }
+    }
+
+    fn declare(&mut self, reg: usize) {
+        self.declared[reg] = true;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(reg);
+        }
+    }
+
+    fn emit(&mut self, stmt: Stmt) {
+        self.close_stores();
+        self.flush_pending();
+        self.stmts.push(stmt);
+    }
+
+    fn is_param(&self, local: &LocalInfo) -> bool {
+        local.start_pc == 0
+            && (local.reg < self.proto.param_count as usize
+                || (local.reg == self.proto.param_count as usize
+                    && local.name == "arg"
+                    && self.proto.is_vararg))
+    }
+
+    fn end_locals(&mut self, pc: usize) {
+        let mut active: Vec<usize> = std::mem::take(&mut self.active_locals);
+        active.retain(|index| {
+            let local: &LocalInfo = &self.locals[*index];
+            if local.end_pc > pc {
+                return true;
+            }
+            if matches!(&self.regs[local.reg], Reg::Local(name) if *name == local.name) {
+                self.regs[local.reg] = Reg::Free;
+            }
+            false
+        });
+        self.active_locals = active;
+    }
+
+    // declares the locals that are alive from pc on, with the values left in their registers
+    fn declare_locals(&mut self, pc: usize) {
+        let mut names: Vec<String> = Vec::new();
+        let mut values: Vec<Option<Expr>> = Vec::new();
+        while let Some(local) = self.locals.get(self.next_local).cloned() {
+            if local.start_pc > pc {
+                break;
+            }
+            self.active_locals.push(self.next_local);
+            self.next_local += 1;
+            if local.reg >= NUM_REGS || local.hidden {
+                continue;
+            }
+            if self.is_param(&local) {
+                self.regs[local.reg] = Reg::Local(local.name);
+                continue;
+            }
+            let value: Option<Expr> = match &self.regs[local.reg] {
+                Reg::Free => None,
+                Reg::Pending(_) | Reg::Extra => Some(self.take(local.reg)),
+                Reg::Local(name) | Reg::Temp(name) => Some(Expr::Name(name.clone())),
+            };
+            self.regs[local.reg] = Reg::Local(local.name.clone());
+            names.push(local.name);
+            values.push(value);
+        }
+        if names.is_empty() {
+            return;
+        }
+        while let Some(None) = values.last() {
+            values.pop();
+        }
+        let values: Vec<Expr> = values
+            .into_iter()
+            .map(|value| value.unwrap_or(Expr::Nil))
+            .collect();
+        self.emit(Stmt::Local(names, values));
+    }
+
+    // None when an expression turned out to need statements, which can only be decompiled
+    // without structure
+    fn decompile(mut self) -> DecompileResult<Option<Function>> {
+        let proto: &'a Proto = self.proto;
+        let mut params: Vec<String> = Vec::with_capacity(proto.param_count as usize);
+        for reg in 0..proto.param_count as usize {
+            let name: String = match self
+                .locals
+                .iter()
+                .find(|local| local.start_pc == 0 && local.reg == reg)
+            {
+                Some(local) => local.name.clone(),
+                None => format!("arg_{}", reg),
+            };
+            self.regs[reg] = Reg::Local(name.clone());
+            params.push(name);
+        }
+        self.declare_locals(0);
+
+        // what doesn't come out as structured code keeps its jumps as comments
+        let block: Option<Block> = match self.structured {
+            true => self.structure(0, proto.code.len(), None),
+            false => None,
+        };
+        match block {
+            Some(block) => {
+                self.unshare(&block.nodes);
+                self.decompile_nodes(&block.nodes)?;
+                if !self.structured {
+                    return Ok(None);
+                }
+            }
+            None => self.decompile_jumps()?,
+        }
+        self.flush();
+
+        Ok(Some(Function {
+            params,
+            is_vararg: proto.is_vararg,
+            body: self.stmts,
+        }))
+    }
+
+    fn decompile_jumps(&mut self) -> DecompileResult<()> {
+        let mut is_target: Vec<bool> = vec![false; self.cfg.blocks.len()];
+        for block in &self.cfg.blocks {
+            if let Some(jump) = block.jump {
+                is_target[jump] = true;
+            }
+        }
+        for (block_index, target) in is_target.into_iter().enumerate() {
+            let (start, end): (usize, usize) = (
+                self.cfg.blocks[block_index].start,
+                self.cfg.blocks[block_index].end,
+            );
+            if !self.is_reachable(start) {
+                continue;
+            }
+            if target {
+                let pc: usize = self.pcs[start];
+                self.stmts.push(Stmt::Comment(format!("::pc_{}::", pc)));
+            }
+            self.decompile_code(start, end)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        self.close_stores();
+        self.flush_pending();
+    }
+
+    // gets ready to decompile the instruction at index
+    fn enter(&mut self, index: usize) {
+        self.index = index;
+        self.pc = self.pcs[index];
+        if let Some(block) = self.cfg.block_at(index) {
+            let (start, end): (usize, usize) =
+                (self.cfg.blocks[block].start, self.cfg.blocks[block].end);
+            if start == index {
+                self.top = None;
+            }
+            self.block_end_pc = self.pc_at(end);
+        }
+        self.end_locals(self.pc);
+        self.declare_locals(self.pc);
+    }
+
+    fn decompile_code(&mut self, start: usize, end: usize) -> DecompileResult<()> {
+        let code_len: usize = self.proto.code.len();
+        let mut index: usize = start;
+        while index < end {
+            if !self.is_reachable(index) {
+                index += 1;
+                continue;
+            }
+            self.enter(index);
+            let next: usize = self.decompile_instruction(index)?.min(code_len);
+            self.declare_locals(self.pc_at(next));
+            let block_end: usize = self
+                .cfg
+                .block_at(index)
+                .map_or(code_len, |block| self.cfg.blocks[block].end);
+            if next >= block_end {
+                self.flush();
+            }
+            index = next;
+        }
+        Ok(())
+    }
+
+    fn decompile_nodes(&mut self, nodes: &[Node]) -> DecompileResult<()> {
+        for node in nodes {
+            match node {
+                Node::Code(start, end) => self.decompile_code(*start, *end)?,
+                Node::Break(index) => {
+                    self.enter(*index);
+                    self.flush();
+                    self.stmts.push(Stmt::Break);
+                }
+                Node::Value(chain) => self.decompile_value(chain)?,
+                Node::If {
+                    condition,
+                    then_block,
+                    else_block,
+                    end,
+                } => {
+                    let test: usize = condition.tests[0].1;
+                    let condition: Expr = self.chain_expr(condition)?;
+                    self.flush();
+                    self.hoist(test, *end);
+                    let then_stmts: Vec<Stmt> = self.decompile_block(then_block)?;
+                    let else_stmts: Vec<Stmt> = match else_block {
+                        Some(else_block) => self.decompile_block(else_block)?,
+                        None => Vec::new(),
+                    };
+                    self.stmts.push(Stmt::If(condition, then_stmts, else_stmts));
+                }
+                Node::While {
+                    head,
+                    condition,
+                    body,
+                    end,
+                } => {
+                    self.enter_loop(*head, *end);
+                    let outer: Vec<Stmt> = self.open_scope();
+                    let chain: &Option<(Block, Chain)> = condition;
+                    let mut condition: Expr = Expr::Boolean(true);
+                    if let Some((before, chain)) = chain {
+                        self.decompile_nodes(&before.nodes)?;
+                        condition = self.chain_expr(chain)?;
+                        self.flush();
+                        // a condition that needed statements of its own is checked in the loop
+                        if !self.stmts.is_empty() {
+                            let exit: Expr = negate_condition(condition);
+                            self.stmts
+                                .push(Stmt::If(exit, vec![Stmt::Break], Vec::new()));
+                            condition = Expr::Boolean(true);
+                        }
+                    }
+                    self.decompile_nodes(&body.nodes)?;
+                    let body: Vec<Stmt> = self.close_scope(outer, body.end);
+                    self.break_targets.pop();
+                    self.stmts.push(Stmt::While(condition, body));
+                }
+                Node::Repeat {
+                    head,
+                    body,
+                    condition,
+                    end,
+                } => {
+                    self.enter_loop(*head, *end);
+                    let outer: Vec<Stmt> = self.open_scope();
+                    self.decompile_nodes(&body.nodes)?;
+                    // the condition still sees the locals of the body
+                    let condition: Expr = self.chain_expr(condition)?;
+                    let body: Vec<Stmt> = self.close_scope(outer, *end);
+                    self.break_targets.pop();
+                    self.stmts.push(Stmt::Repeat(body, condition));
+                }
+                Node::NumericFor { prep, body, end } => {
+                    self.enter(*prep);
+                    let a: usize = match &self.proto.code[*prep].op {
+                        OpCode::OpForPrep(OpMode::ASBX(a, _)) => self.reg(*a + 3)? - 3,
+                        _ => return Err(self.invalid_operand()),
+                    };
+                    self.close_stores();
+                    let mut range: Vec<Expr> = self.read_range(a, a + 2);
+                    if matches!(range[2], Expr::Number(step) if step == 1.0) {
+                        range.pop();
+                    }
+                    self.enter_loop(*prep, *end);
+                    let outer: Vec<Stmt> = self.open_scope();
+                    let names: Vec<String> = self.loop_variables(*prep, a + 3, 1);
+                    self.decompile_nodes(&body.nodes)?;
+                    let body: Vec<Stmt> = self.close_scope(outer, body.end);
+                    self.break_targets.pop();
+                    self.stmts
+                        .push(Stmt::NumericFor(names[0].clone(), range, body));
+                }
+                Node::GenericFor {
+                    prep,
+                    body,
+                    call,
+                    end,
+                } => {
+                    self.enter(*prep);
+                    let (a, count): (usize, usize) = match &self.proto.code[*call].op {
+                        OpCode::OpTForLoop(OpMode::ABC(a, _, c)) if *c > 0 => {
+                            self.reg(*a + 2 + *c)?;
+                            (self.reg(*a)?, *c as usize)
+                        }
+                        _ => return Err(self.invalid_operand()),
+                    };
+                    self.close_stores();
+                    // the explist is adjusted to three values, a call keeps the ones it fills
+                    let mut values: Vec<Expr> = (a..a + 3).map(|reg| self.take(reg)).collect();
+                    while values.len() > 1 && matches!(values.last(), Some(Expr::Nil)) {
+                        values.pop();
+                    }
+                    self.enter_loop(*prep, *end);
+                    let outer: Vec<Stmt> = self.open_scope();
+                    let names: Vec<String> = self.loop_variables(*prep, a + 3, count);
+                    self.decompile_nodes(&body.nodes)?;
+                    let body: Vec<Stmt> = self.close_scope(outer, body.end);
+                    self.break_targets.pop();
+                    self.stmts.push(Stmt::GenericFor(names, values, body));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the expression of a chain, with the code of its operands decompiled on the way
+    fn chain_expr(&mut self, chain: &Chain) -> DecompileResult<Expr> {
+        let mut skips: Vec<Option<Expr>> = Vec::with_capacity(chain.tests.len());
+        for (block, test) in &chain.tests {
+            self.decompile_operand(block)?;
+            self.enter(*test);
+            skips.push(Some(self.condition(*test)?));
+        }
+        let mut tail: Option<Expr> = match (&chain.tail, chain.reg) {
+            (Some(block), Some(reg)) => {
+                self.decompile_operand(block)?;
+                Some(self.take(reg))
+            }
+            _ => None,
+        };
+        Ok(shape_expr(&chain.shape, &mut skips, &mut tail))
+    }
+
+    // the code of an operand after the first, which only runs some of the time and can't
+    // have statements of its own
+    fn decompile_operand(&mut self, block: &Block) -> DecompileResult<()> {
+        let stmt_count: usize = self.stmts.len();
+        for node in &block.nodes {
+            match node {
+                Node::Code(start, end) => {
+                    let mut index: usize = *start;
+                    while index < *end {
+                        self.enter(index);
+                        index = self.decompile_instruction(index)?;
+                    }
+                }
+                Node::Value(chain) => self.decompile_value(chain)?,
+                _ => return Err(self.invalid_operand()),
+            }
+        }
+        if self.stmts.len() > stmt_count || !self.stores.is_empty() {
+            self.structured = false;
+        }
+        Ok(())
+    }
+
+    fn decompile_value(&mut self, chain: &Chain) -> DecompileResult<()> {
+        let reg: usize = match chain.reg {
+            Some(reg) => reg,
+            None => return Err(self.invalid_operand()),
+        };
+        self.close_stores();
+        // a local is assigned the whole value, not each operand that ends up in it
+        let local: Option<String> = match &self.regs[reg] {
+            Reg::Local(name) if !self.stripped || self.captured[reg] => Some(name.clone()),
+            _ => None,
+        };
+        let value_reg: Option<usize> = self.value_reg.replace(reg);
+        let value: DecompileResult<Expr> = self.chain_expr(chain);
+        self.value_reg = value_reg;
+        let value: Expr = value?;
+        match local {
+            Some(name) => {
+                self.regs[reg] = Reg::Local(name.clone());
+                self.store(Expr::Name(name), value);
+            }
+            None => {
+                self.regs[reg] = Reg::Pending(value);
+                self.writes[reg] = chain.end - 1;
+                let end_pc: usize = self.pc_at(chain.end);
+                let starts_local: bool = self.locals[self.next_local..]
+                    .iter()
+                    .any(|local| local.reg == reg && local.start_pc == end_pc);
+                if !starts_local && !self.inline_values.contains(&chain.end) {
+                    self.flush_pending();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // whether the value reg holds at index is read once, by the block index starts
+    fn is_read_once(&self, index: usize, reg: usize) -> bool {
+        let block: usize = match self.cfg.block_at(index) {
+            Some(block) => block,
+            None => return false,
+        };
+        let mut count: usize = 0;
+        for (reads, writes) in &self.uses[index..self.cfg.blocks[block].end] {
+            count += reads.iter().filter(|read| **read == reg).count();
+            if writes.contains(&reg) {
+                return count == 1;
+            }
+        }
+        count == 1 && !self.live_out[block][reg]
+    }
+
+    // whether reg is read once by the straight line code after a value chain, the operands of
+    // the chains in it only run on some paths so reading it there doesn't count
+    fn is_read_once_after(&self, end: usize, rest: &[(usize, usize, bool)], reg: usize) -> bool {
+        let mut count: usize = 0;
+        let mut last: usize = end;
+        for (start, end, is_code) in rest {
+            for (index, (reads, writes)) in self.uses[*start..*end].iter().enumerate() {
+                // the first test of a chain always runs
+                if reads.contains(&reg) && !is_code && index > 0 {
+                    return false;
+                }
+                count += reads.iter().filter(|read| **read == reg).count();
+                if writes.contains(&reg) {
+                    return count == 1;
+                }
+            }
+            last = *end;
+        }
+        if rest.is_empty() {
+            return self.is_read_once(end, reg);
+        }
+        count == 1 && !self.live_in_at(last)[reg]
+    }
+
+    // what a value chain keeps of its operands goes into it instead of being named, the
+    // chain names its own value when that's read more than once
+    fn unshare(&mut self, nodes: &[Node]) {
+        // where the straight line code before the current node starts
+        let mut run: Option<usize> = None;
+        for (position, node) in nodes.iter().enumerate() {
+            match node {
+                Node::Code(start, _) => {
+                    run.get_or_insert(*start);
+                }
+                Node::Value(chain) => {
+                    let rest: Vec<(usize, usize, bool)> = nodes[position + 1..]
+                        .iter()
+                        .map_while(|node| match node {
+                            Node::Code(start, end) => Some((*start, *end, true)),
+                            Node::Value(chain) => Some((chain.tests[0].1, chain.end, false)),
+                            _ => None,
+                        })
+                        .collect();
+                    self.unshare_chain(chain, run, &rest);
+                }
+                _ => run = None,
+            }
+            match node {
+                Node::Code(_, _) | Node::Value(_) | Node::Break(_) => {}
+                Node::If {
+                    condition,
+                    then_block,
+                    else_block,
+                    ..
+                } => {
+                    self.unshare_chain(condition, None, &[]);
+                    self.unshare(&then_block.nodes);
+                    if let Some(else_block) = else_block {
+                        self.unshare(&else_block.nodes);
+                    }
+                }
+                Node::While {
+                    condition, body, ..
+                } => {
+                    if let Some((before, condition)) = condition {
+                        self.unshare(&before.nodes);
+                        self.unshare_chain(condition, None, &[]);
+                    }
+                    self.unshare(&body.nodes);
+                }
+                Node::Repeat {
+                    body, condition, ..
+                } => {
+                    self.unshare(&body.nodes);
+                    self.unshare_chain(condition, None, &[]);
+                }
+                Node::NumericFor { body, .. } | Node::GenericFor { body, .. } => {
+                    self.unshare(&body.nodes)
+                }
+            }
+        }
+    }
+
+    fn unshare_chain(&mut self, chain: &Chain, run: Option<usize>, rest: &[(usize, usize, bool)]) {
+        for (block, _) in &chain.tests {
+            self.unshare(&block.nodes);
+        }
+        if let Some(tail) = &chain.tail {
+            self.unshare(&tail.nodes);
+        }
+        let reg: usize = match chain.reg {
+            Some(reg) => reg,
+            None => return,
+        };
+        self.shared.remove(&(chain.end - 1, reg));
+        if self.is_read_once_after(chain.end, rest, reg) {
+            self.inline_values.insert(chain.end);
+        }
+        if let Some(tail) = &chain.tail {
+            self.shared.remove(&(tail.end - 1, reg));
+        }
+        let first_test: usize = chain.tests[0].1;
+        let first: usize = run.unwrap_or_else(|| {
+            self.cfg
+                .block_at(first_test)
+                .map_or(first_test, |block| self.cfg.blocks[block].start)
+        });
+        // such as the function a call with the chain as argument reads after it
+        let live: Vec<bool> = self.live_in_at(chain.end);
+        for other in (0..NUM_REGS).filter(|other| live[*other] && *other != reg) {
+            let is_untouched: bool = self.uses[first_test..chain.end]
+                .iter()
+                .all(|(reads, writes)| !reads.contains(&other) && !writes.contains(&other));
+            if is_untouched && self.is_read_once_after(chain.end, rest, other) {
+                self.unshare_write(first, first_test, other);
+            }
+        }
+        for (_, test) in &chain.tests {
+            let is_kept: bool = matches!(
+                self.proto.code[*test].op,
+                OpCode::OpTest(OpMode::ABC(a, _, _)) if a as usize == reg
+            );
+            // the operand after the test can read the value too
+            if !is_kept || self.live_in_at(test + 2)[reg] {
+                continue;
+            }
+            let first: usize = self
+                .cfg
+                .block_at(*test)
+                .map_or(*test, |block| self.cfg.blocks[block].start);
+            self.unshare_write(first, *test, reg);
+        }
+    }
+
+    // the last write to reg from first up to index, unless something read it since
+    fn unshare_write(&mut self, first: usize, index: usize, reg: usize) {
+        for index in (first..index).rev() {
+            let (reads, writes): &(Vec<usize>, Vec<usize>) = &self.uses[index];
+            if writes.contains(&reg) {
+                self.shared.remove(&(index, reg));
+                return;
+            }
+            if reads.contains(&reg) {
+                return;
+            }
+        }
+    }
+
+    fn decompile_block(&mut self, block: &Block) -> DecompileResult<Vec<Stmt>> {
+        let outer: Vec<Stmt> = self.open_scope();
+        self.decompile_nodes(&block.nodes)?;
+        Ok(self.close_scope(outer, block.end))
+    }
+
+    // statements before a loop run once, so they're written out before it starts
+    fn enter_loop(&mut self, start: usize, end: usize) {
+        self.flush();
+        self.hoist(start, end);
+        self.break_targets.push(self.resolve(end));
+    }
+
+    fn open_scope(&mut self) -> Vec<Stmt> {
+        self.scopes.push(Vec::new());
+        std::mem::take(&mut self.stmts)
+    }
+
+    // the statements of the block that started with open_scope, end is where it's left
+    fn close_scope(&mut self, outer: Vec<Stmt>, end: usize) -> Vec<Stmt> {
+        self.flush();
+        self.end_locals(self.pc_at(end));
+        for reg in self.scopes.pop().unwrap_or_default() {
+            self.declared[reg] = false;
+            self.captured[reg] = false;
+            if matches!(self.regs[reg], Reg::Local(_) | Reg::Temp(_)) {
+                self.regs[reg] = Reg::Free;
+            }
+        }
+        std::mem::replace(&mut self.stmts, outer)
+    }
+
+    // the variables of the loop entered at prep, which start with its body
+    fn loop_variables(&mut self, prep: usize, first: usize, count: usize) -> Vec<String> {
+        let body_pc: usize = self.pc_at(prep + 1);
+        let mut names: Vec<String> = Vec::with_capacity(count);
+        for reg in first..first + count {
+            let name: String = match self.locals.get(self.next_local) {
+                Some(local) if local.reg == reg && local.start_pc == body_pc => {
+                    self.active_locals.push(self.next_local);
+                    self.next_local += 1;
+                    local.name.clone()
+                }
+                _ => format!("r_{}", reg),
+            };
+            self.regs[reg] = Reg::Local(name.clone());
+            if name == format!("r_{}", reg) {
+                self.declare(reg);
+            } else if let Some(scope) = self.scopes.last_mut() {
+                self.declared[reg] = false;
+                scope.push(reg);
+            }
+            names.push(name);
+        }
+        names
+    }
+
+    // registers some instruction after index reads before writing them
+    fn live_in_at(&self, index: usize) -> Vec<bool> {
+        let block: usize = match self.cfg.block_at(index) {
+            Some(block) => block,
+            None => return vec![false; NUM_REGS],
+        };
+        let mut live: Vec<bool> = self.live_out[block].clone();
+        for (reads, writes) in self.uses[index..self.cfg.blocks[block].end].iter().rev() {
+            for reg in writes {
+                live[*reg] = false;
+            }
+            for reg in reads {
+                live[*reg] = true;
+            }
+        }
+        live
+    }
+
+    // registers the statement from start to end writes that are read after it, which have to
+    // be declared outside of it
+    fn hoist(&mut self, start: usize, end: usize) {
+        let live: Vec<bool> = self.live_in_at(end);
+        let mut written: Vec<bool> = vec![false; NUM_REGS];
+        for (_, writes) in &self.uses[start..end.min(self.uses.len())] {
+            for reg in writes {
+                written[*reg] = true;
+            }
+        }
+        for reg in (0..NUM_REGS).filter(|reg| live[*reg] && written[*reg]) {
+            match &self.regs[reg] {
+                Reg::Local(_) => {}
+                Reg::Temp(name) => self.regs[reg] = Reg::Local(name.clone()),
+                _ => {
+                    let name: String = format!("r_{}", reg);
+                    if !self.declared[reg] {
+                        self.stmts.push(Stmt::Local(vec![name.clone()], Vec::new()));
+                        self.declare(reg);
+                    }
+                    self.regs[reg] = Reg::Local(name);
+                }
+            }
+        }
+    }
+
+    // a method SELF left pending is called on the object it passes as the first argument
+    fn method_call(function: Expr, args: &mut Vec<Expr>) -> Expr {
+        match function {
+            Expr::Method(object, name) if matches!(*object, Expr::Extra) && !args.is_empty() => {
+                Expr::Method(Box::new(args.remove(0)), name)
+            }
+            function => function,
+        }
+    }
+
+    fn is_store(op: &OpCode) -> bool {
+        matches!(
+            op,
+            OpCode::OpMove(_)
+                | OpCode::OpSetGlobal(_)
+                | OpCode::OpSetUpval(_)
+                | OpCode::OpSetTable(_)
+        )
+    }
+
+    // returns the index of the next instruction to decompile
+    fn decompile_instruction(&mut self, index: usize) -> DecompileResult<usize> {
+        let proto: &'a Proto = self.proto;
+        let op: &OpCode = &proto.code[index].op;
+        let pc: usize = self.pc;
+        if !self.stores.is_empty() && !Self::is_store(op) {
+            self.close_stores();
+        }
+
+        match op {
+            OpCode::OpMove(OpMode::ABC(a, b, _)) => {
+                let (a, b): (usize, usize) = (self.reg(*a)?, self.reg(*b)?);
+                let value: Expr = self.read(b);
+                self.write(a, value);
+            }
+            OpCode::OpLoadK(OpMode::ABX(a, bx)) => {
+                let a: usize = self.reg(*a)?;
+                let value: Expr = self.constant(*bx)?;
+                self.write(a, value);
+            }
+            OpCode::OpLoadBool(OpMode::ABC(a, b, c)) => {
+                let a: usize = self.reg(*a)?;
+                self.write(a, Expr::Boolean(*b != 0));
+                if *c != 0 {
+                    self.close_stores();
+                    self.flush_pending();
+                    self.stmts
+                        .push(Stmt::Comment(format!("goto pc_{}", pc + 2)));
+                }
+            }
+            OpCode::OpLoadNil(OpMode::ABC(a, b, _)) => {
+                let (a, b): (usize, usize) = (self.reg(*a)?, self.reg(*b)?);
+                for reg in a..=b {
+                    self.write(reg, Expr::Nil);
+                }
+            }
+            OpCode::OpGetUpval(OpMode::ABC(a, b, _)) => {
+                let a: usize = self.reg(*a)?;
+                let value: Expr = self.upvalue(*b)?;
+                self.write(a, value);
+            }
+            OpCode::OpGetGlobal(OpMode::ABX(a, bx)) => {
+                let a: usize = self.reg(*a)?;
+                let value: Expr = self.global(*bx)?;
+                self.write(a, value);
+            }
+            OpCode::OpGetTable(OpMode::ABC(a, b, c)) => {
+                let (a, b): (usize, usize) = (self.reg(*a)?, self.reg(*b)?);
+                let table: Expr = self.read(b);
+                let key: Expr = self.rk(*c)?;
+                self.write(a, Expr::Index(Box::new(table), Box::new(key)));
+            }
+            OpCode::OpSetGlobal(OpMode::ABX(a, bx)) => {
+                let a: usize = self.reg(*a)?;
+                let target: Expr = self.global(*bx)?;
+                let value: Expr = self.read(a);
+                self.store(target, value);
+            }
+            OpCode::OpSetUpval(OpMode::ABC(a, b, _)) => {
+                let a: usize = self.reg(*a)?;
+                let target: Expr = self.upvalue(*b)?;
+                let value: Expr = self.read(a);
+                self.store(target, value);
+            }
+            OpCode::OpSetTable(OpMode::ABC(a, b, c)) if self.members.contains_key(&index) => {
+                let a: usize = self.reg(*a)?;
+                let key: Expr = self.rk(*b)?;
+                let value: Expr = self.rk(*c)?;
+                match self.open_table(a) {
+                    Some(mut fields) => {
+                        // the items evaluated before the field go first
+                        let first: usize = self.list_start(a);
+                        for reg in first..NUM_REGS {
+                            if !matches!(self.regs[reg], Reg::Pending(_) | Reg::Extra) {
+                                break;
+                            }
+                            fields.push((None, self.take(reg)));
+                        }
+                        fields.push((Some(key), value));
+                        self.write(a, Expr::Table(fields));
+                    }
+                    None => {
+                        let table: Expr = self.read(a);
+                        self.store(Expr::Index(Box::new(table), Box::new(key)), value);
+                    }
+                }
+            }
+            OpCode::OpSetTable(OpMode::ABC(a, b, c)) => {
+                let a: usize = self.reg(*a)?;
+                let table: Expr = self.read(a);
+                let key: Expr = self.rk(*b)?;
+                let value: Expr = self.rk(*c)?;
+                self.store(Expr::Index(Box::new(table), Box::new(key)), value);
+            }
+            OpCode::OpNewTable(OpMode::ABC(a, _, _)) => {
+                let a: usize = self.reg(*a)?;
+                self.write(a, Expr::Table(Vec::new()));
+            }
+            OpCode::OpSelf(OpMode::ABC(a, b, c)) => {
+                let object_reg: usize = self.reg(*a + 1)?;
+                let (a, b): (usize, usize) = (self.reg(*a)?, self.reg(*b)?);
+                let object: Expr = self.read(b);
+                let method: Option<Vec<u8>> = match *c & BITRK != 0 {
+                    true => match self.constant(*c & !BITRK)? {
+                        Expr::String(name) if is_identifier(&name) => Some(name),
+                        _ => None,
+                    },
+                    false => None,
+                };
+                // the call takes the object back out of its first argument
+                if let Some(name) = method {
+                    self.write(object_reg, object);
+                    self.write(a, Expr::Method(Box::new(Expr::Extra), name));
+                    return Ok(index + 1);
+                }
+                // the object is both indexed and passed on
+                let object: Expr = self.shared_value(object_reg, object);
+                if object.is_simple() {
+                    self.write(object_reg, object.clone());
+                }
+                let key: Expr = self.rk(*c)?;
+                self.write(a, Expr::Index(Box::new(object), Box::new(key)));
+            }
+            OpCode::OpAdd(OpMode::ABC(a, b, c))
+            | OpCode::OpSub(OpMode::ABC(a, b, c))
+            | OpCode::OpMul(OpMode::ABC(a, b, c))
+            | OpCode::OpDiv(OpMode::ABC(a, b, c))
+            | OpCode::OpMod(OpMode::ABC(a, b, c))
+            | OpCode::OpPow(OpMode::ABC(a, b, c)) => {
+                let binary_op: BinaryOp = match op {
+                    OpCode::OpAdd(_) => BinaryOp::Add,
+                    OpCode::OpSub(_) => BinaryOp::Sub,
+                    OpCode::OpMul(_) => BinaryOp::Mul,
+                    OpCode::OpDiv(_) => BinaryOp::Div,
+                    OpCode::OpMod(_) => BinaryOp::Mod,
+                    _ => BinaryOp::Pow,
+                };
+                let a: usize = self.reg(*a)?;
+                let left: Expr = self.rk(*b)?;
+                let right: Expr = self.rk(*c)?;
+                self.write(a, Expr::Binary(binary_op, Box::new(left), Box::new(right)));
+            }
+            OpCode::OpUnm(OpMode::ABC(a, b, _))
+            | OpCode::OpNot(OpMode::ABC(a, b, _))
+            | OpCode::OpLen(OpMode::ABC(a, b, _)) => {
+                let unary_op: UnaryOp = match op {
+                    OpCode::OpUnm(_) => UnaryOp::Neg,
+                    OpCode::OpNot(_) => UnaryOp::Not,
+                    _ => UnaryOp::Len,
+                };
+                let (a, b): (usize, usize) = (self.reg(*a)?, self.reg(*b)?);
+                let operand: Expr = self.read(b);
+                self.write(a, Expr::Unary(unary_op, Box::new(operand)));
+            }
+            OpCode::OpConcat(OpMode::ABC(a, b, c)) => {
+                let (a, b, c): (usize, usize, usize) =
+                    (self.reg(*a)?, self.reg(*b)?, self.reg(*c)?);
+                if c < b {
+                    return Err(self.invalid_operand());
+                }
+                let mut parts: Vec<Expr> = self.read_range(b, c);
+                let mut value: Expr = parts.pop().unwrap_or(Expr::Nil);
+                while let Some(part) = parts.pop() {
+                    value = Expr::Binary(BinaryOp::Concat, Box::new(part), Box::new(value));
+                }
+                self.write(a, value);
+            }
+            OpCode::OpJmp(OpMode::ASBX(_, sbx)) => {
+                let target: isize = pc as isize + 1 + *sbx as isize;
+                self.close_stores();
+                self.flush_pending();
+                self.stmts
+                    .push(Stmt::Comment(format!("goto pc_{}", target)));
+            }
+            OpCode::OpEq(_) | OpCode::OpLt(_) | OpCode::OpLe(_) | OpCode::OpTest(_) => {
+                let skip: Expr = self.condition(index)?;
+                self.push_skip(skip, pc);
+            }
+            OpCode::OpTestSet(OpMode::ABC(a, b, c)) => {
+                let (a, b): (usize, usize) = (self.reg(*a)?, self.reg(*b)?);
+                let value: Expr = self.read(b);
+                let value: Expr = self.shared_value(b, value);
+                let skip: Expr = match *c != 0 {
+                    true => Expr::Unary(UnaryOp::Not, Box::new(value.clone())),
+                    false => value.clone(),
+                };
+                self.push_skip(skip, pc);
+                // only happens when the next instruction isn't skipped
+                self.write(a, value);
+            }
+            OpCode::OpCall(OpMode::ABC(a, b, c)) => {
+                let a: usize = self.reg(*a)?;
+                let function: Expr = self.read(a);
+                let mut args: Vec<Expr> = match self.range_end(a + 1, *b)? {
+                    Some(last) => self.read_range(a + 1, last),
+                    None => Vec::new(),
+                };
+                let function: Expr = Self::method_call(function, &mut args);
+                let call = |multi: bool| -> Expr { Expr::Call(Box::new(function), args, multi) };
+                match *c {
+                    0 => {
+                        self.write(a, call(true));
+                        self.top = Some(a);
+                    }
+                    1 => self.emit(Stmt::Call(call(false))),
+                    2 => self.write(a, call(false)),
+                    c => {
+                        let last: usize = self.reg(a as LuaInt + c - 2)?;
+                        self.write_multiple(a, last, call(true));
+                    }
+                }
+            }
+            OpCode::OpTailCall(OpMode::ABC(a, b, _)) => {
+                let a: usize = self.reg(*a)?;
+                let function: Expr = self.read(a);
+                let mut args: Vec<Expr> = match self.range_end(a + 1, *b)? {
+                    Some(last) => self.read_range(a + 1, last),
+                    None => Vec::new(),
+                };
+                let function: Expr = Self::method_call(function, &mut args);
+                self.emit(Stmt::Return(vec![Expr::Call(
+                    Box::new(function),
+                    args,
+                    true,
+                )]));
+            }
+            OpCode::OpReturn(OpMode::ABC(a, b, _)) => {
+                let a: usize = self.reg(*a)?;
+                let values: Vec<Expr> = match self.range_end(a, *b)? {
+                    Some(last) => self.read_range(a, last),
+                    None => Vec::new(),
+                };
+                // every function ends in a return nothing asked for
+                if !(values.is_empty() && index + 1 == proto.code.len()) {
+                    self.emit(Stmt::Return(values));
+                }
+            }
+            OpCode::OpForLoop(OpMode::ASBX(a, sbx)) => {
+                self.reg(*a + 3)?;
+                let a: usize = self.reg(*a)?;
+                let target: isize = pc as isize + 1 + *sbx as isize;
+                let names: Vec<String> = (a..a + 4).map(|reg| self.name_of(reg)).collect();
+                self.close_stores();
+                self.flush_pending();
+                self.stmts.push(Stmt::Comment(format!(
+                    "{0} = {0} + {2}; if {0} is within {1} then {3} = {0}; goto pc_{4}",
+                    names[0], names[1], names[2], names[3], target
+                )));
+            }
+            OpCode::OpForPrep(OpMode::ASBX(a, sbx)) => {
+                self.reg(*a + 2)?;
+                let a: usize = self.reg(*a)?;
+                let target: isize = pc as isize + 1 + *sbx as isize;
+                self.close_stores();
+                self.flush_pending();
+                let (index_name, step_name): (String, String) =
+                    (self.name_of(a), self.name_of(a + 2));
+                self.stmts.push(Stmt::Comment(format!(
+                    "{0} = {0} - {1}; goto pc_{2}",
+                    index_name, step_name, target
+                )));
+            }
+            OpCode::OpTForLoop(OpMode::ABC(a, _, c)) if *c > 0 => {
+                let a: usize = self.reg(*a)?;
+                let last: usize = self.reg(a as LuaInt + 2 + *c)?;
+                self.close_stores();
+                self.flush_pending();
+                let names: Vec<String> = (a + 3..=last).map(|reg| self.name_of(reg)).collect();
+                self.stmts.push(Stmt::Comment(format!(
+                    "{} = {}({}, {}); if {} == nil then goto pc_{} end; {} = {}",
+                    names.join(", "),
+                    self.name_of(a),
+                    self.name_of(a + 1),
+                    self.name_of(a + 2),
+                    names[0],
+                    pc + 2,
+                    self.name_of(a + 2),
+                    names[0]
+                )));
+            }
+            OpCode::OpSetList(OpMode::ABC(a, b, c)) => {
+                let a: usize = self.reg(*a)?;
+                // the items before a field of the constructor went into it there
+                let taken: usize = match self.members.contains_key(&index) {
+                    true => self.list_start(a) - (a + 1),
+                    false => 0,
+                };
+                let values: Vec<Expr> = match *b {
+                    0 => match self.range_end(a + 1, 0)? {
+                        Some(last) => self.read_range(a + 1 + taken, last),
+                        None => Vec::new(),
+                    },
+                    b => {
+                        let last: usize = self.reg(a as LuaInt + b)?;
+                        self.read_range(a + 1 + taken, last)
+                    }
+                };
+                if let Some(mut fields) = self.open_table(a) {
+                    fields.extend(values.into_iter().map(|value| (None, value)));
+                    self.write(a, Expr::Table(fields));
+                    return Ok(index + 1);
+                }
+                let table: Expr = self.read(a);
+                let first: usize = ((*c).max(1) - 1) as usize * FIELDS_PER_FLUSH + 1 + taken;
+                let targets: Vec<Expr> = (0..values.len())
+                    .map(|i| {
+                        Expr::Index(
+                            Box::new(table.clone()),
+                            Box::new(Expr::Number((first + i) as f64)),
+                        )
+                    })
+                    .collect();
+                // one statement per value keeps long lists under the register limit
+                if values.iter().all(Expr::is_simple) {
+                    for (target, value) in targets.into_iter().zip(values) {
+                        self.emit(Stmt::Assign(vec![target], vec![value]));
+                    }
+                } else if !targets.is_empty() {
+                    self.emit(Stmt::Assign(targets, values));
+                }
+            }
+            // the locals from A on are gone, a stripped chunk only learns it here
+            OpCode::OpClose(OpMode::ABX(a, _)) => {
+                let a: usize = self.reg(*a)?;
+                // a break closes the locals of the loop body, which go on after it
+                if self.stripped && !self.is_break(index + 1) {
+                    self.close_stores();
+                    self.flush_pending();
+                    for reg in a..NUM_REGS {
+                        if matches!(self.regs[reg], Reg::Local(_)) {
+                            self.regs[reg] = Reg::Free;
+                        }
+                        self.captured[reg] = false;
+                        self.declared[reg] = false;
+                    }
+                }
+            }
+            OpCode::OpClosure(OpMode::ABX(a, bx)) => {
+                let a: usize = self.reg(*a)?;
+                let child: &'a Proto = match usize::try_from(*bx)
+                    .ok()
+                    .and_then(|index| proto.protos.get(index))
+                {
+                    Some(child) => child,
+                    None => return Err(self.invalid_operand()),
+                };
+                // the child's upvalues come from the pseudo-instructions after the CLOSURE
+                let captures_end: usize = index + 1 + child.upvalue_count as usize;
+                if captures_end > proto.code.len() {
+                    return Err(self.invalid_operand());
+                }
+                // a local function sees itself before the local starts
+                let is_local_function: bool = proto.code[index + 1..captures_end]
+                    .iter()
+                    .any(|capture| matches!(capture.op, OpCode::OpMove(OpMode::ABC(_, b, _)) if b as usize == a));
+                if is_local_function {
+                    let next_pc: usize = self.pcs.get(captures_end).copied().unwrap_or(usize::MAX);
+                    let name: String = match self.locals.get(self.next_local) {
+                        Some(local) if local.reg == a && local.start_pc == next_pc => {
+                            self.active_locals.push(self.next_local);
+                            self.next_local += 1;
+                            local.name.clone()
+                        }
+                        _ => format!("r_{}", a),
+                    };
+                    if matches!(self.regs[a], Reg::Pending(_) | Reg::Extra) {
+                        self.flush_pending();
+                    }
+                    self.regs[a] = Reg::Local(name);
+                }
+                let mut upvalue_names: Vec<String> = Vec::with_capacity(captures_end - index - 1);
+                for capture in &proto.code[index + 1..captures_end] {
+                    let name: String = match &capture.op {
+                        OpCode::OpMove(OpMode::ABC(_, b, _)) => {
+                            let b: usize = self.reg(*b)?;
+                            if matches!(self.regs[b], Reg::Pending(_) | Reg::Extra) {
+                                self.flush_pending();
+                            }
+                            self.captured[b] = true;
+                            self.name_of(b)
+                        }
+                        OpCode::OpGetUpval(OpMode::ABC(_, b, _)) => match self.upvalue(*b)? {
+                            Expr::Name(name) => name,
+                            _ => return Err(self.invalid_operand()),
+                        },
+                        _ => return Err(self.invalid_operand()),
+                    };
+                    upvalue_names.push(name);
+                }
+                let function: Function = decompile_proto(
+                    child,
+                    format!("{}/{}", self.proto_path, child.id),
+                    upvalue_names,
+                )?;
+                match is_local_function {
+                    true => {
+                        let name: String = self.name_of(a);
+                        self.emit(Stmt::LocalFunction(name, Box::new(function)));
+                    }
+                    false => self.write(a, Expr::Function(Box::new(function))),
+                }
+                return Ok(captures_end);
+            }
+            OpCode::OpVararg(OpMode::ABC(a, b, _)) => {
+                let a: usize = self.reg(*a)?;
+                match *b {
+                    0 => {
+                        self.write(a, Expr::Vararg(true));
+                        self.top = Some(a);
+                    }
+                    1 => {}
+                    2 => self.write(a, Expr::Vararg(false)),
+                    b => {
+                        let last: usize = self.reg(a as LuaInt + b - 2)?;
+                        self.write_multiple(a, last, Expr::Vararg(true));
+                    }
+                }
+            }
+            _ => {
+                return Err(self.error(DecompileErrorKind::UnexpectedInstruction { pc }));
+            }
+        }
+        Ok(index + 1)
+    }
+
+    // what the comparison or test at index needs to hold to skip the JMP after it
+    fn condition(&mut self, index: usize) -> DecompileResult<Expr> {
+        let op: &OpCode = &self.proto.code[index].op;
+        match op {
+            OpCode::OpEq(OpMode::ABC(a, b, c))
+            | OpCode::OpLt(OpMode::ABC(a, b, c))
+            | OpCode::OpLe(OpMode::ABC(a, b, c)) => {
+                let left: Expr = self.rk(*b)?;
+                let right: Expr = self.rk(*c)?;
+                // the comparison skips the next instruction when it doesn't come out as A
+                let skip: Expr = match (op, *a != 0) {
+                    (OpCode::OpEq(_), true) => {
+                        Expr::Binary(BinaryOp::Ne, Box::new(left), Box::new(right))
+                    }
+                    (OpCode::OpEq(_), false) => {
+                        Expr::Binary(BinaryOp::Eq, Box::new(left), Box::new(right))
+                    }
+                    (_, expected) => {
+                        let binary_op: BinaryOp = match op {
+                            OpCode::OpLt(_) => BinaryOp::Lt,
+                            _ => BinaryOp::Le,
+                        };
+                        let compare: Expr =
+                            Expr::Binary(binary_op, Box::new(left), Box::new(right));
+                        match expected {
+                            true => Expr::Unary(UnaryOp::Not, Box::new(compare)),
+                            false => compare,
+                        }
+                    }
+                };
+                Ok(skip)
+            }
+            // a TESTSET's value is only kept by a chain
+            OpCode::OpTest(OpMode::ABC(a, _, c)) | OpCode::OpTestSet(OpMode::ABC(_, a, c)) => {
+                let a: usize = self.reg(*a)?;
+                let value: Expr = self.read(a);
+                let skip: Expr = match *c != 0 {
+                    true => Expr::Unary(UnaryOp::Not, Box::new(value)),
+                    false => value,
+                };
+                Ok(skip)
+            }
+            _ => Err(self.error(DecompileErrorKind::UnexpectedInstruction { pc: self.pc })),
+        }
+    }
+
+    // the comparison or test at pc skips the JMP after it when skip holds
+    fn push_skip(&mut self, skip: Expr, pc: usize) {
+        self.close_stores();
+        self.flush_pending();
+        self.stmts.push(Stmt::Comment(format!(
+            "if {} then goto pc_{} end",
+            skip.format(0),
+            pc + 2
+        )));
+    }
+
+    fn pc_at(&self, index: usize) -> usize {
+        match self.pcs.get(index) {
+            Some(pc) => *pc,
+            None => self.pcs.last().map_or(0, |pc| pc + 1),
+        }
+    }
+
+    fn is_reachable(&self, index: usize) -> bool {
+        match self.cfg.block_at(index) {
+            Some(block) => block == 0 || !self.cfg.blocks[block].predecessors.is_empty(),
+            None => false,
+        }
+    }
+
+    fn jump_target(&self, index: usize) -> Option<usize> {
+        match self.proto.code.get(index).map(|inst| &inst.op) {
+            Some(OpCode::OpJmp(OpMode::ASBX(_, sbx)))
+            | Some(OpCode::OpForPrep(OpMode::ASBX(_, sbx)))
+            | Some(OpCode::OpForLoop(OpMode::ASBX(_, sbx))) => jump_index(&self.pcs, index, *sbx),
+            _ => None,
+        }
+    }
+
+    // luac sends jumps that land on a JMP straight on to where that one goes, so targets
+    // are compared by where their chain of JMPs ends
+    fn resolve(&self, index: usize) -> usize {
+        let mut index: usize = index;
+        for _ in 0..self.proto.code.len() {
+            match self.proto.code.get(index).map(|inst| &inst.op) {
+                Some(OpCode::OpJmp(_)) => match self.jump_target(index) {
+                    Some(target) => index = target,
+                    None => break,
+                },
+                _ => break,
+            }
+        }
+        index
+    }
+
+    fn is_break(&self, index: usize) -> bool {
+        matches!(
+            self.proto.code.get(index).map(|inst| &inst.op),
+            Some(OpCode::OpJmp(_))
+        ) && self.jump_target(index).map(|target| self.resolve(target))
+            == self.break_targets.last().copied()
+    }
+
+    // the statements from start up to end, or None when the jumps don't fit together as
+    // if, while, repeat, for and break
+    fn structure(&self, start: usize, end: usize, break_target: Option<usize>) -> Option<Block> {
+        let end_target: usize = self.resolve(end);
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut code_start: usize = start;
+        let mut index: usize = start;
+        while index < end {
+            let next: usize = match self.structure_at(index, end, end_target, break_target)? {
+                Step::Straight => {
+                    index += 1;
+                    continue;
+                }
+                Step::Node(node, next) => {
+                    if code_start < index {
+                        nodes.push(Node::Code(code_start, index));
+                    }
+                    nodes.push(node);
+                    next
+                }
+                Step::Skip(next) => {
+                    if code_start < index {
+                        nodes.push(Node::Code(code_start, index));
+                    }
+                    next
+                }
+            };
+            index = next;
+            code_start = next;
+        }
+        if code_start < end {
+            nodes.push(Node::Code(code_start, end));
+        }
+        Some(Block { nodes, end })
+    }
+
+    fn structure_at(
+        &self,
+        index: usize,
+        end: usize,
+        end_target: usize,
+        break_target: Option<usize>,
+    ) -> Option<Step> {
+        let code: &[Instruction] = &self.proto.code;
+        // such as the RETURN luac puts after every TAILCALL
+        if !self.is_reachable(index) {
+            return Some(Step::Straight);
+        }
+        // jumping back to where the block goes on anyway is the end of a loop body
+        if self.resolve(index) != end_target {
+            let back: Option<usize> = self.back_jumps[index]
+                .iter()
+                .copied()
+                .filter(|back| *back < end)
+                .max();
+            if let Some(back) = back {
+                let (node, next): (Node, usize) = self.structure_loop(index, back)?;
+                return Some(Step::Node(node, next));
+            }
+        }
+        match &code[index].op {
+            OpCode::OpForPrep(_) => {
+                let forloop: usize = self.jump_target(index)?;
+                if forloop <= index
+                    || forloop >= end
+                    || !matches!(code[forloop].op, OpCode::OpForLoop(_))
+                    || self.jump_target(forloop) != Some(index + 1)
+                {
+                    return None;
+                }
+                let body: Block =
+                    self.structure(index + 1, forloop, Some(self.resolve(forloop + 1)))?;
+                let node: Node = Node::NumericFor {
+                    prep: index,
+                    body,
+                    end: forloop + 1,
+                };
+                Some(Step::Node(node, forloop + 1))
+            }
+            OpCode::OpJmp(_) => {
+                let target: usize = self.jump_target(index)?;
+                if generic_for_base(self.proto, &self.pcs, index).is_some() && target + 1 < end {
+                    let body: Block =
+                        self.structure(index + 1, target, Some(self.resolve(target + 2)))?;
+                    let node: Node = Node::GenericFor {
+                        prep: index,
+                        body,
+                        call: target,
+                        end: target + 2,
+                    };
+                    return Some(Step::Node(node, target + 2));
+                }
+                let resolved: usize = self.resolve(target);
+                if Some(resolved) == break_target {
+                    return Some(Step::Node(Node::Break(index), index + 1));
+                }
+                if index + 1 == end && resolved == end_target {
+                    return Some(Step::Skip(end));
+                }
+                None
+            }
+            op if is_test(op) => {
+                if let Some(chain) = self.value_chain(index, end) {
+                    let next: usize = chain.end;
+                    return Some(Step::Node(Node::Value(chain), next));
+                }
+                let (node, next): (Node, usize) =
+                    self.structure_if(index, end, end_target, break_target)?;
+                Some(Step::Node(node, next))
+            }
+            op if is_control(op) => None,
+            _ => Some(Step::Straight),
+        }
+    }
+
+    // a loop from head up to the JMP back at back
+    fn structure_loop(&self, head: usize, back: usize) -> Option<(Node, usize)> {
+        let code: &[Instruction] = &self.proto.code;
+        let end: usize = back + 1;
+        let break_target: Option<usize> = Some(self.resolve(end));
+        // repeat ends in a condition jumping back while it doesn't hold
+        if back > head && is_test(&code[back - 1].op) {
+            for test in (head..back).filter(|test| is_test(&code[*test].op)) {
+                let condition: Chain = match self
+                    .condition_chains(test, end)
+                    .into_iter()
+                    .find(|condition| condition.end == end)
+                {
+                    Some(condition) => condition,
+                    None => continue,
+                };
+                let body: Block = match self.structure(head, test, break_target) {
+                    Some(body) => body,
+                    None => continue,
+                };
+                let node: Node = Node::Repeat {
+                    head,
+                    body,
+                    condition,
+                    end,
+                };
+                return Some((node, end));
+            }
+            return None;
+        }
+        // while tests its condition first and leaves the loop when it doesn't hold
+        let mut index: usize = head;
+        while index < back
+            && self.is_reachable(index)
+            && (index == head || self.back_jumps[index].is_empty())
+        {
+            let op: &OpCode = &code[index].op;
+            if is_test(op) {
+                match self.value_chain(index, back) {
+                    Some(chain) => index = chain.end,
+                    None => break,
+                }
+            } else if is_control(op) {
+                break;
+            } else {
+                index += 1;
+            }
+        }
+        let condition: Option<(Block, Chain)> = match index < back && is_test(&code[index].op) {
+            true => self
+                .condition_chains(index, back)
+                .into_iter()
+                .rev()
+                .find(|condition| Some(self.resolve(condition.jump)) == break_target)
+                .and_then(|condition| Some((self.structure(head, index, None)?, condition))),
+            false => None,
+        };
+        let body: Block = match &condition {
+            Some((_, condition)) => self.structure(condition.end, back, break_target)?,
+            None => self.structure(head, back, break_target)?,
+        };
+        let node: Node = Node::While {
+            head,
+            condition,
+            body,
+            end,
+        };
+        Some((node, end))
+    }
+
+    // the condition starting at test skips its JMPs into the then block, the last one goes on
+    // to the else block or past the if, and a then block followed by an else ends in a JMP
+    // past it
+    fn structure_if(
+        &self,
+        test: usize,
+        end: usize,
+        end_target: usize,
+        break_target: Option<usize>,
+    ) -> Option<(Node, usize)> {
+        self.condition_chains(test, end)
+            .into_iter()
+            .rev()
+            .find_map(|condition| self.structure_if_chain(condition, end, end_target, break_target))
+    }
+
+    fn structure_if_chain(
+        &self,
+        condition: Chain,
+        end: usize,
+        end_target: usize,
+        break_target: Option<usize>,
+    ) -> Option<(Node, usize)> {
+        let code: &[Instruction] = &self.proto.code;
+        let (then_start, target): (usize, usize) = (condition.end, condition.jump);
+        let (then_end, mut next): (usize, usize) = if target >= then_start && target <= end {
+            (target, target)
+        } else if self.resolve(target) == end_target {
+            (end, end)
+        } else {
+            return None;
+        };
+
+        let mut then_stop: usize = then_end;
+        let mut else_range: Option<(usize, usize)> = None;
+        if then_end > then_start {
+            let last: usize = then_end - 1;
+            // the JMP of an if at the end of the then block isn't this one's
+            let is_escape: bool = matches!(code[last].op, OpCode::OpJmp(_))
+                && !(last > then_start && is_test(&code[last - 1].op));
+            if is_escape {
+                let escape: usize = self.jump_target(last)?;
+                if escape >= then_start && escape <= last {
+                    // the end of a loop inside the then block
+                } else if escape >= then_end && escape <= end {
+                    else_range = Some((then_end, escape));
+                    next = escape;
+                    then_stop = last;
+                } else if self.resolve(escape) == end_target {
+                    else_range = Some((then_end, end));
+                    next = end;
+                    then_stop = last;
+                } else if Some(self.resolve(escape)) != break_target {
+                    return None;
+                }
+            }
+        }
+
+        let then_block: Block = self.structure(then_start, then_stop, break_target)?;
+        let else_block: Option<Block> = match else_range {
+            Some((start, end)) => Some(self.structure(start, end, break_target)?),
+            None => None,
+        };
+        let node: Node = Node::If {
+            condition,
+            then_block,
+            else_block,
+            end: next,
+        };
+        Some((node, next))
+    }
+
+    fn starts_local(&self, index: usize) -> bool {
+        let pc: usize = self.pc_at(index);
+        self.locals
+            .iter()
+            .any(|local| local.start_pc == pc && !local.hidden)
+    }
+
+    // the code from start on that only computes values, up to limit or the first instruction
+    // that does something else, with the values made with and/or that end before limit
+    fn expression(&self, start: usize, limit: usize) -> Block {
+        let code: &[Instruction] = &self.proto.code;
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut code_start: usize = start;
+        let mut index: usize = start;
+        while index < limit && self.is_reachable(index) && !self.starts_local(index) {
+            let op: &OpCode = &code[index].op;
+            if is_test(op) {
+                match self.value_chain(index, limit) {
+                    Some(chain) if chain.end < limit => {
+                        if code_start < index {
+                            nodes.push(Node::Code(code_start, index));
+                        }
+                        index = chain.end;
+                        code_start = index;
+                        nodes.push(Node::Value(chain));
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+            if !is_expression(op) && !self.members.contains_key(&index) {
+                break;
+            }
+            index += 1;
+        }
+        if code_start < index {
+            nodes.push(Node::Code(code_start, index));
+        }
+        Block { nodes, end: index }
+    }
+
+    fn is_boolean_pair(&self, index: usize) -> bool {
+        let code: &[Instruction] = &self.proto.code;
+        match (
+            code.get(index).map(|inst| &inst.op),
+            code.get(index + 1).map(|inst| &inst.op),
+        ) {
+            (
+                Some(OpCode::OpLoadBool(OpMode::ABC(a, 0, c))),
+                Some(OpCode::OpLoadBool(OpMode::ABC(other, 1, 0))),
+            ) => a == other && *c != 0,
+            _ => false,
+        }
+    }
+
+    // the value the tests from test on leave in a register, either through TESTSETs and the
+    // like or as the booleans a pair of LOADBOOLs gives comparisons
+    fn value_chain(&self, test: usize, limit: usize) -> Option<Chain> {
+        let code: &[Instruction] = &self.proto.code;
+        let mut tests: Vec<(Block, usize)> = vec![(
+            Block {
+                nodes: Vec::new(),
+                end: test,
+            },
+            test,
+        )];
+        let mut furthest: usize = 0;
+        loop {
+            let last: usize = tests[tests.len() - 1].1;
+            if !matches!(
+                code.get(last + 1).map(|inst| &inst.op),
+                Some(OpCode::OpJmp(_))
+            ) {
+                return None;
+            }
+            let target: usize = self.jump_target(last + 1)?;
+            furthest = furthest.max(target);
+            if target <= last + 1 || furthest > limit {
+                return None;
+            }
+            let next: usize = last + 2;
+            if furthest <= next + 2 && self.is_boolean_pair(next) {
+                return self.close_value(tests, None, Some(next));
+            }
+            let block: Block = self.expression(next, furthest);
+            let stop: usize = block.end;
+            if stop == furthest && stop > next {
+                return self.close_value(tests, Some(block), None);
+            }
+            // a tested last operand jumps over the LOADBOOLs of the comparisons
+            if stop > next
+                && furthest <= stop + 3
+                && self.jump_target(stop) == Some(stop + 3)
+                && self.is_boolean_pair(stop + 1)
+            {
+                return self.close_value(tests, Some(block), Some(stop + 1));
+            }
+            if stop >= furthest || !is_test(&code[stop].op) || self.starts_local(stop) {
+                return None;
+            }
+            tests.push((block, stop));
+        }
+    }
+
+    fn close_value(
+        &self,
+        tests: Vec<(Block, usize)>,
+        tail: Option<Block>,
+        booleans: Option<usize>,
+    ) -> Option<Chain> {
+        let code: &[Instruction] = &self.proto.code;
+        let end: usize = match (&tail, booleans) {
+            (_, Some(booleans)) => booleans + 2,
+            (Some(tail), None) => tail.end,
+            (None, None) => return None,
+        };
+        // a TEST or TESTSET that goes straight to the end leaves the value it tested
+        let value_exit = |test: usize| -> Option<(usize, bool)> {
+            match code[test].op {
+                OpCode::OpTest(OpMode::ABC(a, _, c)) | OpCode::OpTestSet(OpMode::ABC(a, _, c)) => {
+                    Some((usize::try_from(a).ok()?, c != 0))
+                }
+                _ => None,
+            }
+        };
+        let reg: usize = match booleans {
+            Some(booleans) => match code[booleans].op {
+                OpCode::OpLoadBool(OpMode::ABC(a, _, _)) => usize::try_from(a).ok()?,
+                _ => return None,
+            },
+            None => {
+                tests
+                    .iter()
+                    .find(|(_, test)| self.jump_target(test + 1) == Some(end))
+                    .and_then(|(_, test)| value_exit(*test))?
+                    .0
+            }
+        };
+        if let Some(tail) = &tail {
+            if !self.uses[tail.end - 1].1.contains(&reg) {
+                return None;
+            }
+        }
+
+        let mut targets: Vec<Exit> = Vec::with_capacity(tests.len());
+        for (_, test) in &tests {
+            let target: usize = self.jump_target(test + 1)?;
+            let exit: Exit = match target {
+                target if target == end => match value_exit(*test)? {
+                    (test_reg, true) if test_reg == reg => Exit::True,
+                    (test_reg, false) if test_reg == reg => Exit::False,
+                    _ => return None,
+                },
+                _ if matches!(code[*test].op, OpCode::OpTestSet(_)) => return None,
+                target if booleans == Some(target) => Exit::False,
+                target if booleans.map(|booleans| booleans + 1) == Some(target) => Exit::True,
+                target => Exit::At(target),
+            };
+            targets.push(exit);
+        }
+        let mut starts: Vec<Exit> = vec![Exit::At(tests[0].1)];
+        starts.extend(tests.iter().map(|(_, test)| Exit::At(test + 2)));
+        if tail.is_none() {
+            starts[tests.len()] = Exit::False;
+        }
+        let count: usize = tests.len() + tail.iter().count();
+        let shape: Shape = chain_shape(&targets, &starts, 0, count, Exit::True, Exit::False)?;
+
+        // only the value is left once the operands are done
+        let live: Vec<bool> = self.live_in_at(end);
+        let is_clean: bool = self.uses[tests[0].1 + 1..end]
+            .iter()
+            .all(|(_, writes)| writes.iter().all(|write| *write == reg || !live[*write]));
+        if !is_clean {
+            return None;
+        }
+        Some(Chain {
+            tests,
+            tail,
+            shape,
+            reg: Some(reg),
+            end,
+            jump: end,
+        })
+    }
+
+    // the conditions the tests from test on can make, shortest first, each deciding between
+    // going on after its last JMP and where that one goes
+    fn condition_chains(&self, test: usize, limit: usize) -> Vec<Chain> {
+        let code: &[Instruction] = &self.proto.code;
+        let mut chains: Vec<Chain> = Vec::new();
+        let mut tests: Vec<(Block, usize)> = vec![(
+            Block {
+                nodes: Vec::new(),
+                end: test,
+            },
+            test,
+        )];
+        loop {
+            let last: usize = tests[tests.len() - 1].1;
+            let is_condition: bool = last + 1 < limit
+                && matches!(code[last + 1].op, OpCode::OpJmp(_))
+                && !matches!(code[last].op, OpCode::OpTestSet(_));
+            if !is_condition {
+                break;
+            }
+            if let Some(chain) = self.close_condition(&tests) {
+                chains.push(chain);
+            }
+            let block: Block = self.expression(last + 2, limit);
+            let stop: usize = block.end;
+            if stop >= limit || !is_test(&code[stop].op) || self.starts_local(stop) {
+                break;
+            }
+            tests.push((block, stop));
+        }
+        chains
+    }
+
+    fn close_condition(&self, tests: &[(Block, usize)]) -> Option<Chain> {
+        let last: usize = tests[tests.len() - 1].1;
+        let (end, jump): (usize, usize) = (last + 2, self.jump_target(last + 1)?);
+        let (on_true, on_false): (usize, usize) = (self.resolve(end), self.resolve(jump));
+        let mut targets: Vec<Exit> = Vec::with_capacity(tests.len());
+        for (_, test) in tests {
+            let target: usize = self.jump_target(test + 1)?;
+            let exit: Exit = match self.resolve(target) {
+                resolved if resolved == on_true => Exit::True,
+                resolved if resolved == on_false => Exit::False,
+                _ => Exit::At(target),
+            };
+            targets.push(exit);
+        }
+        let mut starts: Vec<Exit> = vec![Exit::At(tests[0].1)];
+        starts.extend(tests.iter().map(|(_, test)| Exit::At(test + 2)));
+        starts[tests.len()] = Exit::True;
+        let shape: Shape = match on_true == on_false {
+            // an empty if
+            true if tests.len() == 1 => Shape::Test(0, false),
+            true => return None,
+            false => chain_shape(&targets, &starts, 0, tests.len(), Exit::True, Exit::False)?,
+        };
+
+        // what the operands after the first compute is gone once the condition is decided
+        let (live_true, live_false): (Vec<bool>, Vec<bool>) =
+            (self.live_in_at(end), self.live_in_at(jump));
+        let is_clean: bool = self.uses[tests[0].1 + 1..end].iter().all(|(_, writes)| {
+            writes
+                .iter()
+                .all(|write| !live_true[*write] && !live_false[*write])
+        });
+        if !is_clean {
+            return None;
+        }
+        Some(Chain {
+            tests: tests.to_vec(),
+            tail: None,
+            shape,
+            reg: None,
+            end,
+            jump,
+        })
+    }
+}
+
+// instructions that skip the JMP after them or not
+fn is_test(op: &OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::OpEq(_)
+            | OpCode::OpLt(_)
+            | OpCode::OpLe(_)
+            | OpCode::OpTest(_)
+            | OpCode::OpTestSet(_)
+    )
+}
+
+// instructions that only compute a value into registers
+fn is_expression(op: &OpCode) -> bool {
+    match op {
+        OpCode::OpLoadBool(OpMode::ABC(_, _, c)) => *c == 0,
+        OpCode::OpCall(OpMode::ABC(_, _, c)) => *c != 1,
+        _ => matches!(
+            op,
+            OpCode::OpMove(_)
+                | OpCode::OpLoadK(_)
+                | OpCode::OpLoadNil(_)
+                | OpCode::OpGetUpval(_)
+                | OpCode::OpGetGlobal(_)
+                | OpCode::OpGetTable(_)
+                | OpCode::OpNewTable(_)
+                | OpCode::OpSelf(_)
+                | OpCode::OpAdd(_)
+                | OpCode::OpSub(_)
+                | OpCode::OpMul(_)
+                | OpCode::OpDiv(_)
+                | OpCode::OpMod(_)
+                | OpCode::OpPow(_)
+                | OpCode::OpUnm(_)
+                | OpCode::OpNot(_)
+                | OpCode::OpLen(_)
+                | OpCode::OpConcat(_)
+                | OpCode::OpClosure(_)
+                | OpCode::OpVararg(_)
+        ),
+    }
+}
+
+// instructions that jump, or skip the one after them
+fn is_control(op: &OpCode) -> bool {
+    match op {
+        OpCode::OpLoadBool(OpMode::ABC(_, _, c)) => *c != 0,
+        _ => {
+            is_test(op)
+                || matches!(
+                    op,
+                    OpCode::OpJmp(_)
+                        | OpCode::OpTestSet(_)
+                        | OpCode::OpForPrep(_)
+                        | OpCode::OpForLoop(_)
+                        | OpCode::OpTForLoop(_)
+                )
+        }
+    }
+}
+
+// only whether a condition holds matters, so not not x can be x
+fn negate_condition(condition: Expr) -> Expr {
+    match condition {
+        Expr::Unary(UnaryOp::Not, operand) => *operand,
+        Expr::Binary(BinaryOp::Eq, left, right) => Expr::Binary(BinaryOp::Ne, left, right),
+        Expr::Binary(BinaryOp::Ne, left, right) => Expr::Binary(BinaryOp::Eq, left, right),
+        Expr::Boolean(bool) => Expr::Boolean(!bool),
+        condition => Expr::Unary(UnaryOp::Not, Box::new(condition)),
+    }
+}
+
+// how the operands from first up to last are joined to come out true when they leave for
+// on_true and false for on_false. Operand i goes to targets[i] or on to starts[i + 1], a value
+// has an untested last operand past the targets
+fn chain_shape(
+    targets: &[Exit],
+    starts: &[Exit],
+    first: usize,
+    last: usize,
+    on_true: Exit,
+    on_false: Exit,
+) -> Option<Shape> {
+    if last == first + 1 {
+        if first == targets.len() {
+            return Some(Shape::Tail);
+        }
+        let (target, next): (Exit, Exit) = (targets[first], starts[first + 1]);
+        return match (
+            target == on_true && next == on_false,
+            target == on_false && next == on_true,
+        ) {
+            (true, _) => Some(Shape::Test(first, true)),
+            (_, true) => Some(Shape::Test(first, false)),
+            _ => None,
+        };
+    }
+    // and and or are left associative, so the last operand is split off first
+    for split in (first + 1..last).rev() {
+        let middle: Exit = starts[split];
+        let inner: &[Exit] = &starts[first + 1..split];
+        let (mut to_true, mut to_false, mut is_split): (bool, bool, bool) = (false, false, true);
+        for target in &targets[first..split] {
+            if *target == middle || inner.contains(target) {
+                continue;
+            }
+            match *target {
+                target if target == on_true => to_true = true,
+                target if target == on_false => to_false = true,
+                _ => is_split = false,
+            }
+        }
+        // the left operand goes on to the right one when it's true for and, false for or
+        let shape: Option<Shape> = match (is_split, to_true, to_false) {
+            (true, false, true) => chain_shape(targets, starts, first, split, middle, on_false)
+                .zip(chain_shape(targets, starts, split, last, on_true, on_false))
+                .map(|(left, right)| Shape::And(Box::new(left), Box::new(right))),
+            (true, true, false) => chain_shape(targets, starts, first, split, on_true, middle)
+                .zip(chain_shape(targets, starts, split, last, on_true, on_false))
+                .map(|(left, right)| Shape::Or(Box::new(left), Box::new(right))),
+            _ => None,
+        };
+        if shape.is_some() {
+            return shape;
+        }
+    }
+    None
+}
+
+// skips are what each test needs to skip its JMP, as condition() gives them
+fn shape_expr(shape: &Shape, skips: &mut [Option<Expr>], tail: &mut Option<Expr>) -> Expr {
+    match shape {
+        Shape::Test(index, jump) => {
+            let skip: Expr = skips[*index].take().unwrap_or(Expr::Nil);
+            match jump {
+                true => negate_condition(skip),
+                false => skip,
+            }
+        }
+        Shape::Tail => tail.take().unwrap_or(Expr::Nil),
+        Shape::And(left, right) | Shape::Or(left, right) => {
+            let binary_op: BinaryOp = match shape {
+                Shape::And(_, _) => BinaryOp::And,
+                _ => BinaryOp::Or,
+            };
+            let left: Expr = shape_expr(left, skips, tail);
+            let right: Expr = shape_expr(right, skips, tail);
+            Expr::Binary(binary_op, Box::new(left), Box::new(right))
+        }
+    }
+}
+
+fn build_function_decompiler(
+    proto: &Proto,
+    proto_path: String,
+    upvalue_names: Vec<String>,
+) -> FunctionDecompiler<'_> {
+    let mut pcs: Vec<usize> = Vec::with_capacity(proto.code.len());
+    let mut pc: usize = 0;
+    for inst in &proto.code {
+        pcs.push(pc);
+        pc += match inst.aux.is_some() {
+            true => 2,
+            false => 1,
+        };
+    }
+    let cfg: Cfg = build_cfg(proto, LuaVersion::Lua51);
+    let mut uses: Vec<(Vec<usize>, Vec<usize>)> = code_register_use(proto, &cfg, &pcs);
+    let members: HashMap<usize, usize> = constructor_members(proto, &pcs, &uses);
+    // each member hands the table on to the next one, so the table is read once by each
+    for (index, table) in &members {
+        uses[*index].1.push(*table);
+    }
+    let Liveness { uses, live_out, .. } = build_liveness(&cfg, uses);
+    let shared: HashSet<(usize, usize)> = shared_writes(&cfg, &uses, &live_out);
+    let mut back_jumps: Vec<Vec<usize>> = vec![Vec::new(); proto.code.len()];
+    for (index, inst) in proto.code.iter().enumerate() {
+        if let OpCode::OpJmp(OpMode::ASBX(_, sbx)) = inst.op {
+            match jump_index(&pcs, index, sbx) {
+                Some(target) if target <= index => back_jumps[target].push(index),
+                _ => {}
+            }
+        }
+    }
+    FunctionDecompiler {
+        proto,
+        proto_path,
+        upvalue_names,
+        pcs,
+        cfg,
+        uses,
+        live_out,
+        back_jumps,
+        locals: local_infos(proto),
+        next_local: 0,
+        active_locals: Vec::new(),
+        stripped: proto.locals.is_empty(),
+        shared,
+        members,
+        captured: vec![false; NUM_REGS],
+        declared: vec![false; NUM_REGS],
+        scopes: vec![Vec::new()],
+        break_targets: Vec::new(),
+        structured: true,
+        value_reg: None,
+        inline_values: HashSet::new(),
+        regs: vec![Reg::Free; NUM_REGS],
+        writes: vec![0; NUM_REGS],
+        top: None,
+        stores: Vec::new(),
+        stmts: Vec::new(),
+        index: 0,
+        pc: 0,
+        block_end_pc: 0,
+    }
+}
+
+fn decompile_proto(
+    proto: &Proto,
+    proto_path: String,
+    upvalue_names: Vec<String>,
+) -> DecompileResult<Function> {
+    // a function that turns out not to fit its structure is done again with goto comments
+    let mut structured: bool = true;
+    loop {
+        let mut decompiler: FunctionDecompiler =
+            build_function_decompiler(proto, proto_path.clone(), upvalue_names.clone());
+        decompiler.structured = structured;
+        match decompiler.decompile()? {
+            Some(function) => return Ok(function),
+            None => structured = false,
+        }
+    }
+}
+
+// the if, while, repeat and for statements of a 5.1 proto as the decompiler recovers them,
+// which --psuedo nests its instructions in; None when the jumps don't fit together
+pub(crate) fn structure_proto(proto: &Proto) -> Option<Block> {
+    build_function_decompiler(proto, String::new(), Vec::new()).structure(0, proto.code.len(), None)
+}
+
+pub struct Decompiler<'a> {
+    pub bytecode: &'a Bytecode,
+}
+
+impl Decompiler<'_> {
+    // control flow comes back as if/while/repeat/for, a function that doesn't
+    // fit keeps its jumps as goto comments
+    pub fn decompile(&self) -> DecompileResult<String> {
+        let bytecode: &Bytecode = self.bytecode;
+        if bytecode.version != LuaVersion::Lua51 {
+            return Err(DecompileError {
+                kind: DecompileErrorKind::UnsupportedVersion(bytecode.version),
+                proto_path: String::new(),
+            });
+        }
+
+        let main: Function = decompile_proto(&bytecode.main_proto, "main".to_string(), Vec::new())?;
+        let time_taken = bytecode.time_taken;
+        let mut source: String = format!(
+            "-- decompiled by inu in {}\n\n",
+            format_time_taken!(time_taken)
+        );
+        format_block(&main.body, 0, &mut source);
+        Ok(source)
+    }
+}
+
+pub fn build_decompiler(bytecode: &Bytecode) -> Decompiler<'_> {
+    Decompiler { bytecode }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Proto;
+    use crate::read::build_reader;
+
+    fn sample() -> Bytecode {
+        let bytes: Vec<u8> = include_bytes!("../tests/fixtures/sample.luac").to_vec();
+        match build_reader(&bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // the lines decompiling tests/fixtures/exprs.luac gives, without the header
+    fn exprs_lines() -> Vec<String> {
+        let bytes: Vec<u8> = include_bytes!("../tests/fixtures/exprs.luac").to_vec();
+        let bytecode: Bytecode = match build_reader(&bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        match build_decompiler(&bytecode).decompile() {
+            Ok(source) => source
+                .lines()
+                .skip(2)
+                .map(|line| line.to_string())
+                .collect(),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn assert_lines(lines: &[String], expected: &[&str]) {
+        assert!(
+            lines
+                .windows(expected.len())
+                .any(|window| window == expected),
+            "{:?} missing from\n{}",
+            expected,
+            lines.join("\n")
+        );
+    }
+
+    // tests/fixtures/sample.decompiled.lua compiles and runs like sample.lua does
+    #[test]
+    fn decompiles_the_sample() {
+        let source: String = match build_decompiler(&sample()).decompile() {
+            Ok(source) => source,
+            Err(err) => panic!("{}", err),
+        };
+        let (header, body): (&str, &str) = source.split_once("\n\n").unwrap();
+        assert!(header.starts_with("-- decompiled by inu in "));
+        assert_eq!(
+            body,
+            include_str!("../tests/fixtures/sample.decompiled.lua")
+        );
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_decompile() {
+        let mut bytecode: Bytecode = sample();
+        bytecode.version = LuaVersion::Lua52;
+        let err: DecompileError = build_decompiler(&bytecode).decompile().unwrap_err();
+        assert_eq!(
+            err.kind,
+            DecompileErrorKind::UnsupportedVersion(LuaVersion::Lua52)
+        );
+
+        let mut bytecode: Bytecode = sample();
+        let bump: &mut Proto = &mut bytecode.main_proto.protos[0];
+        bump.code[0].op = OpCode::OpLoadK(OpMode::ABX(0, 99));
+        let err: DecompileError = build_decompiler(&bytecode).decompile().unwrap_err();
+        assert_eq!(err.kind, DecompileErrorKind::InvalidOperand { pc: 0 });
+        assert_eq!(err.proto_path, "main/0");
+    }
+
+    #[test]
+    fn rebuilds_and_or_chains() {
+        let lines: Vec<String> = exprs_lines();
+        // in value positions, TESTSET and the LOADBOOLs of a not
+        assert_lines(
+            &lines,
+            &[
+                "    local x = a or \"default\"",
+                "    local y = a and b or c",
+                "    local z = not a or not b",
+            ],
+        );
+        // and as conditions, with a comparison among the operands
+        assert_lines(
+            &lines,
+            &["    if a and (b or c) then", "        x = 1", "    end"],
+        );
+        assert_lines(
+            &lines,
+            &["    if not a or b == c then", "        y = 2", "    end"],
+        );
+        assert_lines(
+            &lines,
+            &["    while a and not b do", "        a = c", "    end"],
+        );
+    }
+
+    #[test]
+    fn rebuilds_table_constructors() {
+        let lines: Vec<String> = exprs_lines();
+        // open ranges from a vararg and a call end the list part
+        assert_lines(
+            &lines,
+            &["    local list = {...}", "    local copy = {pick(...)}"],
+        );
+        // two SETLISTs, the second after the record fields
+        let items: Vec<String> = (1..=53).map(|item| item.to_string()).collect();
+        let big: String = format!(
+            "local big = {{{}, name = \"big\", [\"a b\"] = true, [10.5] = wrap}}",
+            items.join(", ")
+        );
+        assert_lines(&lines, &[big.as_str()]);
+    }
+}
@@ -0,0 +1,1320 @@
+// --format json, the whole Bytecode model as JSON so other tools don't have to scrape
+// the listing, and inu from-json reading it back into a 5.1 chunk; the schema is
+// described in the README
+
+use std::{fmt, mem::discriminant, time::Instant};
+
+use crate::asm::{encode_instruction, lookup_op_51, BuildOp};
+use crate::bytecode::{
+    build_bytecode, Bytecode, Constant, Instruction, LocalVar, LuaInstruction, LuaInt, LuaNumber,
+    LuaVersion, OpCode, OpMode, Proto, UpvalueDesc, MAXARG_A, MAXARG_B, MAXARG_BX_32, MAXARG_C,
+    MAXARG_SBX_32, VARARG_ISVARARG,
+};
+
+// nesting past this is refused rather than recursed into
+const MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonErrorKind {
+    Syntax {
+        offset: usize,
+    },
+    MissingField(&'static str),
+    InvalidField(&'static str),
+    UnsupportedVersion(String),
+    UnknownOpcode {
+        pc: usize,
+        op: String,
+    },
+    InvalidMode {
+        pc: usize,
+    },
+    OperandOutOfRange {
+        pc: usize,
+        operand: &'static str,
+        value: i64,
+    },
+    InvalidConstant {
+        index: usize,
+    },
+    UnknownField {
+        pc: usize,
+        field: String,
+    },
+    RawMismatch {
+        pc: usize,
+        raw: i64,
+        expected: LuaInstruction,
+    },
+    MissingAux {
+        pc: usize,
+        c: LuaInt,
+    },
+    AuxMismatch {
+        pc: usize,
+        c: LuaInt,
+        aux: i64,
+    },
+}
+
+impl fmt::Display for JsonErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonErrorKind::Syntax { offset } => write!(f, "invalid JSON at byte {}", offset),
+            JsonErrorKind::MissingField(field) => write!(f, "missing field {}", field),
+            JsonErrorKind::InvalidField(field) => {
+                write!(f, "field {} has the wrong type or is out of range", field)
+            }
+            JsonErrorKind::UnsupportedVersion(version) => {
+                write!(f, "rebuilding {} chunks is not supported", version)
+            }
+            JsonErrorKind::UnknownOpcode { pc, op } => {
+                write!(f, "instruction {} is {}, which 5.1 doesn't have", pc, op)
+            }
+            JsonErrorKind::InvalidMode { pc } => {
+                write!(
+                    f,
+                    "instruction {} doesn't have the mode its opcode uses",
+                    pc
+                )
+            }
+            JsonErrorKind::OperandOutOfRange { pc, operand, value } => write!(
+                f,
+                "operand {} of instruction {} is {}, which doesn't fit its field",
+                operand, pc, value
+            ),
+            JsonErrorKind::InvalidConstant { index } => {
+                write!(f, "constant {} can't be in a 5.1 chunk", index)
+            }
+            JsonErrorKind::UnknownField { pc, field } => {
+                write!(f, "instruction {} has a field {} its mode doesn't use", pc, field)
+            }
+            JsonErrorKind::RawMismatch { pc, raw, expected } => write!(
+                f,
+                "raw of instruction {} is {}, but its operands encode to {}",
+                pc, raw, expected
+            ),
+            JsonErrorKind::MissingAux { pc, c } => write!(
+                f,
+                "operand c of instruction {} is {}, which only fits in an aux word; give it aux {} and a lineinfo entry for that word",
+                pc, c, c
+            ),
+            JsonErrorKind::AuxMismatch { pc, c, aux } => write!(
+                f,
+                "aux of instruction {} is {}, but its operand c is {}",
+                pc, aux, c
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonError {
+    pub kind: JsonErrorKind,
+    // path of the proto being read, e.g. main/3/1; empty while reading the header
+    pub proto_path: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to read JSON")?;
+        if !self.proto_path.is_empty() {
+            write!(f, " in {}", self.proto_path)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+pub type JsonResult<T> = Result<T, JsonError>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    // kept as written, so 64 bit integers don't go through a float
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+fn int(value: impl ToString) -> Json {
+    Json::Number(value.to_string())
+}
+
+fn string(value: &str) -> Json {
+    Json::String(value.to_string())
+}
+
+// strings are bytes in a chunk: valid UTF-8 is a JSON string, anything else an array of bytes
+fn bytes(value: &[u8]) -> Json {
+    match std::str::from_utf8(value) {
+        Ok(text) => string(text),
+        Err(_) => Json::Array(value.iter().map(int).collect()),
+    }
+}
+
+// JSON has no infinities or NaN, those are the strings "inf", "-inf" and "nan"
+fn number(value: LuaNumber) -> Json {
+    match value {
+        value if value.is_nan() => string("nan"),
+        LuaNumber::INFINITY => string("inf"),
+        LuaNumber::NEG_INFINITY => string("-inf"),
+        value => Json::Number(format!("{:?}", value)),
+    }
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+fn constant(value: &Constant) -> Json {
+    let (tag, value): (&str, Option<Json>) = match value {
+        Constant::Nil => ("nil", None),
+        Constant::Boolean(b) => ("boolean", Some(Json::Bool(*b))),
+        Constant::Number(n) => ("number", Some(number(*n))),
+        Constant::Integer(n) => ("integer", Some(int(n))),
+        Constant::String(s) => ("string", Some(bytes(s))),
+        Constant::Import(path) => (
+            "import",
+            Some(Json::Array(path.iter().map(|name| bytes(name)).collect())),
+        ),
+        Constant::Table(keys) => (
+            "table",
+            Some(Json::Array(keys.iter().map(constant).collect())),
+        ),
+        Constant::Closure(index) => ("closure", Some(int(index))),
+        Constant::Vector(x, y, z, w) => (
+            "vector",
+            Some(Json::Array(
+                [x, y, z, w]
+                    .iter()
+                    .map(|n| number(**n as LuaNumber))
+                    .collect(),
+            )),
+        ),
+        Constant::Template(array, hash) => (
+            "template",
+            Some(object(vec![
+                ("array", Json::Array(array.iter().map(constant).collect())),
+                (
+                    "hash",
+                    Json::Array(
+                        hash.iter()
+                            .map(|(key, value)| Json::Array(vec![constant(key), constant(value)]))
+                            .collect(),
+                    ),
+                ),
+            ])),
+        ),
+        Constant::Int64(n) => ("int64", Some(int(n))),
+        Constant::UInt64(n) => ("uint64", Some(int(n))),
+        Constant::Complex(re, im) => ("complex", Some(Json::Array(vec![number(*re), number(*im)]))),
+    };
+    let mut fields: Vec<(&str, Json)> = vec![("type", string(tag))];
+    fields.extend(value.map(|value| ("value", value)));
+    object(fields)
+}
+
+fn instruction(inst: &Instruction) -> Json {
+    let mut fields: Vec<(&str, Json)> = vec![("op", string(&inst.op.name()))];
+    match inst.op.mode() {
+        Some(OpMode::ABC(a, b, c)) => fields.extend([
+            ("mode", string("ABC")),
+            ("a", int(a)),
+            ("b", int(b)),
+            ("c", int(c)),
+        ]),
+        Some(OpMode::ABX(a, bx)) => {
+            fields.extend([("mode", string("ABx")), ("a", int(a)), ("bx", int(bx))])
+        }
+        Some(OpMode::ASBX(a, sbx)) => {
+            fields.extend([("mode", string("AsBx")), ("a", int(a)), ("sbx", int(sbx))])
+        }
+        Some(OpMode::AX(ax)) => fields.extend([("mode", string("Ax")), ("ax", int(ax))]),
+        Some(OpMode::ABCK(a, b, c, k)) => fields.extend([
+            ("mode", string("ABCk")),
+            ("a", int(a)),
+            ("b", int(b)),
+            ("c", int(c)),
+            ("k", Json::Bool(*k)),
+        ]),
+        Some(OpMode::SJ(sj)) => fields.extend([("mode", string("sJ")), ("sj", int(sj))]),
+        Some(OpMode::AD(a, d)) => {
+            fields.extend([("mode", string("AD")), ("a", int(a)), ("d", int(d))])
+        }
+        Some(OpMode::E(e)) => fields.extend([("mode", string("E")), ("e", int(e))]),
+        None => {
+            if let OpCode::OpUnknown(opcode) = inst.op {
+                fields.extend([("mode", Json::Null), ("opcode", int(opcode))]);
+            }
+        }
+    }
+    fields.push(("raw", int(inst.raw)));
+    if let Some(aux) = inst.aux {
+        fields.push(("aux", int(aux)));
+    }
+    object(fields)
+}
+
+fn upvalue(desc: &UpvalueDesc) -> Json {
+    object(vec![
+        ("instack", Json::Bool(desc.instack)),
+        ("idx", int(desc.idx)),
+        ("kind", int(desc.kind)),
+    ])
+}
+
+fn local(local: &LocalVar) -> Json {
+    object(vec![
+        ("name", bytes(&local.name)),
+        ("start_pc", int(local.start_pc)),
+        ("end_pc", int(local.end_pc)),
+        (
+            "reg",
+            match local.reg {
+                Some(reg) => int(reg),
+                None => Json::Null,
+            },
+        ),
+    ])
+}
+
+fn proto(proto: &Proto) -> Json {
+    object(vec![
+        ("id", int(proto.id)),
+        ("is_main", Json::Bool(proto.is_main)),
+        ("source", bytes(&proto.source)),
+        ("line_defined", int(proto.line_defined)),
+        ("last_line_defined", int(proto.last_line_defined)),
+        ("upvalue_count", int(proto.upvalue_count)),
+        ("param_count", int(proto.param_count)),
+        ("is_vararg", Json::Bool(proto.is_vararg)),
+        ("vararg_flags", int(proto.vararg_flags)),
+        ("max_stack_size", int(proto.max_stack_size)),
+        ("two_slot_frame", Json::Bool(proto.two_slot_frame)),
+        (
+            "code",
+            Json::Array(proto.code.iter().map(instruction).collect()),
+        ),
+        (
+            "constants",
+            Json::Array(proto.constants.iter().map(constant).collect()),
+        ),
+        (
+            "number_constants",
+            Json::Array(proto.number_constants.iter().map(constant).collect()),
+        ),
+        (
+            "upvalues",
+            Json::Array(proto.upvalues.iter().map(upvalue).collect()),
+        ),
+        (
+            "param_types",
+            Json::Array(proto.param_types.iter().map(int).collect()),
+        ),
+        (
+            "lineinfo",
+            Json::Array(proto.lineinfo.iter().map(int).collect()),
+        ),
+        (
+            "locals",
+            Json::Array(proto.locals.iter().map(local).collect()),
+        ),
+        (
+            "upvalue_names",
+            Json::Array(proto.upvalue_names.iter().map(|name| bytes(name)).collect()),
+        ),
+        ("debug_name", bytes(&proto.debug_name)),
+        (
+            "protos",
+            Json::Array(proto.protos.iter().map(self::proto).collect()),
+        ),
+    ])
+}
+
+impl Json {
+    fn is_scalar(&self) -> bool {
+        !matches!(self, Json::Array(_) | Json::Object(_))
+    }
+
+    fn write_string(text: &str, out: &mut String) {
+        out.push('"');
+        for c in text.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                    out.push_str(&format!("\\u{:04x}", c as u32))
+                }
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    // arrays and objects of scalars go on one line, so every instruction gets a line
+    // of its own and diffs stay readable
+    fn write(&self, indent: usize, out: &mut String) {
+        let (open, close, items): (char, char, Vec<(Option<&String>, &Json)>) = match self {
+            Json::Null => return out.push_str("null"),
+            Json::Bool(b) => return out.push_str(&b.to_string()),
+            Json::Number(n) => return out.push_str(n),
+            Json::String(s) => return Json::write_string(s, out),
+            Json::Array(items) => ('[', ']', items.iter().map(|item| (None, item)).collect()),
+            Json::Object(fields) => (
+                '{',
+                '}',
+                fields
+                    .iter()
+                    .map(|(key, value)| (Some(key), value))
+                    .collect(),
+            ),
+        };
+        out.push(open);
+        let inline: bool = items.iter().all(|(_, value)| value.is_scalar());
+        for (index, (key, value)) in items.iter().enumerate() {
+            if index > 0 {
+                out.push(',');
+            }
+            match inline {
+                true if index > 0 => out.push(' '),
+                true => {}
+                false => {
+                    out.push('\n');
+                    out.push_str(&"  ".repeat(indent + 1));
+                }
+            }
+            if let Some(key) = key {
+                Json::write_string(key, out);
+                out.push_str(": ");
+            }
+            value.write(indent + 1, out);
+        }
+        if !inline && !items.is_empty() {
+            out.push('\n');
+            out.push_str(&"  ".repeat(indent));
+        }
+        out.push(close);
+    }
+}
+
+pub struct JsonWriter<'a> {
+    pub bytecode: &'a Bytecode,
+}
+
+pub fn build_json_writer(bytecode: &Bytecode) -> JsonWriter<'_> {
+    JsonWriter { bytecode }
+}
+
+impl JsonWriter<'_> {
+    pub fn write(&self) -> String {
+        let bytecode: &Bytecode = self.bytecode;
+        let mut fields: Vec<(&str, Json)> = vec![
+            ("version", string(&format!("{:?}", bytecode.version))),
+            ("format", int(bytecode.format)),
+            ("little_endian", Json::Bool(bytecode.endianness)),
+            ("size_int", int(bytecode.size_int)),
+            ("size_sizet", int(bytecode.size_sizet)),
+            ("size_instruction", int(bytecode.size_instruction)),
+            ("size_luanumber", int(bytecode.size_luanumber)),
+            (
+                "luanumber_integral",
+                Json::Bool(bytecode.luanumber_integral),
+            ),
+            ("luau_version", int(bytecode.luau_version)),
+            ("luau_types_version", int(bytecode.luau_types_version)),
+            ("luajit_version", int(bytecode.luajit_version)),
+            ("luajit_flags", int(bytecode.luajit_flags)),
+            ("main", proto(&bytecode.main_proto)),
+        ];
+        // lua_Integer only has a size in the header from 5.3 on
+        if matches!(bytecode.version, LuaVersion::Lua53 | LuaVersion::Lua54) {
+            fields.insert(6, ("size_luainteger", int(bytecode.size_luainteger)));
+        }
+        let document: Json = object(fields);
+        let mut out: String = String::new();
+        document.write(0, &mut out);
+        out.push('\n');
+        out
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self) -> JsonError {
+        JsonError {
+            kind: JsonErrorKind::Syntax {
+                offset: self.position,
+            },
+            proto_path: String::new(),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(
+            self.text.get(self.position),
+            Some(b' ' | b'\t' | b'\n' | b'\r')
+        ) {
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_space();
+        match self.text.get(self.position) == Some(&c) {
+            true => {
+                self.position += 1;
+                true
+            }
+            false => false,
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> JsonResult<()> {
+        match self.eat(c) {
+            true => Ok(()),
+            false => Err(self.error()),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> JsonResult<Json> {
+        if depth > MAX_DEPTH {
+            return Err(self.error());
+        }
+        self.skip_space();
+        match self.text.get(self.position) {
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields: Vec<(String, Json)> = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_space();
+                    let key: String = self.string()?;
+                    self.expect(b':')?;
+                    fields.push((key, self.value(depth + 1)?));
+                    if self.eat(b'}') {
+                        return Ok(Json::Object(fields));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut items: Vec<Json> = Vec::new();
+                if self.eat(b']') {
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    if self.eat(b']') {
+                        return Ok(Json::Array(items));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error()),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> JsonResult<Json> {
+        match self.text[self.position..].starts_with(word.as_bytes()) {
+            true => {
+                self.position += word.len();
+                Ok(value)
+            }
+            false => Err(self.error()),
+        }
+    }
+
+    fn digits(&mut self) -> usize {
+        let start: usize = self.position;
+        while self.text.get(self.position).is_some_and(u8::is_ascii_digit) {
+            self.position += 1;
+        }
+        self.position - start
+    }
+
+    fn number(&mut self) -> JsonResult<Json> {
+        let start: usize = self.position;
+        if self.text[self.position] == b'-' {
+            self.position += 1;
+        }
+        match self.digits() {
+            0 => return Err(self.error()),
+            // no leading zeros
+            length if length > 1 && self.text[self.position - length] == b'0' => {
+                return Err(self.error())
+            }
+            _ => {}
+        }
+        if self.text.get(self.position) == Some(&b'.') {
+            self.position += 1;
+            if self.digits() == 0 {
+                return Err(self.error());
+            }
+        }
+        if matches!(self.text.get(self.position), Some(b'e' | b'E')) {
+            self.position += 1;
+            if matches!(self.text.get(self.position), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+            if self.digits() == 0 {
+                return Err(self.error());
+            }
+        }
+        let text: &[u8] = &self.text[start..self.position];
+        Ok(Json::Number(String::from_utf8_lossy(text).into_owned()))
+    }
+
+    fn hex4(&mut self) -> JsonResult<u32> {
+        let digits: &[u8] = match self.text.get(self.position..self.position + 4) {
+            Some(digits) => digits,
+            None => return Err(self.error()),
+        };
+        let value: Option<u32> = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        match value {
+            Some(value) => {
+                self.position += 4;
+                Ok(value)
+            }
+            None => Err(self.error()),
+        }
+    }
+
+    fn string(&mut self) -> JsonResult<String> {
+        if self.text.get(self.position) != Some(&b'"') {
+            return Err(self.error());
+        }
+        self.position += 1;
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let c: u8 = match self.text.get(self.position) {
+                Some(c) => *c,
+                None => return Err(self.error()),
+            };
+            self.position += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape: u8 = match self.text.get(self.position) {
+                        Some(escape) => *escape,
+                        None => return Err(self.error()),
+                    };
+                    self.position += 1;
+                    match escape {
+                        b'"' | b'\\' | b'/' => out.push(escape),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'u' => {
+                            let mut code: u32 = self.hex4()?;
+                            // a surrogate pair spells out a character past the BMP
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.text[self.position..].starts_with(b"\\u")
+                            {
+                                self.position += 2;
+                                let low: u32 = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error());
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => {
+                                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                                }
+                                None => return Err(self.error()),
+                            }
+                        }
+                        _ => return Err(self.error()),
+                    }
+                }
+                c if c < 0x20 => return Err(self.error()),
+                c => out.push(c),
+            }
+        }
+        // the text was a str and escapes are whole characters, so this stays UTF-8
+        String::from_utf8(out).map_err(|_| self.error())
+    }
+}
+
+fn parse(text: &str) -> JsonResult<Json> {
+    let mut parser: Parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+    };
+    let value: Json = parser.value(0)?;
+    parser.skip_space();
+    match parser.position == parser.text.len() {
+        true => Ok(value),
+        false => Err(parser.error()),
+    }
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+}
+
+// rebuilds a 5.1 chunk from what --format json printed; the operands are what counts,
+// raw and aux are optional but have to agree with them when given
+pub struct JsonReader<'a> {
+    pub text: &'a str,
+
+    proto_path: Vec<LuaInt>,
+}
+
+pub fn build_json_reader(text: &str) -> JsonReader<'_> {
+    JsonReader {
+        text,
+        proto_path: Vec::new(),
+    }
+}
+
+impl JsonReader<'_> {
+    fn error(&self, kind: JsonErrorKind) -> JsonError {
+        let mut proto_path: String = String::new();
+        if let Some((_, rest)) = self.proto_path.split_first() {
+            proto_path.push_str("main");
+            for id in rest {
+                proto_path.push_str(&format!("/{}", id));
+            }
+        }
+        JsonError { kind, proto_path }
+    }
+
+    fn field<'j>(&self, object: &'j Json, name: &'static str) -> JsonResult<&'j Json> {
+        object
+            .get(name)
+            .ok_or_else(|| self.error(JsonErrorKind::MissingField(name)))
+    }
+
+    fn to_int<T: TryFrom<i64>>(&self, value: &Json, name: &'static str) -> JsonResult<T> {
+        let number: Option<i64> = match value {
+            Json::Number(text) => text.parse().ok(),
+            _ => None,
+        };
+        number
+            .and_then(|number| T::try_from(number).ok())
+            .ok_or_else(|| self.error(JsonErrorKind::InvalidField(name)))
+    }
+
+    fn int<T: TryFrom<i64>>(&self, object: &Json, name: &'static str) -> JsonResult<T> {
+        self.to_int(self.field(object, name)?, name)
+    }
+
+    // header fields a 5.1 chunk always has the same way can be left out
+    fn opt_int<T: TryFrom<i64>>(
+        &self,
+        object: &Json,
+        name: &'static str,
+        default: T,
+    ) -> JsonResult<T> {
+        match object.get(name) {
+            Some(value) => self.to_int(value, name),
+            None => Ok(default),
+        }
+    }
+
+    fn opt_bool(&self, object: &Json, name: &'static str, default: bool) -> JsonResult<bool> {
+        match object.get(name) {
+            Some(Json::Bool(b)) => Ok(*b),
+            Some(_) => Err(self.error(JsonErrorKind::InvalidField(name))),
+            None => Ok(default),
+        }
+    }
+
+    fn to_bytes(&self, value: &Json, name: &'static str) -> JsonResult<Vec<u8>> {
+        match value {
+            Json::String(text) => Ok(text.as_bytes().to_vec()),
+            Json::Array(items) => items.iter().map(|item| self.to_int(item, name)).collect(),
+            _ => Err(self.error(JsonErrorKind::InvalidField(name))),
+        }
+    }
+
+    fn bytes(&self, object: &Json, name: &'static str) -> JsonResult<Vec<u8>> {
+        self.to_bytes(self.field(object, name)?, name)
+    }
+
+    fn array<'j>(&self, object: &'j Json, name: &'static str) -> JsonResult<&'j [Json]> {
+        match self.field(object, name)? {
+            Json::Array(items) => Ok(items),
+            _ => Err(self.error(JsonErrorKind::InvalidField(name))),
+        }
+    }
+
+    // debug info is optional, a stripped chunk has none
+    fn opt_array<'j>(&self, object: &'j Json, name: &'static str) -> JsonResult<&'j [Json]> {
+        match object.get(name) {
+            Some(_) => self.array(object, name),
+            None => Ok(&[]),
+        }
+    }
+
+    pub fn read(&mut self) -> JsonResult<Bytecode> {
+        let start_instant: Instant = Instant::now();
+        let document: Json = parse(self.text)?;
+        match self.field(&document, "version")? {
+            Json::String(version) if version == "Lua51" => {}
+            Json::String(version) => {
+                return Err(self.error(JsonErrorKind::UnsupportedVersion(version.clone())))
+            }
+            _ => return Err(self.error(JsonErrorKind::InvalidField("version"))),
+        }
+        let main: &Json = self.field(&document, "main")?;
+        let main_proto: Proto = self.read_proto(main, 0, true)?;
+        Ok(build_bytecode(
+            LuaVersion::Lua51,
+            self.opt_int(&document, "format", 0)?,
+            self.opt_bool(&document, "little_endian", true)?,
+            self.opt_int(&document, "size_int", 4)?,
+            self.opt_int(&document, "size_sizet", 8)?,
+            self.opt_int(&document, "size_instruction", 4)?,
+            self.opt_int(&document, "size_luainteger", 8)?,
+            self.opt_int(&document, "size_luanumber", 8)?,
+            self.opt_bool(&document, "luanumber_integral", false)?,
+            0,
+            0,
+            0,
+            0,
+            main_proto,
+            start_instant.elapsed(),
+        ))
+    }
+
+    fn read_proto(&mut self, value: &Json, id: LuaInt, is_main: bool) -> JsonResult<Proto> {
+        self.proto_path.push(id);
+        let code: Vec<Instruction> = self
+            .array(value, "code")?
+            .iter()
+            .enumerate()
+            .map(|(pc, inst)| self.read_instruction(inst, pc))
+            .collect::<JsonResult<Vec<Instruction>>>()?;
+        let constants: Vec<Constant> = self
+            .array(value, "constants")?
+            .iter()
+            .enumerate()
+            .map(|(index, constant)| self.read_constant(constant, index))
+            .collect::<JsonResult<Vec<Constant>>>()?;
+        let mut protos: Vec<Proto> = Vec::new();
+        for (index, child) in self.array(value, "protos")?.iter().enumerate() {
+            protos.push(self.read_proto(child, index as LuaInt, false)?);
+        }
+        let lineinfo: Vec<LuaInt> = self
+            .opt_array(value, "lineinfo")?
+            .iter()
+            .map(|line| self.to_int(line, "lineinfo"))
+            .collect::<JsonResult<Vec<LuaInt>>>()?;
+        let mut locals: Vec<LocalVar> = Vec::new();
+        for local in self.opt_array(value, "locals")? {
+            locals.push(LocalVar {
+                name: self.bytes(local, "name")?,
+                start_pc: self.int(local, "start_pc")?,
+                end_pc: self.int(local, "end_pc")?,
+                reg: None,
+            });
+        }
+        let upvalue_names: Vec<Vec<u8>> = self
+            .opt_array(value, "upvalue_names")?
+            .iter()
+            .map(|name| self.to_bytes(name, "upvalue_names"))
+            .collect::<JsonResult<Vec<Vec<u8>>>>()?;
+        let vararg_flags: u8 = self.int(value, "vararg_flags")?;
+        let proto: Proto = Proto {
+            is_main,
+            id,
+
+            source: self.bytes(value, "source")?,
+            line_defined: self.int(value, "line_defined")?,
+            last_line_defined: self.int(value, "last_line_defined")?,
+            upvalue_count: self.int(value, "upvalue_count")?,
+            param_count: self.int(value, "param_count")?,
+            is_vararg: vararg_flags & VARARG_ISVARARG != 0,
+            vararg_flags,
+            max_stack_size: self.int(value, "max_stack_size")?,
+            code,
+            constants,
+            protos,
+            upvalues: Vec::new(),
+            param_types: Vec::new(),
+            number_constants: Vec::new(),
+            two_slot_frame: false,
+
+            lineinfo,
+            locals,
+            upvalue_names,
+            debug_name: Vec::new(),
+        };
+        self.proto_path.pop();
+        Ok(proto)
+    }
+
+    fn operand(
+        &self,
+        value: &Json,
+        name: &'static str,
+        pc: usize,
+        range: (LuaInt, LuaInt),
+    ) -> JsonResult<LuaInt> {
+        let operand: i64 = self.int(value, name)?;
+        match (range.0 as i64..=range.1 as i64).contains(&operand) {
+            true => Ok(operand as LuaInt),
+            false => Err(self.error(JsonErrorKind::OperandOutOfRange {
+                pc,
+                operand: name,
+                value: operand,
+            })),
+        }
+    }
+
+    fn read_instruction(&self, value: &Json, pc: usize) -> JsonResult<Instruction> {
+        let name: &str = match self.field(value, "op")? {
+            Json::String(name) => name,
+            _ => return Err(self.error(JsonErrorKind::InvalidField("op"))),
+        };
+        // the variant names are the luac mnemonics once the Op is gone
+        let build: BuildOp = match name.strip_prefix("Op").and_then(lookup_op_51) {
+            Some(build) => build,
+            None => {
+                return Err(self.error(JsonErrorKind::UnknownOpcode {
+                    pc,
+                    op: name.to_string(),
+                }))
+            }
+        };
+        // SETLIST keeps a C too big for its field in the word after it
+        let max_c: LuaInt = match name {
+            "OpSetList" => LuaInt::MAX,
+            _ => MAXARG_C,
+        };
+        let a: LuaInt = self.operand(value, "a", pc, (0, MAXARG_A))?;
+        let (mode, operands): (OpMode, &[&str]) = match self.field(value, "mode")? {
+            Json::String(mode) if mode == "ABC" => (
+                OpMode::ABC(
+                    a,
+                    self.operand(value, "b", pc, (0, MAXARG_B))?,
+                    self.operand(value, "c", pc, (0, max_c))?,
+                ),
+                &["a", "b", "c"],
+            ),
+            Json::String(mode) if mode == "ABx" => (
+                OpMode::ABX(a, self.operand(value, "bx", pc, (0, MAXARG_BX_32))?),
+                &["a", "bx"],
+            ),
+            Json::String(mode) if mode == "AsBx" => (
+                OpMode::ASBX(
+                    a,
+                    self.operand(
+                        value,
+                        "sbx",
+                        pc,
+                        (-MAXARG_SBX_32, MAXARG_BX_32 - MAXARG_SBX_32),
+                    )?,
+                ),
+                &["a", "sbx"],
+            ),
+            _ => return Err(self.error(JsonErrorKind::InvalidMode { pc })),
+        };
+        if let Json::Object(fields) = value {
+            for (field, _) in fields {
+                let known: bool = matches!(field.as_str(), "op" | "mode" | "raw" | "aux")
+                    || operands.contains(&field.as_str());
+                if !known {
+                    return Err(self.error(JsonErrorKind::UnknownField {
+                        pc,
+                        field: field.clone(),
+                    }));
+                }
+            }
+        }
+        // the aux word takes a lineinfo entry of its own, so it has to be asked for rather
+        // than appear behind the other entries' backs
+        let aux: Option<LuaInstruction> = match (&mode, value.get("aux")) {
+            (OpMode::ABC(_, _, c), None) if *c > MAXARG_C => {
+                return Err(self.error(JsonErrorKind::MissingAux { pc, c: *c }))
+            }
+            (_, None) => None,
+            (OpMode::ABC(_, _, c), Some(aux)) if name == "OpSetList" => {
+                let aux: i64 = self.to_int(aux, "aux")?;
+                match aux == *c as i64 {
+                    true => Some(aux as LuaInstruction),
+                    false => return Err(self.error(JsonErrorKind::AuxMismatch { pc, c: *c, aux })),
+                }
+            }
+            (_, Some(_)) => {
+                return Err(self.error(JsonErrorKind::UnknownField {
+                    pc,
+                    field: "aux".to_string(),
+                }))
+            }
+        };
+        let expected = discriminant(&mode);
+        let inst: Instruction = match encode_instruction(build(mode), aux) {
+            Some(inst) if inst.op.mode().map(discriminant) == Some(expected) => inst,
+            _ => return Err(self.error(JsonErrorKind::InvalidMode { pc })),
+        };
+        match value.get("raw") {
+            Some(raw) => {
+                let raw: i64 = self.to_int(raw, "raw")?;
+                match raw == inst.raw as i64 {
+                    true => Ok(inst),
+                    false => Err(self.error(JsonErrorKind::RawMismatch {
+                        pc,
+                        raw,
+                        expected: inst.raw,
+                    })),
+                }
+            }
+            None => Ok(inst),
+        }
+    }
+
+    fn read_constant(&self, value: &Json, index: usize) -> JsonResult<Constant> {
+        let invalid = || self.error(JsonErrorKind::InvalidConstant { index });
+        let tag: &str = match self.field(value, "type")? {
+            Json::String(tag) => tag,
+            _ => return Err(invalid()),
+        };
+        match (tag, value.get("value")) {
+            ("nil", _) => Ok(Constant::Nil),
+            ("boolean", Some(Json::Bool(b))) => Ok(Constant::Boolean(*b)),
+            ("number", Some(Json::Number(text))) => match text.parse() {
+                Ok(n) => Ok(Constant::Number(n)),
+                Err(_) => Err(invalid()),
+            },
+            ("number", Some(Json::String(text))) => match text.as_str() {
+                "inf" => Ok(Constant::Number(LuaNumber::INFINITY)),
+                "-inf" => Ok(Constant::Number(LuaNumber::NEG_INFINITY)),
+                "nan" => Ok(Constant::Number(LuaNumber::NAN)),
+                _ => Err(invalid()),
+            },
+            ("string", Some(text)) => Ok(Constant::String(self.to_bytes(text, "value")?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read::build_reader;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.luac");
+
+    fn sample() -> Bytecode {
+        match build_reader(&SAMPLE.to_vec()).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn export(bytecode: &Bytecode) -> Json {
+        match parse(&build_json_writer(bytecode).write()) {
+            Ok(document) => document,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn path<'j>(document: &'j Json, keys: &[&str]) -> &'j Json {
+        keys.iter().fold(document, |value, key| match value {
+            Json::Array(items) => &items[key.parse::<usize>().unwrap()],
+            _ => value.get(key).unwrap_or_else(|| panic!("missing {}", key)),
+        })
+    }
+
+    #[test]
+    fn exports_the_header_and_protos() {
+        let document: Json = export(&sample());
+        assert_eq!(path(&document, &["version"]), &string("Lua51"));
+        assert_eq!(path(&document, &["little_endian"]), &Json::Bool(true));
+        // a 5.1 header has no lua_Integer size to export
+        assert!(document.get("size_luainteger").is_none());
+        assert_eq!(path(&document, &["size_luanumber"]), &int(8));
+        assert_eq!(path(&document, &["main", "source"]), &string("@sample.lua"));
+        assert_eq!(
+            path(&document, &["main", "protos", "1", "upvalue_names", "0"]),
+            &string("format")
+        );
+        assert_eq!(
+            path(&document, &["main", "code", "0"]),
+            &object(vec![
+                ("op", string("OpGetGlobal")),
+                ("mode", string("ABx")),
+                ("a", int(0)),
+                ("bx", int(0)),
+                ("raw", int(5)),
+            ])
+        );
+    }
+
+    #[test]
+    fn exports_the_lua_integer_size_from_5_3_on() {
+        let bytes: Vec<u8> = include_bytes!("../tests/fixtures/lua53.luac").to_vec();
+        let bytecode: Bytecode = match build_reader(&bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        let document: Json = export(&bytecode);
+        assert_eq!(path(&document, &["version"]), &string("Lua53"));
+        assert_eq!(path(&document, &["size_luainteger"]), &int(8));
+    }
+
+    #[test]
+    fn exports_values_json_cannot_hold() {
+        let mut bytecode: Bytecode = sample();
+        bytecode.main_proto.constants = vec![
+            Constant::String(vec![b'a', 0xff]),
+            Constant::Number(LuaNumber::NEG_INFINITY),
+            Constant::Number(LuaNumber::NAN),
+            Constant::Number(0.5),
+        ];
+        let document: Json = export(&bytecode);
+        let values: Vec<&Json> = (0..4)
+            .map(|index| {
+                path(
+                    &document,
+                    &["main", "constants", &index.to_string(), "value"],
+                )
+            })
+            .collect();
+        assert_eq!(values[0], &Json::Array(vec![int(97), int(255)]));
+        assert_eq!(values[1], &string("-inf"));
+        assert_eq!(values[2], &string("nan"));
+        assert_eq!(values[3], &Json::Number("0.5".to_string()));
+    }
+
+    #[test]
+    fn escapes_strings() {
+        let text: String = build_json_writer(&sample()).write();
+        assert!(text.contains(r#""value": "obj\u0000\n\"quoted\"""#));
+        let document: Json = export(&sample());
+        let constants: &[Json] = match path(&document, &["main", "constants"]) {
+            Json::Array(constants) => constants,
+            _ => panic!("constants isn't an array"),
+        };
+        assert!(constants
+            .iter()
+            .any(|constant| constant.get("value") == Some(&string("obj\0\n\"quoted\""))));
+    }
+
+    fn import(text: &str) -> JsonResult<Vec<u8>> {
+        let bytecode: Bytecode = build_json_reader(text).read()?;
+        match crate::write::build_writer(&bytecode).write() {
+            Ok(bytes) => Ok(bytes),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // the export of the sample with from swapped for to, which has to be refused
+    fn edit_error(from: &str, to: &str) -> JsonError {
+        let text: String = build_json_writer(&sample()).write();
+        assert!(text.contains(from), "the export has no {}", from);
+        match import(&text.replacen(from, to, 1)) {
+            Ok(_) => panic!("expected {} to be refused", to),
+            Err(err) => err,
+        }
+    }
+
+    fn edit_kind(from: &str, to: &str) -> JsonErrorKind {
+        edit_error(from, to).kind
+    }
+
+    fn without(value: &mut Json, key: &str) {
+        match value {
+            Json::Object(fields) => {
+                fields.retain(|(name, _)| name != key);
+                fields.iter_mut().for_each(|(_, value)| without(value, key));
+            }
+            Json::Array(items) => items.iter_mut().for_each(|item| without(item, key)),
+            _ => {}
+        }
+    }
+
+    // pc 2 of main, the LOADK of 2.5 into the list constructor
+    const LOADK: &str = r#""a": 1, "bx": 2, "raw": 32833"#;
+    // pc 13 of main, storing that constructor's five items
+    const SETLIST: &str = r#""a": 3, "b": 5, "c": 1, "raw": 41959650"#;
+
+    #[test]
+    fn sample_round_trips() {
+        let text: String = build_json_writer(&sample()).write();
+        assert_eq!(import(&text), Ok(SAMPLE.to_vec()));
+    }
+
+    #[test]
+    fn raw_can_be_left_out() {
+        let mut document: Json = export(&sample());
+        without(&mut document, "raw");
+        let mut text: String = String::new();
+        document.write(0, &mut text);
+        assert_eq!(import(&text), Ok(SAMPLE.to_vec()));
+    }
+
+    #[test]
+    fn setlist_count_word_round_trips() {
+        let bytecode: Bytecode =
+            match crate::asm::build_assembler("    SETLIST r0, 1, 600\n").assemble() {
+                Ok(bytecode) => bytecode,
+                Err(err) => panic!("{}", err),
+            };
+        let text: String = build_json_writer(&bytecode).write();
+        assert!(text.contains(r#""c": 600, "raw": 8388642, "aux": 600"#));
+        assert_eq!(
+            import(&text),
+            Ok(crate::write::build_writer(&bytecode).write().unwrap())
+        );
+    }
+
+    #[test]
+    fn operands_out_of_range() {
+        assert_eq!(
+            edit_kind(LOADK, r#""a": 256, "bx": 2"#),
+            JsonErrorKind::OperandOutOfRange {
+                pc: 2,
+                operand: "a",
+                value: 256
+            }
+        );
+        assert_eq!(
+            edit_kind(LOADK, r#""a": 1, "bx": 262144"#),
+            JsonErrorKind::OperandOutOfRange {
+                pc: 2,
+                operand: "bx",
+                value: 262144
+            }
+        );
+        let err: JsonError = edit_error(SETLIST, r#""a": 3, "b": -1, "c": 1"#);
+        assert_eq!(
+            err.to_string(),
+            "Failed to read JSON in main: operand b of instruction 13 is -1, which doesn't fit its field"
+        );
+    }
+
+    #[test]
+    fn opcodes_and_modes() {
+        assert_eq!(
+            edit_kind(r#""op": "OpGetGlobal""#, r#""op": "OpLoadKx""#),
+            JsonErrorKind::UnknownOpcode {
+                pc: 0,
+                op: "OpLoadKx".to_string()
+            }
+        );
+        assert_eq!(
+            edit_kind(
+                r#""mode": "ABx", "a": 1, "bx": 2"#,
+                r#""mode": "AsBx", "a": 1, "sbx": 2"#
+            ),
+            JsonErrorKind::InvalidMode { pc: 2 }
+        );
+    }
+
+    #[test]
+    fn raw_has_to_match() {
+        assert_eq!(
+            edit_kind(LOADK, r#""a": 1, "bx": 2, "raw": 32834"#),
+            JsonErrorKind::RawMismatch {
+                pc: 2,
+                raw: 32834,
+                expected: 32833
+            }
+        );
+        assert_eq!(
+            edit_kind(LOADK, r#""a": 1, "bx": 3, "raw": 32833"#),
+            JsonErrorKind::RawMismatch {
+                pc: 2,
+                raw: 32833,
+                expected: 49217
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_fields() {
+        assert_eq!(
+            edit_kind(LOADK, r#""a": 1, "bx": 2, "c": 0"#),
+            JsonErrorKind::UnknownField {
+                pc: 2,
+                field: "c".to_string()
+            }
+        );
+        assert_eq!(
+            edit_kind(LOADK, r#""a": 1, "bx": 2, "aux": 2"#),
+            JsonErrorKind::UnknownField {
+                pc: 2,
+                field: "aux".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn setlist_counts() {
+        assert_eq!(
+            edit_kind(SETLIST, r#""a": 3, "b": 5, "c": 600"#),
+            JsonErrorKind::MissingAux { pc: 13, c: 600 }
+        );
+        assert_eq!(
+            edit_kind(SETLIST, r#""a": 3, "b": 5, "c": 600, "aux": 601"#),
+            JsonErrorKind::AuxMismatch {
+                pc: 13,
+                c: 600,
+                aux: 601
+            }
+        );
+    }
+
+    #[test]
+    fn document_errors() {
+        let text: String = build_json_writer(&sample()).write();
+        assert_eq!(
+            import(&text[..20]).unwrap_err().kind,
+            JsonErrorKind::Syntax { offset: 20 }
+        );
+        assert_eq!(
+            edit_kind(r#""version": "Lua51""#, r#""version": "Lua52""#),
+            JsonErrorKind::UnsupportedVersion("Lua52".to_string())
+        );
+        assert_eq!(
+            edit_kind(r#""vararg_flags": 2,"#, ""),
+            JsonErrorKind::MissingField("vararg_flags")
+        );
+        let err: JsonError = edit_error(
+            r#"{"type": "string", "value": "format"}"#,
+            r#"{"type": "integer", "value": 1}"#,
+        );
+        assert!(matches!(err.kind, JsonErrorKind::InvalidConstant { .. }));
+        assert_eq!(err.proto_path, "main");
+    }
+}
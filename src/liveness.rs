@@ -0,0 +1,914 @@
+use crate::{
+    bytecode::{Constant, Instruction, LuaInt, LuaVersion, OpCode, OpMode, Proto, BITRK},
+    cfg::Cfg,
+};
+
+// registers are up to 8 bits but B and C can name 9 bit ones in malformed code
+pub const NUM_REGS: usize = 512;
+
+// the registers of one instruction, operands that can't be registers are dropped
+struct Operands {
+    reads: Vec<usize>,
+    writes: Vec<usize>,
+    // where a range up to an unknown top stops, the frame of the function
+    stack_size: usize,
+}
+
+fn reg(value: LuaInt) -> Option<usize> {
+    usize::try_from(value).ok().filter(|reg| *reg < NUM_REGS)
+}
+
+fn range(first: LuaInt, last: LuaInt) -> Vec<usize> {
+    (first.max(0)..=last).filter_map(reg).collect()
+}
+
+impl Operands {
+    fn read(&mut self, value: LuaInt) {
+        self.reads.extend(reg(value));
+    }
+    fn read_range(&mut self, first: LuaInt, last: LuaInt) {
+        self.reads.extend(range(first, last));
+    }
+    // up to 5.3 constants are told apart by BITRK, Luau's 8 bit operands never have it
+    fn read_rk(&mut self, value: LuaInt) {
+        if value & BITRK == 0 {
+            self.read(value);
+        }
+    }
+    // 5.4 flags a constant C with k instead
+    fn read_ck(&mut self, value: LuaInt, k: bool) {
+        if !k {
+            self.read(value);
+        }
+    }
+    // count is one more than the number of registers, 0 is everything up to top
+    fn read_counted(&mut self, first: LuaInt, count: LuaInt, top: &mut Option<usize>) {
+        match count {
+            0 => self.read_top(first, top),
+            count => self.read_range(first, first + count - 2),
+        }
+    }
+    // without a producer in the same block every register the frame has might be read
+    fn read_top(&mut self, first: LuaInt, top: &mut Option<usize>) {
+        let last: LuaInt = match top.take() {
+            Some(top) => top as LuaInt,
+            None => self.stack_size as LuaInt - 1,
+        };
+        self.read_range(first, last);
+    }
+    fn write(&mut self, value: LuaInt) {
+        self.writes.extend(reg(value));
+    }
+    fn write_range(&mut self, first: LuaInt, last: LuaInt) {
+        self.writes.extend(range(first, last));
+    }
+    // a count of 0 keeps every result, the first one is where top starts
+    fn write_counted(&mut self, first: LuaInt, count: LuaInt, top: &mut Option<usize>) {
+        match count {
+            0 => {
+                self.write(first);
+                *top = reg(first);
+            }
+            count => self.write_range(first, first + count - 2),
+        }
+    }
+}
+
+// 5.1 to 5.4, the modes tell apart the opcodes that changed operands between versions
+fn register_use_lua(
+    op: &OpCode,
+    version: LuaVersion,
+    top: &mut Option<usize>,
+    regs: &mut Operands,
+) {
+    match op {
+        OpCode::OpMove(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _))
+        | OpCode::OpUnm(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _))
+        | OpCode::OpBnot(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _))
+        | OpCode::OpNot(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _))
+        | OpCode::OpLen(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _))
+        | OpCode::OpGetI(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpGetField(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpAddI(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpAddK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpSubK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpMulK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpModK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpPowK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpDivK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpIdivK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpBandK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpBorK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpBxorK(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpShrI(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpShlI(OpMode::ABCK(a, b, _, _)) => {
+            regs.read(*b);
+            regs.write(*a);
+        }
+        OpCode::OpLoadK(OpMode::ABX(a, _))
+        | OpCode::OpLoadKx(OpMode::ABX(a, _))
+        | OpCode::OpLoadI(OpMode::ASBX(a, _))
+        | OpCode::OpLoadF(OpMode::ASBX(a, _))
+        | OpCode::OpLoadBool(OpMode::ABC(a, _, _))
+        | OpCode::OpLoadFalse(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpLFalseSkip(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpLoadTrue(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpGetUpval(OpMode::ABC(a, _, _) | OpMode::ABCK(a, _, _, _))
+        | OpCode::OpGetGlobal(OpMode::ABX(a, _))
+        | OpCode::OpGetTabUp(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpNewTable(OpMode::ABC(a, _, _) | OpMode::ABCK(a, _, _, _))
+        | OpCode::OpClosure(OpMode::ABX(a, _)) => regs.write(*a),
+        OpCode::OpLoadNil(OpMode::ABC(a, b, _)) => match version {
+            LuaVersion::Lua51 => regs.write_range(*a, *b),
+            _ => regs.write_range(*a, *a + *b),
+        },
+        OpCode::OpLoadNil(OpMode::ABCK(a, b, _, _)) => regs.write_range(*a, *a + *b),
+        OpCode::OpGetTabUp(OpMode::ABC(a, _, c)) => {
+            regs.read_rk(*c);
+            regs.write(*a);
+        }
+        OpCode::OpGetTable(OpMode::ABC(a, b, c)) => {
+            regs.read(*b);
+            regs.read_rk(*c);
+            regs.write(*a);
+        }
+        OpCode::OpGetTable(OpMode::ABCK(a, b, c, _)) => {
+            regs.read(*b);
+            regs.read(*c);
+            regs.write(*a);
+        }
+        OpCode::OpSetGlobal(OpMode::ABX(a, _))
+        | OpCode::OpSetUpval(OpMode::ABC(a, _, _) | OpMode::ABCK(a, _, _, _))
+        | OpCode::OpTest(OpMode::ABC(a, _, _) | OpMode::ABCK(a, _, _, _))
+        | OpCode::OpEqK(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpEqI(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpLtI(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpLeI(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpGtI(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpGeI(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpMmBinI(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpMmBinK(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpTbc(OpMode::ABCK(a, _, _, _))
+        | OpCode::OpReturn1(OpMode::ABCK(a, _, _, _)) => regs.read(*a),
+        OpCode::OpSetTabUp(OpMode::ABC(_, b, c)) => {
+            regs.read_rk(*b);
+            regs.read_rk(*c);
+        }
+        OpCode::OpSetTabUp(OpMode::ABCK(_, _, c, k)) => regs.read_ck(*c, *k),
+        OpCode::OpSetTable(OpMode::ABC(a, b, c)) => {
+            regs.read(*a);
+            regs.read_rk(*b);
+            regs.read_rk(*c);
+        }
+        OpCode::OpSetTable(OpMode::ABCK(a, b, c, k)) => {
+            regs.read(*a);
+            regs.read(*b);
+            regs.read_ck(*c, *k);
+        }
+        OpCode::OpSetI(OpMode::ABCK(a, _, c, k)) | OpCode::OpSetField(OpMode::ABCK(a, _, c, k)) => {
+            regs.read(*a);
+            regs.read_ck(*c, *k);
+        }
+        OpCode::OpSelf(OpMode::ABC(a, b, c)) => {
+            regs.read(*b);
+            regs.read_rk(*c);
+            regs.write_range(*a, *a + 1);
+        }
+        OpCode::OpSelf(OpMode::ABCK(a, b, c, k)) => {
+            regs.read(*b);
+            regs.read_ck(*c, *k);
+            regs.write_range(*a, *a + 1);
+        }
+        OpCode::OpAdd(OpMode::ABC(a, b, c))
+        | OpCode::OpSub(OpMode::ABC(a, b, c))
+        | OpCode::OpMul(OpMode::ABC(a, b, c))
+        | OpCode::OpDiv(OpMode::ABC(a, b, c))
+        | OpCode::OpMod(OpMode::ABC(a, b, c))
+        | OpCode::OpPow(OpMode::ABC(a, b, c))
+        | OpCode::OpIdiv(OpMode::ABC(a, b, c))
+        | OpCode::OpBand(OpMode::ABC(a, b, c))
+        | OpCode::OpBor(OpMode::ABC(a, b, c))
+        | OpCode::OpBxor(OpMode::ABC(a, b, c))
+        | OpCode::OpShl(OpMode::ABC(a, b, c))
+        | OpCode::OpShr(OpMode::ABC(a, b, c)) => {
+            regs.read_rk(*b);
+            regs.read_rk(*c);
+            regs.write(*a);
+        }
+        OpCode::OpAdd(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpSub(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpMul(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpDiv(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpMod(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpPow(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpIdiv(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpBand(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpBor(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpBxor(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpShl(OpMode::ABCK(a, b, c, _))
+        | OpCode::OpShr(OpMode::ABCK(a, b, c, _)) => {
+            regs.read(*b);
+            regs.read(*c);
+            regs.write(*a);
+        }
+        // the result goes to the A of the arithmetic before, which already wrote it
+        OpCode::OpMmBin(OpMode::ABCK(a, b, _, _)) => {
+            regs.read(*a);
+            regs.read(*b);
+        }
+        OpCode::OpConcat(OpMode::ABC(a, b, c)) => {
+            regs.read_range(*b, *c);
+            regs.write(*a);
+        }
+        OpCode::OpConcat(OpMode::ABCK(a, b, _, _)) => {
+            regs.read_range(*a, *a + *b - 1);
+            regs.write(*a);
+        }
+        OpCode::OpEq(OpMode::ABC(_, b, c))
+        | OpCode::OpLt(OpMode::ABC(_, b, c))
+        | OpCode::OpLe(OpMode::ABC(_, b, c)) => {
+            regs.read_rk(*b);
+            regs.read_rk(*c);
+        }
+        OpCode::OpEq(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpLt(OpMode::ABCK(a, b, _, _))
+        | OpCode::OpLe(OpMode::ABCK(a, b, _, _)) => {
+            regs.read(*a);
+            regs.read(*b);
+        }
+        OpCode::OpTestSet(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _)) => {
+            regs.read(*b);
+            regs.write(*a);
+        }
+        OpCode::OpCall(OpMode::ABC(a, b, c) | OpMode::ABCK(a, b, c, _)) => {
+            regs.read(*a);
+            regs.read_counted(*a + 1, *b, top);
+            regs.write_counted(*a, *c, top);
+        }
+        OpCode::OpTailCall(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _)) => {
+            regs.read(*a);
+            regs.read_counted(*a + 1, *b, top);
+        }
+        OpCode::OpReturn(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _)) => {
+            regs.read_counted(*a, *b, top)
+        }
+        OpCode::OpForLoop(OpMode::ASBX(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write(*a);
+            regs.write(*a + 3);
+        }
+        // 5.4 counts the iterations left in place of the limit
+        OpCode::OpForLoop(OpMode::ABX(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write_range(*a, *a + 1);
+            regs.write(*a + 3);
+        }
+        OpCode::OpForPrep(OpMode::ASBX(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write(*a);
+        }
+        OpCode::OpForPrep(OpMode::ABX(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write_range(*a, *a + 3);
+        }
+        // the closing value of a 5.4 generic for
+        OpCode::OpTForPrep(OpMode::ABX(a, _)) => regs.read(*a + 3),
+        OpCode::OpTForCall(OpMode::ABC(a, _, c)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write_range(*a + 3, *a + 2 + *c);
+        }
+        OpCode::OpTForCall(OpMode::ABCK(a, _, c, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write_range(*a + 4, *a + 3 + *c);
+        }
+        // 5.1 calls the iterator itself and copies the first result to the control variable
+        OpCode::OpTForLoop(OpMode::ABC(a, _, c)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write_range(*a + 2, *a + 2 + *c);
+        }
+        OpCode::OpTForLoop(OpMode::ASBX(a, _)) => {
+            regs.read(*a + 1);
+            regs.write(*a);
+        }
+        OpCode::OpTForLoop(OpMode::ABX(a, _)) => {
+            regs.read(*a + 4);
+            regs.write(*a + 2);
+        }
+        OpCode::OpSetList(OpMode::ABC(a, b, _) | OpMode::ABCK(a, b, _, _)) => {
+            regs.read(*a);
+            match *b {
+                0 => regs.read_top(*a + 1, top),
+                b => regs.read_range(*a + 1, *a + b),
+            }
+        }
+        OpCode::OpVararg(OpMode::ABC(a, b, _)) => regs.write_counted(*a, *b, top),
+        OpCode::OpVararg(OpMode::ABCK(a, _, c, _)) => regs.write_counted(*a, *c, top),
+        _ => {}
+    }
+}
+
+// the AUX word after the Luau instruction at index
+fn aux_luau(code: &[Instruction], index: usize) -> LuaInt {
+    match code.get(index + 1).map(|inst| &inst.op) {
+        Some(OpCode::OpAux(OpMode::AX(aux))) => *aux,
+        _ => 0,
+    }
+}
+
+fn register_use_luau(
+    code: &[Instruction],
+    index: usize,
+    top: &mut Option<usize>,
+    regs: &mut Operands,
+) {
+    match &code[index].op {
+        OpCode::OpLoadNil(OpMode::ABC(a, _, _))
+        | OpCode::OpLoadB(OpMode::ABC(a, _, _))
+        | OpCode::OpLoadN(OpMode::AD(a, _))
+        | OpCode::OpLoadK(OpMode::AD(a, _))
+        | OpCode::OpLoadKx(OpMode::AD(a, _))
+        | OpCode::OpGetGlobal(OpMode::ABC(a, _, _))
+        | OpCode::OpGetUpval(OpMode::ABC(a, _, _))
+        | OpCode::OpGetImport(OpMode::AD(a, _))
+        | OpCode::OpNewTable(OpMode::ABC(a, _, _))
+        | OpCode::OpDupTable(OpMode::AD(a, _))
+        | OpCode::OpClosure(OpMode::AD(a, _))
+        | OpCode::OpDupClosure(OpMode::AD(a, _)) => regs.write(*a),
+        OpCode::OpMove(OpMode::ABC(a, b, _))
+        | OpCode::OpNot(OpMode::ABC(a, b, _))
+        | OpCode::OpUnm(OpMode::ABC(a, b, _))
+        | OpCode::OpLen(OpMode::ABC(a, b, _))
+        | OpCode::OpGetTableKs(OpMode::ABC(a, b, _))
+        | OpCode::OpGetTableN(OpMode::ABC(a, b, _))
+        | OpCode::OpAddK(OpMode::ABC(a, b, _))
+        | OpCode::OpSubK(OpMode::ABC(a, b, _))
+        | OpCode::OpMulK(OpMode::ABC(a, b, _))
+        | OpCode::OpDivK(OpMode::ABC(a, b, _))
+        | OpCode::OpModK(OpMode::ABC(a, b, _))
+        | OpCode::OpPowK(OpMode::ABC(a, b, _))
+        | OpCode::OpIdivK(OpMode::ABC(a, b, _))
+        | OpCode::OpAndK(OpMode::ABC(a, b, _))
+        | OpCode::OpOrK(OpMode::ABC(a, b, _)) => {
+            regs.read(*b);
+            regs.write(*a);
+        }
+        OpCode::OpSubRk(OpMode::ABC(a, _, c)) | OpCode::OpDivRk(OpMode::ABC(a, _, c)) => {
+            regs.read(*c);
+            regs.write(*a);
+        }
+        OpCode::OpGetTable(OpMode::ABC(a, b, c))
+        | OpCode::OpAdd(OpMode::ABC(a, b, c))
+        | OpCode::OpSub(OpMode::ABC(a, b, c))
+        | OpCode::OpMul(OpMode::ABC(a, b, c))
+        | OpCode::OpDiv(OpMode::ABC(a, b, c))
+        | OpCode::OpMod(OpMode::ABC(a, b, c))
+        | OpCode::OpPow(OpMode::ABC(a, b, c))
+        | OpCode::OpIdiv(OpMode::ABC(a, b, c))
+        | OpCode::OpAnd(OpMode::ABC(a, b, c))
+        | OpCode::OpOr(OpMode::ABC(a, b, c)) => {
+            regs.read(*b);
+            regs.read(*c);
+            regs.write(*a);
+        }
+        OpCode::OpSetGlobal(OpMode::ABC(a, _, _))
+        | OpCode::OpSetUpval(OpMode::ABC(a, _, _))
+        | OpCode::OpJumpIf(OpMode::AD(a, _))
+        | OpCode::OpJumpIfNot(OpMode::AD(a, _))
+        | OpCode::OpJumpXEqKNil(OpMode::AD(a, _))
+        | OpCode::OpJumpXEqKB(OpMode::AD(a, _))
+        | OpCode::OpJumpXEqKN(OpMode::AD(a, _))
+        | OpCode::OpJumpXEqKS(OpMode::AD(a, _)) => regs.read(*a),
+        OpCode::OpSetTable(OpMode::ABC(a, b, c)) => {
+            regs.read(*a);
+            regs.read(*b);
+            regs.read(*c);
+        }
+        OpCode::OpSetTableKs(OpMode::ABC(a, b, _)) | OpCode::OpSetTableN(OpMode::ABC(a, b, _)) => {
+            regs.read(*a);
+            regs.read(*b);
+        }
+        // the other register of a comparison is in the AUX word
+        OpCode::OpJumpIfEq(OpMode::AD(a, _))
+        | OpCode::OpJumpIfLe(OpMode::AD(a, _))
+        | OpCode::OpJumpIfLt(OpMode::AD(a, _))
+        | OpCode::OpJumpIfNotEq(OpMode::AD(a, _))
+        | OpCode::OpJumpIfNotLe(OpMode::AD(a, _))
+        | OpCode::OpJumpIfNotLt(OpMode::AD(a, _)) => {
+            regs.read(*a);
+            regs.read(aux_luau(code, index));
+        }
+        OpCode::OpNamecall(OpMode::ABC(a, b, _)) => {
+            regs.read(*b);
+            regs.write_range(*a, *a + 1);
+        }
+        OpCode::OpCall(OpMode::ABC(a, b, c)) => {
+            regs.read(*a);
+            regs.read_counted(*a + 1, *b, top);
+            regs.write_counted(*a, *c, top);
+        }
+        OpCode::OpReturn(OpMode::ABC(a, b, _)) => regs.read_counted(*a, *b, top),
+        OpCode::OpVararg(OpMode::ABC(a, b, _)) => regs.write_counted(*a, *b, top),
+        OpCode::OpConcat(OpMode::ABC(a, b, c)) => {
+            regs.read_range(*b, *c);
+            regs.write(*a);
+        }
+        OpCode::OpSetList(OpMode::ABC(a, b, c)) => {
+            regs.read(*a);
+            regs.read_counted(*b, *c, top);
+        }
+        OpCode::OpForNPrep(OpMode::AD(a, _))
+        | OpCode::OpForGPrep(OpMode::AD(a, _))
+        | OpCode::OpForGPrepINext(OpMode::AD(a, _))
+        | OpCode::OpForGPrepNext(OpMode::AD(a, _)) => regs.read_range(*a, *a + 2),
+        OpCode::OpForNLoop(OpMode::AD(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write(*a + 2);
+        }
+        // the low byte of the AUX word is how many variables the loop has
+        OpCode::OpForGLoop(OpMode::AD(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write_range(*a + 2, *a + 2 + (aux_luau(code, index) & 0xFF));
+        }
+        // value and reference captures name a register, upvalue ones don't
+        OpCode::OpCapture(OpMode::ABC(a, b, _)) if *a < 2 => regs.read(*b),
+        // a builtin reads its arguments and leaves the results of the CALL it skips
+        OpCode::OpFastCall(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall1(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall2(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall2K(OpMode::ABC(_, _, c))
+        | OpCode::OpFastCall3(OpMode::ABC(_, _, c)) => {
+            let aux: LuaInt = aux_luau(code, index);
+            match &code[index].op {
+                OpCode::OpFastCall1(OpMode::ABC(_, b, _))
+                | OpCode::OpFastCall2K(OpMode::ABC(_, b, _)) => regs.read(*b),
+                OpCode::OpFastCall2(OpMode::ABC(_, b, _)) => {
+                    regs.read(*b);
+                    regs.read(aux);
+                }
+                OpCode::OpFastCall3(OpMode::ABC(_, b, _)) => {
+                    regs.read(*b);
+                    regs.read(aux & 0xFF);
+                    regs.read((aux >> 8) & 0xFF);
+                }
+                _ => {}
+            }
+            let call: Option<&OpCode> = usize::try_from(*c)
+                .ok()
+                .and_then(|c| code.get(index + 1 + c))
+                .map(|inst| &inst.op);
+            if let Some(OpCode::OpCall(OpMode::ABC(a, b, c))) = call {
+                // the plain FASTCALL comes after the arguments are in place
+                if let OpCode::OpFastCall(_) = &code[index].op {
+                    let mut peek: Option<usize> = *top;
+                    regs.read_counted(*a + 1, *b, &mut peek);
+                }
+                // the CALL still has to see the top it would start from
+                let mut keep: Option<usize> = None;
+                regs.write_counted(*a, *c, &mut keep);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn register_use_luajit(op: &OpCode, frame: LuaInt, top: &mut Option<usize>, regs: &mut Operands) {
+    match op {
+        OpCode::OpIsLt(OpMode::AD(a, d))
+        | OpCode::OpIsGe(OpMode::AD(a, d))
+        | OpCode::OpIsLe(OpMode::AD(a, d))
+        | OpCode::OpIsGt(OpMode::AD(a, d))
+        | OpCode::OpIsEqV(OpMode::AD(a, d))
+        | OpCode::OpIsNeV(OpMode::AD(a, d)) => {
+            regs.read(*a);
+            regs.read(*d);
+        }
+        OpCode::OpIsEqS(OpMode::AD(a, _))
+        | OpCode::OpIsNeS(OpMode::AD(a, _))
+        | OpCode::OpIsEqN(OpMode::AD(a, _))
+        | OpCode::OpIsNeN(OpMode::AD(a, _))
+        | OpCode::OpIsEqP(OpMode::AD(a, _))
+        | OpCode::OpIsNeP(OpMode::AD(a, _))
+        | OpCode::OpIsType(OpMode::AD(a, _))
+        | OpCode::OpIsNum(OpMode::AD(a, _))
+        | OpCode::OpGSet(OpMode::AD(a, _))
+        | OpCode::OpRet1(OpMode::AD(a, _)) => regs.read(*a),
+        OpCode::OpIsT(OpMode::AD(_, d))
+        | OpCode::OpIsF(OpMode::AD(_, d))
+        | OpCode::OpUSetV(OpMode::AD(_, d)) => regs.read(*d),
+        OpCode::OpIsTC(OpMode::AD(a, d))
+        | OpCode::OpIsFC(OpMode::AD(a, d))
+        | OpCode::OpMove(OpMode::AD(a, d))
+        | OpCode::OpNot(OpMode::AD(a, d))
+        | OpCode::OpUnm(OpMode::AD(a, d))
+        | OpCode::OpLen(OpMode::AD(a, d)) => {
+            regs.read(*d);
+            regs.write(*a);
+        }
+        OpCode::OpAddVN(OpMode::ABC(a, b, _))
+        | OpCode::OpSubVN(OpMode::ABC(a, b, _))
+        | OpCode::OpMulVN(OpMode::ABC(a, b, _))
+        | OpCode::OpDivVN(OpMode::ABC(a, b, _))
+        | OpCode::OpModVN(OpMode::ABC(a, b, _))
+        | OpCode::OpAddNV(OpMode::ABC(a, b, _))
+        | OpCode::OpSubNV(OpMode::ABC(a, b, _))
+        | OpCode::OpMulNV(OpMode::ABC(a, b, _))
+        | OpCode::OpDivNV(OpMode::ABC(a, b, _))
+        | OpCode::OpModNV(OpMode::ABC(a, b, _))
+        | OpCode::OpTGetS(OpMode::ABC(a, b, _))
+        | OpCode::OpTGetB(OpMode::ABC(a, b, _)) => {
+            regs.read(*b);
+            regs.write(*a);
+        }
+        OpCode::OpAddVV(OpMode::ABC(a, b, c))
+        | OpCode::OpSubVV(OpMode::ABC(a, b, c))
+        | OpCode::OpMulVV(OpMode::ABC(a, b, c))
+        | OpCode::OpDivVV(OpMode::ABC(a, b, c))
+        | OpCode::OpModVV(OpMode::ABC(a, b, c))
+        | OpCode::OpPow(OpMode::ABC(a, b, c))
+        | OpCode::OpTGetV(OpMode::ABC(a, b, c))
+        | OpCode::OpTGetR(OpMode::ABC(a, b, c)) => {
+            regs.read(*b);
+            regs.read(*c);
+            regs.write(*a);
+        }
+        OpCode::OpCat(OpMode::ABC(a, b, c)) => {
+            regs.read_range(*b, *c);
+            regs.write(*a);
+        }
+        OpCode::OpKStr(OpMode::AD(a, _))
+        | OpCode::OpKCData(OpMode::AD(a, _))
+        | OpCode::OpKShort(OpMode::AD(a, _))
+        | OpCode::OpKNum(OpMode::AD(a, _))
+        | OpCode::OpKPri(OpMode::AD(a, _))
+        | OpCode::OpUGet(OpMode::AD(a, _))
+        | OpCode::OpFNew(OpMode::AD(a, _))
+        | OpCode::OpTNew(OpMode::AD(a, _))
+        | OpCode::OpTDup(OpMode::AD(a, _))
+        | OpCode::OpGGet(OpMode::AD(a, _)) => regs.write(*a),
+        OpCode::OpKNil(OpMode::AD(a, d)) => regs.write_range(*a, *d),
+        OpCode::OpTSetV(OpMode::ABC(a, b, c)) | OpCode::OpTSetR(OpMode::ABC(a, b, c)) => {
+            regs.read(*a);
+            regs.read(*b);
+            regs.read(*c);
+        }
+        OpCode::OpTSetS(OpMode::ABC(a, b, _)) | OpCode::OpTSetB(OpMode::ABC(a, b, _)) => {
+            regs.read(*a);
+            regs.read(*b);
+        }
+        // the table is in the slot below the values
+        OpCode::OpTSetM(OpMode::AD(a, _)) => {
+            regs.read(*a - 1);
+            regs.read_top(*a, top);
+        }
+        // B counts the results and C the arguments, both one more than there are
+        OpCode::OpCall(OpMode::ABC(a, b, c)) => {
+            regs.read(*a);
+            regs.read_counted(*a + 1 + frame, *c, top);
+            regs.write_counted(*a, *b, top);
+        }
+        // C only counts the fixed arguments, the rest run up to top
+        OpCode::OpCallM(OpMode::ABC(a, b, _)) => {
+            regs.read(*a);
+            regs.read_top(*a + 1 + frame, top);
+            regs.write_counted(*a, *b, top);
+        }
+        OpCode::OpCallT(OpMode::AD(a, d)) => {
+            regs.read(*a);
+            regs.read_counted(*a + 1 + frame, *d, top);
+        }
+        OpCode::OpCallMT(OpMode::AD(a, _)) => {
+            regs.read(*a);
+            regs.read_top(*a + 1 + frame, top);
+        }
+        // the iterator, its state and the control variable sit below the results
+        OpCode::OpIterC(OpMode::ABC(a, b, _)) | OpCode::OpIterN(OpMode::ABC(a, b, _)) => {
+            regs.read_range(*a - 3, *a - 1);
+            regs.write_counted(*a, *b, top);
+        }
+        OpCode::OpIsNext(OpMode::AD(a, _)) => regs.read_range(*a - 3, *a - 1),
+        OpCode::OpVarg(OpMode::ABC(a, b, _)) => regs.write_counted(*a, *b, top),
+        OpCode::OpRetM(OpMode::AD(a, _)) => regs.read_top(*a, top),
+        OpCode::OpRet(OpMode::AD(a, d)) => regs.read_counted(*a, *d, top),
+        OpCode::OpForI(OpMode::AD(a, _)) | OpCode::OpJForI(OpMode::AD(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write(*a + 3);
+        }
+        OpCode::OpForL(OpMode::AD(a, _))
+        | OpCode::OpIForL(OpMode::AD(a, _))
+        | OpCode::OpJForL(OpMode::AD(a, _)) => {
+            regs.read_range(*a, *a + 2);
+            regs.write(*a);
+            regs.write(*a + 3);
+        }
+        OpCode::OpIterL(OpMode::AD(a, _))
+        | OpCode::OpIIterL(OpMode::AD(a, _))
+        | OpCode::OpJIterL(OpMode::AD(a, _)) => {
+            regs.read(*a);
+            regs.write(*a - 1);
+        }
+        _ => {}
+    }
+}
+
+// the child a closure instruction creates, Luau and LuaJIT name it through a constant
+fn closure_child<'a>(proto: &'a Proto, op: &OpCode) -> Option<&'a Proto> {
+    let index: LuaInt = match op {
+        OpCode::OpClosure(OpMode::ABX(_, index) | OpMode::AD(_, index)) => *index,
+        OpCode::OpDupClosure(OpMode::AD(_, d)) | OpCode::OpFNew(OpMode::AD(_, d)) => {
+            match usize::try_from(*d)
+                .ok()
+                .and_then(|d| proto.constants.get(d))
+            {
+                Some(Constant::Closure(index)) => *index,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| proto.protos.get(index))
+}
+
+// the registers a closure instruction at index captures, a local function capturing itself
+// is left out since that's the register the instruction writes. 5.1 lists them in the MOVEs
+// after it, Luau in CAPTURE instructions of its own that read them instead
+fn closure_captures(proto: &Proto, index: usize, version: LuaVersion) -> Vec<usize> {
+    let op: &OpCode = &proto.code[index].op;
+    let a: LuaInt = match op {
+        OpCode::OpClosure(OpMode::ABX(a, _) | OpMode::AD(a, _))
+        | OpCode::OpFNew(OpMode::AD(a, _)) => *a,
+        _ => return Vec::new(),
+    };
+    let child: &Proto = match closure_child(proto, op) {
+        Some(child) => child,
+        None => return Vec::new(),
+    };
+    let captures: Vec<LuaInt> = match version {
+        LuaVersion::Lua51 => proto
+            .code
+            .iter()
+            .skip(index + 1)
+            .take(child.upvalue_count as usize)
+            .filter_map(|capture| match capture.op {
+                OpCode::OpMove(OpMode::ABC(_, b, _)) => Some(b),
+                _ => None,
+            })
+            .collect(),
+        LuaVersion::Luau => Vec::new(),
+        _ => child
+            .upvalues
+            .iter()
+            .filter(|upvalue| upvalue.instack)
+            .map(|upvalue| upvalue.idx as LuaInt)
+            .collect(),
+    };
+    captures
+        .into_iter()
+        .filter(|capture| *capture != a)
+        .filter_map(reg)
+        .collect()
+}
+
+// the registers read and written by every instruction, indexed like Proto.code. Ranges that
+// end at top follow the last call or vararg that kept every result in the same block, and
+// unreachable blocks are left empty. The MOVEs and GETUPVALs after a 5.1 CLOSURE only
+// describe its captures, they do nothing
+pub fn register_uses(
+    proto: &Proto,
+    cfg: &Cfg,
+    version: LuaVersion,
+) -> Vec<(Vec<usize>, Vec<usize>)> {
+    let code_len: usize = proto.code.len();
+    let frame: LuaInt = proto.two_slot_frame as LuaInt;
+    let mut uses: Vec<(Vec<usize>, Vec<usize>)> = vec![(Vec::new(), Vec::new()); code_len];
+    for (block_index, block) in cfg.blocks.iter().enumerate() {
+        // code no path reaches, like the RETURN after a TAILCALL, does nothing
+        if block_index > 0 && block.predecessors.is_empty() {
+            continue;
+        }
+        let mut top: Option<usize> = None;
+        let mut index: usize = block.start;
+        while index < block.end {
+            let mut regs: Operands = Operands {
+                reads: Vec::new(),
+                writes: Vec::new(),
+                stack_size: proto.max_stack_size as usize,
+            };
+            match version {
+                LuaVersion::Luau => register_use_luau(&proto.code, index, &mut top, &mut regs),
+                LuaVersion::LuaJit => {
+                    register_use_luajit(&proto.code[index].op, frame, &mut top, &mut regs)
+                }
+                _ => register_use_lua(&proto.code[index].op, version, &mut top, &mut regs),
+            }
+            regs.reads.extend(closure_captures(proto, index, version));
+            uses[index] = (regs.reads, regs.writes);
+            let op: &OpCode = &proto.code[index].op;
+            let skip: usize = match (version, op) {
+                (LuaVersion::Lua51, OpCode::OpClosure(_)) => closure_child(proto, op)
+                    .map_or(0, |child| child.upvalue_count as usize)
+                    .min(block.end - index - 1),
+                _ => 0,
+            };
+            index += 1 + skip;
+        }
+    }
+    uses
+}
+
+// registers a closure may still reach through an open upvalue, a store to them isn't dead
+// just because no instruction reads it back. Luau copies values into closures unless it
+// captures by reference
+pub fn captured_registers(proto: &Proto, version: LuaVersion) -> Vec<bool> {
+    let mut captured: Vec<bool> = vec![false; NUM_REGS];
+    for (index, inst) in proto.code.iter().enumerate() {
+        for reg in closure_captures(proto, index, version) {
+            captured[reg] = true;
+        }
+        if let OpCode::OpCapture(OpMode::ABC(1, b, _)) = inst.op {
+            if let Some(reg) = reg(b) {
+                captured[reg] = true;
+            }
+        }
+    }
+    captured
+}
+
+pub struct Liveness {
+    // what every instruction reads and writes, as register_uses gives it
+    pub uses: Vec<(Vec<usize>, Vec<usize>)>,
+    // per block, registers some path from its start or end reads before writing them
+    pub live_in: Vec<Vec<bool>>,
+    pub live_out: Vec<Vec<bool>>,
+}
+
+pub fn build_liveness(cfg: &Cfg, uses: Vec<(Vec<usize>, Vec<usize>)>) -> Liveness {
+    let block_count: usize = cfg.blocks.len();
+    let mut gen: Vec<Vec<bool>> = vec![vec![false; NUM_REGS]; block_count];
+    let mut kill: Vec<Vec<bool>> = vec![vec![false; NUM_REGS]; block_count];
+    for (block_index, block) in cfg.blocks.iter().enumerate() {
+        for (reads, writes) in &uses[block.start..block.end] {
+            for reg in reads {
+                if !kill[block_index][*reg] {
+                    gen[block_index][*reg] = true;
+                }
+            }
+            for reg in writes {
+                kill[block_index][*reg] = true;
+            }
+        }
+    }
+
+    let mut live_in: Vec<Vec<bool>> = gen;
+    let mut live_out: Vec<Vec<bool>> = vec![vec![false; NUM_REGS]; block_count];
+    let mut changed: bool = true;
+    while changed {
+        changed = false;
+        for block_index in (0..block_count).rev() {
+            for successor in &cfg.blocks[block_index].successors {
+                for reg in 0..NUM_REGS {
+                    if live_in[*successor][reg] && !live_out[block_index][reg] {
+                        live_out[block_index][reg] = true;
+                        if !kill[block_index][reg] && !live_in[block_index][reg] {
+                            live_in[block_index][reg] = true;
+                        }
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    Liveness {
+        uses,
+        live_in,
+        live_out,
+    }
+}
+
+impl Liveness {
+    // registers live just after each instruction, walking every block back from its end
+    pub fn live_after(&self, cfg: &Cfg) -> Vec<Vec<bool>> {
+        let mut live_after: Vec<Vec<bool>> = vec![Vec::new(); self.uses.len()];
+        for (block_index, block) in cfg.blocks.iter().enumerate() {
+            let mut live: Vec<bool> = self.live_out[block_index].clone();
+            for index in (block.start..block.end).rev() {
+                live_after[index] = live.clone();
+                let (reads, writes): &(Vec<usize>, Vec<usize>) = &self.uses[index];
+                for reg in writes {
+                    live[*reg] = false;
+                }
+                for reg in reads {
+                    live[*reg] = true;
+                }
+            }
+        }
+        live_after
+    }
+
+    // writes nothing reads before the register is written again or the function returns,
+    // as (index, register). Registers a closure captured are never reported
+    pub fn dead_stores(&self, cfg: &Cfg, captured: &[bool]) -> Vec<(usize, usize)> {
+        let live_after: Vec<Vec<bool>> = self.live_after(cfg);
+        let mut dead: Vec<(usize, usize)> = Vec::new();
+        for (index, (_, writes)) in self.uses.iter().enumerate() {
+            for reg in writes {
+                if !live_after[index][*reg] && !captured[*reg] {
+                    dead.push((index, *reg));
+                }
+            }
+        }
+        dead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::bytecode::Bytecode;
+    use crate::cfg::build_cfg;
+
+    fn assemble(text: &str) -> Bytecode {
+        match build_assembler(text).assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn live_regs(live: &[bool]) -> Vec<usize> {
+        (0..live.len()).filter(|reg| live[*reg]).collect()
+    }
+
+    #[test]
+    fn live_sets_follow_every_path() {
+        let bytecode: Bytecode = assemble(
+            r#"
+                LOADK r0, 1
+                LOADK r1, 2
+                LOADK r1, 3
+                TEST r0, 0
+                JMP skip
+                ADD r2, r0, r1
+                RETURN r2, 2
+            skip:
+                RETURN r0, 2
+            "#,
+        );
+        let proto: &Proto = &bytecode.main_proto;
+        let cfg: Cfg = build_cfg(proto, bytecode.version);
+        let uses: Vec<(Vec<usize>, Vec<usize>)> = register_uses(proto, &cfg, bytecode.version);
+        assert_eq!(uses[0], (vec![], vec![0]));
+        assert_eq!(uses[3], (vec![0], vec![]));
+        assert_eq!(uses[5], (vec![0, 1], vec![2]));
+        assert_eq!(uses[7], (vec![0], vec![]));
+
+        let liveness: Liveness = build_liveness(&cfg, uses);
+        let entry: usize = cfg.block_at(0).unwrap();
+        let add: usize = cfg.block_at(5).unwrap();
+        let skip: usize = cfg.block_at(7).unwrap();
+        assert_eq!(live_regs(&liveness.live_in[entry]), []);
+        assert_eq!(live_regs(&liveness.live_out[entry]), [0, 1]);
+        assert_eq!(live_regs(&liveness.live_in[add]), [0, 1]);
+        assert_eq!(live_regs(&liveness.live_in[skip]), [0]);
+        assert_eq!(live_regs(&liveness.live_out[skip]), []);
+
+        let live_after: Vec<Vec<bool>> = liveness.live_after(&cfg);
+        assert_eq!(live_regs(&live_after[1]), [0]);
+        assert_eq!(live_regs(&live_after[5]), [2]);
+        // the first store to r1 is overwritten before anything reads it
+        assert_eq!(
+            liveness.dead_stores(&cfg, &captured_registers(proto, bytecode.version)),
+            [(1, 1)]
+        );
+    }
+
+    #[test]
+    fn captured_stores_stay_alive() {
+        let bytecode: Bytecode = assemble(
+            r#"
+                LOADK r0, 1
+                LOADK r1, 2
+                CLOSURE r2, get
+                MOVE r0, r1
+                RETURN r2, 2
+            .proc get
+                .upvalue value
+                GETUPVAL r0, u0
+                RETURN r0, 2
+            .end
+            "#,
+        );
+        let proto: &Proto = &bytecode.main_proto;
+        let cfg: Cfg = build_cfg(proto, bytecode.version);
+        let uses: Vec<(Vec<usize>, Vec<usize>)> = register_uses(proto, &cfg, bytecode.version);
+        // the MOVE only names the capture, the CLOSURE reads it
+        assert_eq!(uses[2], (vec![1], vec![2]));
+        assert_eq!(uses[3], (vec![], vec![]));
+
+        let captured: Vec<bool> = captured_registers(proto, bytecode.version);
+        assert_eq!(live_regs(&captured), [1]);
+        let liveness: Liveness = build_liveness(&cfg, uses);
+        assert_eq!(liveness.dead_stores(&cfg, &captured), [(0, 0)]);
+    }
+}
@@ -0,0 +1,387 @@
+// --luac-listing, the listing of luac -l -l for 5.1 chunks, laid out the way print.c
+// prints it so scripts written against luac can read inu's output
+
+use crate::bytecode::{Bytecode, Constant, Instruction, LuaInt, OpCode, OpMode, Proto, BITRK};
+use crate::verify::{arg_modes, ArgMode};
+use crate::vm::format_number;
+
+pub struct LuacLister<'a> {
+    pub bytecode: &'a Bytecode,
+}
+
+pub fn build_luac_lister(bytecode: &Bytecode) -> LuacLister<'_> {
+    LuacLister { bytecode }
+}
+
+// the S and SS macros of print.c
+fn plural(n: usize) -> &'static str {
+    match n {
+        1 => "",
+        _ => "s",
+    }
+}
+
+// a constant operand is printed as -1 - its index
+fn rk(value: LuaInt) -> LuaInt {
+    match value & BITRK {
+        0 => value,
+        _ => -1 - (value & !BITRK),
+    }
+}
+
+// PrintString, escapes what isprint rejects as \ddd
+fn quote(bytes: &[u8]) -> String {
+    let mut text: String = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'"' => text.push_str("\\\""),
+            b'\\' => text.push_str("\\\\"),
+            0x07 => text.push_str("\\a"),
+            0x08 => text.push_str("\\b"),
+            0x0c => text.push_str("\\f"),
+            b'\n' => text.push_str("\\n"),
+            b'\r' => text.push_str("\\r"),
+            b'\t' => text.push_str("\\t"),
+            0x0b => text.push_str("\\v"),
+            0x20..=0x7e => text.push(*byte as char),
+            _ => text.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn constant(proto: &Proto, index: LuaInt) -> String {
+    let value: Option<&Constant> = usize::try_from(index)
+        .ok()
+        .and_then(|index| proto.constants.get(index));
+    match value {
+        Some(Constant::Nil) => String::from("nil"),
+        Some(Constant::Boolean(b)) => b.to_string(),
+        Some(Constant::Number(n)) => format_number(*n),
+        Some(Constant::String(s)) => quote(s),
+        // left for the verifier to complain about
+        _ => String::from("?"),
+    }
+}
+
+// the operands of an RK pair, - for the ones in registers
+fn rk_comment(proto: &Proto, b: LuaInt, c: LuaInt) -> String {
+    if b & BITRK == 0 && c & BITRK == 0 {
+        return String::new();
+    }
+    let side = |value: LuaInt| match value & BITRK {
+        0 => String::from("-"),
+        _ => constant(proto, value & !BITRK),
+    };
+    format!("\t; {} {}", side(b), side(c))
+}
+
+// getstr of a constant, GETGLOBAL and SETGLOBAL print the name without quotes
+fn raw_string(proto: &Proto, index: LuaInt) -> String {
+    let value: Option<&Constant> = usize::try_from(index)
+        .ok()
+        .and_then(|index| proto.constants.get(index));
+    match value {
+        Some(Constant::String(s)) => String::from_utf8_lossy(s).into_owned(),
+        _ => String::from("?"),
+    }
+}
+
+impl LuacLister<'_> {
+    pub fn write(&self) -> String {
+        let mut out: String = String::new();
+        // lundump names a chunk saved without its source =?
+        let source: &[u8] = match self.bytecode.main_proto.source.is_empty() {
+            true => b"=?",
+            false => &self.bytecode.main_proto.source,
+        };
+        self.write_proto(&mut out, &self.bytecode.main_proto, source);
+        out
+    }
+
+    // children dumped without a source share their parent's
+    fn write_proto(&self, out: &mut String, proto: &Proto, parent_source: &[u8]) {
+        let source: &[u8] = match proto.source.is_empty() {
+            true => parent_source,
+            false => &proto.source,
+        };
+        self.write_header(out, proto, source);
+        self.write_code(out, proto);
+        self.write_constants(out, proto);
+        self.write_locals(out, proto);
+        self.write_upvalues(out, proto);
+        for child in &proto.protos {
+            self.write_proto(out, child, source);
+        }
+    }
+
+    fn write_header(&self, out: &mut String, proto: &Proto, source: &[u8]) {
+        let name: String = match source.first() {
+            Some(b'@') | Some(b'=') => String::from_utf8_lossy(&source[1..]).into_owned(),
+            Some(0x1b) => String::from("(bstring)"),
+            _ => String::from("(string)"),
+        };
+        let words: usize = word_count(proto);
+        out.push_str(&format!(
+            "\n{} <{}:{},{}> ({} instruction{}, {} bytes at {:p})\n",
+            match proto.line_defined {
+                0 => "main",
+                _ => "function",
+            },
+            name,
+            proto.line_defined,
+            proto.last_line_defined,
+            words,
+            plural(words),
+            words * 4,
+            proto
+        ));
+        out.push_str(&format!(
+            "{}{} param{}, {} slot{}, {} upvalue{}, ",
+            proto.param_count,
+            match proto.is_vararg {
+                true => "+",
+                false => "",
+            },
+            plural(proto.param_count as usize),
+            proto.max_stack_size,
+            plural(proto.max_stack_size as usize),
+            proto.upvalue_count,
+            plural(proto.upvalue_count as usize)
+        ));
+        out.push_str(&format!(
+            "{} local{}, {} constant{}, {} function{}\n",
+            proto.locals.len(),
+            plural(proto.locals.len()),
+            proto.constants.len(),
+            plural(proto.constants.len()),
+            proto.protos.len(),
+            plural(proto.protos.len())
+        ));
+    }
+
+    fn write_code(&self, out: &mut String, proto: &Proto) {
+        // pcs count words, a SETLIST count is a word of its own
+        let mut pc: usize = 0;
+        for inst in &proto.code {
+            let line: String = match proto.lineinfo.get(pc) {
+                Some(line) if *line > 0 => format!("[{}]", line),
+                _ => String::from("[-]"),
+            };
+            let (operands, comment): (String, String) = self.operands(proto, inst, pc);
+            let mnemonic: String = inst.op.name()[2..].to_uppercase();
+            out.push_str(&format!(
+                "\t{}\t{}\t{:<9}\t{}{}\n",
+                pc + 1,
+                line,
+                mnemonic,
+                operands,
+                comment
+            ));
+            pc += match inst.aux.is_some() {
+                true => 2,
+                false => 1,
+            };
+        }
+    }
+
+    // the operands luaP_opmodes says an instruction uses, and the comment print.c adds
+    fn operands(&self, proto: &Proto, inst: &Instruction, pc: usize) -> (String, String) {
+        let pc: LuaInt = pc as LuaInt;
+        let op: &OpCode = &inst.op;
+        match op {
+            OpCode::OpLoadK(OpMode::ABX(a, bx)) => (
+                format!("{} {}", a, -1 - bx),
+                format!("\t; {}", constant(proto, *bx)),
+            ),
+            OpCode::OpGetGlobal(OpMode::ABX(a, bx)) | OpCode::OpSetGlobal(OpMode::ABX(a, bx)) => (
+                format!("{} {}", a, -1 - bx),
+                format!("\t; {}", raw_string(proto, *bx)),
+            ),
+            OpCode::OpClosure(OpMode::ABX(a, bx)) => {
+                let child: String = match usize::try_from(*bx)
+                    .ok()
+                    .and_then(|index| proto.protos.get(index))
+                {
+                    Some(child) => format!("{:p}", child),
+                    None => String::from("(nil)"),
+                };
+                (format!("{} {}", a, bx), format!("\t; {}", child))
+            }
+            // CLOSE only uses A, inu keeps the rest of the word as Bx
+            OpCode::OpClose(OpMode::ABX(a, _)) => (a.to_string(), String::new()),
+            OpCode::OpJmp(OpMode::ASBX(_, sbx)) => {
+                (sbx.to_string(), format!("\t; to {}", sbx + pc + 2))
+            }
+            OpCode::OpForLoop(OpMode::ASBX(a, sbx)) | OpCode::OpForPrep(OpMode::ASBX(a, sbx)) => {
+                (format!("{} {}", a, sbx), format!("\t; to {}", sbx + pc + 2))
+            }
+            OpCode::OpGetUpval(OpMode::ABC(a, b, _)) | OpCode::OpSetUpval(OpMode::ABC(a, b, _)) => {
+                // print.c indexes the names by B whenever there are any
+                let name: String = match proto.upvalue_names.is_empty() {
+                    true => String::from("-"),
+                    false => match usize::try_from(*b)
+                        .ok()
+                        .and_then(|index| proto.upvalue_names.get(index))
+                    {
+                        Some(name) => String::from_utf8_lossy(name).into_owned(),
+                        None => String::from("?"),
+                    },
+                };
+                (format!("{} {}", a, b), format!("\t; {}", name))
+            }
+            OpCode::OpGetTable(OpMode::ABC(a, b, c)) | OpCode::OpSelf(OpMode::ABC(a, b, c)) => {
+                let comment: String = match c & BITRK {
+                    0 => String::new(),
+                    _ => format!("\t; {}", constant(proto, c & !BITRK)),
+                };
+                (format!("{} {} {}", a, b, rk(*c)), comment)
+            }
+            OpCode::OpSetTable(OpMode::ABC(a, b, c))
+            | OpCode::OpAdd(OpMode::ABC(a, b, c))
+            | OpCode::OpSub(OpMode::ABC(a, b, c))
+            | OpCode::OpMul(OpMode::ABC(a, b, c))
+            | OpCode::OpDiv(OpMode::ABC(a, b, c))
+            | OpCode::OpPow(OpMode::ABC(a, b, c))
+            | OpCode::OpEq(OpMode::ABC(a, b, c))
+            | OpCode::OpLt(OpMode::ABC(a, b, c))
+            | OpCode::OpLe(OpMode::ABC(a, b, c)) => (
+                format!("{} {} {}", a, rk(*b), rk(*c)),
+                rk_comment(proto, *b, *c),
+            ),
+            // a C of 0 has the count in the next word, which the comment shows
+            OpCode::OpSetList(OpMode::ABC(a, b, c)) => match inst.aux {
+                Some(aux) => (format!("{} {} 0", a, b), format!("\t; {}", aux)),
+                // print.c applies ISK to C here too, a C past 255 lists as a constant
+                None => (format!("{} {} {}", a, b, rk(*c)), format!("\t; {}", c)),
+            },
+            OpCode::OpUnknown(_) => (String::new(), String::new()),
+            _ => {
+                let mut text: String = String::new();
+                if let Some(OpMode::ABC(a, b, c)) = op.mode() {
+                    let (b_mode, c_mode): (ArgMode, ArgMode) = arg_modes(op);
+                    text.push_str(&a.to_string());
+                    if b_mode != ArgMode::N {
+                        text.push_str(&format!(" {}", rk(*b)));
+                    }
+                    if c_mode != ArgMode::N {
+                        text.push_str(&format!(" {}", rk(*c)));
+                    }
+                }
+                (text, String::new())
+            }
+        }
+    }
+
+    fn write_constants(&self, out: &mut String, proto: &Proto) {
+        out.push_str(&format!(
+            "constants ({}) for {:p}:\n",
+            proto.constants.len(),
+            proto
+        ));
+        for index in 0..proto.constants.len() {
+            out.push_str(&format!(
+                "\t{}\t{}\n",
+                index + 1,
+                constant(proto, index as LuaInt)
+            ));
+        }
+    }
+
+    fn write_locals(&self, out: &mut String, proto: &Proto) {
+        out.push_str(&format!(
+            "locals ({}) for {:p}:\n",
+            proto.locals.len(),
+            proto
+        ));
+        for (index, local) in proto.locals.iter().enumerate() {
+            out.push_str(&format!(
+                "\t{}\t{}\t{}\t{}\n",
+                index,
+                String::from_utf8_lossy(&local.name),
+                local.start_pc + 1,
+                local.end_pc + 1
+            ));
+        }
+    }
+
+    fn write_upvalues(&self, out: &mut String, proto: &Proto) {
+        out.push_str(&format!(
+            "upvalues ({}) for {:p}:\n",
+            proto.upvalue_names.len(),
+            proto
+        ));
+        for (index, name) in proto.upvalue_names.iter().enumerate() {
+            out.push_str(&format!("\t{}\t{}\n", index, String::from_utf8_lossy(name)));
+        }
+    }
+}
+
+// sizecode, what the header counts
+fn word_count(proto: &Proto) -> usize {
+    proto
+        .code
+        .iter()
+        .map(|inst| match inst.aux.is_some() {
+            true => 2,
+            false => 1,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::read::build_reader;
+
+    // the addresses luac prints change from run to run
+    fn without_addresses(listing: &str) -> String {
+        let mut result: String = String::new();
+        let mut rest: &str = listing;
+        while let Some(start) = rest.find("0x") {
+            result.push_str(&rest[..start + 2]);
+            rest = rest[start + 2..].trim_start_matches(|c: char| c.is_ascii_hexdigit());
+            result.push('?');
+        }
+        result.push_str(rest);
+        result
+    }
+
+    fn listing(bytecode: &Bytecode) -> String {
+        without_addresses(&build_luac_lister(bytecode).write())
+    }
+
+    // tests/fixtures/sample.listing is what a stock luac -l -l printed for sample.luac
+    #[test]
+    fn matches_luac() {
+        let bytes: Vec<u8> = include_bytes!("../tests/fixtures/sample.luac").to_vec();
+        let bytecode: Bytecode = match build_reader(&bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(
+            listing(&bytecode),
+            include_str!("../tests/fixtures/sample.listing")
+        );
+    }
+
+    #[test]
+    fn setlist_count_word_takes_a_pc() {
+        let bytecode: Bytecode = match build_assembler("    SETLIST r0, 1, 600\n").assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(
+            listing(&bytecode),
+            "\nmain <?:0,0> (3 instructions, 12 bytes at 0x?)\n\
+             0+ params, 2 slots, 0 upvalues, 0 locals, 0 constants, 0 functions\n\
+             \t1\t[-]\tSETLIST  \t0 1 0\t; 600\n\
+             \t3\t[-]\tRETURN   \t0 1\n\
+             constants (0) for 0x?:\n\
+             locals (0) for 0x?:\n\
+             upvalues (0) for 0x?:\n"
+        );
+    }
+}
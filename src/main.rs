@@ -1,10 +1,32 @@
-use bytecode::Bytecode;
+use asm::{build_assembler, Assembler};
+use bytecode::{Bytecode, LuaVersion};
+use color::ColorChoice;
+use decompile::build_decompiler;
+use json::{build_json_reader, build_json_writer, JsonReader};
+use luac::build_luac_lister;
 use read::{build_reader, Reader};
 use std::{env, fs, process::exit};
+use trace::{build_tracer, open_sandbox, Tracer};
+use verify::{build_verifier, VerifyError};
+use vm::{build_vm, new_table, TableRef, Value, Vm};
+use write::build_writer;
 
+pub mod asm;
 pub mod bytecode;
+pub mod cfg;
+pub mod color;
+pub mod decompile;
+pub mod json;
+pub mod liveness;
+pub mod luac;
+pub mod pattern;
 pub mod read;
+pub mod stdlib;
+pub mod trace;
 pub mod util;
+pub mod verify;
+pub mod vm;
+pub mod write;
 
 fn main() {
     let argv: Vec<String> = env::args().collect();
@@ -14,11 +36,39 @@ fn main() {
         display_help();
     }
 
+    if argv[1] == "asm" {
+        assemble(&argv[2..]);
+        return;
+    }
+    if argv[1] == "from-json" {
+        from_json(&argv[2..]);
+        return;
+    }
+    if argv[1] == "verify" {
+        verify(&argv[2..]);
+        return;
+    }
+    if argv[1] == "run" {
+        run(&argv[2..]);
+        return;
+    }
+    if argv[1] == "trace" {
+        trace(&argv[2..]);
+        return;
+    }
+
     let mut do_decompile: bool = false;
     let mut do_psuedo_code: bool = false;
+    let mut do_print_blocks: bool = false;
+    let mut do_dot: bool = false;
+    let mut do_print_live: bool = false;
+    let mut do_json: bool = false;
+    let mut do_luac_listing: bool = false;
+    let mut color: ColorChoice = ColorChoice::Auto;
     let mut input_path: Option<&String> = None;
-    for i in 1..argv.len() {
-        let value: &String = &argv[i];
+    let mut output_path: Option<&String> = None;
+    let mut args = argv.iter().skip(1);
+    while let Some(value) = args.next() {
         if value.starts_with("--") {
             let option: &str = &value[2..value.len()];
             match option {
@@ -28,6 +78,39 @@ fn main() {
                 "psuedo" => {
                     do_psuedo_code = true;
                 }
+                "blocks" => {
+                    do_print_blocks = true;
+                }
+                "dot" => {
+                    do_dot = true;
+                }
+                "live" => {
+                    do_print_live = true;
+                }
+                "luac-listing" => {
+                    do_luac_listing = true;
+                }
+                "format" => {
+                    let format: &String = args
+                        .next()
+                        .unwrap_or_else(|| panic!("expected a format after {}", value));
+                    match format.as_str() {
+                        "text" => do_json = false,
+                        "json" => do_json = true,
+                        _ => panic!("unexpected format {} found, expected text or json", format),
+                    }
+                }
+                _ if option.starts_with("color=") => {
+                    color = ColorChoice::parse(&option["color=".len()..]).unwrap_or_else(|| {
+                        panic!("unexpected {} found, expected always, never or auto", value)
+                    });
+                }
+                "out" => {
+                    output_path = Some(
+                        args.next()
+                            .unwrap_or_else(|| panic!("expected a file after {}", value)),
+                    );
+                }
                 _ => {
                     panic!("unexpected argument {} found", value);
                 }
@@ -44,23 +127,307 @@ fn main() {
     let input_path: &String = input_path.unwrap();
 
     let input_bytes: Vec<u8> =
-        fs::read(input_path).expect(format!("Failed to read file at {}", input_path).as_str());
+        fs::read(input_path).unwrap_or_else(|_| panic!("Failed to read file at {}", input_path));
 
     let mut reader: Reader = build_reader(&input_bytes);
-    let mut bytecode: Bytecode = reader.read();
+    let mut bytecode: Bytecode = match reader.read() {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+
+    if let Some(output_path) = output_path {
+        let output_bytes: Vec<u8> = match build_writer(&bytecode).write() {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        };
+        fs::write(output_path, output_bytes)
+            .unwrap_or_else(|_| panic!("Failed to write file at {}", output_path));
+        return;
+    }
 
     if do_decompile {
-        eprintln!("decompile is currently disabled");
+        match build_decompiler(&bytecode).decompile() {
+            Ok(source) => print!("{}", source),
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(1);
+            }
+        }
+        return;
+    }
+    if do_luac_listing {
+        if bytecode.version != LuaVersion::Lua51 {
+            eprintln!("--luac-listing only lists Lua 5.1 chunks");
+            exit(1);
+        }
+        print!("{}", build_luac_lister(&bytecode).write());
+        return;
+    }
+    if do_json {
+        print!("{}", build_json_writer(&bytecode).write());
         return;
     }
+    if do_dot {
+        bytecode.print_dot();
+        return;
+    }
+    bytecode.print_blocks = do_print_blocks;
+    bytecode.print_live = do_print_live;
+    bytecode.color = color.enabled();
     bytecode.print_disassembly(do_psuedo_code);
 }
+// inu asm [--out <file>] file, writes luac.out by default like luac does
+fn assemble(args: &[String]) {
+    let mut input_path: Option<&String> = None;
+    let mut output_path: &str = "luac.out";
+    let mut args = args.iter();
+    while let Some(value) = args.next() {
+        match value.as_str() {
+            "--out" => {
+                output_path = args
+                    .next()
+                    .unwrap_or_else(|| panic!("expected a file after {}", value));
+            }
+            _ if value.starts_with("--") => {
+                panic!("unexpected argument {} found", value);
+            }
+            _ => {
+                input_path = Some(value);
+            }
+        }
+    }
+
+    let input_path: &String = input_path.expect("expected argument file not found");
+    let text: String = fs::read_to_string(input_path)
+        .unwrap_or_else(|_| panic!("Failed to read file at {}", input_path));
+
+    let mut assembler: Assembler = build_assembler(&text);
+    let bytecode: Bytecode = match assembler.assemble() {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    let output_bytes: Vec<u8> = match build_writer(&bytecode).write() {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    fs::write(output_path, output_bytes)
+        .unwrap_or_else(|_| panic!("Failed to write file at {}", output_path));
+}
+
+// inu from-json [--out <file>] file, the JSON --format json prints back into a chunk
+fn from_json(args: &[String]) {
+    let mut input_path: Option<&String> = None;
+    let mut output_path: &str = "luac.out";
+    let mut args = args.iter();
+    while let Some(value) = args.next() {
+        match value.as_str() {
+            "--out" => {
+                output_path = args
+                    .next()
+                    .unwrap_or_else(|| panic!("expected a file after {}", value));
+            }
+            _ if value.starts_with("--") => {
+                panic!("unexpected argument {} found", value);
+            }
+            _ => {
+                input_path = Some(value);
+            }
+        }
+    }
+
+    let input_path: &String = input_path.expect("expected argument file not found");
+    let text: String = fs::read_to_string(input_path)
+        .unwrap_or_else(|_| panic!("Failed to read file at {}", input_path));
+
+    let mut reader: JsonReader = build_json_reader(&text);
+    let bytecode: Bytecode = match reader.read() {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    // an edit can leave operands that fit their fields but make no sense
+    let errors: Vec<VerifyError> = build_verifier(&bytecode).verify();
+    if !errors.is_empty() {
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+        exit(1);
+    }
+    let output_bytes: Vec<u8> = match build_writer(&bytecode).write() {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    fs::write(output_path, output_bytes)
+        .unwrap_or_else(|_| panic!("Failed to write file at {}", output_path));
+}
+
+// inu verify file, exits with 1 when anything is wrong with it
+fn verify(args: &[String]) {
+    let mut input_path: Option<&String> = None;
+    for value in args {
+        if value.starts_with("--") {
+            panic!("unexpected argument {} found", value);
+        }
+        input_path = Some(value);
+    }
+
+    let input_path: &String = input_path.expect("expected argument file not found");
+    let input_bytes: Vec<u8> =
+        fs::read(input_path).unwrap_or_else(|_| panic!("Failed to read file at {}", input_path));
+
+    let mut reader: Reader = build_reader(&input_bytes);
+    let bytecode: Bytecode = match reader.read() {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    let errors: Vec<VerifyError> = build_verifier(&bytecode).verify();
+    if errors.is_empty() {
+        println!("{}: ok", input_path);
+        return;
+    }
+    for err in &errors {
+        println!("{}", err);
+    }
+    exit(1);
+}
+
+// inu run file [args], the args go to the chunk as ... and in the global arg like lua does
+fn run(args: &[String]) {
+    let input_path: &String = args.first().expect("expected argument file not found");
+    run_chunk(build_vm(), input_path, &args[1..]);
+}
+
+// inu trace [--pcs <from>-<to>] [--steps <n>] [--dump <dir>] file [args]
+fn trace(args: &[String]) {
+    let mut tracer: Tracer = build_tracer();
+    tracer.step_limit = Some(1_000_000);
+    let mut args = args.iter();
+    let input_path: &String = loop {
+        let value: &String = args.next().expect("expected argument file not found");
+        let mut option_value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("expected a value after {}", value))
+        };
+        match value.as_str() {
+            "--pcs" => {
+                let range: &String = option_value();
+                let bounds: Option<(usize, usize)> = range
+                    .split_once('-')
+                    .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)));
+                match bounds {
+                    Some((from, to)) => tracer.pc_range = Some(from..=to),
+                    None => panic!("expected a pc range like 10-20, found {}", range),
+                }
+            }
+            "--steps" => {
+                let steps: &String = option_value();
+                tracer.step_limit = match steps.parse() {
+                    Ok(0) => None,
+                    Ok(steps) => Some(steps),
+                    Err(_) => panic!("expected a number of steps, found {}", steps),
+                };
+            }
+            "--dump" => {
+                tracer.dump_dir = Some(option_value().clone());
+            }
+            _ if value.starts_with("--") => {
+                panic!("unexpected argument {} found", value);
+            }
+            _ => break value,
+        }
+    };
+    let script_args: Vec<String> = args.cloned().collect();
+
+    let mut vm: Vm = build_vm();
+    open_sandbox(&mut vm);
+    vm.tracer = Some(tracer);
+    run_chunk(vm, input_path, &script_args);
+}
+
+fn run_chunk(mut vm: Vm, input_path: &String, args: &[String]) {
+    let input_bytes: Vec<u8> =
+        fs::read(input_path).unwrap_or_else(|_| panic!("Failed to read file at {}", input_path));
+
+    let mut reader: Reader = build_reader(&input_bytes);
+    let bytecode: Bytecode = match reader.read() {
+        Ok(bytecode) => bytecode,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+
+    let main: Value = match vm.load(bytecode) {
+        Ok(main) => main,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit(1);
+        }
+    };
+    let script_args: Vec<Value> = args
+        .iter()
+        .map(|arg| Value::string(arg.as_bytes()))
+        .collect();
+    let arg_table: TableRef = new_table();
+    let _ = arg_table
+        .borrow_mut()
+        .set(Value::Number(0.0), Value::string(input_path.as_bytes()));
+    for (index, arg) in args.iter().enumerate() {
+        let _ = arg_table.borrow_mut().set(
+            Value::Number((index + 1) as f64),
+            Value::string(arg.as_bytes()),
+        );
+    }
+    vm.set_global("arg", Value::Table(arg_table));
+    if let Err(err) = vm.call(main, script_args) {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
 fn display_help() {
     println!("inu - A disassembler for lua\n");
     println!("Usage: inu [options] file");
+    println!("       inu asm [--out <file>] file");
+    println!(
+        "       inu from-json [--out <file>] file, rebuild a Lua 5.1 chunk from --format json"
+    );
+    println!("       inu verify file, check every operand of a Lua 5.1 chunk");
+    println!("       inu run file [args], run a Lua 5.1 chunk without a C lua");
+    println!("       inu trace [--pcs <from>-<to>] [--steps <n>] [--dump <dir>] file [args]");
+    println!("           run it with stubbed globals, logging every instruction and the");
+    println!("           registers it changes; --steps 0 lifts the limit of 1000000");
+    println!("           and --dump writes what loadstring is given into dir");
     println!();
     println!("options:");
-    println!("    --dec : decompile");
+    println!("    --dec : decompile, Lua 5.1 only");
     println!("    --psuedo: omit disassembly");
+    println!("    --blocks: split the listing into basic blocks");
+    println!("    --live: note the registers live after each instruction and dead stores");
+    println!("    --format text|json: print the listing, or the whole chunk as JSON");
+    println!("    --luac-listing: print the listing the way luac -l -l does, Lua 5.1 only");
+    println!("    --color=always|never|auto: color the listing, auto only does on a terminal");
+    println!("    --dot: print the control flow graph of every function as graphviz");
+    println!("    --out <file>: write the chunk back out instead, Lua 5.1 only");
     exit(0);
 }
@@ -0,0 +1,372 @@
+// Lua 5.1 patterns, a port of the matcher in lstrlib.c working on byte offsets
+
+use crate::vm::is_space;
+
+// LUA_MAXCAPTURES in 5.1's luaconf.h
+const MAX_CAPTURES: usize = 32;
+// the matcher recurses on the Rust stack, so deep backtracking gives up like 5.2 does
+const MAX_MATCH_DEPTH: usize = 200;
+const ESCAPE: u8 = b'%';
+// a pattern without any of these is found as plain text
+pub const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CaptureLength {
+    Unfinished,
+    Position,
+    Length(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    // start and end offsets of the captured bytes
+    Span(usize, usize),
+    // a () capture, 1 based like string.find
+    Position(usize),
+}
+
+pub struct MatchState<'a> {
+    source: &'a [u8],
+    pattern: &'a [u8],
+    level: usize,
+    depth: usize,
+    captures: [(usize, CaptureLength); MAX_CAPTURES],
+}
+
+// a pattern given without its ^ anchor, the callers deal with that
+pub fn build_match_state<'a>(source: &'a [u8], pattern: &'a [u8]) -> MatchState<'a> {
+    MatchState {
+        source,
+        pattern,
+        level: 0,
+        depth: 0,
+        captures: [(0, CaptureLength::Unfinished); MAX_CAPTURES],
+    }
+}
+
+// isalpha and friends for %a, %d... upper case classes are the complement
+fn match_class(c: u8, class: u8) -> bool {
+    let matched: bool = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => is_space(c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+    match class.is_ascii_uppercase() {
+        true => !matched,
+        false => matched,
+    }
+}
+
+impl MatchState<'_> {
+    // forgets the captures of the last attempt
+    pub fn reset(&mut self) {
+        self.level = 0;
+        self.depth = 0;
+    }
+
+    // how many values a match gives, the whole match when there are no captures
+    pub fn capture_count(&self, whole: bool) -> usize {
+        match self.level == 0 && whole {
+            true => 1,
+            false => self.level,
+        }
+    }
+
+    // capture index of a match from start to end, get_onecapture
+    pub fn capture(&self, index: usize, start: usize, end: usize) -> Result<Capture, String> {
+        if index >= self.level {
+            return match index {
+                0 => Ok(Capture::Span(start, end)),
+                _ => Err("invalid capture index".to_string()),
+            };
+        }
+        match self.captures[index] {
+            (_, CaptureLength::Unfinished) => Err("unfinished capture".to_string()),
+            (init, CaptureLength::Position) => Ok(Capture::Position(init + 1)),
+            (init, CaptureLength::Length(length)) => Ok(Capture::Span(init, init + length)),
+        }
+    }
+
+    // where a match of the pattern from p starting at s ends, like match in lstrlib.c
+    pub fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if self.depth >= MAX_MATCH_DEPTH {
+            return Err("pattern too complex".to_string());
+        }
+        self.depth += 1;
+        let result: Result<Option<usize>, String> = self.match_from(s, p);
+        self.depth -= 1;
+        result
+    }
+
+    // the gotos of the C version are the continues here
+    fn match_from(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        loop {
+            let current: u8 = match self.pattern.get(p) {
+                Some(current) => *current,
+                None => return Ok(Some(s)),
+            };
+            let next: Option<u8> = self.pattern.get(p + 1).copied();
+            match (current, next) {
+                (b'(', Some(b')')) => return self.start_capture(s, p + 2, CaptureLength::Position),
+                (b'(', _) => return self.start_capture(s, p + 1, CaptureLength::Unfinished),
+                (b')', _) => return self.end_capture(s, p + 1),
+                (b'$', None) => {
+                    return Ok(match s == self.source.len() {
+                        true => Some(s),
+                        false => None,
+                    })
+                }
+                (ESCAPE, Some(b'b')) => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                    }
+                    None => return Ok(None),
+                },
+                (ESCAPE, Some(b'f')) => {
+                    p += 2;
+                    if self.pattern.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let end: usize = self.class_end(p)?;
+                    let previous: u8 = match s {
+                        0 => 0,
+                        s => self.source[s - 1],
+                    };
+                    let following: u8 = self.source.get(s).copied().unwrap_or(0);
+                    if self.match_bracket_class(previous, p, end - 1)
+                        || !self.match_bracket_class(following, p, end - 1)
+                    {
+                        return Ok(None);
+                    }
+                    p = end;
+                }
+                (ESCAPE, Some(digit)) if digit.is_ascii_digit() => {
+                    match self.match_capture(s, digit)? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => {
+                    let end: usize = self.class_end(p)?;
+                    let matched: bool =
+                        s < self.source.len() && self.single_match(self.source[s], p, end);
+                    match self.pattern.get(end) {
+                        Some(b'?') => {
+                            if matched {
+                                if let Some(result) = self.do_match(s + 1, end + 1)? {
+                                    return Ok(Some(result));
+                                }
+                            }
+                            p = end + 1;
+                        }
+                        Some(b'*') => return self.max_expand(s, p, end),
+                        Some(b'+') => {
+                            return match matched {
+                                true => self.max_expand(s + 1, p, end),
+                                false => Ok(None),
+                            }
+                        }
+                        Some(b'-') => return self.min_expand(s, p, end),
+                        _ => {
+                            if !matched {
+                                return Ok(None);
+                            }
+                            s += 1;
+                            p = end;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // the end of the single character class at p
+    fn class_end(&self, p: usize) -> Result<usize, String> {
+        let pattern: &[u8] = self.pattern;
+        let mut p: usize = p + 1;
+        match pattern[p - 1] {
+            ESCAPE => match p < pattern.len() {
+                true => Ok(p + 1),
+                false => Err("malformed pattern (ends with '%')".to_string()),
+            },
+            b'[' => {
+                if pattern.get(p) == Some(&b'^') {
+                    p += 1;
+                }
+                // the first ] is part of the set, as is an escaped one
+                loop {
+                    if p >= pattern.len() {
+                        return Err("malformed pattern (missing ']')".to_string());
+                    }
+                    let current: u8 = pattern[p];
+                    p += 1;
+                    if current == ESCAPE && p < pattern.len() {
+                        p += 1;
+                    }
+                    if pattern.get(p) == Some(&b']') {
+                        return Ok(p + 1);
+                    }
+                }
+            }
+            _ => Ok(p),
+        }
+    }
+
+    fn single_match(&self, c: u8, p: usize, end: usize) -> bool {
+        match self.pattern[p] {
+            b'.' => true,
+            ESCAPE => match_class(c, self.pattern[p + 1]),
+            b'[' => self.match_bracket_class(c, p, end - 1),
+            current => current == c,
+        }
+    }
+
+    // a [set] from p to the ] at end
+    fn match_bracket_class(&self, c: u8, p: usize, end: usize) -> bool {
+        let pattern: &[u8] = self.pattern;
+        let mut p: usize = p;
+        let mut found: bool = true;
+        if pattern[p + 1] == b'^' {
+            found = false;
+            p += 1;
+        }
+        loop {
+            p += 1;
+            if p >= end {
+                return !found;
+            }
+            if pattern[p] == ESCAPE {
+                p += 1;
+                if match_class(c, pattern[p]) {
+                    return found;
+                }
+            } else if pattern[p + 1] == b'-' && p + 2 < end {
+                p += 2;
+                if pattern[p - 2] <= c && c <= pattern[p] {
+                    return found;
+                }
+            } else if pattern[p] == c {
+                return found;
+            }
+        }
+    }
+
+    // x*, as many as possible then backing off
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut count: usize = 0;
+        while s + count < self.source.len() && self.single_match(self.source[s + count], p, end) {
+            count += 1;
+        }
+        loop {
+            if let Some(result) = self.do_match(s + count, end + 1)? {
+                return Ok(Some(result));
+            }
+            match count {
+                0 => return Ok(None),
+                _ => count -= 1,
+            }
+        }
+    }
+
+    // x-, as few as possible
+    fn min_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut s: usize = s;
+        loop {
+            if let Some(result) = self.do_match(s, end + 1)? {
+                return Ok(Some(result));
+            }
+            match s < self.source.len() && self.single_match(self.source[s], p, end) {
+                true => s += 1,
+                false => return Ok(None),
+            }
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        length: CaptureLength,
+    ) -> Result<Option<usize>, String> {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+        self.captures[self.level] = (s, length);
+        self.level += 1;
+        let result: Option<usize> = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let open: usize = match (0..self.level)
+            .rev()
+            .find(|level| self.captures[*level].1 == CaptureLength::Unfinished)
+        {
+            Some(open) => open,
+            None => return Err("invalid pattern capture".to_string()),
+        };
+        self.captures[open].1 = CaptureLength::Length(s - self.captures[open].0);
+        let result: Option<usize> = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[open].1 = CaptureLength::Unfinished;
+        }
+        Ok(result)
+    }
+
+    // %bxy, from an x to the y balancing it
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        if p + 1 >= self.pattern.len() {
+            return Err("unbalanced pattern".to_string());
+        }
+        let (open, close): (u8, u8) = (self.pattern[p], self.pattern[p + 1]);
+        if self.source.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth: usize = 1;
+        for (offset, c) in self.source[s + 1..].iter().enumerate() {
+            if *c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(s + offset + 2));
+                }
+            } else if *c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // %1 to %9, the same bytes an earlier capture got
+    fn match_capture(&self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let index: usize = match (digit as usize).checked_sub(b'1' as usize) {
+            Some(index) if index < self.level => index,
+            _ => return Err("invalid capture index".to_string()),
+        };
+        let (init, length): (usize, usize) = match self.captures[index] {
+            (_, CaptureLength::Unfinished) => return Err("invalid capture index".to_string()),
+            (init, CaptureLength::Length(length)) => (init, length),
+            (_, CaptureLength::Position) => return Ok(None),
+        };
+        let matched: bool = self.source.len() - s >= length
+            && self.source[init..init + length] == self.source[s..s + length];
+        Ok(match matched {
+            true => Some(s + length),
+            false => None,
+        })
+    }
+}
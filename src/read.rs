@@ -1,241 +1,596 @@
-use std::time::Instant;
+use std::{fmt, time::Instant};
 
 use crate::bytecode::{
-    build_bytecode, build_instruction, Bytecode, Constant, Instruction, LuaInstruction, LuaInt,
-    LuaNumber, LuaUint, LuaVersion, Proto,
+    build_bytecode, build_instruction, Bytecode, Constant, Instruction, LocalVar, LuaInstruction,
+    LuaInt, LuaInteger, LuaNumber, LuaVersion, OpCode, OpMode, Proto, UpvalueDesc,
 };
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadErrorKind {
+    UnexpectedEof { size: usize },
+    InvalidSignature,
+    UnsupportedVersion(u8),
+    UnhandledSize { field: &'static str, size: u8 },
+    InvalidCount { field: &'static str, count: LuaInt },
+    UnterminatedString,
+    InvalidConstantType(u8),
+    MissingAux,
+    InvalidTail,
+    InvalidHeaderCheck { field: &'static str },
+    VarintOverflow { field: &'static str },
+    LuauCompileError(String),
+    UnsupportedTypesVersion(u8),
+    InvalidIndex { field: &'static str, index: u64 },
+    TooDeep,
+}
+
+impl fmt::Display for ReadErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadErrorKind::UnexpectedEof { size } => {
+                write!(f, "attempt to read {} byte(s) past bytecode length", size)
+            }
+            ReadErrorKind::InvalidSignature => write!(f, "expected signature \\27Lua or \\27LJ"),
+            ReadErrorKind::UnsupportedVersion(version) => {
+                write!(f, "unsupported version number ({})", version)
+            }
+            ReadErrorKind::UnhandledSize { field, size } => {
+                write!(f, "unhandled {} size {}", field, size)
+            }
+            ReadErrorKind::InvalidCount { field, count } => {
+                write!(f, "invalid {} count {}", field, count)
+            }
+            ReadErrorKind::UnterminatedString => {
+                write!(f, "expected last character in string to be a 0")
+            }
+            ReadErrorKind::InvalidConstantType(constant_type) => {
+                write!(f, "invalid constant type {}", constant_type)
+            }
+            ReadErrorKind::MissingAux => write!(f, "missing aux word after the last instruction"),
+            ReadErrorKind::InvalidTail => write!(f, "header tail is corrupted"),
+            ReadErrorKind::InvalidHeaderCheck { field } => {
+                write!(f, "{} does not match, the chunk is corrupted", field)
+            }
+            ReadErrorKind::VarintOverflow { field } => {
+                write!(f, "{} overflows its varint encoding", field)
+            }
+            ReadErrorKind::LuauCompileError(message) => {
+                write!(f, "chunk holds a Luau compile error: {}", message)
+            }
+            ReadErrorKind::UnsupportedTypesVersion(version) => {
+                write!(f, "unsupported Luau type info version ({})", version)
+            }
+            ReadErrorKind::InvalidIndex { field, index } => {
+                write!(f, "invalid {} index {}", field, index)
+            }
+            ReadErrorKind::TooDeep => {
+                write!(f, "functions nested over {} deep", MAX_PROTO_DEPTH)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadError {
+    pub kind: ReadErrorKind,
+    // byte offset the failing read started at
+    pub position: usize,
+    // path of the proto being read, e.g. main/3/1 or proto 4 for Luau; empty while reading the header
+    pub proto_path: String,
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to read bytecode at offset {}", self.position)?;
+        if !self.proto_path.is_empty() {
+            write!(f, " in {}", self.proto_path)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+pub type ReadResult<T> = Result<T, ReadError>;
+
+// catches chunks mangled by text-mode conversions, checked from 5.2 on
+const LUAC_TAIL: &[u8; 6] = b"\x19\x93\r\n\x1a\n";
+// from 5.3 on, used to check the integer and float formats
+const LUAC_INT: LuaInteger = 0x5678;
+const LUAC_NUM: LuaNumber = 370.5;
+
+// LUAI_MAXCCALLS in 5.1's luaconf.h, past it lundump.c gives up with "code too deep";
+// everything after the reader walks the proto tree recursively too
+const MAX_PROTO_DEPTH: usize = 200;
+
+// marks a lineinfo entry whose line has to be taken from abslineinfo, 5.4 only
+const ABSLINEINFO: i8 = -128;
+
+// range of Luau bytecode versions, 0 marks a chunk holding a compile error instead
+const LUAU_VERSION_MIN: u8 = 3;
+const LUAU_VERSION_MAX: u8 = 6;
+const LUAU_TYPES_VERSION_MIN: u8 = 1;
+const LUAU_TYPES_VERSION_MAX: u8 = 3;
+
+// lineinfo, locals and upvalue names of a single proto
+type DebugInfo = (Vec<LuaInt>, Vec<LocalVar>, Vec<Vec<u8>>);
+
+const LUAJIT_SIGNATURE: &[u8; 3] = b"\x1bLJ";
+// dump version 1 is LuaJIT 2.0, 2 is LuaJIT 2.1
+const LUAJIT_VERSION_MIN: u8 = 1;
+const LUAJIT_VERSION_MAX: u8 = 2;
+// BCDUMP_F_* header flags: big endian, stripped, uses the FFI and two slot frames
+const LUAJIT_FLAG_BE: u32 = 0x01;
+const LUAJIT_FLAG_STRIP: u32 = 0x02;
+const LUAJIT_FLAG_FR2: u32 = 0x08;
+const LUAJIT_FLAGS_KNOWN: u32 = 0x0F;
+const LUAJIT_PROTO_VARARG: u8 = 0x02;
+// upvalue refs to a local of the parent instead of one of its upvalues
+const LUAJIT_UV_LOCAL: u16 = 0x8000;
+const LUAJIT_UV_IMMUTABLE: u16 = 0x4000;
+// internal locals are stored as a single byte instead of their name, VARNAME_* in lj_debug.h
+const LUAJIT_VARNAMES: [&str; 6] = [
+    "(for index)",
+    "(for limit)",
+    "(for step)",
+    "(for generator)",
+    "(for state)",
+    "(for control)",
+];
+
 pub struct Reader<'a> {
     pub bytes: &'a Vec<u8>,
     pub bytes_size: usize,
 
     position: usize,
+    proto_path: Vec<LuaInt>,
+    version: LuaVersion,
     endianness: bool,
     size_int: u8,
     max_int: LuaInt,
     size_sizet: u8,
     size_instruction: u8,
+    size_luainteger: u8,
     size_luanumber: u8,
 }
 
 impl<'a> Reader<'a> {
-    fn validate_read(&self, size: usize) {
-        assert!(
-            self.position + size <= self.bytes_size,
-            "Attempt to read past bytecode length (position: {}, size: {}, bytes_size: {})",
-            self.position,
-            size,
-            self.bytes_size
-        );
+    fn error_at(&self, position: usize, kind: ReadErrorKind) -> ReadError {
+        let proto_path: String = match (self.version, self.proto_path.split_first()) {
+            (_, None) => String::new(),
+            // Luau and LuaJIT protos live in a flat list instead of inside their parents
+            (LuaVersion::Luau | LuaVersion::LuaJit, Some((id, _))) => format!("proto {}", id),
+            (_, Some((_, rest))) => {
+                let mut path: String = String::from("main");
+                for id in rest {
+                    path.push_str(&format!("/{}", id));
+                }
+                path
+            }
+        };
+        ReadError {
+            kind,
+            position,
+            proto_path,
+        }
+    }
+    fn error(&self, kind: ReadErrorKind) -> ReadError {
+        self.error_at(self.position, kind)
     }
-    fn read_u8(&mut self) -> u8 {
-        self.validate_read(size_of::<u8>());
+
+    fn validate_read(&self, size: usize) -> ReadResult<()> {
+        if size > self.bytes_size - self.position {
+            return Err(self.error(ReadErrorKind::UnexpectedEof { size }));
+        }
+        Ok(())
+    }
+    // counts are stored as ints; a negative one can only come from a corrupt chunk
+    fn validate_count(&self, field: &'static str, count: LuaInt) -> ReadResult<usize> {
+        if count < 0 {
+            return Err(self.error(ReadErrorKind::InvalidCount { field, count }));
+        }
+        Ok(count as usize)
+    }
+
+    fn read_u8(&mut self) -> ReadResult<u8> {
+        self.validate_read(size_of::<u8>())?;
 
         let result: u8 = self.bytes[self.position];
         self.position += 1;
-        return result;
+        Ok(result)
     }
-    fn read_u8s(&mut self, length: usize) -> Vec<u8> {
-        self.validate_read(length * size_of::<u8>());
+    fn read_u8s(&mut self, length: usize) -> ReadResult<Vec<u8>> {
+        self.validate_read(length * size_of::<u8>())?;
 
         let result: &[u8] = &self.bytes[self.position..self.position + length];
         self.position += length * size_of::<u8>();
-        return result.into();
-    }
-
-    fn read_u32(&mut self) -> u32 {
-        let bytes: Vec<u8> = self.read_u8s(size_of::<u32>());
-        if self.endianness {
-            return bytes
-                .try_into()
-                .map(u32::from_le_bytes)
-                .expect("Failed to read u32: could not map bytes");
-        }
-        return bytes
-            .try_into()
-            .map(u32::from_be_bytes)
-            .expect("Failed to read u32: could not map bytes");
-    }
-    fn read_u64(&mut self) -> u64 {
-        let bytes: Vec<u8> = self.read_u8s(size_of::<u64>());
-        if self.endianness {
-            return bytes
-                .try_into()
-                .map(u64::from_le_bytes)
-                .expect("Failed to read u64: could not map bytes");
-        }
-        return bytes
-            .try_into()
-            .map(u64::from_be_bytes)
-            .expect("Failed to read u64: could not map bytes");
-    }
-    fn read_i32(&mut self) -> i32 {
-        let bytes: Vec<u8> = self.read_u8s(size_of::<i32>());
-        if self.endianness {
-            return bytes
-                .try_into()
-                .map(i32::from_le_bytes)
-                .expect("Failed to read i32: could not map bytes");
-        }
-        return bytes
-            .try_into()
-            .map(i32::from_be_bytes)
-            .expect("Failed to read i32: could not map bytes");
-    }
-    fn read_f64(&mut self) -> f64 {
-        let bytes: Vec<u8> = self.read_u8s(size_of::<f64>());
-        if self.endianness {
-            return bytes
-                .try_into()
-                .map(f64::from_le_bytes)
-                .expect("Failed to read f64: could not map bytes");
-        }
-        return bytes
-            .try_into()
-            .map(f64::from_be_bytes)
-            .expect("Failed to read f64: could not map bytes");
+        Ok(result.into())
+    }
+    fn read_array<const N: usize>(&mut self) -> ReadResult<[u8; N]> {
+        self.validate_read(N)?;
+
+        let mut result: [u8; N] = [0; N];
+        result.copy_from_slice(&self.bytes[self.position..self.position + N]);
+        self.position += N;
+        Ok(result)
+    }
+
+    fn read_u16(&mut self) -> ReadResult<u16> {
+        let bytes: [u8; 2] = self.read_array()?;
+        Ok(match self.endianness {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+    fn read_u32(&mut self) -> ReadResult<u32> {
+        let bytes: [u8; 4] = self.read_array()?;
+        Ok(match self.endianness {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+    fn read_u64(&mut self) -> ReadResult<u64> {
+        let bytes: [u8; 8] = self.read_array()?;
+        Ok(match self.endianness {
+            true => u64::from_le_bytes(bytes),
+            false => u64::from_be_bytes(bytes),
+        })
+    }
+    fn read_i32(&mut self) -> ReadResult<i32> {
+        let bytes: [u8; 4] = self.read_array()?;
+        Ok(match self.endianness {
+            true => i32::from_le_bytes(bytes),
+            false => i32::from_be_bytes(bytes),
+        })
+    }
+    fn read_f32(&mut self) -> ReadResult<f32> {
+        let bytes: [u8; 4] = self.read_array()?;
+        Ok(match self.endianness {
+            true => f32::from_le_bytes(bytes),
+            false => f32::from_be_bytes(bytes),
+        })
+    }
+    fn read_f64(&mut self) -> ReadResult<f64> {
+        let bytes: [u8; 8] = self.read_array()?;
+        Ok(match self.endianness {
+            true => f64::from_le_bytes(bytes),
+            false => f64::from_be_bytes(bytes),
+        })
+    }
+
+    // 5.4 stores ints and size_ts as 7 bit groups, most significant first,
+    // with the high bit set on the last byte
+    fn read_varint(&mut self, field: &'static str, limit: u64) -> ReadResult<u64> {
+        let start: usize = self.position;
+        let mut result: u64 = 0;
+        loop {
+            let byte: u8 = self.read_u8()?;
+            if result >= limit >> 7 {
+                return Err(self.error_at(start, ReadErrorKind::VarintOverflow { field }));
+            }
+            result = (result << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 != 0 {
+                return Ok(result);
+            }
+        }
+    }
+
+    // Luau and LuaJIT use ULEB128 instead: least significant group first,
+    // with the high bit set on every byte but the last
+    fn read_uleb128(&mut self, field: &'static str) -> ReadResult<u32> {
+        let start: usize = self.position;
+        let mut result: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte: u8 = self.read_u8()?;
+            result |= ((byte & 0x7F) as u64) << shift;
+            if result > u32::MAX as u64 {
+                return Err(self.error_at(start, ReadErrorKind::VarintOverflow { field }));
+            }
+            if byte & 0x80 == 0 {
+                return Ok(result as u32);
+            }
+            shift += 7;
+            if shift > 28 {
+                return Err(self.error_at(start, ReadErrorKind::VarintOverflow { field }));
+            }
+        }
     }
 
     // return type should be the biggest of all possible types
     // make sure to reflect changes here to LuaInt as well
-    fn read_int(&mut self) -> LuaInt {
-        return if self.size_int == size_of::<i32>() as u8 {
+    fn read_int(&mut self) -> ReadResult<LuaInt> {
+        if self.version == LuaVersion::Lua54 {
+            Ok(self.read_varint("int", self.max_int as u64)? as LuaInt)
+        } else if self.size_int == size_of::<i32>() as u8 {
             self.read_i32()
         } else {
-            panic!("Failed to read int: unhandled size {}", self.size_int);
-        };
+            Err(self.error(ReadErrorKind::UnhandledSize {
+                field: "int",
+                size: self.size_int,
+            }))
+        }
+    }
+    // return type should be the biggest of all possible types
+    // make sure to reflect changes here to LuaInteger as well
+    fn read_integer(&mut self) -> ReadResult<LuaInteger> {
+        if self.size_luainteger == size_of::<i64>() as u8 {
+            let bytes: [u8; 8] = self.read_array()?;
+            Ok(match self.endianness {
+                true => i64::from_le_bytes(bytes),
+                false => i64::from_be_bytes(bytes),
+            })
+        } else if self.size_luainteger == size_of::<i32>() as u8 {
+            Ok(self.read_i32()? as LuaInteger)
+        } else {
+            Err(self.error(ReadErrorKind::UnhandledSize {
+                field: "integer",
+                size: self.size_luainteger,
+            }))
+        }
     }
     // return type should be the biggest of all possible types
-    fn read_sizet(&mut self) -> u64 {
-        return if self.size_sizet == size_of::<u64>() as u8 {
+    fn read_sizet(&mut self) -> ReadResult<u64> {
+        if self.version == LuaVersion::Lua54 {
+            self.read_varint("size_t", u64::MAX)
+        } else if self.size_sizet == size_of::<u64>() as u8 {
             self.read_u64()
         } else if self.size_sizet == size_of::<u32>() as u8 {
-            self.read_u32() as u64
+            Ok(self.read_u32()? as u64)
         } else {
-            panic!("Failed to read sizet: unhandled size {}", self.size_sizet);
-        };
+            Err(self.error(ReadErrorKind::UnhandledSize {
+                field: "size_t",
+                size: self.size_sizet,
+            }))
+        }
     }
     // return type should be the biggest of all possible types
     // make sure to reflect changes here to LuaNumber as well
-    fn read_number(&mut self) -> LuaNumber {
-        return if self.size_luanumber == size_of::<f64>() as u8 {
+    fn read_number(&mut self) -> ReadResult<LuaNumber> {
+        if self.size_luanumber == size_of::<f64>() as u8 {
             self.read_f64()
         } else {
-            panic!(
-                "Failed to read number: unhandled size {}",
-                self.size_luanumber
-            );
-        };
+            Err(self.error(ReadErrorKind::UnhandledSize {
+                field: "number",
+                size: self.size_luanumber,
+            }))
+        }
     }
 
-    fn read_string(&mut self) -> Vec<u8> {
-        let size = self.read_sizet();
+    fn read_string(&mut self) -> ReadResult<Vec<u8>> {
+        match self.version {
+            LuaVersion::Lua53 => return self.read_string_53(),
+            LuaVersion::Lua54 => return self.read_string_54(),
+            _ => {}
+        }
+
+        let size = self.read_sizet()?;
         if size == 0 {
-            return vec![];
+            return Ok(vec![]);
         }
 
-        let mut bytes = self.read_u8s(size as usize);
-        assert_eq!(bytes.last(), Some(&0), "Expected last character in string to be a 0");
+        let start: usize = self.position;
+        // anything bigger than the rest of the chunk fails validate_read anyway
+        let size: usize = size.min((self.bytes_size - start + 1) as u64) as usize;
+        let mut bytes = self.read_u8s(size)?;
+        if bytes.last() != Some(&0) {
+            return Err(self.error_at(start, ReadErrorKind::UnterminatedString));
+        }
 
         bytes.remove(bytes.len() - 1);
 
-        bytes
+        Ok(bytes)
+    }
+
+    // short strings store their size + 1 in a byte, long ones escape to a size_t with 0xFF
+    // and neither is NUL terminated
+    fn read_string_53(&mut self) -> ReadResult<Vec<u8>> {
+        let mut size: u64 = self.read_u8()? as u64;
+        if size == 0xFF {
+            size = self.read_sizet()?;
+        }
+        if size == 0 {
+            return Ok(vec![]);
+        }
+
+        let size: usize = (size - 1).min((self.bytes_size - self.position + 1) as u64) as usize;
+        self.read_u8s(size)
+    }
+
+    // the size + 1 is a plain varint, 0 still meaning no string at all
+    fn read_string_54(&mut self) -> ReadResult<Vec<u8>> {
+        let size: u64 = self.read_sizet()?;
+        if size == 0 {
+            return Ok(vec![]);
+        }
+
+        let size: usize = (size - 1).min((self.bytes_size - self.position + 1) as u64) as usize;
+        self.read_u8s(size)
     }
 
     // return type should be the biggest of all possible types
-    fn read_instruction(&mut self) -> LuaInstruction {
-        return if self.size_instruction == size_of::<u32>() as u8 {
-            self.read_i32() as u32
+    fn read_instruction(&mut self) -> ReadResult<LuaInstruction> {
+        if self.size_instruction == size_of::<u32>() as u8 {
+            self.read_u32()
         } else {
-            panic!(
-                "Failed to read instruction: unhandled size {}",
-                self.size_instruction
-            );
+            Err(self.error(ReadErrorKind::UnhandledSize {
+                field: "instruction",
+                size: self.size_instruction,
+            }))
+        }
+    }
+
+    fn read_size_int(&mut self) -> ReadResult<()> {
+        self.size_int = self.read_u8()?;
+        self.max_int = if self.size_int == size_of::<i32>() as u8 {
+            i32::MAX
+        } else {
+            return Err(self.error_at(
+                self.position - 1,
+                ReadErrorKind::UnhandledSize {
+                    field: "int",
+                    size: self.size_int,
+                },
+            ));
         };
+        Ok(())
+    }
+    // LUAC_TAIL in 5.2, LUAC_DATA from 5.3 on
+    fn check_tail(&mut self) -> ReadResult<()> {
+        if self.read_array()? != *LUAC_TAIL {
+            return Err(self.error_at(self.position - LUAC_TAIL.len(), ReadErrorKind::InvalidTail));
+        }
+        Ok(())
     }
 
-    pub fn read(&mut self) -> Bytecode {
+    pub fn read(&mut self) -> ReadResult<Bytecode> {
         let start_instant: Instant = Instant::now();
 
-        assert_eq!(
-            self.read_u8(),
-            0o33,
-            "Failed to read bytecode: expected first byte to equal octal 33"
-        );
-        assert_eq!(
-            self.read_u8s(3),
-            ['L', 'u', 'a']
-                .iter()
-                .map(|&c| c as u8)
-                .collect::<Vec<u8>>(),
-            "Failed to read bytecode: expected the next 3 bytes to equal 'Lua'"
-        );
-        let version_number: u8 = self.read_u8();
+        // Luau chunks have no signature and start with their version instead
+        if let Some(first) = self.bytes.first() {
+            if *first == 0 || (LUAU_VERSION_MIN..=LUAU_VERSION_MAX).contains(first) {
+                return self.read_luau(start_instant);
+            }
+        }
+
+        if self.bytes.starts_with(LUAJIT_SIGNATURE) {
+            return self.read_luajit(start_instant);
+        }
+
+        if self.read_u8s(4)? != b"\x1bLua" {
+            return Err(self.error_at(0, ReadErrorKind::InvalidSignature));
+        }
+        let version_number: u8 = self.read_u8()?;
 
         let version: LuaVersion = match version_number {
             0x51 => LuaVersion::Lua51,
+            0x52 => LuaVersion::Lua52,
+            0x53 => LuaVersion::Lua53,
+            0x54 => LuaVersion::Lua54,
             _ => {
-                panic!(
-                    "Failed to read bytecode: unsupported version number ({})",
-                    version_number
-                );
+                return Err(self.error_at(
+                    self.position - 1,
+                    ReadErrorKind::UnsupportedVersion(version_number),
+                ));
             }
         };
 
-        let format: u8 = self.read_u8();
-        let endianness: bool = self.read_u8() == 1;
-        let size_int: u8 = self.read_u8();
-        let size_sizet: u8 = self.read_u8();
-        let size_instruction: u8 = self.read_u8();
-        let size_luanumber: u8 = self.read_u8();
-        let luanumber_integral: bool = self.read_u8() == 1;
-
-        self.endianness = endianness;
-        self.size_int = size_int;
-        self.max_int = if size_int == size_of::<i32>() as u8 {
-            i32::MAX
-        } else {
-            panic!(
-                "Failed to set max_int: unhandled int size {}",
-                self.size_int
-            );
-        };
-        self.size_sizet = size_sizet;
-        self.size_instruction = size_instruction;
-        self.size_luanumber = size_luanumber;
+        self.version = version;
+        let format: u8 = self.read_u8()?;
+        let mut luanumber_integral: bool = false;
+        match version {
+            LuaVersion::Lua51 | LuaVersion::Lua52 => {
+                self.endianness = self.read_u8()? == 1;
+                self.read_size_int()?;
+                self.size_sizet = self.read_u8()?;
+                self.size_instruction = self.read_u8()?;
+                self.size_luanumber = self.read_u8()?;
+                luanumber_integral = self.read_u8()? == 1;
+
+                if version == LuaVersion::Lua52 {
+                    self.check_tail()?;
+                }
+            }
+            LuaVersion::Lua53 | LuaVersion::Lua54 => {
+                self.check_tail()?;
+                if version == LuaVersion::Lua54 {
+                    // ints and size_ts are varints from 5.4 on, so their sizes aren't stored
+                    self.size_int = 0;
+                    self.max_int = i32::MAX;
+                    self.size_sizet = 0;
+                } else {
+                    self.read_size_int()?;
+                    self.size_sizet = self.read_u8()?;
+                }
+                self.size_instruction = self.read_u8()?;
+                self.size_luainteger = self.read_u8()?;
+                self.size_luanumber = self.read_u8()?;
+
+                // there is no endianness byte anymore, so infer it from LUAC_INT
+                let position: usize = self.position;
+                let luac_int: Vec<u8> = self.read_u8s(self.size_luainteger as usize)?;
+                let mut expected: Vec<u8> = LUAC_INT.to_le_bytes().to_vec();
+                expected.truncate(luac_int.len());
+                self.endianness = if luac_int == expected {
+                    true
+                } else if luac_int.iter().rev().eq(expected.iter()) {
+                    false
+                } else {
+                    return Err(self.error_at(
+                        position,
+                        ReadErrorKind::InvalidHeaderCheck { field: "LUAC_INT" },
+                    ));
+                };
+                let position: usize = self.position;
+                if self.read_number()? != LUAC_NUM {
+                    return Err(self.error_at(
+                        position,
+                        ReadErrorKind::InvalidHeaderCheck { field: "LUAC_NUM" },
+                    ));
+                }
+
+                // upvalue count of the main closure, always the same as its proto's
+                self.read_u8()?;
+            }
+            LuaVersion::Luau => unreachable!("Luau chunks are read by read_luau"),
+            LuaVersion::LuaJit => unreachable!("LuaJIT chunks are read by read_luajit"),
+        }
 
-        let main_proto: Proto = self.read_proto(0, true);
+        let main_proto: Proto = self.read_proto(0, true)?;
 
         let bytecode: Bytecode = build_bytecode(
             version,
             format,
-            endianness,
-            size_int,
-            size_sizet,
-            size_instruction,
-            size_luanumber,
+            self.endianness,
+            self.size_int,
+            self.size_sizet,
+            self.size_instruction,
+            self.size_luainteger,
+            self.size_luanumber,
             luanumber_integral,
+            0,
+            0,
+            0,
+            0,
             main_proto,
             start_instant.elapsed(),
         );
 
-        return bytecode;
+        Ok(bytecode)
     }
 
-    fn read_proto(&mut self, id: LuaInt, is_main: bool) -> Proto {
-        let source: Vec<u8> = self.read_string();
-        let line_defined: LuaInt = self.read_int();
-        let last_line_defined: LuaInt = self.read_int();
-        let upvalues_count: u8 = self.read_u8();
-        let param_count: u8 = self.read_u8();
-        let is_vararg: bool = self.read_u8() != 0;
-        let max_stack_size: u8 = self.read_u8();
+    fn read_proto(&mut self, id: LuaInt, is_main: bool) -> ReadResult<Proto> {
+        self.proto_path.push(id);
+        if self.proto_path.len() > MAX_PROTO_DEPTH {
+            return Err(self.error(ReadErrorKind::TooDeep));
+        }
+        let result: Proto = match self.version {
+            LuaVersion::Lua51 => self.read_proto_51(id, is_main)?,
+            LuaVersion::Lua52 => self.read_proto_52(id, is_main)?,
+            // 5.4 kept the 5.3 layout, only the encodings inside it changed
+            LuaVersion::Lua53 | LuaVersion::Lua54 => self.read_proto_53(id, is_main)?,
+            LuaVersion::Luau => unreachable!("Luau protos are read by read_luau"),
+            LuaVersion::LuaJit => unreachable!("LuaJIT protos are read by read_luajit"),
+        };
+        self.proto_path.pop();
+
+        Ok(result)
+    }
 
-        let code: Vec<Instruction> = self.read_code();
-        let constants: Vec<Constant> = self.read_constants();
-        let protos: Vec<Proto> = self.read_protos();
-        // eventually we might read instead of skip, depending on if someone requests such functionality
-        self.skip_debug();
+    fn read_proto_51(&mut self, id: LuaInt, is_main: bool) -> ReadResult<Proto> {
+        let source: Vec<u8> = self.read_string()?;
+        let line_defined: LuaInt = self.read_int()?;
+        let last_line_defined: LuaInt = self.read_int()?;
+        let upvalues_count: u8 = self.read_u8()?;
+        let param_count: u8 = self.read_u8()?;
+        let vararg_flags: u8 = self.read_u8()?;
+        let is_vararg: bool = vararg_flags != 0;
+        let max_stack_size: u8 = self.read_u8()?;
+
+        let code: Vec<Instruction> = self.read_code()?;
+        let constants: Vec<Constant> = self.read_constants()?;
+        let protos: Vec<Proto> = self.read_protos()?;
+        let (lineinfo, locals, upvalue_names) = self.read_debug()?;
 
         let result: Proto = Proto {
             is_main,
@@ -247,40 +602,159 @@ impl<'a> Reader<'a> {
             upvalue_count: upvalues_count,
             param_count,
             is_vararg,
+            vararg_flags,
             max_stack_size,
             code,
             constants,
             protos,
+            upvalues: Vec::new(),
+            param_types: Vec::new(),
+            number_constants: Vec::new(),
+            two_slot_frame: false,
+
+            lineinfo,
+            locals,
+            upvalue_names,
+            debug_name: Vec::new(),
         };
 
-        return result;
+        Ok(result)
     }
 
-    fn read_code(&mut self) -> Vec<Instruction> {
-        let size_code: LuaUint = self.read_int() as LuaUint;
-        let mut result: Vec<Instruction> = Vec::with_capacity(size_code as usize);
+    fn read_proto_52(&mut self, id: LuaInt, is_main: bool) -> ReadResult<Proto> {
+        let line_defined: LuaInt = self.read_int()?;
+        let last_line_defined: LuaInt = self.read_int()?;
+        let param_count: u8 = self.read_u8()?;
+        let is_vararg: bool = self.read_u8()? != 0;
+        let max_stack_size: u8 = self.read_u8()?;
+
+        let code: Vec<Instruction> = self.read_code()?;
+        let constants: Vec<Constant> = self.read_constants()?;
+        let protos: Vec<Proto> = self.read_protos()?;
+        let upvalues: Vec<UpvalueDesc> = self.read_upvalues()?;
+        // the source moved into the debug section
+        let source: Vec<u8> = self.read_string()?;
+        let (lineinfo, locals, upvalue_names) = self.read_debug()?;
+
+        let result: Proto = Proto {
+            is_main,
+            id,
 
-        let mut raw_instructions: Vec<LuaInstruction> = Vec::with_capacity(size_code as usize);
+            source,
+            line_defined,
+            last_line_defined,
+            upvalue_count: upvalues.len() as u8,
+            param_count,
+            is_vararg,
+            vararg_flags: 0,
+            max_stack_size,
+            code,
+            constants,
+            protos,
+            upvalues,
+            param_types: Vec::new(),
+            number_constants: Vec::new(),
+            two_slot_frame: false,
+
+            lineinfo,
+            locals,
+            upvalue_names,
+            debug_name: Vec::new(),
+        };
+
+        Ok(result)
+    }
+
+    fn read_proto_53(&mut self, id: LuaInt, is_main: bool) -> ReadResult<Proto> {
+        // empty when the dump reuses the parent's source
+        let source: Vec<u8> = self.read_string()?;
+        let line_defined: LuaInt = self.read_int()?;
+        let last_line_defined: LuaInt = self.read_int()?;
+        let param_count: u8 = self.read_u8()?;
+        let is_vararg: bool = self.read_u8()? != 0;
+        let max_stack_size: u8 = self.read_u8()?;
+
+        let code: Vec<Instruction> = self.read_code()?;
+        let constants: Vec<Constant> = self.read_constants()?;
+        let upvalues: Vec<UpvalueDesc> = self.read_upvalues()?;
+        let protos: Vec<Proto> = self.read_protos()?;
+        let (lineinfo, locals, upvalue_names) = match self.version {
+            LuaVersion::Lua54 => self.read_debug_54(line_defined)?,
+            _ => self.read_debug()?,
+        };
+
+        let result: Proto = Proto {
+            is_main,
+            id,
+
+            source,
+            line_defined,
+            last_line_defined,
+            upvalue_count: upvalues.len() as u8,
+            param_count,
+            is_vararg,
+            vararg_flags: 0,
+            max_stack_size,
+            code,
+            constants,
+            protos,
+            upvalues,
+            param_types: Vec::new(),
+            number_constants: Vec::new(),
+            two_slot_frame: false,
+
+            lineinfo,
+            locals,
+            upvalue_names,
+            debug_name: Vec::new(),
+        };
+
+        Ok(result)
+    }
+
+    fn read_code(&mut self) -> ReadResult<Vec<Instruction>> {
+        let size_code: LuaInt = self.read_int()?;
+        let size_code: usize = self.validate_count("code", size_code)?;
+        // also reject counts the rest of the chunk can't hold before allocating for them
+        self.validate_read(size_code.saturating_mul(self.size_instruction as usize))?;
+        let mut result: Vec<Instruction> = Vec::with_capacity(size_code);
+
+        let mut raw_instructions: Vec<LuaInstruction> = Vec::with_capacity(size_code);
         for _ in 0..size_code {
-            raw_instructions.push(self.read_instruction());
+            raw_instructions.push(self.read_instruction()?);
         }
 
-        let mut i: LuaUint = 0;
+        let num_bits_int: LuaInt = match self.size_int {
+            4 => 32,
+            // unused by the 5.4 decoder
+            _ if self.version == LuaVersion::Lua54 => 32,
+            _ => {
+                return Err(self.error(ReadErrorKind::UnhandledSize {
+                    field: "int",
+                    size: self.size_int,
+                }));
+            }
+        };
+
+        let mut i: usize = 0;
         while i < size_code {
             let mut inst = build_instruction(
-                raw_instructions[i as usize],
-                match self.size_int {
-                    4 => 32,
-                    _ => {
-                        panic!(
-                            "Failed to get bitsint: unhandled size_int {}",
-                            self.size_int
-                        )
-                    }
-                } as LuaInt,
+                raw_instructions[i],
+                self.version,
+                num_bits_int,
                 self.max_int,
             );
-            if inst.handle_aux(raw_instructions.get(i as usize + 1)) {
+            // from 5.2 on the aux word is a real EXTRAARG instruction
+            if self.version == LuaVersion::Lua51 && inst.needs_aux() {
+                let aux: LuaInstruction = match raw_instructions.get(i + 1) {
+                    Some(aux) => *aux,
+                    None => {
+                        let position: usize =
+                            self.position - (size_code - i) * self.size_instruction as usize;
+                        return Err(self.error_at(position, ReadErrorKind::MissingAux));
+                    }
+                };
+                inst.handle_aux(aux);
                 i += 1;
             }
 
@@ -288,68 +762,1417 @@ impl<'a> Reader<'a> {
             i += 1;
         }
 
-        return result;
+        Ok(result)
     }
-    fn read_constants(&mut self) -> Vec<Constant> {
-        let size_constants: LuaInt = self.read_int();
-        let mut result: Vec<Constant> = Vec::with_capacity(size_constants as usize);
+    fn read_constants(&mut self) -> ReadResult<Vec<Constant>> {
+        let size_constants: LuaInt = self.read_int()?;
+        let size_constants: usize = self.validate_count("constant", size_constants)?;
+        // every constant takes at least its type byte
+        self.validate_read(size_constants)?;
+        let mut result: Vec<Constant> = Vec::with_capacity(size_constants);
 
         for _ in 0..size_constants {
-            let constant_type: u8 = self.read_u8();
-            result.push(match constant_type {
-                0 => Constant::Nil,
-                1 => Constant::Boolean(self.read_u8() == 1),
-                3 => Constant::Number(self.read_number()),
-                4 => Constant::String(self.read_string()),
+            let constant_type: u8 = self.read_u8()?;
+            result.push(match (self.version, constant_type) {
+                (LuaVersion::Lua54, 0) => Constant::Nil,
+                // 5.4 folds the boolean value into the tag
+                (LuaVersion::Lua54, 1) => Constant::Boolean(false),
+                (LuaVersion::Lua54, 17) => Constant::Boolean(true),
+                // and swapped the integer and float variant tags
+                (LuaVersion::Lua54, 3) => Constant::Integer(self.read_integer()?),
+                (LuaVersion::Lua54, 19) => Constant::Number(self.read_number()?),
+                (LuaVersion::Lua54, 4 | 20) => Constant::String(self.read_string()?),
+                (LuaVersion::Lua54, _) => {
+                    return Err(self.error_at(
+                        self.position - 1,
+                        ReadErrorKind::InvalidConstantType(constant_type),
+                    ));
+                }
+                (_, 0) => Constant::Nil,
+                (_, 1) => Constant::Boolean(self.read_u8()? == 1),
+                (_, 3) => Constant::Number(self.read_number()?),
+                (_, 4) => Constant::String(self.read_string()?),
+                // from 5.3 on, integers and long strings get their own variant tags
+                (LuaVersion::Lua53, 19) => Constant::Integer(self.read_integer()?),
+                (LuaVersion::Lua53, 20) => Constant::String(self.read_string()?),
                 _ => {
-                    panic!("Failed to read constant: invalid type {}", constant_type);
+                    return Err(self.error_at(
+                        self.position - 1,
+                        ReadErrorKind::InvalidConstantType(constant_type),
+                    ));
                 }
             });
         }
 
-        return result;
+        Ok(result)
     }
-    fn read_protos(&mut self) -> Vec<Proto> {
-        let size_proto: LuaInt = self.read_int();
-        let mut result: Vec<Proto> = Vec::with_capacity(size_proto as usize);
+    fn read_protos(&mut self) -> ReadResult<Vec<Proto>> {
+        let size_proto: LuaInt = self.read_int()?;
+        let size_proto: usize = self.validate_count("proto", size_proto)?;
+        self.validate_read(size_proto)?;
+        let mut result: Vec<Proto> = Vec::with_capacity(size_proto);
 
         for id in 0..size_proto {
-            result.push(self.read_proto(id, false));
+            result.push(self.read_proto(id as LuaInt, false)?);
+        }
+
+        Ok(result)
+    }
+
+    fn read_upvalues(&mut self) -> ReadResult<Vec<UpvalueDesc>> {
+        let size_upvalues: LuaInt = self.read_int()?;
+        let size_upvalues: usize = self.validate_count("upvalue", size_upvalues)?;
+        let size_upvalue: usize = match self.version {
+            LuaVersion::Lua54 => 3,
+            _ => 2,
+        };
+        self.validate_read(size_upvalues.saturating_mul(size_upvalue))?;
+        let mut result: Vec<UpvalueDesc> = Vec::with_capacity(size_upvalues);
+
+        for _ in 0..size_upvalues {
+            result.push(UpvalueDesc {
+                instack: self.read_u8()? != 0,
+                idx: self.read_u8()?,
+                kind: match self.version {
+                    LuaVersion::Lua54 => self.read_u8()?,
+                    _ => 0,
+                },
+            });
         }
 
-        return result;
+        Ok(result)
     }
 
-    fn skip_debug(&mut self) {
-        let size_lineinfo: LuaInt = self.read_int();
+    fn read_debug(&mut self) -> ReadResult<DebugInfo> {
+        let size_lineinfo: LuaInt = self.read_int()?;
+        let size_lineinfo: usize = self.validate_count("lineinfo", size_lineinfo)?;
+        self.validate_read(size_lineinfo.saturating_mul(self.size_int as usize))?;
+        let mut lineinfo: Vec<LuaInt> = Vec::with_capacity(size_lineinfo);
         for _ in 0..size_lineinfo {
-            self.read_int();
+            lineinfo.push(self.read_int()?);
         }
 
-        let size_localvars: LuaInt = self.read_int();
+        let size_localvars: LuaInt = self.read_int()?;
+        let size_localvars: usize = self.validate_count("local", size_localvars)?;
+        self.validate_read(size_localvars)?;
+        let mut locals: Vec<LocalVar> = Vec::with_capacity(size_localvars);
         for _ in 0..size_localvars {
-            self.read_string();
-            self.read_int();
-            self.read_int();
+            locals.push(LocalVar {
+                name: self.read_string()?,
+                start_pc: self.read_int()?,
+                end_pc: self.read_int()?,
+                reg: None,
+            });
         }
 
-        let size_upvalues: LuaInt = self.read_int();
+        let size_upvalues: LuaInt = self.read_int()?;
+        let size_upvalues: usize = self.validate_count("upvalue", size_upvalues)?;
+        self.validate_read(size_upvalues)?;
+        let mut upvalue_names: Vec<Vec<u8>> = Vec::with_capacity(size_upvalues);
         for _ in 0..size_upvalues {
-            self.read_string();
+            upvalue_names.push(self.read_string()?);
         }
+
+        Ok((lineinfo, locals, upvalue_names))
     }
+
+    // 5.4 stores lines as byte deltas from the previous instruction, with
+    // absolute (pc, line) pairs for deltas that don't fit
+    fn read_debug_54(&mut self, line_defined: LuaInt) -> ReadResult<DebugInfo> {
+        let size_lineinfo: LuaInt = self.read_int()?;
+        let size_lineinfo: usize = self.validate_count("lineinfo", size_lineinfo)?;
+        let deltas: Vec<u8> = self.read_u8s(size_lineinfo)?;
+
+        let size_abslineinfo: LuaInt = self.read_int()?;
+        let size_abslineinfo: usize = self.validate_count("abslineinfo", size_abslineinfo)?;
+        self.validate_read(size_abslineinfo.saturating_mul(2))?;
+        let mut abslineinfo: Vec<(LuaInt, LuaInt)> = Vec::with_capacity(size_abslineinfo);
+        for _ in 0..size_abslineinfo {
+            abslineinfo.push((self.read_int()?, self.read_int()?));
+        }
+
+        let mut lineinfo: Vec<LuaInt> = Vec::with_capacity(size_lineinfo);
+        let mut line: LuaInt = line_defined;
+        for (pc, delta) in deltas.iter().enumerate() {
+            let delta: i8 = *delta as i8;
+            if delta == ABSLINEINFO {
+                if let Some((_, absolute)) = abslineinfo
+                    .iter()
+                    .find(|(abs_pc, _)| *abs_pc == pc as LuaInt)
+                {
+                    line = *absolute;
+                }
+            } else {
+                line = line.wrapping_add(delta as LuaInt);
+            }
+            lineinfo.push(line);
+        }
+
+        let size_localvars: LuaInt = self.read_int()?;
+        let size_localvars: usize = self.validate_count("local", size_localvars)?;
+        self.validate_read(size_localvars)?;
+        let mut locals: Vec<LocalVar> = Vec::with_capacity(size_localvars);
+        for _ in 0..size_localvars {
+            locals.push(LocalVar {
+                name: self.read_string()?,
+                start_pc: self.read_int()?,
+                end_pc: self.read_int()?,
+                reg: None,
+            });
+        }
+
+        let size_upvalues: LuaInt = self.read_int()?;
+        let size_upvalues: usize = self.validate_count("upvalue", size_upvalues)?;
+        self.validate_read(size_upvalues)?;
+        let mut upvalue_names: Vec<Vec<u8>> = Vec::with_capacity(size_upvalues);
+        for _ in 0..size_upvalues {
+            upvalue_names.push(self.read_string()?);
+        }
+
+        Ok((lineinfo, locals, upvalue_names))
+    }
+
+    fn read_luau(&mut self, start_instant: Instant) -> ReadResult<Bytecode> {
+        self.version = LuaVersion::Luau;
+        self.endianness = true;
+
+        let version_number: u8 = self.read_u8()?;
+        if version_number == 0 {
+            // the rest of the chunk is the error message
+            let message: String =
+                String::from_utf8_lossy(&self.bytes[self.position..]).into_owned();
+            return Err(self.error_at(0, ReadErrorKind::LuauCompileError(message)));
+        }
+        let types_version: u8 = if version_number >= 4 {
+            let types_version: u8 = self.read_u8()?;
+            if !(LUAU_TYPES_VERSION_MIN..=LUAU_TYPES_VERSION_MAX).contains(&types_version) {
+                return Err(self.error_at(
+                    self.position - 1,
+                    ReadErrorKind::UnsupportedTypesVersion(types_version),
+                ));
+            }
+            types_version
+        } else {
+            0
+        };
+
+        // every string in the chunk is a 1-based index into this table
+        let size_strings: usize = self.read_uleb128("string count")? as usize;
+        self.validate_read(size_strings)?;
+        let mut strings: Vec<Vec<u8>> = Vec::with_capacity(size_strings);
+        for _ in 0..size_strings {
+            let size: usize = self.read_uleb128("string size")? as usize;
+            strings.push(self.read_u8s(size)?);
+        }
+
+        // names of host defined userdata types, only needed to run the chunk
+        if types_version == 3 {
+            while self.read_u8()? != 0 {
+                self.read_string_luau(&strings)?;
+            }
+        }
+
+        let size_protos: usize = self.read_uleb128("proto count")? as usize;
+        self.validate_read(size_protos)?;
+        let mut protos: Vec<(Proto, Vec<usize>)> = Vec::with_capacity(size_protos);
+        // each proto belongs to one parent, so the tree rebuilt from the table is no bigger
+        let mut claimed: Vec<bool> = vec![false; size_protos];
+        let mut heights: Vec<usize> = Vec::with_capacity(size_protos);
+        for id in 0..size_protos {
+            self.proto_path.push(id as LuaInt);
+            let (proto, children): (Proto, Vec<usize>) =
+                self.read_proto_luau(id, version_number, types_version, &strings, &mut claimed)?;
+            let height: usize = children
+                .iter()
+                .map(|child| heights[*child])
+                .max()
+                .unwrap_or(0)
+                + 1;
+            if height > MAX_PROTO_DEPTH {
+                return Err(self.error(ReadErrorKind::TooDeep));
+            }
+            heights.push(height);
+            protos.push((proto, children));
+            self.proto_path.pop();
+        }
+
+        let main_id: usize = self.read_uleb128("main proto")? as usize;
+        if main_id >= protos.len() {
+            return Err(self.error(ReadErrorKind::InvalidIndex {
+                field: "main proto",
+                index: main_id as u64,
+            }));
+        }
+
+        let bytecode: Bytecode = build_bytecode(
+            LuaVersion::Luau,
+            0,
+            self.endianness,
+            // ints and size_ts are varints, instructions are always 32 bit words
+            0,
+            0,
+            4,
+            0,
+            8,
+            false,
+            version_number,
+            types_version,
+            0,
+            0,
+            assemble_luau_proto(&protos, main_id, 0, true),
+            start_instant.elapsed(),
+        );
+
+        Ok(bytecode)
+    }
+
+    fn read_string_luau(&mut self, strings: &[Vec<u8>]) -> ReadResult<Vec<u8>> {
+        let position: usize = self.position;
+        let index: u32 = self.read_uleb128("string")?;
+        if index == 0 {
+            return Ok(vec![]);
+        }
+        match strings.get(index as usize - 1) {
+            Some(string) => Ok(string.clone()),
+            None => Err(self.error_at(
+                position,
+                ReadErrorKind::InvalidIndex {
+                    field: "string",
+                    index: index as u64,
+                },
+            )),
+        }
+    }
+
+    // returns the proto along with the table indices of its children
+    fn read_proto_luau(
+        &mut self,
+        id: usize,
+        version_number: u8,
+        types_version: u8,
+        strings: &[Vec<u8>],
+        claimed: &mut [bool],
+    ) -> ReadResult<(Proto, Vec<usize>)> {
+        let max_stack_size: u8 = self.read_u8()?;
+        let param_count: u8 = self.read_u8()?;
+        let upvalue_count: u8 = self.read_u8()?;
+        let is_vararg: bool = self.read_u8()? != 0;
+
+        let mut param_types: Vec<u8> = Vec::new();
+        if version_number >= 4 {
+            // flags only matter to native code generation
+            self.read_u8()?;
+            let size_typeinfo: usize = self.read_uleb128("typeinfo size")? as usize;
+            self.validate_read(size_typeinfo)?;
+            let end: usize = self.position + size_typeinfo;
+            if size_typeinfo > 0 {
+                param_types = self.read_param_types_luau(types_version, size_typeinfo)?;
+            }
+            self.position = end;
+        }
+
+        let code: Vec<Instruction> = self.read_code_luau()?;
+
+        let size_constants: usize = self.read_uleb128("constant count")? as usize;
+        self.validate_read(size_constants)?;
+        let mut constants: Vec<Constant> = Vec::with_capacity(size_constants);
+        for _ in 0..size_constants {
+            let constant: Constant = self.read_constant_luau(&constants, strings)?;
+            constants.push(constant);
+        }
+
+        let size_protos: usize = self.read_uleb128("proto count")? as usize;
+        self.validate_read(size_protos)?;
+        let mut children: Vec<usize> = Vec::with_capacity(size_protos);
+        for _ in 0..size_protos {
+            let position: usize = self.position;
+            let child: usize = self.read_uleb128("proto")? as usize;
+            // children are always written before their parent, and only one parent has each
+            if child >= id || claimed[child] {
+                return Err(self.error_at(
+                    position,
+                    ReadErrorKind::InvalidIndex {
+                        field: "proto",
+                        index: child as u64,
+                    },
+                ));
+            }
+            claimed[child] = true;
+            children.push(child);
+        }
+        // closure constants refer to the proto table, point them at the child instead
+        for constant in constants.iter_mut() {
+            if let Constant::Closure(index) = constant {
+                match children.iter().position(|child| *child == *index as usize) {
+                    Some(child) => *index = child as LuaInt,
+                    None => {
+                        return Err(self.error(ReadErrorKind::InvalidIndex {
+                            field: "closure",
+                            index: *index as u64,
+                        }));
+                    }
+                }
+            }
+        }
+
+        let line_defined: LuaInt = self.read_uleb128("linedefined")? as LuaInt;
+        let debug_name: Vec<u8> = self.read_string_luau(strings)?;
+
+        let mut lineinfo: Vec<LuaInt> = Vec::new();
+        if self.read_u8()? != 0 {
+            lineinfo = self.read_lineinfo_luau(code.len())?;
+        }
+
+        let mut locals: Vec<LocalVar> = Vec::new();
+        let mut upvalue_names: Vec<Vec<u8>> = Vec::new();
+        if self.read_u8()? != 0 {
+            let size_locals: usize = self.read_uleb128("local count")? as usize;
+            self.validate_read(size_locals)?;
+            locals.reserve(size_locals);
+            for _ in 0..size_locals {
+                locals.push(LocalVar {
+                    name: self.read_string_luau(strings)?,
+                    start_pc: self.read_uleb128("local start")? as LuaInt,
+                    end_pc: self.read_uleb128("local end")? as LuaInt,
+                    reg: Some(self.read_u8()?),
+                });
+            }
+
+            let size_upvalues: usize = self.read_uleb128("upvalue count")? as usize;
+            self.validate_read(size_upvalues)?;
+            upvalue_names.reserve(size_upvalues);
+            for _ in 0..size_upvalues {
+                upvalue_names.push(self.read_string_luau(strings)?);
+            }
+        }
+
+        let result: Proto = Proto {
+            is_main: false,
+            id: id as LuaInt,
+
+            // the chunk name isn't part of Luau bytecode
+            source: Vec::new(),
+            line_defined,
+            last_line_defined: 0,
+            upvalue_count,
+            param_count,
+            is_vararg,
+            vararg_flags: 0,
+            max_stack_size,
+            code,
+            constants,
+            protos: Vec::new(),
+            upvalues: Vec::new(),
+            param_types,
+            number_constants: Vec::new(),
+            two_slot_frame: false,
+
+            lineinfo,
+            locals,
+            upvalue_names,
+            debug_name,
+        };
+
+        Ok((result, children))
+    }
+
+    // only the parameter types are kept, the rest describes upvalues and locals
+    fn read_param_types_luau(&mut self, types_version: u8, size: usize) -> ReadResult<Vec<u8>> {
+        // version 1 is just the function type, later ones prefix it with the size of each part
+        let size_function: usize = match types_version {
+            1 => size,
+            _ => {
+                let size_function: usize = self.read_uleb128("typeinfo size")? as usize;
+                self.read_uleb128("typeinfo upvalue count")?;
+                self.read_uleb128("typeinfo local count")?;
+                size_function
+            }
+        };
+        // the function type starts with its tag and parameter count
+        if size_function < 2 {
+            return Ok(vec![]);
+        }
+        self.read_u8s(2)?;
+        self.read_u8s(size_function - 2)
+    }
+
+    fn read_code_luau(&mut self) -> ReadResult<Vec<Instruction>> {
+        let size_code: usize = self.read_uleb128("code count")? as usize;
+        self.validate_read(size_code.saturating_mul(self.size_instruction as usize))?;
+        let mut raw_instructions: Vec<LuaInstruction> = Vec::with_capacity(size_code);
+        for _ in 0..size_code {
+            raw_instructions.push(self.read_instruction()?);
+        }
+
+        let mut result: Vec<Instruction> = Vec::with_capacity(size_code);
+        let mut i: usize = 0;
+        while i < size_code {
+            let inst: Instruction =
+                build_instruction(raw_instructions[i], self.version, 32, self.max_int);
+            let has_aux: bool = inst.has_aux_luau();
+            result.push(inst);
+            // jump offsets count AUX words, so they keep their own slot
+            if has_aux {
+                let aux: LuaInstruction = match raw_instructions.get(i + 1) {
+                    Some(aux) => *aux,
+                    None => {
+                        let position: usize =
+                            self.position - (size_code - i) * self.size_instruction as usize;
+                        return Err(self.error_at(position, ReadErrorKind::MissingAux));
+                    }
+                };
+                result.push(Instruction {
+                    raw: aux,
+                    aux: None,
+                    op: OpCode::OpAux(OpMode::AX(aux as LuaInt)),
+                });
+                i += 1;
+            }
+            i += 1;
+        }
+
+        Ok(result)
+    }
+
+    fn read_constant_luau(
+        &mut self,
+        constants: &[Constant],
+        strings: &[Vec<u8>],
+    ) -> ReadResult<Constant> {
+        let constant_type: u8 = self.read_u8()?;
+        Ok(match constant_type {
+            0 => Constant::Nil,
+            1 => Constant::Boolean(self.read_u8()? != 0),
+            2 => Constant::Number(self.read_f64()?),
+            3 => Constant::String(self.read_string_luau(strings)?),
+            4 => {
+                // up to three 10 bit constant indices, the count is in the top 2 bits
+                let position: usize = self.position;
+                let id: u32 = self.read_u32()?;
+                let mut path: Vec<Vec<u8>> = Vec::new();
+                for shift in [20, 10, 0].iter().take((id >> 30) as usize) {
+                    let index: usize = ((id >> shift) & 1023) as usize;
+                    match constants.get(index) {
+                        Some(Constant::String(name)) => path.push(name.clone()),
+                        _ => {
+                            return Err(self.error_at(
+                                position,
+                                ReadErrorKind::InvalidIndex {
+                                    field: "import",
+                                    index: index as u64,
+                                },
+                            ));
+                        }
+                    }
+                }
+                Constant::Import(path)
+            }
+            5 => {
+                let size_keys: usize = self.read_uleb128("table key count")? as usize;
+                self.validate_read(size_keys)?;
+                let mut keys: Vec<Constant> = Vec::with_capacity(size_keys);
+                for _ in 0..size_keys {
+                    let position: usize = self.position;
+                    let index: usize = self.read_uleb128("table key")? as usize;
+                    match constants.get(index) {
+                        Some(key) => keys.push(key.clone()),
+                        None => {
+                            return Err(self.error_at(
+                                position,
+                                ReadErrorKind::InvalidIndex {
+                                    field: "table key",
+                                    index: index as u64,
+                                },
+                            ));
+                        }
+                    }
+                }
+                Constant::Table(keys)
+            }
+            // still an index into the proto table, remapped once the children are known
+            6 => Constant::Closure(self.read_uleb128("closure")? as LuaInt),
+            7 => Constant::Vector(
+                self.read_f32()?,
+                self.read_f32()?,
+                self.read_f32()?,
+                self.read_f32()?,
+            ),
+            _ => {
+                return Err(self.error_at(
+                    self.position - 1,
+                    ReadErrorKind::InvalidConstantType(constant_type),
+                ));
+            }
+        })
+    }
+
+    // lines are stored as byte offsets from an absolute line kept
+    // for every 2^linegaplog2 instructions
+    fn read_lineinfo_luau(&mut self, size_code: usize) -> ReadResult<Vec<LuaInt>> {
+        let line_gap_log2: u8 = self.read_u8()?;
+        if line_gap_log2 >= 32 {
+            return Err(self.error_at(
+                self.position - 1,
+                ReadErrorKind::InvalidCount {
+                    field: "line gap",
+                    count: line_gap_log2 as LuaInt,
+                },
+            ));
+        }
+        let intervals: usize = match size_code {
+            0 => 0,
+            _ => ((size_code - 1) >> line_gap_log2) + 1,
+        };
+
+        let mut offsets: Vec<u8> = Vec::with_capacity(size_code);
+        let mut offset: u8 = 0;
+        for delta in self.read_u8s(size_code)? {
+            offset = offset.wrapping_add(delta);
+            offsets.push(offset);
+        }
+
+        self.validate_read(intervals.saturating_mul(size_of::<i32>()))?;
+        let mut abslineinfo: Vec<LuaInt> = Vec::with_capacity(intervals);
+        let mut line: LuaInt = 0;
+        for _ in 0..intervals {
+            line = line.wrapping_add(self.read_i32()?);
+            abslineinfo.push(line);
+        }
+
+        Ok(offsets
+            .iter()
+            .enumerate()
+            .map(|(pc, offset)| abslineinfo[pc >> line_gap_log2].wrapping_add(*offset as LuaInt))
+            .collect())
+    }
+
+    fn read_luajit(&mut self, start_instant: Instant) -> ReadResult<Bytecode> {
+        self.version = LuaVersion::LuaJit;
+        self.read_u8s(LUAJIT_SIGNATURE.len())?;
+
+        let version_number: u8 = self.read_u8()?;
+        if !(LUAJIT_VERSION_MIN..=LUAJIT_VERSION_MAX).contains(&version_number) {
+            return Err(self.error_at(
+                self.position - 1,
+                ReadErrorKind::UnsupportedVersion(version_number),
+            ));
+        }
+        let position: usize = self.position;
+        let flags: u32 = self.read_uleb128("flags")?;
+        if flags & !LUAJIT_FLAGS_KNOWN != 0 {
+            return Err(self.error_at(
+                position,
+                ReadErrorKind::InvalidHeaderCheck { field: "flags" },
+            ));
+        }
+        self.endianness = flags & LUAJIT_FLAG_BE == 0;
+        let stripped: bool = flags & LUAJIT_FLAG_STRIP != 0;
+        let source: Vec<u8> = match stripped {
+            true => Vec::new(),
+            false => {
+                let size: usize = self.read_uleb128("chunk name size")? as usize;
+                self.read_u8s(size)?
+            }
+        };
+
+        // every proto is prefixed by its size and a size of 0 ends the chunk; children come
+        // before their parent, which takes them back off this stack through its constants
+        let mut protos: Vec<Proto> = Vec::new();
+        // how deep the tree under each proto on the stack goes
+        let mut heights: Vec<usize> = Vec::new();
+        let mut id: LuaInt = 0;
+        loop {
+            let size: usize = self.read_uleb128("proto size")? as usize;
+            if size == 0 {
+                break;
+            }
+            self.validate_read(size)?;
+            let start: usize = self.position;
+            self.proto_path.push(id);
+            let proto: Proto = self.read_proto_luajit(version_number, flags, &mut protos)?;
+            if self.position != start + size {
+                return Err(self.error_at(
+                    start,
+                    ReadErrorKind::InvalidHeaderCheck {
+                        field: "proto size",
+                    },
+                ));
+            }
+            let height: usize = heights[protos.len()..].iter().max().unwrap_or(&0) + 1;
+            if height > MAX_PROTO_DEPTH {
+                return Err(self.error_at(start, ReadErrorKind::TooDeep));
+            }
+            heights.truncate(protos.len());
+            heights.push(height);
+            self.proto_path.pop();
+            protos.push(proto);
+            id += 1;
+        }
+
+        // the main proto is the last one and every other has to belong to a parent
+        if protos.len() != 1 {
+            return Err(self.error(ReadErrorKind::InvalidCount {
+                field: "main proto",
+                count: protos.len() as LuaInt,
+            }));
+        }
+        let mut main_proto: Proto = protos.remove(0);
+        main_proto.is_main = true;
+        main_proto.source = source;
+
+        let bytecode: Bytecode = build_bytecode(
+            LuaVersion::LuaJit,
+            0,
+            self.endianness,
+            // sizes are ULEB128 encoded, instructions are always 32 bit words
+            0,
+            0,
+            4,
+            0,
+            8,
+            false,
+            0,
+            0,
+            version_number,
+            flags as u8,
+            main_proto,
+            start_instant.elapsed(),
+        );
+
+        Ok(bytecode)
+    }
+
+    fn read_proto_luajit(
+        &mut self,
+        version_number: u8,
+        flags: u32,
+        stack: &mut Vec<Proto>,
+    ) -> ReadResult<Proto> {
+        let proto_flags: u8 = self.read_u8()?;
+        let param_count: u8 = self.read_u8()?;
+        let max_stack_size: u8 = self.read_u8()?;
+        let upvalue_count: u8 = self.read_u8()?;
+        let size_constants: usize = self.read_uleb128("constant count")? as usize;
+        let size_numbers: usize = self.read_uleb128("number constant count")? as usize;
+        // the FUNCF header instruction isn't part of the dump
+        let size_code: usize = self.read_uleb128("code count")? as usize;
+
+        let mut size_debug: usize = 0;
+        let mut line_defined: LuaInt = 0;
+        let mut size_lines: LuaInt = 0;
+        if flags & LUAJIT_FLAG_STRIP == 0 {
+            size_debug = self.read_uleb128("debug size")? as usize;
+            if size_debug > 0 {
+                line_defined = self.read_uleb128("first line")? as LuaInt;
+                size_lines = self.read_uleb128("line count")? as LuaInt;
+            }
+        }
+
+        let code: Vec<Instruction> = self.read_code_luajit(version_number, size_code)?;
+
+        self.validate_read((upvalue_count as usize) * size_of::<u16>())?;
+        let mut upvalues: Vec<UpvalueDesc> = Vec::with_capacity(upvalue_count as usize);
+        for _ in 0..upvalue_count {
+            let upvalue: u16 = self.read_u16()?;
+            upvalues.push(UpvalueDesc {
+                instack: upvalue & LUAJIT_UV_LOCAL != 0,
+                idx: upvalue as u8,
+                // immutable upvalues are reported like 5.4's <const> ones
+                kind: (upvalue & LUAJIT_UV_IMMUTABLE != 0) as u8,
+            });
+        }
+
+        self.validate_read(size_constants)?;
+        let mut constants: Vec<Constant> = Vec::with_capacity(size_constants);
+        let mut protos: Vec<Proto> = Vec::new();
+        for _ in 0..size_constants {
+            let constant: Constant = self.read_constant_luajit(stack, &mut protos)?;
+            constants.push(constant);
+        }
+        // operands index these from the end
+        constants.reverse();
+        // children were popped last defined first, number them in definition order instead
+        protos.reverse();
+        for (id, child) in protos.iter_mut().enumerate() {
+            child.id = id as LuaInt;
+        }
+        for constant in constants.iter_mut() {
+            if let Constant::Closure(index) = constant {
+                *index = protos.len() as LuaInt - 1 - *index;
+            }
+        }
+
+        self.validate_read(size_numbers)?;
+        let mut number_constants: Vec<Constant> = Vec::with_capacity(size_numbers);
+        for _ in 0..size_numbers {
+            let (low, is_number) = self.read_uleb128_33("number constant")?;
+            number_constants.push(match is_number {
+                true => {
+                    let high: u32 = self.read_uleb128("number constant")?;
+                    Constant::Number(f64::from_bits(((high as u64) << 32) | low as u64))
+                }
+                false => Constant::Integer(low as i32 as LuaInteger),
+            });
+        }
+
+        let (lineinfo, locals, upvalue_names) = match size_debug {
+            0 => (Vec::new(), Vec::new(), Vec::new()),
+            _ => {
+                self.validate_read(size_debug)?;
+                let end: usize = self.position + size_debug;
+                let debug: DebugInfo =
+                    self.read_debug_luajit(size_code, upvalue_count, line_defined, size_lines)?;
+                if self.position > end {
+                    return Err(self.error_at(
+                        end - size_debug,
+                        ReadErrorKind::InvalidHeaderCheck {
+                            field: "debug size",
+                        },
+                    ));
+                }
+                self.position = end;
+                debug
+            }
+        };
+
+        let result: Proto = Proto {
+            // both are set by whatever ends up owning the proto
+            is_main: false,
+            id: 0,
+
+            source: Vec::new(),
+            line_defined,
+            last_line_defined: line_defined.wrapping_add(size_lines),
+            upvalue_count,
+            param_count,
+            is_vararg: proto_flags & LUAJIT_PROTO_VARARG != 0,
+            vararg_flags: 0,
+            max_stack_size,
+            code,
+            constants,
+            protos,
+            upvalues,
+            param_types: Vec::new(),
+            number_constants,
+            two_slot_frame: flags & LUAJIT_FLAG_FR2 != 0,
+
+            lineinfo,
+            locals,
+            upvalue_names,
+            debug_name: Vec::new(),
+        };
+
+        Ok(result)
+    }
+
+    fn read_code_luajit(
+        &mut self,
+        version_number: u8,
+        size_code: usize,
+    ) -> ReadResult<Vec<Instruction>> {
+        self.validate_read(size_code.saturating_mul(self.size_instruction as usize))?;
+        let mut result: Vec<Instruction> = Vec::with_capacity(size_code);
+        for _ in 0..size_code {
+            let mut raw: LuaInstruction = self.read_instruction()?;
+            if version_number == 1 {
+                raw = (raw & !0xFF) | luajit_20_opcode(raw & 0xFF);
+            }
+            result.push(build_instruction(raw, self.version, 32, self.max_int));
+        }
+
+        Ok(result)
+    }
+
+    // like read_uleb128, but the lowest bit of the first byte tells numbers from integers
+    fn read_uleb128_33(&mut self, field: &'static str) -> ReadResult<(u32, bool)> {
+        let start: usize = self.position;
+        let first: u8 = self.read_u8()?;
+        let mut result: u64 = ((first >> 1) & 0x3F) as u64;
+        if first & 0x80 != 0 {
+            let mut shift: u32 = 6;
+            loop {
+                let byte: u8 = self.read_u8()?;
+                result |= ((byte & 0x7F) as u64) << shift;
+                if result > u32::MAX as u64 {
+                    return Err(self.error_at(start, ReadErrorKind::VarintOverflow { field }));
+                }
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                shift += 7;
+                if shift > 27 {
+                    return Err(self.error_at(start, ReadErrorKind::VarintOverflow { field }));
+                }
+            }
+        }
+        Ok((result as u32, first & 1 != 0))
+    }
+
+    // a number split into the ULEB128s of its low and high word
+    fn read_number_luajit(&mut self, field: &'static str) -> ReadResult<u64> {
+        let low: u32 = self.read_uleb128(field)?;
+        let high: u32 = self.read_uleb128(field)?;
+        Ok(((high as u64) << 32) | low as u64)
+    }
+
+    fn read_constant_luajit(
+        &mut self,
+        stack: &mut Vec<Proto>,
+        protos: &mut Vec<Proto>,
+    ) -> ReadResult<Constant> {
+        let position: usize = self.position;
+        let constant_type: u32 = self.read_uleb128("constant type")?;
+        Ok(match constant_type {
+            0 => match stack.pop() {
+                Some(child) => {
+                    protos.push(child);
+                    Constant::Closure(protos.len() as LuaInt - 1)
+                }
+                None => {
+                    return Err(self.error_at(
+                        position,
+                        ReadErrorKind::InvalidCount {
+                            field: "child proto",
+                            count: protos.len() as LuaInt + 1,
+                        },
+                    ));
+                }
+            },
+            1 => {
+                let size_array: usize = self.read_uleb128("table array size")? as usize;
+                let size_hash: usize = self.read_uleb128("table hash size")? as usize;
+                self.validate_read(size_array.saturating_add(size_hash.saturating_mul(2)))?;
+                let mut array: Vec<Constant> = Vec::with_capacity(size_array);
+                for _ in 0..size_array {
+                    array.push(self.read_table_constant_luajit()?);
+                }
+                let mut hash: Vec<(Constant, Constant)> = Vec::with_capacity(size_hash);
+                for _ in 0..size_hash {
+                    hash.push((
+                        self.read_table_constant_luajit()?,
+                        self.read_table_constant_luajit()?,
+                    ));
+                }
+                Constant::Template(array, hash)
+            }
+            2 => Constant::Int64(self.read_number_luajit("int64 constant")? as i64),
+            3 => Constant::UInt64(self.read_number_luajit("uint64 constant")?),
+            4 => Constant::Complex(
+                f64::from_bits(self.read_number_luajit("complex constant")?),
+                f64::from_bits(self.read_number_luajit("complex constant")?),
+            ),
+            // the rest are strings with their size added to the type
+            _ => Constant::String(self.read_u8s(constant_type as usize - 5)?),
+        })
+    }
+
+    fn read_table_constant_luajit(&mut self) -> ReadResult<Constant> {
+        let constant_type: u32 = self.read_uleb128("table constant type")?;
+        Ok(match constant_type {
+            0 => Constant::Nil,
+            1 => Constant::Boolean(false),
+            2 => Constant::Boolean(true),
+            3 => Constant::Integer(self.read_uleb128("table constant")? as i32 as LuaInteger),
+            4 => Constant::Number(f64::from_bits(self.read_number_luajit("table constant")?)),
+            _ => Constant::String(self.read_u8s(constant_type as usize - 5)?),
+        })
+    }
+
+    fn read_string_luajit(&mut self) -> ReadResult<Vec<u8>> {
+        let mut result: Vec<u8> = Vec::new();
+        loop {
+            match self.read_u8()? {
+                0 => return Ok(result),
+                byte => result.push(byte),
+            }
+        }
+    }
+
+    // lines are offsets from the first line, as wide as the line count needs
+    fn read_debug_luajit(
+        &mut self,
+        size_code: usize,
+        upvalue_count: u8,
+        line_defined: LuaInt,
+        size_lines: LuaInt,
+    ) -> ReadResult<DebugInfo> {
+        let mut lineinfo: Vec<LuaInt> = Vec::with_capacity(size_code);
+        for _ in 0..size_code {
+            let offset: u32 = match size_lines {
+                0..256 => self.read_u8()? as u32,
+                256..65536 => self.read_u16()? as u32,
+                _ => self.read_u32()?,
+            };
+            lineinfo.push(line_defined.wrapping_add(offset as LuaInt));
+        }
+
+        let mut upvalue_names: Vec<Vec<u8>> = Vec::with_capacity(upvalue_count as usize);
+        for _ in 0..upvalue_count {
+            upvalue_names.push(self.read_string_luajit()?);
+        }
+
+        // pcs count the FUNCF header, so shift them onto the dumped instructions
+        let mut locals: Vec<LocalVar> = Vec::new();
+        let mut last_pc: LuaInt = 0;
+        loop {
+            let name: Vec<u8> = match self.read_u8()? {
+                0 => break,
+                kind @ 1..=6 => LUAJIT_VARNAMES[kind as usize - 1].as_bytes().to_vec(),
+                _ => {
+                    self.position -= 1;
+                    self.read_string_luajit()?
+                }
+            };
+            let start_pc: LuaInt =
+                last_pc.wrapping_add(self.read_uleb128("local start")? as LuaInt);
+            let end_pc: LuaInt = start_pc.wrapping_add(self.read_uleb128("local end")? as LuaInt);
+            last_pc = start_pc;
+            locals.push(LocalVar {
+                name,
+                start_pc: (start_pc - 1).max(0),
+                end_pc: end_pc - 1,
+                reg: None,
+            });
+        }
+
+        Ok((lineinfo, locals, upvalue_names))
+    }
+}
+
+// Luau protos refer to their children by index into the chunk's proto table,
+// rebuild the tree the rest of inu works with
+fn assemble_luau_proto(
+    protos: &[(Proto, Vec<usize>)],
+    index: usize,
+    id: LuaInt,
+    is_main: bool,
+) -> Proto {
+    let (proto, children) = &protos[index];
+    let mut result: Proto = proto.clone();
+    result.id = id;
+    result.is_main = is_main;
+    result.protos = children
+        .iter()
+        .enumerate()
+        .map(|(id, child)| assemble_luau_proto(protos, *child, id as LuaInt, false))
+        .collect();
+    result
 }
 
-pub fn build_reader(bytes: &Vec<u8>) -> Reader {
+// LuaJIT 2.0 lacks ISTYPE, ISNUM, TGETR and TSETR, shift its opcodes onto the 2.1 ones
+fn luajit_20_opcode(op: u32) -> u32 {
+    match op {
+        0..=15 => op,
+        16..=56 => op + 2,
+        57..=60 => op + 3,
+        _ => op + 4,
+    }
+}
+
+pub fn build_reader(bytes: &Vec<u8>) -> Reader<'_> {
     Reader {
         bytes,
         bytes_size: bytes.len(),
         position: 0,
+        proto_path: Vec::new(),
+        version: LuaVersion::Lua51,
         endianness: false,
         size_int: 4,
         max_int: LuaInt::MAX,
         size_sizet: 8,
         size_instruction: 4,
+        size_luainteger: 8,
         size_luanumber: 8,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::write::build_writer;
+
+    // compiled from tests/fixtures/sample.lua by a stock 5.1 luac
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.luac");
+    // the 12 byte header and the "@sample.lua" source go before the main proto's counts
+    const CODE_COUNT: usize = 12 + 8 + 12 + 4 + 4 + 4;
+    // each compiled from the source of the same name in tests/fixtures
+    const LUA52: &[u8] = include_bytes!("../tests/fixtures/lua52.luac");
+    const LUA53: &[u8] = include_bytes!("../tests/fixtures/lua53.luac");
+    const LUA54: &[u8] = include_bytes!("../tests/fixtures/lua54.luac");
+    const LUAU: &[u8] = include_bytes!("../tests/fixtures/luau.luauc");
+    const LUAJIT: &[u8] = include_bytes!("../tests/fixtures/luajit.ljbc");
+
+    fn read_error(bytes: &Vec<u8>) -> ReadError {
+        match build_reader(bytes).read() {
+            Ok(_) => panic!("expected the chunk to be rejected"),
+            Err(err) => err,
+        }
+    }
+
+    fn read_fixture(bytes: &[u8]) -> Bytecode {
+        match build_reader(&bytes.to_vec()).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn with_code_count(count: i32) -> Vec<u8> {
+        let mut bytes: Vec<u8> = SAMPLE.to_vec();
+        bytes[CODE_COUNT..CODE_COUNT + 4].copy_from_slice(&count.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn reads_the_sample() {
+        let bytes: Vec<u8> = SAMPLE.to_vec();
+        let bytecode: Bytecode = match build_reader(&bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(bytecode.version, LuaVersion::Lua51);
+        assert_eq!(main.source, b"@sample.lua");
+        assert_eq!(main.code.len(), 90);
+        assert_eq!(main.lineinfo.len(), 90);
+        assert_eq!(main.constants.len(), 27);
+        assert_eq!(main.protos.len(), 2);
+        assert_eq!(main.locals[0].name, b"format");
+        assert_eq!(
+            main.protos[1].upvalue_names,
+            [b"format".to_vec(), b"counter".to_vec()]
+        );
+    }
+
+    #[test]
+    fn reads_lua52() {
+        let bytecode: Bytecode = read_fixture(LUA52);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(bytecode.version, LuaVersion::Lua52);
+        assert_eq!(main.source, b"@lua52.lua");
+        assert_eq!(main.code.len(), 22);
+        assert!(matches!(
+            main.code[15].op,
+            OpCode::OpGetTabUp(OpMode::ABC(3, 0, 260))
+        ));
+        assert!(matches!(
+            main.code[14].op,
+            OpCode::OpJmp(OpMode::ASBX(0, 3))
+        ));
+        // numbers are all floats before 5.3
+        assert_eq!(main.constants[1].format(), "1");
+        assert!(matches!(main.constants[6], Constant::Number(n) if n == 2.5));
+        assert_eq!(main.upvalue_names, [b"_ENV".to_vec()]);
+        let shout: &Proto = &main.protos[0];
+        assert_eq!(shout.upvalues.len(), 1);
+        assert!(shout.upvalues[0].instack);
+        assert_eq!(shout.upvalues[0].idx, 0);
+        assert_eq!(shout.lineinfo[..2], [3, 3]);
+    }
+
+    #[test]
+    fn reads_lua53() {
+        let bytecode: Bytecode = read_fixture(LUA53);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(bytecode.version, LuaVersion::Lua53);
+        assert_eq!(bytecode.size_luainteger, 8);
+        assert_eq!(main.code.len(), 17);
+        assert!(matches!(
+            main.code[2].op,
+            OpCode::OpBand(OpMode::ABC(2, 0, 1))
+        ));
+        assert!(matches!(
+            main.code[8].op,
+            OpCode::OpIdiv(OpMode::ABC(4, 0, 1))
+        ));
+        assert!(matches!(
+            main.code[14].op,
+            OpCode::OpBnot(OpMode::ABC(10, 0, 0))
+        ));
+        assert!(matches!(main.constants[0], Constant::Integer(7)));
+        assert!(matches!(main.constants[3], Constant::Number(n) if n == 0.25));
+        // past 2^53, so it only survives as an integer
+        assert!(matches!(
+            main.constants[4],
+            Constant::Integer(9007199254740993)
+        ));
+    }
+
+    #[test]
+    fn reads_lua54() {
+        let bytecode: Bytecode = read_fixture(LUA54);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(bytecode.version, LuaVersion::Lua54);
+        assert_eq!(main.code.len(), 19);
+        assert!(matches!(
+            main.code[9].op,
+            OpCode::OpTbc(OpMode::ABCK(3, 0, 0, false))
+        ));
+        assert!(matches!(
+            main.code[17].op,
+            OpCode::OpReturn(OpMode::ABCK(3, 2, 1, true))
+        ));
+        // the 200 blank lines before the call don't fit a line delta and need abslineinfo
+        assert_eq!(main.lineinfo[13..16], [10, 212, 212]);
+        assert_eq!(main.lineinfo.last(), Some(&213));
+        let bump: &Proto = &main.protos[0];
+        assert_eq!(bump.upvalue_names, [b"count".to_vec(), b"names".to_vec()]);
+        // names is <const> but a table, so it stays an upvalue marked as such
+        assert_eq!(bump.upvalues[0].kind, 0);
+        assert_eq!(bump.upvalues[1].kind, 1);
+        assert!(matches!(
+            bump.code[3].op,
+            OpCode::OpAddI(OpMode::ABCK(1, 1, 10, false))
+        ));
+    }
+
+    #[test]
+    fn reads_luau() {
+        let bytecode: Bytecode = read_fixture(LUAU);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(bytecode.version, LuaVersion::Luau);
+        // AUX words keep their own slot
+        assert_eq!(main.code.len(), 17);
+        assert!(matches!(
+            main.code[3].op,
+            OpCode::OpGetImport(OpMode::AD(2, 3))
+        ));
+        assert!(matches!(main.code[4].op, OpCode::OpAux(_)));
+        assert!(matches!(
+            main.code[11].op,
+            OpCode::OpNamecall(OpMode::ABC(4, 4, 141))
+        ));
+        assert!(matches!(main.constants[0], Constant::Closure(0)));
+        assert!(
+            matches!(&main.constants[3], Constant::Import(path) if path == &[b"print".to_vec()])
+        );
+        let area: &Proto = &main.protos[0];
+        assert_eq!(area.debug_name, b"area");
+        // both parameters are typed as number
+        assert_eq!(area.param_types, [2, 2]);
+        assert_eq!(area.param_type(0), Some("number".to_string()));
+        assert!(matches!(
+            &area.constants[2],
+            Constant::Import(path) if path == &[b"math".to_vec(), b"floor".to_vec()]
+        ));
+        assert_eq!(area.locals[0].reg, Some(0));
+        assert_eq!(area.lineinfo, [2; 6]);
+    }
+
+    #[test]
+    fn reads_luajit() {
+        let bytecode: Bytecode = read_fixture(LUAJIT);
+        let main: &Proto = &bytecode.main_proto;
+        assert_eq!(bytecode.version, LuaVersion::LuaJit);
+        assert_eq!(bytecode.luajit_version, 2);
+        assert_eq!(main.source, b"@luajit.lua");
+        assert!(main.two_slot_frame);
+        assert!(matches!(main.code[0].op, OpCode::OpTDup(OpMode::AD(0, 0))));
+        assert!(matches!(
+            main.code[3].op,
+            OpCode::OpSubNV(OpMode::ABC(3, 2, 2))
+        ));
+        assert_eq!(
+            main.constants[0].format(),
+            "{\"first\", [\"x\"] = 1, [\"y\"] = 2}"
+        );
+        assert!(matches!(main.constants[1], Constant::Closure(0)));
+        // 3000000000 doesn't fit the 32 bit integer form and takes both halves of a double
+        assert!(matches!(main.number_constants[0], Constant::Number(n) if n == 0.5));
+        assert!(matches!(main.number_constants[1], Constant::Number(n) if n == 3000000000.0));
+        assert!(matches!(
+            main.number_constants[2],
+            Constant::Integer(100000)
+        ));
+        let inner: &Proto = &main.protos[0].protos[0];
+        assert_eq!(
+            inner.upvalue_names,
+            [b"n".to_vec(), b"scale".to_vec(), b"big".to_vec()]
+        );
+        assert!(inner.upvalues[0].instack);
+        assert!(!inner.upvalues[1].instack);
+        assert_eq!(inner.lineinfo, [6; 6]);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let err: ReadError = read_error(&SAMPLE[..8].to_vec());
+        assert_eq!(err.kind, ReadErrorKind::UnexpectedEof { size: 1 });
+        assert_eq!(err.position, 8);
+        assert_eq!(err.proto_path, "");
+    }
+
+    #[test]
+    fn truncated_proto() {
+        let err: ReadError = read_error(&SAMPLE[..SAMPLE.len() - 3].to_vec());
+        assert!(matches!(err.kind, ReadErrorKind::UnexpectedEof { .. }));
+        assert_eq!(err.proto_path, "main");
+    }
+
+    #[test]
+    fn bad_signature() {
+        let mut bytes: Vec<u8> = SAMPLE.to_vec();
+        bytes[3] = b'x';
+        let err: ReadError = read_error(&bytes);
+        assert_eq!(err.kind, ReadErrorKind::InvalidSignature);
+        assert_eq!(err.position, 0);
+        assert_eq!(
+            err.to_string(),
+            "Failed to read bytecode at offset 0: expected signature \\27Lua or \\27LJ"
+        );
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytes: Vec<u8> = SAMPLE.to_vec();
+        bytes[4] = 0x50;
+        let err: ReadError = read_error(&bytes);
+        assert_eq!(err.kind, ReadErrorKind::UnsupportedVersion(0x50));
+        assert_eq!(err.position, 4);
+    }
+
+    #[test]
+    fn oversized_code_count() {
+        let err: ReadError = read_error(&with_code_count(i32::MAX));
+        assert_eq!(
+            err.kind,
+            ReadErrorKind::UnexpectedEof {
+                size: i32::MAX as usize * 4
+            }
+        );
+        assert_eq!(err.position, CODE_COUNT + 4);
+        assert_eq!(err.proto_path, "main");
+    }
+
+    #[test]
+    fn negative_code_count() {
+        let err: ReadError = read_error(&with_code_count(-1));
+        assert_eq!(
+            err.kind,
+            ReadErrorKind::InvalidCount {
+                field: "code",
+                count: -1
+            }
+        );
+    }
+
+    #[test]
+    fn missing_aux() {
+        let bytecode: Bytecode =
+            match build_assembler(".source \"@t.lua\"\n    SETLIST r0, 1, 600\n").assemble() {
+                Ok(bytecode) => bytecode,
+                Err(err) => panic!("{}", err),
+            };
+        let mut bytes: Vec<u8> = match build_writer(&bytecode).write() {
+            Ok(bytes) => bytes,
+            Err(err) => panic!("{}", err),
+        };
+        // cut the code off right after the SETLIST, before its count word
+        let code_count: usize = 12 + 8 + b"@t.lua\0".len() + 12;
+        bytes[code_count..code_count + 4].copy_from_slice(&1i32.to_le_bytes());
+        let err: ReadError = read_error(&bytes);
+        assert_eq!(err.kind, ReadErrorKind::MissingAux);
+        assert_eq!(err.position, code_count + 4);
+    }
+
+    // functions each holding the next one, depth of them with main counted
+    fn nested_51(depth: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = SAMPLE[..12].to_vec();
+        for level in 0..depth {
+            bytes.extend_from_slice(&0u64.to_le_bytes());
+            bytes.extend_from_slice(&[0; 8]);
+            bytes.extend_from_slice(&[0, 0, 2, 2]);
+            bytes.extend_from_slice(&1i32.to_le_bytes());
+            bytes.extend_from_slice(&0x0080_001eu32.to_le_bytes());
+            bytes.extend_from_slice(&0i32.to_le_bytes());
+            let children: i32 = (level + 1 < depth) as i32;
+            bytes.extend_from_slice(&children.to_le_bytes());
+        }
+        for _ in 0..depth {
+            bytes.extend_from_slice(&[0; 12]);
+        }
+        bytes
+    }
+
+    fn push_uleb128(bytes: &mut Vec<u8>, mut value: usize) {
+        while value >= 0x80 {
+            bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+    }
+
+    // a Luau v3 chunk of protos that only RETURN, each with the children listed for it
+    fn luau_chunk(children: &[Vec<usize>]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![3, 0];
+        push_uleb128(&mut bytes, children.len());
+        for list in children {
+            bytes.extend_from_slice(&[1, 0, 0, 0, 1, 0x16, 0, 1, 0, 0]);
+            push_uleb128(&mut bytes, list.len());
+            for child in list {
+                push_uleb128(&mut bytes, *child);
+            }
+            bytes.extend_from_slice(&[0, 0, 0, 0]);
+        }
+        push_uleb128(&mut bytes, children.len() - 1);
+        bytes
+    }
+
+    // a stripped LuaJIT chunk of protos each holding the one before
+    fn luajit_chain(depth: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = vec![0x1b, b'L', b'J', 2, 0x02];
+        for id in 0..depth {
+            // a RET0 and the KGC_CHILD of the proto before
+            let mut proto: Vec<u8> = vec![0, 0, 1, 0, (id > 0) as u8, 0, 1, 0x4b, 0, 1, 0];
+            if id > 0 {
+                proto.push(0);
+            }
+            push_uleb128(&mut bytes, proto.len());
+            bytes.extend_from_slice(&proto);
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    #[test]
+    fn nesting_depth() {
+        assert!(build_reader(&nested_51(MAX_PROTO_DEPTH)).read().is_ok());
+        let err: ReadError = read_error(&nested_51(MAX_PROTO_DEPTH + 1));
+        assert_eq!(err.kind, ReadErrorKind::TooDeep);
+        assert_eq!(err.proto_path.matches('/').count(), MAX_PROTO_DEPTH);
+
+        let chain: Vec<Vec<usize>> = (0..=MAX_PROTO_DEPTH)
+            .map(|id| match id {
+                0 => vec![],
+                id => vec![id - 1],
+            })
+            .collect();
+        let err: ReadError = read_error(&luau_chunk(&chain));
+        assert_eq!(err.kind, ReadErrorKind::TooDeep);
+        assert_eq!(err.proto_path, format!("proto {}", MAX_PROTO_DEPTH));
+
+        assert!(build_reader(&luajit_chain(MAX_PROTO_DEPTH)).read().is_ok());
+        let err: ReadError = read_error(&luajit_chain(MAX_PROTO_DEPTH + 1));
+        assert_eq!(err.kind, ReadErrorKind::TooDeep);
+    }
+
+    #[test]
+    fn luau_children_have_one_parent() {
+        let bytecode: Bytecode = match build_reader(&luau_chunk(&[vec![], vec![0]])).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(bytecode.main_proto.protos.len(), 1);
+        let err: ReadError = read_error(&luau_chunk(&[vec![], vec![0, 0]]));
+        assert_eq!(
+            err.kind,
+            ReadErrorKind::InvalidIndex {
+                field: "proto",
+                index: 0
+            }
+        );
+        assert_eq!(err.proto_path, "proto 1");
+        let err: ReadError = read_error(&luau_chunk(&[vec![], vec![0], vec![0, 1]]));
+        assert_eq!(err.proto_path, "proto 2");
+    }
+}
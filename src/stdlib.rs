@@ -0,0 +1,1130 @@
+// the host functions a chunk run by the vm sees as globals: the base library, string,
+// table and math, without anything that reaches the file system or the OS
+
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::bytecode::LuaNumber;
+use crate::pattern::{build_match_state, Capture, MatchState, SPECIALS};
+use crate::vm::{
+    first, format_float, new_table, TableRef, Value, Vm, VmError, VmErrorKind, VmResult,
+};
+
+// LUAI_MAXCSTACK in 5.1's luaconf.h, the most values unpack gives
+const MAX_RESULTS: i64 = 8000;
+// past this string.rep fails instead of trying to allocate it
+const MAX_STRING_SIZE: usize = 1 << 30;
+
+pub fn host_function(
+    func: impl Fn(&mut Vm, Vec<Value>) -> VmResult<Vec<Value>> + 'static,
+) -> Value {
+    Value::HostFunction(Rc::new(func))
+}
+
+fn register(
+    table: &TableRef,
+    name: &str,
+    func: impl Fn(&mut Vm, Vec<Value>) -> VmResult<Vec<Value>> + 'static,
+) {
+    table.borrow_mut().set_str(name, host_function(func));
+}
+
+fn arg(args: &[Value], n: usize) -> Value {
+    args.get(n - 1).cloned().unwrap_or(Value::Nil)
+}
+
+// luaL_argerror, the name comes from the call a Lua function made so there is none when a
+// host function like pcall calls another
+fn arg_error(vm: &Vm, n: usize, name: &str, message: &str) -> VmError {
+    let name: &str = match vm.called_by_host() {
+        true => "?",
+        false => name,
+    };
+    vm.error(&format!("bad argument #{} to '{}' ({})", n, name, message))
+}
+
+fn type_error(vm: &Vm, args: &[Value], n: usize, name: &str, expected: &str) -> VmError {
+    let got: &str = match args.get(n - 1) {
+        Some(value) => value.type_name(),
+        None => "no value",
+    };
+    arg_error(vm, n, name, &format!("{} expected, got {}", expected, got))
+}
+
+fn check_any(vm: &Vm, args: &[Value], n: usize, name: &str) -> VmResult<Value> {
+    match args.get(n - 1) {
+        Some(value) => Ok(value.clone()),
+        None => Err(arg_error(vm, n, name, "value expected")),
+    }
+}
+
+fn check_string(vm: &Vm, args: &[Value], n: usize, name: &str) -> VmResult<Rc<[u8]>> {
+    match args.get(n - 1).and_then(Value::to_bytes) {
+        Some(bytes) => Ok(bytes),
+        None => Err(type_error(vm, args, n, name, "string")),
+    }
+}
+
+fn check_number(vm: &Vm, args: &[Value], n: usize, name: &str) -> VmResult<LuaNumber> {
+    match args.get(n - 1).and_then(Value::to_number) {
+        Some(number) => Ok(number),
+        None => Err(type_error(vm, args, n, name, "number")),
+    }
+}
+
+// lua_Integer is a ptrdiff_t, numbers are truncated into it
+fn check_integer(vm: &Vm, args: &[Value], n: usize, name: &str) -> VmResult<i64> {
+    check_number(vm, args, n, name).map(|number| number as i64)
+}
+
+fn opt_integer(vm: &Vm, args: &[Value], n: usize, name: &str, default: i64) -> VmResult<i64> {
+    match args.get(n - 1) {
+        None | Some(Value::Nil) => Ok(default),
+        Some(_) => check_integer(vm, args, n, name),
+    }
+}
+
+fn check_table(vm: &Vm, args: &[Value], n: usize, name: &str) -> VmResult<TableRef> {
+    match args.get(n - 1) {
+        Some(Value::Table(table)) => Ok(table.clone()),
+        _ => Err(type_error(vm, args, n, name, "table")),
+    }
+}
+
+fn number(n: impl Into<f64>) -> Value {
+    Value::Number(n.into())
+}
+
+pub fn open_stdlib(vm: &mut Vm) {
+    let globals: TableRef = vm.globals.clone();
+    open_base(&globals);
+
+    let string: TableRef = new_table();
+    open_string(&string);
+    globals
+        .borrow_mut()
+        .set_str("string", Value::Table(string.clone()));
+    let string_metatable: TableRef = new_table();
+    string_metatable
+        .borrow_mut()
+        .set_str("__index", Value::Table(string));
+    vm.string_metatable = Some(string_metatable);
+
+    let table: TableRef = new_table();
+    open_table(&table);
+    globals.borrow_mut().set_str("table", Value::Table(table));
+
+    let math: TableRef = new_table();
+    open_math(&math);
+    globals.borrow_mut().set_str("math", Value::Table(math));
+}
+
+fn open_base(globals: &TableRef) {
+    globals
+        .borrow_mut()
+        .set_str("_G", Value::Table(globals.clone()));
+    globals
+        .borrow_mut()
+        .set_str("_VERSION", Value::string(b"Lua 5.1"));
+    register(globals, "assert", assert);
+    register(globals, "error", error);
+    register(globals, "getfenv", getfenv);
+    register(globals, "getmetatable", getmetatable);
+    register(globals, "loadstring", loadstring);
+    register(globals, "pcall", pcall);
+    register(globals, "print", print);
+    register(globals, "rawequal", rawequal);
+    register(globals, "rawget", rawget);
+    register(globals, "rawset", rawset);
+    register(globals, "select", select);
+    register(globals, "setfenv", setfenv);
+    register(globals, "setmetatable", setmetatable);
+    register(globals, "tonumber", tonumber);
+    register(globals, "tostring", tostring);
+    register(globals, "type", lua_type);
+    register(globals, "unpack", unpack);
+    register(globals, "xpcall", xpcall);
+
+    // pairs and ipairs hand out the same iterator every time
+    let next_function: Value = host_function(next);
+    globals.borrow_mut().set_str("next", next_function.clone());
+    register(globals, "pairs", move |vm, args| {
+        let table: TableRef = check_table(vm, &args, 1, "pairs")?;
+        Ok(vec![next_function.clone(), Value::Table(table), Value::Nil])
+    });
+    let ipairs_function: Value = host_function(ipairs_next);
+    register(globals, "ipairs", move |vm, args| {
+        let table: TableRef = check_table(vm, &args, 1, "ipairs")?;
+        Ok(vec![
+            ipairs_function.clone(),
+            Value::Table(table),
+            number(0),
+        ])
+    });
+}
+
+fn assert(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    if check_any(vm, &args, 1, "assert")?.is_truthy() {
+        return Ok(args);
+    }
+    let message: Rc<[u8]> = match arg(&args, 2) {
+        Value::Nil => Rc::from(&b"assertion failed!"[..]),
+        _ => check_string(vm, &args, 2, "assert")?,
+    };
+    Err(vm.error(&String::from_utf8_lossy(&message)))
+}
+
+fn error(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let level: i64 = opt_integer(vm, &args, 2, "error", 1)?;
+    let value: Value = arg(&args, 1);
+    match (&value, level > 0) {
+        (Value::String(_) | Value::Number(_), true) => {
+            let mut message: Vec<u8> = vm.location(level as usize).into_bytes();
+            message.extend_from_slice(&value.to_bytes().unwrap_or_else(|| Rc::from(&b""[..])));
+            Err(vm.raise(Value::string(&message)))
+        }
+        _ => Err(vm.raise(value)),
+    }
+}
+
+// the function getfenv and setfenv are about, None for the thread's globals at level 0
+fn env_target(vm: &Vm, args: &[Value], name: &str) -> VmResult<Option<Value>> {
+    let level: i64 = match arg(args, 1) {
+        function if function.is_function() => return Ok(Some(function)),
+        Value::Nil => 1,
+        _ => check_integer(vm, args, 1, name)?,
+    };
+    if level < 0 {
+        return Err(arg_error(vm, 1, name, "level must be non-negative"));
+    }
+    if level == 0 {
+        return Ok(None);
+    }
+    match vm.caller(level as usize) {
+        Some(closure) => Ok(Some(Value::Closure(closure))),
+        None => Err(arg_error(vm, 1, name, "invalid level")),
+    }
+}
+
+fn getfenv(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let env: TableRef = match env_target(vm, &args, "getfenv")? {
+        Some(Value::Closure(closure)) => closure.env.borrow().clone(),
+        _ => vm.globals.clone(),
+    };
+    Ok(vec![Value::Table(env)])
+}
+
+fn setfenv(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let env: TableRef = check_table(vm, &args, 2, "setfenv")?;
+    match env_target(vm, &args, "setfenv")? {
+        None => {
+            vm.globals = env;
+            Ok(Vec::new())
+        }
+        Some(Value::Closure(closure)) => {
+            *closure.env.borrow_mut() = env;
+            Ok(vec![Value::Closure(closure)])
+        }
+        Some(_) => Err(vm.error("'setfenv' cannot change environment of given object")),
+    }
+}
+
+fn getmetatable(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let value: Value = check_any(vm, &args, 1, "getmetatable")?;
+    let metatable: TableRef = match vm.metatable(&value) {
+        Some(metatable) => metatable,
+        None => return Ok(vec![Value::Nil]),
+    };
+    let protected: Value = metatable.borrow().get_str("__metatable");
+    match protected {
+        Value::Nil => Ok(vec![Value::Table(metatable)]),
+        protected => Ok(vec![protected]),
+    }
+}
+
+fn setmetatable(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "setmetatable")?;
+    let metatable: Option<TableRef> = match arg(&args, 2) {
+        Value::Nil => None,
+        Value::Table(metatable) => Some(metatable),
+        _ => return Err(type_error(vm, &args, 2, "setmetatable", "nil or table")),
+    };
+    let protected: bool = match &table.borrow().metatable {
+        Some(current) => !current.borrow().get_str("__metatable").is_nil(),
+        None => false,
+    };
+    if protected {
+        return Err(vm.error("cannot change a protected metatable"));
+    }
+    table.borrow_mut().metatable = metatable;
+    Ok(vec![Value::Table(table)])
+}
+
+// a failed load gives nil and the message rather than raising it
+fn loadstring(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let chunk: Rc<[u8]> = check_string(vm, &args, 1, "loadstring")?;
+    match vm.load_string(&chunk) {
+        Ok(function) => Ok(vec![function]),
+        Err(err) => Ok(vec![Value::Nil, err.into_value()]),
+    }
+}
+
+fn pcall(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    check_any(vm, &args, 1, "pcall")?;
+    let mut args: Vec<Value> = args;
+    let function: Value = args.remove(0);
+    match vm.call(function, args) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(
+            err @ VmError {
+                kind: VmErrorKind::StepLimit(_),
+                ..
+            },
+        ) => Err(err),
+        Err(err) => Ok(vec![Value::Boolean(false), err.into_value()]),
+    }
+}
+
+// the handler runs after the stack has unwound, unlike 5.1 where it sees the error's
+fn xpcall(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let handler: Value = check_any(vm, &args, 2, "xpcall")?;
+    match vm.call(arg(&args, 1), Vec::new()) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(
+            err @ VmError {
+                kind: VmErrorKind::StepLimit(_),
+                ..
+            },
+        ) => Err(err),
+        Err(err) => {
+            let handled: Value = first(vm.call(handler, vec![err.into_value()])?);
+            Ok(vec![Value::Boolean(false), handled])
+        }
+    }
+}
+
+fn print(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let mut line: Vec<u8> = Vec::new();
+    for (index, value) in args.iter().enumerate() {
+        if index > 0 {
+            line.push(b'\t');
+        }
+        match vm.tostring(value)?.to_bytes() {
+            Some(bytes) => line.extend_from_slice(&bytes),
+            None => return Err(vm.error("'tostring' must return a string to 'print'")),
+        }
+    }
+    line.push(b'\n');
+    let _ = io::stdout().write_all(&line);
+    Ok(Vec::new())
+}
+
+fn rawequal(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let a: Value = check_any(vm, &args, 1, "rawequal")?;
+    let b: Value = check_any(vm, &args, 2, "rawequal")?;
+    Ok(vec![Value::Boolean(a.raw_equals(&b))])
+}
+
+fn rawget(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "rawget")?;
+    let key: Value = check_any(vm, &args, 2, "rawget")?;
+    let value: Value = table.borrow().get(&key);
+    Ok(vec![value])
+}
+
+fn rawset(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "rawset")?;
+    let key: Value = check_any(vm, &args, 2, "rawset")?;
+    let value: Value = check_any(vm, &args, 3, "rawset")?;
+    let set: Result<(), &str> = table.borrow_mut().set(key, value);
+    set.map_err(|message| vm.error(message))?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let count: usize = args.len();
+    if let Value::String(selector) = arg(&args, 1) {
+        if &*selector == b"#" {
+            return Ok(vec![number(count.saturating_sub(1) as f64)]);
+        }
+    }
+    let mut index: i64 = check_integer(vm, &args, 1, "select")?;
+    if index < 0 {
+        index += count as i64;
+    } else if index > count as i64 {
+        index = count as i64;
+    }
+    if index < 1 {
+        return Err(arg_error(vm, 1, "select", "index out of range"));
+    }
+    Ok(args[index as usize..].to_vec())
+}
+
+fn tonumber(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let base: i64 = opt_integer(vm, &args, 2, "tonumber", 10)?;
+    if base == 10 {
+        let value: Value = check_any(vm, &args, 1, "tonumber")?;
+        return Ok(vec![match value.to_number() {
+            Some(n) => number(n),
+            None => Value::Nil,
+        }]);
+    }
+    let text: Rc<[u8]> = check_string(vm, &args, 1, "tonumber")?;
+    if !(2..=36).contains(&base) {
+        return Err(arg_error(vm, 2, "tonumber", "base out of range"));
+    }
+    // strtoul, space around it and a sign are allowed
+    let text: &[u8] = text.trim_ascii();
+    let (negative, digits): (bool, &[u8]) = match text.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, text),
+    };
+    let mut value: u64 = 0;
+    for digit in digits {
+        match (*digit as char).to_digit(base as u32) {
+            Some(digit) => value = value.wrapping_mul(base as u64).wrapping_add(digit as u64),
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+    Ok(vec![number(match negative {
+        true => value.wrapping_neg() as f64,
+        false => value as f64,
+    })])
+}
+
+fn tostring(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let value: Value = check_any(vm, &args, 1, "tostring")?;
+    Ok(vec![vm.tostring(&value)?])
+}
+
+fn lua_type(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let value: Value = check_any(vm, &args, 1, "type")?;
+    Ok(vec![Value::string(value.type_name().as_bytes())])
+}
+
+fn unpack(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "unpack")?;
+    let start: i64 = opt_integer(vm, &args, 2, "unpack", 1)?;
+    let end: i64 = match arg(&args, 3) {
+        Value::Nil => table.borrow().length() as i64,
+        _ => check_integer(vm, &args, 3, "unpack")?,
+    };
+    if start > end {
+        return Ok(Vec::new());
+    }
+    if end.saturating_sub(start) >= MAX_RESULTS {
+        return Err(vm.error("too many results to unpack"));
+    }
+    let table = table.borrow();
+    Ok((start..=end)
+        .map(|index| table.get(&number(index as f64)))
+        .collect())
+}
+
+fn next(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "next")?;
+    let entry: Result<Option<(Value, Value)>, &str> = table.borrow().next(&arg(&args, 2));
+    match entry.map_err(|message| vm.error(message))? {
+        Some((key, value)) => Ok(vec![key, value]),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+fn ipairs_next(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "ipairs")?;
+    let index: i64 = check_integer(vm, &args, 2, "ipairs")? + 1;
+    let value: Value = table.borrow().get(&number(index as f64));
+    match value {
+        Value::Nil => Ok(Vec::new()),
+        value => Ok(vec![number(index as f64), value]),
+    }
+}
+
+fn open_string(string: &TableRef) {
+    register(string, "byte", str_byte);
+    register(string, "char", str_char);
+    register(string, "find", |vm, args| str_find(vm, args, true));
+    register(string, "format", str_format);
+    register(string, "gmatch", str_gmatch);
+    register(string, "gsub", str_gsub);
+    register(string, "len", |vm, args| {
+        let s: Rc<[u8]> = check_string(vm, &args, 1, "len")?;
+        Ok(vec![number(s.len() as f64)])
+    });
+    register(string, "lower", |vm, args| {
+        let s: Rc<[u8]> = check_string(vm, &args, 1, "lower")?;
+        Ok(vec![Value::string(&s.to_ascii_lowercase())])
+    });
+    register(string, "match", |vm, args| str_find(vm, args, false));
+    register(string, "rep", str_rep);
+    register(string, "reverse", |vm, args| {
+        let s: Rc<[u8]> = check_string(vm, &args, 1, "reverse")?;
+        Ok(vec![Value::string(
+            &s.iter().rev().copied().collect::<Vec<u8>>(),
+        )])
+    });
+    register(string, "sub", str_sub);
+    register(string, "upper", |vm, args| {
+        let s: Rc<[u8]> = check_string(vm, &args, 1, "upper")?;
+        Ok(vec![Value::string(&s.to_ascii_uppercase())])
+    });
+}
+
+// a negative position counts back from the end, posrelat
+fn relative_position(position: i64, length: usize) -> i64 {
+    let position: i64 = match position < 0 {
+        true => position + length as i64 + 1,
+        false => position,
+    };
+    position.max(0)
+}
+
+fn str_byte(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let s: Rc<[u8]> = check_string(vm, &args, 1, "byte")?;
+    let start: i64 = relative_position(opt_integer(vm, &args, 2, "byte", 1)?, s.len());
+    let end: i64 = relative_position(opt_integer(vm, &args, 3, "byte", start)?, s.len());
+    let start: i64 = start.max(1);
+    let end: i64 = end.min(s.len() as i64);
+    if start > end {
+        return Ok(Vec::new());
+    }
+    Ok(s[start as usize - 1..end as usize]
+        .iter()
+        .map(|b| number(*b))
+        .collect())
+}
+
+fn str_char(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(args.len());
+    for n in 1..=args.len() {
+        let c: i64 = check_integer(vm, &args, n, "char")?;
+        match u8::try_from(c) {
+            Ok(c) => bytes.push(c),
+            Err(_) => return Err(arg_error(vm, n, "char", "invalid value")),
+        }
+    }
+    Ok(vec![Value::string(&bytes)])
+}
+
+fn str_rep(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let s: Rc<[u8]> = check_string(vm, &args, 1, "rep")?;
+    let count: usize = check_integer(vm, &args, 2, "rep")?.max(0) as usize;
+    if s.len().saturating_mul(count) > MAX_STRING_SIZE {
+        return Err(vm.error("resulting string too large"));
+    }
+    Ok(vec![Value::string(&s.repeat(count))])
+}
+
+fn str_sub(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let s: Rc<[u8]> = check_string(vm, &args, 1, "sub")?;
+    let start: i64 = relative_position(check_integer(vm, &args, 2, "sub")?, s.len()).max(1);
+    let end: i64 =
+        relative_position(opt_integer(vm, &args, 3, "sub", -1)?, s.len()).min(s.len() as i64);
+    match start <= end {
+        true => Ok(vec![Value::string(&s[start as usize - 1..end as usize])]),
+        false => Ok(vec![Value::string(b"")]),
+    }
+}
+
+fn capture_value(
+    vm: &Vm,
+    state: &MatchState,
+    source: &[u8],
+    index: usize,
+    start: usize,
+    end: usize,
+) -> VmResult<Value> {
+    match state
+        .capture(index, start, end)
+        .map_err(|message| vm.error(&message))?
+    {
+        Capture::Span(start, end) => Ok(Value::string(&source[start..end])),
+        Capture::Position(position) => Ok(number(position as f64)),
+    }
+}
+
+// the captures of a match, or the match itself when whole and there are none
+fn captures(
+    vm: &Vm,
+    state: &MatchState,
+    source: &[u8],
+    start: usize,
+    end: usize,
+    whole: bool,
+) -> VmResult<Vec<Value>> {
+    (0..state.capture_count(whole))
+        .map(|index| capture_value(vm, state, source, index, start, end))
+        .collect()
+}
+
+// find and match, str_find_aux
+fn str_find(vm: &mut Vm, args: Vec<Value>, find: bool) -> VmResult<Vec<Value>> {
+    let name: &str = match find {
+        true => "find",
+        false => "match",
+    };
+    let s: Rc<[u8]> = check_string(vm, &args, 1, name)?;
+    let pattern: Rc<[u8]> = check_string(vm, &args, 2, name)?;
+    let init: usize =
+        (relative_position(opt_integer(vm, &args, 3, name, 1)?, s.len()) - 1).max(0) as usize;
+    let init: usize = init.min(s.len());
+    let plain: bool = arg(&args, 4).is_truthy() || !pattern.iter().any(|c| SPECIALS.contains(c));
+    if find && plain {
+        let found: Option<usize> = match pattern.len() {
+            0 => Some(0),
+            length => s[init..]
+                .windows(length)
+                .position(|window| *window == *pattern),
+        };
+        return Ok(match found {
+            Some(offset) => vec![
+                number((init + offset + 1) as f64),
+                number((init + offset + pattern.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+    let (anchor, pattern): (bool, &[u8]) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, &pattern),
+    };
+    let mut state: MatchState = build_match_state(&s, pattern);
+    let mut start: usize = init;
+    loop {
+        state.reset();
+        if let Some(end) = state
+            .do_match(start, 0)
+            .map_err(|message| vm.error(&message))?
+        {
+            if !find {
+                return captures(vm, &state, &s, start, end, true);
+            }
+            let mut results: Vec<Value> = vec![number((start + 1) as f64), number(end as f64)];
+            results.extend(captures(vm, &state, &s, start, end, false)?);
+            return Ok(results);
+        }
+        start += 1;
+        if anchor || start > s.len() {
+            return Ok(vec![Value::Nil]);
+        }
+    }
+}
+
+fn str_gmatch(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let s: Rc<[u8]> = check_string(vm, &args, 1, "gmatch")?;
+    let pattern: Rc<[u8]> = check_string(vm, &args, 2, "gmatch")?;
+    let position: Cell<usize> = Cell::new(0);
+    Ok(vec![host_function(move |vm, _| {
+        let mut state: MatchState = build_match_state(&s, &pattern);
+        for start in position.get()..=s.len() {
+            state.reset();
+            if let Some(end) = state
+                .do_match(start, 0)
+                .map_err(|message| vm.error(&message))?
+            {
+                // an empty match moves on by one so the next call finds something new
+                position.set(match end == start {
+                    true => end + 1,
+                    false => end,
+                });
+                return captures(vm, &state, &s, start, end, true);
+            }
+        }
+        Ok(Vec::new())
+    })])
+}
+
+// a replacement string, where %0 to %9 stand for the match and its captures
+fn add_string(
+    vm: &Vm,
+    state: &MatchState,
+    source: &[u8],
+    (start, end): (usize, usize),
+    replacement: &[u8],
+    out: &mut Vec<u8>,
+) -> VmResult<()> {
+    let mut bytes = replacement.iter();
+    while let Some(c) = bytes.next() {
+        if *c != b'%' {
+            out.push(*c);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'0') => out.extend_from_slice(&source[start..end]),
+            Some(digit) if digit.is_ascii_digit() => {
+                let index: usize = (digit - b'1') as usize;
+                let value: Value = capture_value(vm, state, source, index, start, end)?;
+                out.extend_from_slice(&value.to_bytes().unwrap_or_else(|| Rc::from(&b""[..])));
+            }
+            Some(other) => out.push(*other),
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn str_gsub(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let source: Rc<[u8]> = check_string(vm, &args, 1, "gsub")?;
+    let pattern: Rc<[u8]> = check_string(vm, &args, 2, "gsub")?;
+    let replacement: Value = arg(&args, 3);
+    if matches!(replacement, Value::Nil | Value::Boolean(_)) {
+        return Err(type_error(vm, &args, 3, "gsub", "string/function/table"));
+    }
+    let max: i64 = opt_integer(vm, &args, 4, "gsub", source.len() as i64 + 1)?;
+    let (anchor, pattern): (bool, &[u8]) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, &pattern),
+    };
+    let mut state: MatchState = build_match_state(&source, pattern);
+    let mut out: Vec<u8> = Vec::new();
+    let mut start: usize = 0;
+    let mut count: i64 = 0;
+    while count < max {
+        state.reset();
+        let end: Option<usize> = state
+            .do_match(start, 0)
+            .map_err(|message| vm.error(&message))?;
+        if let Some(end) = end {
+            count += 1;
+            let value: Value = match &replacement {
+                Value::String(_) | Value::Number(_) => {
+                    let replacement: Rc<[u8]> = replacement.to_bytes().unwrap_or_default();
+                    add_string(vm, &state, &source, (start, end), &replacement, &mut out)?;
+                    Value::Nil
+                }
+                Value::Table(_) => {
+                    let key: Value = capture_value(vm, &state, &source, 0, start, end)?;
+                    match vm.index(&replacement, &key)? {
+                        // nil and false keep what matched
+                        value if !value.is_truthy() => Value::string(&source[start..end]),
+                        value => value,
+                    }
+                }
+                function => {
+                    let values: Vec<Value> = captures(vm, &state, &source, start, end, true)?;
+                    match first(vm.call(function.clone(), values)?) {
+                        value if !value.is_truthy() => Value::string(&source[start..end]),
+                        value => value,
+                    }
+                }
+            };
+            match value {
+                Value::Nil => {}
+                value => match value.to_bytes() {
+                    Some(bytes) => out.extend_from_slice(&bytes),
+                    None => {
+                        return Err(vm.error(&format!(
+                            "invalid replacement value (a {})",
+                            value.type_name()
+                        )))
+                    }
+                },
+            }
+        }
+        match end {
+            Some(end) if end > start => start = end,
+            _ if start < source.len() => {
+                out.push(source[start]);
+                start += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    out.extend_from_slice(&source[start..]);
+    Ok(vec![Value::string(&out), number(count as f64)])
+}
+
+// the flags, width and precision of a % conversion
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    // pads sign and body out to the width, zeros go between the two
+    fn pad(&self, out: &mut Vec<u8>, sign: &[u8], body: &[u8], numeric: bool) {
+        let fill: usize = self.width.saturating_sub(sign.len() + body.len());
+        if self.left {
+            out.extend_from_slice(sign);
+            out.extend_from_slice(body);
+            out.extend(std::iter::repeat_n(b' ', fill));
+        } else if self.zero && numeric {
+            out.extend_from_slice(sign);
+            out.extend(std::iter::repeat_n(b'0', fill));
+            out.extend_from_slice(body);
+        } else {
+            out.extend(std::iter::repeat_n(b' ', fill));
+            out.extend_from_slice(sign);
+            out.extend_from_slice(body);
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static [u8] {
+        match (negative, self.plus, self.space) {
+            (true, _, _) => b"-",
+            (false, true, _) => b"+",
+            (false, false, true) => b" ",
+            _ => b"",
+        }
+    }
+}
+
+// string.format's %q
+fn add_quoted(s: &[u8], out: &mut Vec<u8>) {
+    out.push(b'"');
+    for c in s {
+        match c {
+            b'"' | b'\\' | b'\n' => out.extend_from_slice(&[b'\\', *c]),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            0 => out.extend_from_slice(b"\\000"),
+            _ => out.push(*c),
+        }
+    }
+    out.push(b'"');
+}
+
+fn str_format(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let format: Rc<[u8]> = check_string(vm, &args, 1, "format")?;
+    let mut out: Vec<u8> = Vec::new();
+    let mut n: usize = 1;
+    let mut i: usize = 0;
+    while i < format.len() {
+        let c: u8 = format[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        if format.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+        let mut spec: FormatSpec = FormatSpec::default();
+        let flags_start: usize = i;
+        while let Some(flag) = format.get(i).filter(|flag| b"-+ #0".contains(flag)) {
+            match flag {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alternate = true,
+                _ => spec.zero = true,
+            }
+            i += 1;
+        }
+        if i - flags_start > 5 {
+            return Err(vm.error("invalid format (repeated flags)"));
+        }
+        // two digits at most for the width and the precision
+        let digits = |i: &mut usize| -> usize {
+            let mut value: usize = 0;
+            for _ in 0..2 {
+                match format.get(*i).filter(|digit| digit.is_ascii_digit()) {
+                    Some(digit) => value = value * 10 + (digit - b'0') as usize,
+                    None => break,
+                }
+                *i += 1;
+            }
+            value
+        };
+        spec.width = digits(&mut i);
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = Some(digits(&mut i));
+        }
+        if format.get(i).is_some_and(u8::is_ascii_digit) {
+            return Err(vm.error("invalid format (width or precision too long)"));
+        }
+        let conversion: u8 = format.get(i).copied().unwrap_or(0);
+        i += 1;
+        n += 1;
+        match conversion {
+            b'c' => {
+                let c: u8 = check_number(vm, &args, n, "format")? as i64 as u8;
+                spec.pad(&mut out, b"", &[c], false);
+            }
+            b'd' | b'i' => {
+                let value: i64 = check_number(vm, &args, n, "format")? as i64;
+                let mut body: Vec<u8> = value.unsigned_abs().to_string().into_bytes();
+                if let Some(precision) = spec.precision {
+                    if precision == 0 && value == 0 {
+                        body.clear();
+                    }
+                    let zeros: usize = precision.saturating_sub(body.len());
+                    body.splice(0..0, std::iter::repeat_n(b'0', zeros));
+                    spec.zero = false;
+                }
+                let sign: &[u8] = spec.sign(value < 0);
+                spec.pad(&mut out, sign, &body, true);
+            }
+            b'o' | b'u' | b'x' | b'X' => {
+                let value: u64 = check_number(vm, &args, n, "format")? as i64 as u64;
+                let mut body: Vec<u8> = match conversion {
+                    b'o' => format!("{:o}", value),
+                    b'x' => format!("{:x}", value),
+                    b'X' => format!("{:X}", value),
+                    _ => value.to_string(),
+                }
+                .into_bytes();
+                if let Some(precision) = spec.precision {
+                    if precision == 0 && value == 0 {
+                        body.clear();
+                    }
+                    let zeros: usize = precision.saturating_sub(body.len());
+                    body.splice(0..0, std::iter::repeat_n(b'0', zeros));
+                    spec.zero = false;
+                }
+                let prefix: &[u8] = match (spec.alternate, conversion) {
+                    (true, b'o') if body.first() != Some(&b'0') => b"0",
+                    (true, b'x') if value != 0 => b"0x",
+                    (true, b'X') if value != 0 => b"0X",
+                    _ => b"",
+                };
+                spec.pad(&mut out, prefix, &body, true);
+            }
+            b'e' | b'E' | b'f' | b'g' | b'G' => {
+                let value: LuaNumber = check_number(vm, &args, n, "format")?;
+                let text: String = format_float(
+                    value.abs(),
+                    spec.precision.unwrap_or(6),
+                    conversion,
+                    spec.alternate,
+                );
+                let numeric: bool = value.is_finite();
+                let sign: &[u8] = spec.sign(value.is_sign_negative() && !value.is_nan());
+                spec.pad(&mut out, sign, text.as_bytes(), numeric);
+            }
+            b'q' => add_quoted(&check_string(vm, &args, n, "format")?, &mut out),
+            b's' => {
+                let s: Rc<[u8]> = check_string(vm, &args, n, "format")?;
+                match spec.precision {
+                    // no precision and a long string goes in as is
+                    None if s.len() >= 100 => out.extend_from_slice(&s),
+                    precision => {
+                        let length: usize = precision.unwrap_or(s.len()).min(s.len());
+                        spec.pad(&mut out, b"", &s[..length], false);
+                    }
+                }
+            }
+            other => {
+                return Err(vm.error(&format!("invalid option '%{}' to 'format'", other as char)))
+            }
+        }
+    }
+    Ok(vec![Value::string(&out)])
+}
+
+fn open_table(table: &TableRef) {
+    register(table, "concat", table_concat);
+    register(table, "getn", |vm, args| {
+        let table: TableRef = check_table(vm, &args, 1, "getn")?;
+        let length: usize = table.borrow().length();
+        Ok(vec![number(length as f64)])
+    });
+    register(table, "insert", table_insert);
+    register(table, "maxn", table_maxn);
+    register(table, "remove", table_remove);
+    register(table, "sort", table_sort);
+}
+
+fn table_concat(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "concat")?;
+    let separator: Rc<[u8]> = match arg(&args, 2) {
+        Value::Nil => Rc::from(&b""[..]),
+        _ => check_string(vm, &args, 2, "concat")?,
+    };
+    let start: i64 = opt_integer(vm, &args, 3, "concat", 1)?;
+    let end: i64 = match arg(&args, 4) {
+        Value::Nil => table.borrow().length() as i64,
+        _ => check_integer(vm, &args, 4, "concat")?,
+    };
+    let mut out: Vec<u8> = Vec::new();
+    for index in start..=end {
+        let value: Value = table.borrow().get(&number(index as f64));
+        match value.to_bytes() {
+            Some(bytes) => out.extend_from_slice(&bytes),
+            None => {
+                return Err(vm.error(&format!(
+                    "invalid value (at index {}) in table for 'concat'",
+                    index
+                )))
+            }
+        }
+        if index != end {
+            out.extend_from_slice(&separator);
+        }
+    }
+    Ok(vec![Value::string(&out)])
+}
+
+fn table_insert(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "insert")?;
+    let mut end: i64 = table.borrow().length() as i64 + 1;
+    let position: i64 = match args.len() {
+        2 => end,
+        3 => {
+            let position: i64 = check_integer(vm, &args, 2, "insert")?;
+            end = end.max(position);
+            let mut table = table.borrow_mut();
+            for index in (position + 1..=end).rev() {
+                let value: Value = table.get(&number((index - 1) as f64));
+                let _ = table.set(number(index as f64), value);
+            }
+            position
+        }
+        _ => return Err(vm.error("wrong number of arguments to 'insert'")),
+    };
+    let value: Value = args[args.len() - 1].clone();
+    let set: Result<(), &str> = table.borrow_mut().set(number(position as f64), value);
+    set.map_err(|message| vm.error(message))?;
+    Ok(Vec::new())
+}
+
+fn table_remove(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "remove")?;
+    let end: i64 = table.borrow().length() as i64;
+    let position: i64 = opt_integer(vm, &args, 2, "remove", end)?;
+    if !(1 <= position && position <= end) {
+        return Ok(Vec::new());
+    }
+    let mut table = table.borrow_mut();
+    let removed: Value = table.get(&number(position as f64));
+    for index in position..end {
+        let value: Value = table.get(&number((index + 1) as f64));
+        let _ = table.set(number(index as f64), value);
+    }
+    let _ = table.set(number(end as f64), Value::Nil);
+    Ok(vec![removed])
+}
+
+fn table_maxn(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "maxn")?;
+    let table = table.borrow();
+    let mut max: LuaNumber = 0.0;
+    let mut key: Value = Value::Nil;
+    while let Ok(Some((next, _))) = table.next(&key) {
+        if let Value::Number(n) = next {
+            max = max.max(n);
+        }
+        key = next;
+    }
+    Ok(vec![number(max)])
+}
+
+// a merge sort, stable and fine with comparators that aren't consistent or that fail
+fn sort_values(vm: &mut Vm, mut values: Vec<Value>, comparator: &Value) -> VmResult<Vec<Value>> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right: Vec<Value> = values.split_off(values.len() / 2);
+    let left: Vec<Value> = sort_values(vm, values, comparator)?;
+    let right: Vec<Value> = sort_values(vm, right, comparator)?;
+    let mut merged: Vec<Value> = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        let right_first: bool = match comparator {
+            Value::Nil => vm.less_than(b, a)?,
+            comparator => {
+                first(vm.call(comparator.clone(), vec![b.clone(), a.clone()])?).is_truthy()
+            }
+        };
+        match right_first {
+            true => merged.extend(right.next()),
+            false => merged.extend(left.next()),
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn table_sort(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let table: TableRef = check_table(vm, &args, 1, "sort")?;
+    let comparator: Value = arg(&args, 2);
+    if !comparator.is_nil() && !comparator.is_function() {
+        return Err(type_error(vm, &args, 2, "sort", "function"));
+    }
+    let length: usize = table.borrow().length();
+    let values: Vec<Value> = (1..=length)
+        .map(|index| table.borrow().get(&number(index as f64)))
+        .collect();
+    let sorted: Vec<Value> = sort_values(vm, values, &comparator)?;
+    let mut table = table.borrow_mut();
+    for (index, value) in sorted.into_iter().enumerate() {
+        let _ = table.set(number((index + 1) as f64), value);
+    }
+    Ok(Vec::new())
+}
+
+fn math_unary(math: &TableRef, name: &'static str, func: fn(LuaNumber) -> LuaNumber) {
+    register(math, name, move |vm, args| {
+        Ok(vec![number(func(check_number(vm, &args, 1, name)?))])
+    });
+}
+
+fn open_math(math: &TableRef) {
+    math_unary(math, "abs", LuaNumber::abs);
+    math_unary(math, "ceil", LuaNumber::ceil);
+    math_unary(math, "cos", LuaNumber::cos);
+    math_unary(math, "exp", LuaNumber::exp);
+    math_unary(math, "floor", LuaNumber::floor);
+    math_unary(math, "log", LuaNumber::ln);
+    math_unary(math, "log10", LuaNumber::log10);
+    math_unary(math, "sin", LuaNumber::sin);
+    math_unary(math, "sqrt", LuaNumber::sqrt);
+    math_unary(math, "tan", LuaNumber::tan);
+    register(math, "fmod", |vm, args| {
+        let a: LuaNumber = check_number(vm, &args, 1, "fmod")?;
+        let b: LuaNumber = check_number(vm, &args, 2, "fmod")?;
+        Ok(vec![number(a % b)])
+    });
+    register(math, "modf", |vm, args| {
+        let n: LuaNumber = check_number(vm, &args, 1, "modf")?;
+        Ok(vec![number(n.trunc()), number(n.fract())])
+    });
+    register(math, "pow", |vm, args| {
+        let a: LuaNumber = check_number(vm, &args, 1, "pow")?;
+        let b: LuaNumber = check_number(vm, &args, 2, "pow")?;
+        Ok(vec![number(a.powf(b))])
+    });
+    register(math, "max", |vm, args| {
+        let mut max: LuaNumber = check_number(vm, &args, 1, "max")?;
+        for n in 2..=args.len() {
+            max = max.max(check_number(vm, &args, n, "max")?);
+        }
+        Ok(vec![number(max)])
+    });
+    register(math, "min", |vm, args| {
+        let mut min: LuaNumber = check_number(vm, &args, 1, "min")?;
+        for n in 2..=args.len() {
+            min = min.min(check_number(vm, &args, n, "min")?);
+        }
+        Ok(vec![number(min)])
+    });
+    math.borrow_mut()
+        .set_str("huge", number(LuaNumber::INFINITY));
+    math.borrow_mut()
+        .set_str("pi", number(std::f64::consts::PI));
+}
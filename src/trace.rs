@@ -0,0 +1,343 @@
+// inu trace, runs a chunk with stubbed globals and logs every instruction it executes
+
+use std::{fs, ops::RangeInclusive, path::Path};
+
+use crate::bytecode::Instruction;
+use crate::stdlib::host_function;
+use crate::vm::{new_table, TableRef, Value, Vm, VmErrorKind, VmResult};
+
+// longer register values are cut short in the log
+const MAX_VALUE_WIDTH: usize = 60;
+
+pub enum Step {
+    Logged,
+    // counted but outside the pc range
+    Skipped,
+    // the step limit ran out before this one
+    Limit(usize),
+}
+
+pub struct Tracer {
+    // only instructions at these pcs are logged, every one is counted
+    pub pc_range: Option<RangeInclusive<usize>>,
+    pub step_limit: Option<usize>,
+    // where loadstring payloads are written, they are only reported without one
+    pub dump_dir: Option<String>,
+    steps: usize,
+    // the lines logged so far when they're kept instead of printed
+    log: Option<Vec<String>>,
+}
+
+pub fn build_tracer() -> Tracer {
+    Tracer {
+        pc_range: None,
+        step_limit: None,
+        dump_dir: None,
+        steps: 0,
+        log: None,
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    let text: String = format!("{:?}", value);
+    match text.char_indices().nth(MAX_VALUE_WIDTH) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+// the registers in regs, once each and in order
+fn format_registers(registers: &[Value], regs: &[usize]) -> String {
+    let mut regs: Vec<usize> = regs.to_vec();
+    regs.sort_unstable();
+    regs.dedup();
+    regs.iter()
+        .filter_map(|reg| {
+            let value: &Value = registers.get(*reg)?;
+            Some(format!("r{}={}", reg, format_value(value)))
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+impl Tracer {
+    pub fn emit(&mut self, line: String) {
+        match &mut self.log {
+            Some(log) => log.push(line),
+            None => println!("{}", line),
+        }
+    }
+
+    // counts the instruction at index and logs it with the registers it reads
+    pub fn step(
+        &mut self,
+        path: &str,
+        index: usize,
+        inst: &Instruction,
+        registers: &[Value],
+        reads: &[usize],
+    ) -> Step {
+        if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+            return Step::Limit(self.steps);
+        }
+        self.steps += 1;
+        if let Some(range) = &self.pc_range {
+            if !range.contains(&index) {
+                return Step::Skipped;
+            }
+        }
+        let line: String = format!(
+            "{} {}  {:?}  {}",
+            path,
+            index,
+            inst.op,
+            format_registers(registers, reads)
+        );
+        self.emit(line.trim_end().to_string());
+        Step::Logged
+    }
+
+    // the registers the logged instruction wrote
+    pub fn changes(&mut self, before: &[Value], after: &[Value]) {
+        let changed: Vec<String> = after
+            .iter()
+            .enumerate()
+            .filter(|(reg, value)| match before.get(*reg) {
+                Some(old) => !old.raw_equals(value),
+                None => true,
+            })
+            .map(|(reg, value)| format!("r{}={}", reg, format_value(value)))
+            .collect();
+        if !changed.is_empty() {
+            self.emit(format!("    -> {}", changed.join(" ")));
+        }
+    }
+
+    // how the frame of the logged instruction ended, what it returned or the error it raised
+    pub fn exit(&mut self, results: &VmResult<Vec<Value>>) {
+        match results {
+            Ok(values) => {
+                let values: Vec<String> = values.iter().map(format_value).collect();
+                self.emit(format!("    -> return {}", values.join(", ")));
+            }
+            Err(err) => {
+                // running out of steps isn't an error of the chunk's
+                if let VmErrorKind::Error(value) = &err.kind {
+                    self.emit(format!("    -> error {}", format_value(value)));
+                }
+            }
+        }
+    }
+
+    // a chunk given to loadstring, written out so inu can list or decompile it
+    pub fn payload(&mut self, name: &str, chunk: &[u8]) {
+        let dir: String = match &self.dump_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                self.emit(format!("-- {}: {} byte payload", name, chunk.len()));
+                return;
+            }
+        };
+        let extension: &str = match chunk.starts_with(b"\x1bLua") {
+            true => "luac",
+            false => "lua",
+        };
+        let path: String = Path::new(&dir)
+            .join(format!("{}.{}", name, extension))
+            .to_string_lossy()
+            .into_owned();
+        let line: String = match fs::write(&path, chunk) {
+            Ok(()) => format!(
+                "-- {}: {} byte payload written to {}",
+                name,
+                chunk.len(),
+                path
+            ),
+            Err(err) => format!(
+                "-- {}: {} byte payload, failed to write {}: {}",
+                name,
+                chunk.len(),
+                path,
+                err
+            ),
+        };
+        self.emit(line);
+    }
+}
+
+// a value standing in for a global the sandbox doesn't have, any field of it is one too
+fn stub(name: String) -> Value {
+    let metatable: TableRef = new_table();
+    let index_name: String = name.clone();
+    metatable.borrow_mut().set_str(
+        "__index",
+        host_function(move |_, args| {
+            let key: String = args.get(1).map(|key| key.to_string()).unwrap_or_default();
+            Ok(vec![stub(format!("{}.{}", index_name, key))])
+        }),
+    );
+    let call_name: String = name.clone();
+    metatable.borrow_mut().set_str(
+        "__call",
+        host_function(move |vm, args| {
+            let args: Vec<String> = args.iter().skip(1).map(format_value).collect();
+            log(vm, format!("-- stub {}({})", call_name, args.join(", ")));
+            Ok(Vec::new())
+        }),
+    );
+    let text: Value = Value::string(format!("stub: {}", name).as_bytes());
+    metatable.borrow_mut().set_str(
+        "__tostring",
+        host_function(move |_, _| Ok(vec![text.clone()])),
+    );
+    let table: TableRef = new_table();
+    table.borrow_mut().metatable = Some(metatable);
+    Value::Table(table)
+}
+
+fn print(vm: &mut Vm, args: Vec<Value>) -> VmResult<Vec<Value>> {
+    let mut texts: Vec<String> = Vec::with_capacity(args.len());
+    for value in &args {
+        texts.push(vm.tostring(value)?.to_string());
+    }
+    log(vm, format!("-- print {}", texts.join("\t")));
+    Ok(Vec::new())
+}
+
+// what the sandbox reports goes in with the instructions
+fn log(vm: &mut Vm, line: String) {
+    match vm.tracer.as_mut() {
+        Some(tracer) => tracer.emit(line),
+        None => println!("{}", line),
+    }
+}
+
+// print goes into the log and reading a missing global gives a stub instead of nil
+pub fn open_sandbox(vm: &mut Vm) {
+    vm.set_global("print", host_function(print));
+    let metatable: TableRef = new_table();
+    metatable.borrow_mut().set_str(
+        "__index",
+        host_function(|_, args| {
+            let name: String = args.get(1).map(|name| name.to_string()).unwrap_or_default();
+            Ok(vec![stub(name)])
+        }),
+    );
+    let globals: TableRef = vm.globals.clone();
+    globals.borrow_mut().metatable = Some(metatable);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::bytecode::Bytecode;
+    use crate::vm::{build_vm, VmResult};
+    use crate::write::build_writer;
+
+    fn assemble(text: &str) -> Bytecode {
+        match build_assembler(text).assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    // prints, calls a stubbed global and runs a chunk handed to loadstring
+    const LOADER: &str = r#"
+            .source "@loader.lua"
+                GETGLOBAL r0, "print"
+                LOADK r1, "hi"
+                CALL r0, 2, 1
+                GETGLOBAL r0, "socket"
+                GETTABLE r0, r0, "connect"
+                LOADK r1, 80
+                CALL r0, 2, 1
+                GETGLOBAL r0, "loadstring"
+                GETGLOBAL r1, "payload"
+                CALL r0, 2, 2
+                CALL r0, 1, 2
+                RETURN r0, 2
+            "#;
+
+    // runs LOADER in the sandbox, with what the tracer logged
+    fn trace(tracer: Tracer) -> (VmResult<Vec<Value>>, Vec<String>) {
+        let payload: Vec<u8> =
+            match build_writer(&assemble("    LOADK r0, 7\n    RETURN r0, 2\n")).write() {
+                Ok(bytes) => bytes,
+                Err(err) => panic!("{}", err),
+            };
+        let mut tracer: Tracer = tracer;
+        tracer.log = Some(Vec::new());
+        let mut vm: Vm = build_vm();
+        open_sandbox(&mut vm);
+        vm.set_global("payload", Value::string(&payload));
+        vm.tracer = Some(tracer);
+        let results: VmResult<Vec<Value>> = vm
+            .load(assemble(LOADER))
+            .and_then(|main| vm.call(main, Vec::new()));
+        let log: Vec<String> = match vm.tracer.take().and_then(|tracer| tracer.log) {
+            Some(log) => log,
+            None => panic!("the tracer went missing"),
+        };
+        (results, log)
+    }
+
+    #[test]
+    fn logs_every_instruction_and_payload() {
+        let (results, log): (VmResult<Vec<Value>>, Vec<String>) = trace(build_tracer());
+        match results {
+            Ok(results) => assert!(matches!(results.as_slice(), [Value::Number(n)] if *n == 7.0)),
+            Err(err) => panic!("{}", err),
+        }
+        // only the registers an instruction reads are logged with it
+        for (start, end) in [
+            ("main 0  OpGetGlobal(ABX(0, 0))", ""),
+            ("main 2  OpCall(ABC(0, 2, 1))  r0=function: ", " r1=\"hi\""),
+            ("-- print hi", ""),
+            ("main 5  OpLoadK(ABX(1, 4))", ""),
+            ("    -> r1=80", ""),
+            ("-- stub socket.connect(80)", ""),
+            ("-- loadstring1: 73 byte payload", ""),
+            ("loadstring1 0  OpLoadK(ABX(0, 0))", ""),
+            ("    -> r0=7", ""),
+            ("    -> return 7", ""),
+        ] {
+            assert!(
+                log.iter().any(|logged| logged.starts_with(start)
+                    && logged.ends_with(end)
+                    && (!end.is_empty() || logged == start)),
+                "{}...{} missing from\n{}",
+                start,
+                end,
+                log.join("\n")
+            );
+        }
+    }
+
+    #[test]
+    fn filters_pcs_and_stops_at_the_step_limit() {
+        let mut tracer: Tracer = build_tracer();
+        tracer.pc_range = Some(1..=1);
+        let (results, log): (VmResult<Vec<Value>>, Vec<String>) = trace(tracer);
+        assert!(results.is_ok());
+        let steps: Vec<&String> = log
+            .iter()
+            .filter(|line| !line.starts_with(' ') && !line.starts_with("--"))
+            .collect();
+        assert_eq!(steps.len(), 2, "{}", log.join("\n"));
+        assert!(steps[0].starts_with("main 1  OpLoadK"));
+        assert!(steps[1].starts_with("loadstring1 1  OpReturn"));
+
+        let mut tracer: Tracer = build_tracer();
+        tracer.step_limit = Some(4);
+        let (results, log): (VmResult<Vec<Value>>, Vec<String>) = trace(tracer);
+        match results {
+            Ok(_) => panic!("expected the step limit to stop the chunk"),
+            Err(err) => assert!(matches!(err.kind, VmErrorKind::StepLimit(4))),
+        }
+        assert!(log
+            .iter()
+            .any(|line| line.starts_with("main 3  OpGetGlobal")));
+        assert!(!log.iter().any(|line| line.starts_with("main 4")));
+    }
+}
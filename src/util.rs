@@ -1,20 +1,33 @@
-pub mod util {
-    macro_rules! format_time_taken {
-        ( $time_taken:expr ) => {{
-            let nanoseconds: u128 = $time_taken.as_nanos();
-            let seconds: u128 = nanoseconds / 1e9 as u128;
-            if seconds == 0 {
-                let milliseconds: u128 = nanoseconds / 1e6 as u128;
-                if milliseconds == 0 {
-                    format!("{} microseconds", $time_taken.as_micros())
-                } else {
-                    format!("{} milliseconds", milliseconds)
-                }
+macro_rules! format_time_taken {
+    ( $time_taken:expr ) => {{
+        let nanoseconds: u128 = $time_taken.as_nanos();
+        let seconds: u128 = nanoseconds / 1e9 as u128;
+        if seconds == 0 {
+            let milliseconds: u128 = nanoseconds / 1e6 as u128;
+            if milliseconds == 0 {
+                format!("{} microseconds", $time_taken.as_micros())
             } else {
-                format!("{} seconds", seconds)
+                format!("{} milliseconds", milliseconds)
             }
-        }};
-    }
+        } else {
+            format!("{} seconds", seconds)
+        }
+    }};
+}
+
+pub(crate) use format_time_taken;
 
-    pub(crate) use format_time_taken;
+const KEYWORDS: [&str; 21] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in", "local",
+    "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// a name that can be written as is, like a.name or a:name()
+pub(crate) fn is_identifier(name: &[u8]) -> bool {
+    match name.first() {
+        Some(first) if first.is_ascii_alphabetic() || *first == b'_' => {}
+        _ => return false,
+    }
+    name.iter().all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        && !KEYWORDS.iter().any(|keyword| keyword.as_bytes() == name)
 }
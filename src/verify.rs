@@ -0,0 +1,769 @@
+use std::fmt;
+
+use crate::bytecode::{
+    Bytecode, Constant, Instruction, LuaInt, LuaVersion, OpCode, OpMode, Proto, BITRK,
+    MAX_STACK_SIZE, VARARG_HASARG, VARARG_ISVARARG, VARARG_NEEDSARG,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyErrorKind {
+    UnsupportedVersion(LuaVersion),
+    StackTooLarge { size: u8 },
+    ParamsOutOfStack,
+    InvalidVarargFlags { flags: u8 },
+    UpvalueNameCount { names: usize, upvalues: u8 },
+    LineInfoCount { lines: usize, code: usize },
+    MissingReturn,
+    UnknownOpcode { pc: usize },
+    RegisterOutOfRange { pc: usize, reg: LuaInt },
+    ConstantOutOfRange { pc: usize, index: LuaInt },
+    UpvalueOutOfRange { pc: usize, index: LuaInt },
+    ProtoOutOfRange { pc: usize, index: LuaInt },
+    JumpOutOfRange { pc: usize, target: LuaInt },
+    JumpIntoAux { pc: usize, target: LuaInt },
+    MissingJump { pc: usize },
+    UnusedOperand { pc: usize },
+    InvalidOperand { pc: usize },
+    GlobalNameNotString { pc: usize },
+    MissingCapture { pc: usize },
+    MissingAux { pc: usize },
+    MissingOpenUse { pc: usize },
+    VarargInFixedFunction { pc: usize },
+}
+
+impl fmt::Display for VerifyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyErrorKind::UnsupportedVersion(version) => {
+                write!(f, "verifying {:?} chunks is not supported", version)
+            }
+            VerifyErrorKind::StackTooLarge { size } => {
+                write!(f, "max stack size {} is over {}", size, MAX_STACK_SIZE)
+            }
+            VerifyErrorKind::ParamsOutOfStack => {
+                write!(f, "the parameters don't fit in the stack")
+            }
+            VerifyErrorKind::InvalidVarargFlags { flags } => {
+                write!(
+                    f,
+                    "vararg flags {} need an arg table without having one",
+                    flags
+                )
+            }
+            VerifyErrorKind::UpvalueNameCount { names, upvalues } => {
+                write!(f, "{} upvalue names for {} upvalues", names, upvalues)
+            }
+            VerifyErrorKind::LineInfoCount { lines, code } => {
+                write!(f, "{} line entries for {} instructions", lines, code)
+            }
+            VerifyErrorKind::MissingReturn => write!(f, "the last instruction isn't a RETURN"),
+            VerifyErrorKind::UnknownOpcode { pc } => {
+                write!(f, "instruction {} has an unknown opcode", pc)
+            }
+            VerifyErrorKind::RegisterOutOfRange { pc, reg } => {
+                write!(
+                    f,
+                    "instruction {} uses register {} outside the stack",
+                    pc, reg
+                )
+            }
+            VerifyErrorKind::ConstantOutOfRange { pc, index } => {
+                write!(
+                    f,
+                    "instruction {} uses constant {} which doesn't exist",
+                    pc, index
+                )
+            }
+            VerifyErrorKind::UpvalueOutOfRange { pc, index } => {
+                write!(
+                    f,
+                    "instruction {} uses upvalue {} which doesn't exist",
+                    pc, index
+                )
+            }
+            VerifyErrorKind::ProtoOutOfRange { pc, index } => {
+                write!(
+                    f,
+                    "instruction {} creates proto {} which doesn't exist",
+                    pc, index
+                )
+            }
+            VerifyErrorKind::JumpOutOfRange { pc, target } => {
+                write!(
+                    f,
+                    "instruction {} jumps to {} outside the function",
+                    pc, target
+                )
+            }
+            VerifyErrorKind::JumpIntoAux { pc, target } => {
+                write!(
+                    f,
+                    "instruction {} jumps to {}, the count of a SETLIST",
+                    pc, target
+                )
+            }
+            VerifyErrorKind::MissingJump { pc } => {
+                write!(f, "instruction {} is a test without a JMP after it", pc)
+            }
+            VerifyErrorKind::UnusedOperand { pc } => {
+                write!(f, "instruction {} sets an operand it doesn't use", pc)
+            }
+            VerifyErrorKind::InvalidOperand { pc } => {
+                write!(f, "instruction {} has an operand out of range", pc)
+            }
+            VerifyErrorKind::GlobalNameNotString { pc } => {
+                write!(
+                    f,
+                    "instruction {} names a global with a constant that isn't a string",
+                    pc
+                )
+            }
+            VerifyErrorKind::MissingCapture { pc } => {
+                write!(
+                    f,
+                    "instruction {} is missing the MOVE or GETUPVAL of a capture",
+                    pc
+                )
+            }
+            VerifyErrorKind::MissingAux { pc } => {
+                write!(f, "instruction {} is missing its SETLIST count word", pc)
+            }
+            VerifyErrorKind::MissingOpenUse { pc } => {
+                write!(
+                    f,
+                    "instruction {} keeps every result but nothing after it takes them",
+                    pc
+                )
+            }
+            VerifyErrorKind::VarargInFixedFunction { pc } => {
+                write!(f, "instruction {} uses ... in a function without it", pc)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub kind: VerifyErrorKind,
+    // path of the proto the problem is in, e.g. main/3/1; empty for the whole chunk
+    pub proto_path: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid bytecode")?;
+        if !self.proto_path.is_empty() {
+            write!(f, " in {}", self.proto_path)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+// what an operand names, like OpArgMask in 5.1's lopcodes.h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ArgMode {
+    // unused, has to be 0
+    N,
+    // used as is
+    U,
+    R,
+    // a register or a constant flagged with BITRK
+    K,
+}
+
+// the B and C modes of the iABC instructions, from luaP_opmodes
+pub(crate) fn arg_modes(op: &OpCode) -> (ArgMode, ArgMode) {
+    match op {
+        OpCode::OpMove(_)
+        | OpCode::OpLoadNil(_)
+        | OpCode::OpUnm(_)
+        | OpCode::OpNot(_)
+        | OpCode::OpLen(_) => (ArgMode::R, ArgMode::N),
+        OpCode::OpGetUpval(_)
+        | OpCode::OpSetUpval(_)
+        | OpCode::OpReturn(_)
+        | OpCode::OpVararg(_) => (ArgMode::U, ArgMode::N),
+        OpCode::OpGetTable(_) | OpCode::OpSelf(_) => (ArgMode::R, ArgMode::K),
+        OpCode::OpSetTable(_)
+        | OpCode::OpAdd(_)
+        | OpCode::OpSub(_)
+        | OpCode::OpMul(_)
+        | OpCode::OpDiv(_)
+        | OpCode::OpMod(_)
+        | OpCode::OpPow(_)
+        | OpCode::OpEq(_)
+        | OpCode::OpLt(_)
+        | OpCode::OpLe(_) => (ArgMode::K, ArgMode::K),
+        OpCode::OpConcat(_) => (ArgMode::R, ArgMode::R),
+        OpCode::OpTest(_) | OpCode::OpTestSet(_) => (ArgMode::R, ArgMode::U),
+        OpCode::OpTForLoop(_) => (ArgMode::N, ArgMode::U),
+        _ => (ArgMode::U, ArgMode::U),
+    }
+}
+
+// instructions that skip the JMP after them
+fn is_test(op: &OpCode) -> bool {
+    matches!(
+        op,
+        OpCode::OpEq(_)
+            | OpCode::OpLt(_)
+            | OpCode::OpLe(_)
+            | OpCode::OpTest(_)
+            | OpCode::OpTestSet(_)
+            | OpCode::OpTForLoop(_)
+    )
+}
+
+// the checks of one proto, pcs count words like jumps do so a SETLIST count has its own
+struct ProtoVerifier<'a> {
+    proto: &'a Proto,
+    pcs: Vec<usize>,
+    code_size: usize,
+    errors: Vec<VerifyErrorKind>,
+}
+
+impl ProtoVerifier<'_> {
+    fn check_reg(&mut self, pc: usize, reg: LuaInt) {
+        if reg >= self.proto.max_stack_size as LuaInt {
+            self.errors
+                .push(VerifyErrorKind::RegisterOutOfRange { pc, reg });
+        }
+    }
+
+    fn check_constant(&mut self, pc: usize, index: LuaInt) -> bool {
+        let exists: bool =
+            usize::try_from(index).is_ok_and(|index| index < self.proto.constants.len());
+        if !exists {
+            self.errors
+                .push(VerifyErrorKind::ConstantOutOfRange { pc, index });
+        }
+        exists
+    }
+
+    fn check_arg(&mut self, pc: usize, value: LuaInt, mode: ArgMode) {
+        match mode {
+            ArgMode::N if value != 0 => self.errors.push(VerifyErrorKind::UnusedOperand { pc }),
+            ArgMode::R => self.check_reg(pc, value),
+            ArgMode::K if value & BITRK != 0 => {
+                self.check_constant(pc, value & !BITRK);
+            }
+            ArgMode::K => self.check_reg(pc, value),
+            _ => {}
+        }
+    }
+
+    // target is a word pc, which has to be an instruction and not the count of a SETLIST
+    fn check_target(&mut self, pc: usize, target: LuaInt) {
+        match usize::try_from(target) {
+            Ok(target) if target < self.code_size => {
+                if self.pcs.binary_search(&target).is_err() {
+                    self.errors.push(VerifyErrorKind::JumpIntoAux {
+                        pc,
+                        target: target as LuaInt,
+                    });
+                }
+            }
+            _ => self
+                .errors
+                .push(VerifyErrorKind::JumpOutOfRange { pc, target }),
+        }
+    }
+
+    // a call or vararg keeping every result has to be followed by what takes them
+    fn check_open_use(&mut self, index: usize, pc: usize) {
+        let is_open: bool = matches!(
+            self.proto.code.get(index + 1).map(|inst| &inst.op),
+            Some(
+                OpCode::OpCall(OpMode::ABC(_, 0, _))
+                    | OpCode::OpTailCall(OpMode::ABC(_, 0, _))
+                    | OpCode::OpReturn(OpMode::ABC(_, 0, _))
+                    | OpCode::OpSetList(OpMode::ABC(_, 0, _))
+            )
+        );
+        if !is_open {
+            self.errors.push(VerifyErrorKind::MissingOpenUse { pc });
+        }
+    }
+
+    // precheck in 5.1's ldebug.c
+    fn check_header(&mut self) {
+        let proto: &Proto = self.proto;
+        if proto.max_stack_size as LuaInt > MAX_STACK_SIZE {
+            self.errors.push(VerifyErrorKind::StackTooLarge {
+                size: proto.max_stack_size,
+            });
+        }
+        let has_arg: u8 = proto.vararg_flags & VARARG_HASARG;
+        if proto.param_count as usize + has_arg as usize > proto.max_stack_size as usize {
+            self.errors.push(VerifyErrorKind::ParamsOutOfStack);
+        }
+        if proto.vararg_flags & VARARG_NEEDSARG != 0 && has_arg == 0 {
+            self.errors.push(VerifyErrorKind::InvalidVarargFlags {
+                flags: proto.vararg_flags,
+            });
+        }
+        if proto.upvalue_names.len() > proto.upvalue_count as usize {
+            self.errors.push(VerifyErrorKind::UpvalueNameCount {
+                names: proto.upvalue_names.len(),
+                upvalues: proto.upvalue_count,
+            });
+        }
+        if !proto.lineinfo.is_empty() && proto.lineinfo.len() != self.code_size {
+            self.errors.push(VerifyErrorKind::LineInfoCount {
+                lines: proto.lineinfo.len(),
+                code: self.code_size,
+            });
+        }
+        if !matches!(
+            proto.code.last().map(|inst| &inst.op),
+            Some(OpCode::OpReturn(_))
+        ) {
+            self.errors.push(VerifyErrorKind::MissingReturn);
+        }
+    }
+
+    // symbexec in 5.1's ldebug.c, run over the whole function without following jumps
+    fn check_instruction(&mut self, index: usize) {
+        let proto: &Proto = self.proto;
+        let inst: &Instruction = &proto.code[index];
+        let pc: usize = self.pcs[index];
+        let mode: &OpMode = match &inst.op {
+            OpCode::OpMove(mode)
+            | OpCode::OpLoadK(mode)
+            | OpCode::OpLoadBool(mode)
+            | OpCode::OpLoadNil(mode)
+            | OpCode::OpGetUpval(mode)
+            | OpCode::OpGetGlobal(mode)
+            | OpCode::OpGetTable(mode)
+            | OpCode::OpSetGlobal(mode)
+            | OpCode::OpSetUpval(mode)
+            | OpCode::OpSetTable(mode)
+            | OpCode::OpNewTable(mode)
+            | OpCode::OpSelf(mode)
+            | OpCode::OpAdd(mode)
+            | OpCode::OpSub(mode)
+            | OpCode::OpMul(mode)
+            | OpCode::OpDiv(mode)
+            | OpCode::OpMod(mode)
+            | OpCode::OpPow(mode)
+            | OpCode::OpUnm(mode)
+            | OpCode::OpNot(mode)
+            | OpCode::OpLen(mode)
+            | OpCode::OpConcat(mode)
+            | OpCode::OpJmp(mode)
+            | OpCode::OpEq(mode)
+            | OpCode::OpLt(mode)
+            | OpCode::OpLe(mode)
+            | OpCode::OpTest(mode)
+            | OpCode::OpTestSet(mode)
+            | OpCode::OpCall(mode)
+            | OpCode::OpTailCall(mode)
+            | OpCode::OpReturn(mode)
+            | OpCode::OpForLoop(mode)
+            | OpCode::OpForPrep(mode)
+            | OpCode::OpTForLoop(mode)
+            | OpCode::OpSetList(mode)
+            | OpCode::OpClose(mode)
+            | OpCode::OpClosure(mode)
+            | OpCode::OpVararg(mode) => mode,
+            _ => {
+                self.errors.push(VerifyErrorKind::UnknownOpcode { pc });
+                return;
+            }
+        };
+        match mode {
+            OpMode::ABC(a, b, c) => {
+                self.check_reg(pc, *a);
+                let (b_mode, c_mode): (ArgMode, ArgMode) = arg_modes(&inst.op);
+                self.check_arg(pc, *b, b_mode);
+                // a folded SETLIST count can be any size
+                if inst.aux.is_none() {
+                    self.check_arg(pc, *c, c_mode);
+                }
+            }
+            // JMP's A isn't kept
+            OpMode::ASBX(_, _) if matches!(inst.op, OpCode::OpJmp(_)) => {}
+            OpMode::ABX(a, _) | OpMode::ASBX(a, _) => self.check_reg(pc, *a),
+            _ => {
+                self.errors.push(VerifyErrorKind::UnknownOpcode { pc });
+                return;
+            }
+        }
+
+        if is_test(&inst.op) {
+            let has_jump: bool = matches!(
+                proto.code.get(index + 1).map(|next| &next.op),
+                Some(OpCode::OpJmp(_))
+            );
+            if !has_jump || pc + 2 >= self.code_size {
+                self.errors.push(VerifyErrorKind::MissingJump { pc });
+            }
+        }
+
+        match &inst.op {
+            OpCode::OpLoadK(OpMode::ABX(_, bx)) => {
+                self.check_constant(pc, *bx);
+            }
+            // skipping the next instruction mustn't land on a count
+            OpCode::OpLoadBool(OpMode::ABC(_, _, c)) if *c != 0 => {
+                self.check_target(pc, pc as LuaInt + 2)
+            }
+            OpCode::OpGetUpval(OpMode::ABC(_, b, _)) | OpCode::OpSetUpval(OpMode::ABC(_, b, _))
+                if *b >= proto.upvalue_count as LuaInt =>
+            {
+                self.errors
+                    .push(VerifyErrorKind::UpvalueOutOfRange { pc, index: *b })
+            }
+            OpCode::OpGetGlobal(OpMode::ABX(_, bx)) | OpCode::OpSetGlobal(OpMode::ABX(_, bx)) => {
+                match self.check_constant(pc, *bx) {
+                    true if !matches!(proto.constants[*bx as usize], Constant::String(_)) => self
+                        .errors
+                        .push(VerifyErrorKind::GlobalNameNotString { pc }),
+                    _ => {}
+                }
+            }
+            OpCode::OpSelf(OpMode::ABC(a, _, _)) => self.check_reg(pc, *a + 1),
+            OpCode::OpConcat(OpMode::ABC(_, b, c)) if b >= c => {
+                self.errors.push(VerifyErrorKind::InvalidOperand { pc })
+            }
+            OpCode::OpTForLoop(OpMode::ABC(a, _, c)) => {
+                if *c < 1 {
+                    self.errors.push(VerifyErrorKind::InvalidOperand { pc });
+                }
+                self.check_reg(pc, *a + 2 + *c);
+            }
+            OpCode::OpForLoop(OpMode::ASBX(a, sbx)) | OpCode::OpForPrep(OpMode::ASBX(a, sbx)) => {
+                self.check_reg(pc, *a + 3);
+                self.check_target(pc, pc as LuaInt + 1 + *sbx);
+            }
+            OpCode::OpJmp(OpMode::ASBX(_, sbx)) => self.check_target(pc, pc as LuaInt + 1 + *sbx),
+            OpCode::OpCall(OpMode::ABC(a, b, c)) | OpCode::OpTailCall(OpMode::ABC(a, b, c)) => {
+                if *b != 0 {
+                    self.check_reg(pc, *a + *b - 1);
+                }
+                match *c {
+                    0 => self.check_open_use(index, pc),
+                    1 => {}
+                    c => self.check_reg(pc, *a + c - 2),
+                }
+            }
+            OpCode::OpReturn(OpMode::ABC(a, b, _)) if *b > 1 => self.check_reg(pc, *a + *b - 2),
+            OpCode::OpSetList(OpMode::ABC(a, b, c)) => {
+                if *b > 0 {
+                    self.check_reg(pc, *a + *b);
+                }
+                if *c == 0 && inst.aux.is_none() {
+                    self.errors.push(VerifyErrorKind::MissingAux { pc });
+                }
+            }
+            OpCode::OpClose(OpMode::ABX(_, bx)) if *bx != 0 => {
+                self.errors.push(VerifyErrorKind::UnusedOperand { pc })
+            }
+            // the captures are pseudo instructions right after it
+            OpCode::OpClosure(OpMode::ABX(_, bx)) => {
+                match usize::try_from(*bx)
+                    .ok()
+                    .and_then(|bx| proto.protos.get(bx))
+                {
+                    Some(child) => {
+                        let upvalue_count: usize = child.upvalue_count as usize;
+                        let captures: usize = proto.code[index + 1..]
+                            .iter()
+                            .take(upvalue_count)
+                            .take_while(|capture| {
+                                matches!(capture.op, OpCode::OpMove(_) | OpCode::OpGetUpval(_))
+                            })
+                            .count();
+                        if captures < upvalue_count {
+                            self.errors.push(VerifyErrorKind::MissingCapture { pc });
+                        }
+                    }
+                    None => self
+                        .errors
+                        .push(VerifyErrorKind::ProtoOutOfRange { pc, index: *bx }),
+                }
+            }
+            OpCode::OpVararg(OpMode::ABC(a, b, _)) => {
+                let flags: u8 = proto.vararg_flags;
+                if flags & VARARG_ISVARARG == 0 || flags & VARARG_NEEDSARG != 0 {
+                    self.errors
+                        .push(VerifyErrorKind::VarargInFixedFunction { pc });
+                }
+                match *b {
+                    0 => self.check_open_use(index, pc),
+                    b => self.check_reg(pc, *a + b - 2),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn verify_proto(proto: &Proto, proto_path: String, errors: &mut Vec<VerifyError>) {
+    let mut pcs: Vec<usize> = Vec::with_capacity(proto.code.len());
+    let mut pc: usize = 0;
+    for inst in &proto.code {
+        pcs.push(pc);
+        pc += match inst.aux.is_some() {
+            true => 2,
+            false => 1,
+        };
+    }
+    let mut verifier: ProtoVerifier = ProtoVerifier {
+        proto,
+        pcs,
+        code_size: pc,
+        errors: Vec::new(),
+    };
+    verifier.check_header();
+    for index in 0..proto.code.len() {
+        verifier.check_instruction(index);
+    }
+    errors.extend(verifier.errors.into_iter().map(|kind| VerifyError {
+        kind,
+        proto_path: proto_path.clone(),
+    }));
+
+    for child in &proto.protos {
+        verify_proto(child, format!("{}/{}", proto_path, child.id), errors);
+    }
+}
+
+pub struct Verifier<'a> {
+    pub bytecode: &'a Bytecode,
+}
+
+impl Verifier<'_> {
+    // every problem luaG_checkcode would stop at, in every function, in code order
+    pub fn verify(&self) -> Vec<VerifyError> {
+        let bytecode: &Bytecode = self.bytecode;
+        if bytecode.version != LuaVersion::Lua51 {
+            return vec![VerifyError {
+                kind: VerifyErrorKind::UnsupportedVersion(bytecode.version),
+                proto_path: String::new(),
+            }];
+        }
+        let mut errors: Vec<VerifyError> = Vec::new();
+        verify_proto(&bytecode.main_proto, "main".to_string(), &mut errors);
+        errors
+    }
+}
+
+pub fn build_verifier(bytecode: &Bytecode) -> Verifier<'_> {
+    Verifier { bytecode }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+
+    // four registers, a string and a number constant, no upvalues or children
+    fn base() -> Bytecode {
+        let text: &str = ".maxstack 4\n.const \"x\"\n.const 1\n    RETURN r0, 1\n";
+        match build_assembler(text).assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn kinds(bytecode: &Bytecode) -> Vec<VerifyErrorKind> {
+        build_verifier(bytecode)
+            .verify()
+            .into_iter()
+            .map(|err| err.kind)
+            .collect()
+    }
+
+    fn inst(op: OpCode) -> Instruction {
+        Instruction {
+            raw: 0,
+            aux: None,
+            op,
+        }
+    }
+
+    fn proto_errors(edit: impl FnOnce(&mut Proto)) -> Vec<VerifyErrorKind> {
+        let mut bytecode: Bytecode = base();
+        edit(&mut bytecode.main_proto);
+        kinds(&bytecode)
+    }
+
+    // code is followed by a RETURN unless it leaves it out on purpose
+    fn code_errors(code: Vec<Instruction>) -> Vec<VerifyErrorKind> {
+        proto_errors(|proto| {
+            proto.code = code;
+            proto.lineinfo.clear();
+        })
+    }
+
+    fn ret() -> Instruction {
+        inst(OpCode::OpReturn(OpMode::ABC(0, 1, 0)))
+    }
+
+    #[test]
+    fn accepts_the_sample() {
+        let bytes: Vec<u8> = include_bytes!("../tests/fixtures/sample.luac").to_vec();
+        let bytecode: Bytecode = match crate::read::build_reader(&bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        assert_eq!(build_verifier(&bytecode).verify(), []);
+        assert_eq!(kinds(&base()), []);
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut bytecode: Bytecode = base();
+        bytecode.version = LuaVersion::Lua53;
+        let errors: Vec<VerifyError> = build_verifier(&bytecode).verify();
+        assert_eq!(
+            errors,
+            [VerifyError {
+                kind: VerifyErrorKind::UnsupportedVersion(LuaVersion::Lua53),
+                proto_path: String::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn header_checks() {
+        assert_eq!(
+            proto_errors(|proto| proto.max_stack_size = 251),
+            [VerifyErrorKind::StackTooLarge { size: 251 }]
+        );
+        assert_eq!(
+            proto_errors(|proto| proto.param_count = 5),
+            [VerifyErrorKind::ParamsOutOfStack]
+        );
+        assert_eq!(
+            proto_errors(|proto| proto.vararg_flags = VARARG_ISVARARG | VARARG_NEEDSARG),
+            [VerifyErrorKind::InvalidVarargFlags { flags: 6 }]
+        );
+        assert_eq!(
+            proto_errors(|proto| proto.upvalue_names = vec![b"a".to_vec()]),
+            [VerifyErrorKind::UpvalueNameCount {
+                names: 1,
+                upvalues: 0
+            }]
+        );
+        assert_eq!(
+            proto_errors(|proto| proto.lineinfo = vec![1, 1, 1]),
+            [VerifyErrorKind::LineInfoCount { lines: 3, code: 1 }]
+        );
+        assert_eq!(
+            code_errors(vec![inst(OpCode::OpLoadK(OpMode::ABX(0, 0)))]),
+            [VerifyErrorKind::MissingReturn]
+        );
+    }
+
+    #[test]
+    fn operand_checks() {
+        let cases: Vec<(OpCode, VerifyErrorKind)> = vec![
+            (
+                OpCode::OpUnknown(63),
+                VerifyErrorKind::UnknownOpcode { pc: 0 },
+            ),
+            (
+                OpCode::OpMove(OpMode::ABC(4, 0, 0)),
+                VerifyErrorKind::RegisterOutOfRange { pc: 0, reg: 4 },
+            ),
+            (
+                OpCode::OpLoadK(OpMode::ABX(0, 2)),
+                VerifyErrorKind::ConstantOutOfRange { pc: 0, index: 2 },
+            ),
+            (
+                OpCode::OpGetUpval(OpMode::ABC(0, 0, 0)),
+                VerifyErrorKind::UpvalueOutOfRange { pc: 0, index: 0 },
+            ),
+            (
+                OpCode::OpClosure(OpMode::ABX(0, 0)),
+                VerifyErrorKind::ProtoOutOfRange { pc: 0, index: 0 },
+            ),
+            (
+                OpCode::OpJmp(OpMode::ASBX(0, 5)),
+                VerifyErrorKind::JumpOutOfRange { pc: 0, target: 6 },
+            ),
+            (
+                OpCode::OpEq(OpMode::ABC(0, 0, 0)),
+                VerifyErrorKind::MissingJump { pc: 0 },
+            ),
+            (
+                OpCode::OpMove(OpMode::ABC(0, 0, 1)),
+                VerifyErrorKind::UnusedOperand { pc: 0 },
+            ),
+            (
+                OpCode::OpConcat(OpMode::ABC(0, 1, 0)),
+                VerifyErrorKind::InvalidOperand { pc: 0 },
+            ),
+            (
+                OpCode::OpGetGlobal(OpMode::ABX(0, 1)),
+                VerifyErrorKind::GlobalNameNotString { pc: 0 },
+            ),
+            (
+                OpCode::OpSetList(OpMode::ABC(0, 1, 0)),
+                VerifyErrorKind::MissingAux { pc: 0 },
+            ),
+            (
+                OpCode::OpCall(OpMode::ABC(0, 1, 0)),
+                VerifyErrorKind::MissingOpenUse { pc: 0 },
+            ),
+        ];
+        for (op, kind) in cases {
+            assert_eq!(code_errors(vec![inst(op), ret()]), [kind]);
+        }
+    }
+
+    #[test]
+    fn jump_into_setlist_count() {
+        let mut setlist: Instruction = inst(OpCode::OpSetList(OpMode::ABC(0, 1, 600)));
+        setlist.aux = Some(600);
+        // the JMP is at word pc 2, after the count word it jumps back to
+        let jump: Instruction = inst(OpCode::OpJmp(OpMode::ASBX(0, -2)));
+        assert_eq!(
+            code_errors(vec![setlist, jump, ret()]),
+            [VerifyErrorKind::JumpIntoAux { pc: 2, target: 1 }]
+        );
+    }
+
+    #[test]
+    fn missing_capture() {
+        let errors: Vec<VerifyErrorKind> = proto_errors(|proto| {
+            let mut child: Proto = proto.clone();
+            child.is_main = false;
+            child.upvalue_count = 1;
+            proto.protos = vec![child];
+            proto.code = vec![inst(OpCode::OpClosure(OpMode::ABX(0, 0))), ret()];
+            proto.lineinfo.clear();
+        });
+        assert_eq!(errors, [VerifyErrorKind::MissingCapture { pc: 0 }]);
+    }
+
+    #[test]
+    fn vararg_in_fixed_function() {
+        let errors: Vec<VerifyErrorKind> = proto_errors(|proto| {
+            proto.vararg_flags = 0;
+            proto.code = vec![inst(OpCode::OpVararg(OpMode::ABC(0, 2, 0))), ret()];
+            proto.lineinfo.clear();
+        });
+        assert_eq!(errors, [VerifyErrorKind::VarargInFixedFunction { pc: 0 }]);
+    }
+
+    #[test]
+    fn error_names_the_proto() {
+        let mut bytecode: Bytecode = base();
+        let mut child: Proto = bytecode.main_proto.clone();
+        child.is_main = false;
+        child.code = vec![inst(OpCode::OpMove(OpMode::ABC(9, 0, 0))), ret()];
+        child.lineinfo.clear();
+        bytecode.main_proto.protos = vec![child];
+        let errors: Vec<VerifyError> = build_verifier(&bytecode).verify();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Invalid bytecode in main/0: instruction 0 uses register 9 outside the stack"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use std::fmt;
+
+use crate::bytecode::{Bytecode, Constant, Instruction, LuaInstruction, LuaInt, LuaVersion, Proto};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WriteErrorKind {
+    UnsupportedVersion(LuaVersion),
+    UnhandledSize { field: &'static str, size: u8 },
+    CountOverflow { field: &'static str, count: usize },
+    InvalidInstruction { pc: usize },
+    InvalidConstant { index: usize },
+    IntegralNumbers,
+}
+
+impl fmt::Display for WriteErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteErrorKind::UnsupportedVersion(version) => {
+                write!(f, "writing {:?} chunks is not supported", version)
+            }
+            WriteErrorKind::UnhandledSize { field, size } => {
+                write!(f, "unhandled {} size {}", field, size)
+            }
+            WriteErrorKind::CountOverflow { field, count } => {
+                write!(f, "{} count {} does not fit an int", field, count)
+            }
+            WriteErrorKind::InvalidInstruction { pc } => {
+                write!(f, "instruction {} can't be encoded for this version", pc)
+            }
+            WriteErrorKind::InvalidConstant { index } => {
+                write!(f, "constant {} can't be encoded for this version", index)
+            }
+            WriteErrorKind::IntegralNumbers => {
+                write!(f, "integral lua_Number chunks are not supported")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteError {
+    pub kind: WriteErrorKind,
+    // path of the proto being written, e.g. main/3/1; empty while writing the header
+    pub proto_path: String,
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to write bytecode")?;
+        if !self.proto_path.is_empty() {
+            write!(f, " in {}", self.proto_path)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+pub type WriteResult<T> = Result<T, WriteError>;
+
+// the header fields a chunk is written with are the ones it was read with, so reading
+// an unmodified chunk and writing it back out gives the same bytes
+pub struct Writer<'a> {
+    pub bytecode: &'a Bytecode,
+
+    bytes: Vec<u8>,
+    proto_path: Vec<LuaInt>,
+}
+
+impl<'a> Writer<'a> {
+    fn error(&self, kind: WriteErrorKind) -> WriteError {
+        let mut proto_path: String = String::new();
+        if let Some((_, rest)) = self.proto_path.split_first() {
+            proto_path.push_str("main");
+            for id in rest {
+                proto_path.push_str(&format!("/{}", id));
+            }
+        }
+        WriteError { kind, proto_path }
+    }
+
+    fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    // takes little endian bytes and flips them for big endian chunks
+    fn write_endian(&mut self, bytes: &[u8]) {
+        match self.bytecode.endianness {
+            true => self.bytes.extend_from_slice(bytes),
+            false => self.bytes.extend(bytes.iter().rev()),
+        }
+    }
+
+    fn write_int(&mut self, value: LuaInt) -> WriteResult<()> {
+        match self.bytecode.size_int {
+            4 => self.write_endian(&value.to_le_bytes()),
+            8 => self.write_endian(&(value as i64).to_le_bytes()),
+            size => {
+                return Err(self.error(WriteErrorKind::UnhandledSize { field: "int", size }));
+            }
+        }
+        Ok(())
+    }
+    fn write_count(&mut self, field: &'static str, count: usize) -> WriteResult<()> {
+        match LuaInt::try_from(count) {
+            Ok(count) => self.write_int(count),
+            Err(_) => Err(self.error(WriteErrorKind::CountOverflow { field, count })),
+        }
+    }
+    fn write_sizet(&mut self, value: usize) -> WriteResult<()> {
+        match self.bytecode.size_sizet {
+            4 => match u32::try_from(value) {
+                Ok(value) => self.write_endian(&value.to_le_bytes()),
+                Err(_) => {
+                    return Err(self.error(WriteErrorKind::CountOverflow {
+                        field: "string size",
+                        count: value,
+                    }));
+                }
+            },
+            8 => self.write_endian(&(value as u64).to_le_bytes()),
+            size => {
+                return Err(self.error(WriteErrorKind::UnhandledSize {
+                    field: "size_t",
+                    size,
+                }));
+            }
+        }
+        Ok(())
+    }
+    // only doubles, like the reader; write checks the header before any number is written
+    fn write_number(&mut self, value: f64) {
+        self.write_endian(&value.to_le_bytes());
+    }
+    fn write_instruction(&mut self, value: LuaInstruction) -> WriteResult<()> {
+        match self.bytecode.size_instruction {
+            4 => self.write_endian(&value.to_le_bytes()),
+            8 => self.write_endian(&(value as u64).to_le_bytes()),
+            size => {
+                return Err(self.error(WriteErrorKind::UnhandledSize {
+                    field: "instruction",
+                    size,
+                }));
+            }
+        }
+        Ok(())
+    }
+    // sizes count the terminating NUL, an empty string is written as no string at all
+    fn write_string(&mut self, bytes: &[u8]) -> WriteResult<()> {
+        if bytes.is_empty() {
+            return self.write_sizet(0);
+        }
+        self.write_sizet(bytes.len() + 1)?;
+        self.bytes.extend_from_slice(bytes);
+        self.write_u8(0);
+        Ok(())
+    }
+
+    pub fn write(&mut self) -> WriteResult<Vec<u8>> {
+        self.bytes.clear();
+        self.proto_path.clear();
+
+        if self.bytecode.version != LuaVersion::Lua51 {
+            return Err(self.error(WriteErrorKind::UnsupportedVersion(self.bytecode.version)));
+        }
+        // anything but a double lua_Number would lose precision, and couldn't be read back
+        if self.bytecode.size_luanumber != 8 {
+            return Err(self.error(WriteErrorKind::UnhandledSize {
+                field: "number",
+                size: self.bytecode.size_luanumber,
+            }));
+        }
+        if self.bytecode.luanumber_integral {
+            return Err(self.error(WriteErrorKind::IntegralNumbers));
+        }
+
+        self.bytes.extend_from_slice(b"\x1bLua");
+        self.write_u8(0x51);
+        self.write_u8(self.bytecode.format);
+        self.write_u8(self.bytecode.endianness as u8);
+        self.write_u8(self.bytecode.size_int);
+        self.write_u8(self.bytecode.size_sizet);
+        self.write_u8(self.bytecode.size_instruction);
+        self.write_u8(self.bytecode.size_luanumber);
+        self.write_u8(self.bytecode.luanumber_integral as u8);
+
+        let bytecode: &'a Bytecode = self.bytecode;
+        self.write_proto(&bytecode.main_proto)?;
+
+        Ok(std::mem::take(&mut self.bytes))
+    }
+
+    fn write_proto(&mut self, proto: &Proto) -> WriteResult<()> {
+        self.proto_path.push(proto.id);
+
+        self.write_string(&proto.source)?;
+        self.write_int(proto.line_defined)?;
+        self.write_int(proto.last_line_defined)?;
+        self.write_u8(proto.upvalue_count);
+        self.write_u8(proto.param_count);
+        // chunks built by hand may only have set is_vararg
+        self.write_u8(match (proto.vararg_flags, proto.is_vararg) {
+            (0, true) => 2,
+            (flags, _) => flags,
+        });
+        self.write_u8(proto.max_stack_size);
+
+        self.write_code(&proto.code)?;
+        self.write_constants(&proto.constants)?;
+        self.write_count("proto", proto.protos.len())?;
+        for child in &proto.protos {
+            self.write_proto(child)?;
+        }
+        self.write_debug(proto)?;
+
+        self.proto_path.pop();
+        Ok(())
+    }
+
+    fn write_code(&mut self, code: &[Instruction]) -> WriteResult<()> {
+        let num_bits_int: LuaInt = self.bytecode.size_int as LuaInt * 8;
+        let max_int: LuaInt = LuaInt::MAX;
+
+        let mut words: Vec<LuaInstruction> = Vec::with_capacity(code.len());
+        for (pc, inst) in code.iter().enumerate() {
+            match inst.encode_51(num_bits_int, max_int) {
+                Some(encoded) => words.extend(encoded),
+                None => return Err(self.error(WriteErrorKind::InvalidInstruction { pc })),
+            }
+        }
+
+        self.write_count("code", words.len())?;
+        for word in words {
+            self.write_instruction(word)?;
+        }
+        Ok(())
+    }
+
+    fn write_constants(&mut self, constants: &[Constant]) -> WriteResult<()> {
+        self.write_count("constant", constants.len())?;
+        for (index, constant) in constants.iter().enumerate() {
+            match constant {
+                Constant::Nil => self.write_u8(0),
+                Constant::Boolean(value) => {
+                    self.write_u8(1);
+                    self.write_u8(*value as u8);
+                }
+                Constant::Number(value) => {
+                    self.write_u8(3);
+                    self.write_number(*value);
+                }
+                Constant::String(bytes) => {
+                    self.write_u8(4);
+                    // unlike sources, an empty string constant still has its NUL
+                    self.write_sizet(bytes.len() + 1)?;
+                    self.bytes.extend_from_slice(bytes);
+                    self.write_u8(0);
+                }
+                _ => return Err(self.error(WriteErrorKind::InvalidConstant { index })),
+            }
+        }
+        Ok(())
+    }
+
+    fn write_debug(&mut self, proto: &Proto) -> WriteResult<()> {
+        self.write_count("lineinfo", proto.lineinfo.len())?;
+        for line in &proto.lineinfo {
+            self.write_int(*line)?;
+        }
+
+        self.write_count("local", proto.locals.len())?;
+        for local in &proto.locals {
+            self.write_string(&local.name)?;
+            self.write_int(local.start_pc)?;
+            self.write_int(local.end_pc)?;
+        }
+
+        self.write_count("upvalue", proto.upvalue_names.len())?;
+        for name in &proto.upvalue_names {
+            self.write_string(name)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn build_writer(bytecode: &Bytecode) -> Writer<'_> {
+    Writer {
+        bytecode,
+        bytes: Vec::new(),
+        proto_path: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::build_assembler;
+    use crate::bytecode::{OpCode, OpMode};
+    use crate::read::build_reader;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.luac");
+
+    fn read(bytes: &Vec<u8>) -> Bytecode {
+        match build_reader(bytes).read() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn write(bytecode: &Bytecode) -> WriteResult<Vec<u8>> {
+        build_writer(bytecode).write()
+    }
+
+    #[test]
+    fn sample_round_trips() {
+        let bytes: Vec<u8> = SAMPLE.to_vec();
+        assert_eq!(write(&read(&bytes)), Ok(bytes));
+    }
+
+    #[test]
+    fn setlist_count_word_round_trips() {
+        let bytecode: Bytecode = match build_assembler("    SETLIST r0, 1, 600\n").assemble() {
+            Ok(bytecode) => bytecode,
+            Err(err) => panic!("{}", err),
+        };
+        let bytes: Vec<u8> = match write(&bytecode) {
+            Ok(bytes) => bytes,
+            Err(err) => panic!("{}", err),
+        };
+        let reread: Bytecode = read(&bytes);
+        assert_eq!(reread.main_proto.code[0].aux, Some(600));
+        assert_eq!(write(&reread), Ok(bytes));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytecode: Bytecode = read(&SAMPLE.to_vec());
+        bytecode.version = LuaVersion::Lua54;
+        let err: WriteError = match write(&bytecode) {
+            Ok(_) => panic!("expected a 5.4 chunk to be refused"),
+            Err(err) => err,
+        };
+        assert_eq!(
+            err.kind,
+            WriteErrorKind::UnsupportedVersion(LuaVersion::Lua54)
+        );
+        assert_eq!(err.proto_path, "");
+    }
+
+    #[test]
+    fn rejects_operands_that_overflow() {
+        let mut bytecode: Bytecode = read(&SAMPLE.to_vec());
+        bytecode.main_proto.protos[0].code[1].op = OpCode::OpMove(OpMode::ABC(300, 0, 0));
+        let err: WriteError = match write(&bytecode) {
+            Ok(_) => panic!("expected a 300 register A to be refused"),
+            Err(err) => err,
+        };
+        assert_eq!(err.kind, WriteErrorKind::InvalidInstruction { pc: 1 });
+        assert_eq!(err.proto_path, "main/0");
+    }
+
+    #[test]
+    fn rejects_numbers_it_cannot_read_back() {
+        let mut bytecode: Bytecode = read(&SAMPLE.to_vec());
+        bytecode.size_luanumber = 4;
+        assert_eq!(
+            write(&bytecode).unwrap_err().kind,
+            WriteErrorKind::UnhandledSize {
+                field: "number",
+                size: 4
+            }
+        );
+
+        let mut bytecode: Bytecode = read(&SAMPLE.to_vec());
+        bytecode.luanumber_integral = true;
+        let err: WriteError = match write(&bytecode) {
+            Ok(_) => panic!("expected integral numbers to be refused"),
+            Err(err) => err,
+        };
+        assert_eq!(err.kind, WriteErrorKind::IntegralNumbers);
+        assert_eq!(
+            err.to_string(),
+            "Failed to write bytecode: integral lua_Number chunks are not supported"
+        );
+    }
+
+    #[test]
+    fn rejects_a_jmp_with_an_a() {
+        let mut bytecode: Bytecode = read(&SAMPLE.to_vec());
+        let pc: usize = match bytecode
+            .main_proto
+            .code
+            .iter()
+            .position(|inst| matches!(inst.op, OpCode::OpJmp(_)))
+        {
+            Some(pc) => pc,
+            None => panic!("the sample has no JMP"),
+        };
+        let sbx: LuaInt = match bytecode.main_proto.code[pc].op {
+            OpCode::OpJmp(OpMode::ASBX(_, sbx)) => sbx,
+            _ => unreachable!(),
+        };
+        bytecode.main_proto.code[pc].op = OpCode::OpJmp(OpMode::ASBX(1, sbx));
+        let err: WriteError = match write(&bytecode) {
+            Ok(_) => panic!("expected a JMP with an A to be refused"),
+            Err(err) => err,
+        };
+        assert_eq!(err.kind, WriteErrorKind::InvalidInstruction { pc });
+        assert_eq!(err.proto_path, "main");
+    }
+}
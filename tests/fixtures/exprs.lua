@@ -0,0 +1,31 @@
+-- and/or chains, table constructors and open top ranges for the decompiler and describe tests
+local function pick(a, b, c, ...)
+  local x = a or "default"
+  local y = a and b or c
+  local z = not (a and b)
+  if a and (b or c) then
+    x = 1
+  end
+  if not a or b == c then
+    y = 2
+  end
+  while a and not b do
+    a = c
+  end
+  return x, y, z, ...
+end
+
+local function wrap(...)
+  local list = {...}
+  local copy = {pick(...)}
+  return select("#", ...), list, copy
+end
+
+local big = {
+  1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
+  21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40,
+  41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53,
+  name = "big", ["a b"] = true, [10.5] = wrap,
+}
+
+return pick(big[1], big.name, nil, wrap(1, 2)), big
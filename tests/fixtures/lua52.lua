@@ -0,0 +1,12 @@
+local greeting = "hello"
+local function shout(name)
+  return greeting .. ", " .. name .. "!"
+end
+local t = {}
+for i = 1, 3 do
+  t[i] = shout("n" .. i)
+end
+goto done
+print("skipped")
+::done::
+return t, 2.5
@@ -0,0 +1,6 @@
+local a, b = 7, 3
+local bits = (a & b) | (a ~ b) << 2
+local half = a / 2 + 0.25
+local whole = a // b
+local big = 9007199254740993
+return bits, half, whole, big, ~a
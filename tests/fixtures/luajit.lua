@@ -0,0 +1,8 @@
+local point = { x = 1, y = 2, "first" }
+local scale = 0.5
+local big = 3000000000
+local mid = 100000 - big
+local function make(n)
+  return function() return n * scale + big end
+end
+return point, make(4)()
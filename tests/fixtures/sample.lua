@@ -0,0 +1,43 @@
+-- covers the common 5.1 instructions for the reader, writer, JSON and listing tests
+local format = string.format
+local counter = 0
+
+local function bump(step, ...)
+  counter = counter + (step or 1)
+  local extra = select("#", ...)
+  return counter, extra, ...
+end
+
+local list = {1, 2.5, "three", true, nil, [10] = "ten", key = "value"}
+for i = 1, #list do
+  if type(list[i]) == "number" and list[i] > 1 then
+    list[i] = list[i] * 2 - 1 / 4 % 3 ^ 2
+  elseif not list[i] then
+    break
+  end
+end
+
+for k, v in pairs(list) do
+  counter = counter + #(tostring(k) .. "")
+end
+
+local obj = {name = "obj\0\n\"quoted\""}
+function obj:describe(prefix)
+  local name = self.name
+  return function()
+    return format("%s %s", prefix, name), -counter
+  end
+end
+
+while counter < 100 do
+  counter = bump(counter > 10 and 7 or 3, obj, unpack(list))
+  if counter == 50 or counter <= 20 then
+    counter = counter + 1
+  end
+end
+
+repeat
+  local done = counter >= 0
+until done
+
+return obj:describe("done")(), bump()
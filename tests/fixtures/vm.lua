@@ -0,0 +1,123 @@
+-- checks of the VM against what a stock 5.1 lua does, every check has to hold
+local function eq(got, expected, what)
+  if got ~= expected then
+    error(what .. ": expected " .. tostring(expected) .. ", got " .. tostring(got), 2)
+  end
+end
+
+local function message(f, ...)
+  local ok, err = pcall(f, ...)
+  eq(ok, false, "pcall")
+  return err
+end
+
+-- arithmetic and coercion
+eq(7 % -3, -2, "mod")
+eq(-7 % 3, 2, "negative mod")
+eq(2 ^ 10, 1024, "pow")
+eq("10" + 5, 15, "string arith")
+eq(10 .. 20, "1020", "number concat")
+eq(1 / 0, math.huge, "inf")
+eq(tostring(0 / 0) == tostring(0 / 0), true, "nan")
+eq(tostring(1e15), "1e+15", "%.14g")
+eq(tostring(-0.5), "-0.5", "negative float")
+
+-- tonumber, hex floats included
+eq(tonumber("0x10"), 16, "hex")
+eq(tonumber("0x1p4"), 16, "hex exponent")
+eq(tonumber("0x.8"), 0.5, "hex fraction")
+eq(tonumber(" 0x1.8p1 "), 3, "hex float with spaces")
+eq(tonumber("0x"), nil, "bare 0x")
+eq(tonumber("0x1p"), nil, "missing exponent")
+eq(tonumber("ff", 16), 255, "base 16")
+eq(tonumber("z", 36), 35, "base 36")
+eq(tonumber("1e2"), 100, "exponent")
+
+-- the border # finds
+local t = {1, 2, nil, 4}
+eq(#t, 4, "constructor border")
+t = {}
+for i = 1, 10 do t[i] = i end
+t[5] = nil
+eq(#t, 10, "hole in the array part")
+t = {n = 1}
+t[1], t[2], t[4] = 1, 2, 4
+eq(#t, 4, "hash part border")
+eq(#"abc", 3, "string length")
+local big = {}
+for i = 1, 100 do big[i] = i end
+for i = 100, 51, -1 do big[i] = nil end
+eq(#big, 50, "shrunk array")
+
+-- closures and upvalues
+local function counter()
+  local n = 0
+  return function() n = n + 1 return n end
+end
+local c1, c2 = counter(), counter()
+c1() c1()
+eq(c1(), 3, "closure state")
+eq(c2(), 1, "separate upvalues")
+local fs = {}
+for i = 1, 3 do fs[i] = function() return i end end
+eq(fs[1]() + fs[2]() + fs[3](), 6, "fresh loop upvalue")
+
+-- varargs, select and unpack
+local function pack(...) return select("#", ...), ... end
+eq(pack(nil, nil), 2, "select #")
+eq(select(-1, 1, 2, 3), 3, "negative select")
+eq(select(2, unpack({1, 2, 3})), 2, "unpack")
+local function tail(n) if n == 0 then return "done" end return tail(n - 1) end
+eq(tail(10000), "done", "tail calls")
+
+-- metatables
+local v = setmetatable({x = 1}, {
+  __add = function(a, b) return a.x + b end,
+  __index = function(_, k) return k .. "!" end,
+  __call = function(self, y) return self.x + y end,
+  __eq = function() return true end,
+  __lt = function(a, b) return a.x < b.x end,
+  __concat = function(a, b) return "cat" end,
+  __len = function() return 99 end,
+})
+eq(v + 2, 3, "__add")
+eq(v.missing, "missing!", "__index")
+eq(v(4), 5, "__call")
+eq(v .. "s", "cat", "__concat")
+eq(#v, 1 - 1, "__len is ignored for tables")
+local w = setmetatable({x = 2}, getmetatable(v))
+eq(v == w, true, "__eq")
+eq(v < w, true, "__lt")
+eq(rawequal(v, w), false, "rawequal")
+
+-- error messages name what went wrong the way lua does
+local a = {x = {}}
+eq(message(function() return a.x.y.z end), "vm.lua:95: attempt to index field 'y' (a nil value)", "field")
+eq(message(function() local l; return l.x end), "vm.lua:96: attempt to index local 'l' (a nil value)", "local")
+eq(message(function() return missing.x end), "vm.lua:97: attempt to index global 'missing' (a nil value)", "global")
+eq(message(function() return a.x + 1 end), "vm.lua:98: attempt to perform arithmetic on field 'x' (a table value)", "arith")
+eq(message(function() a:nomethod() end), "vm.lua:99: attempt to call method 'nomethod' (a nil value)", "method")
+eq(message(function() return #a.q end), "vm.lua:100: attempt to get length of field 'q' (a nil value)", "length")
+eq(message(function() return a < 1 end), "vm.lua:101: attempt to compare table with number", "compare")
+eq(message(function() return c1 .. "" end), "vm.lua:102: attempt to concatenate upvalue 'c1' (a function value)", "upvalue")
+eq(message(error, "plain"), "plain", "error from pcall")
+eq(message(function() error("lvl") end), "vm.lua:104: lvl", "error level 1")
+eq(message(function() error({}) end) ~= nil, true, "error with a table")
+eq(message(string.rep), "bad argument #1 to '?' (string expected, got no value)", "host called from pcall")
+eq(message(function() string.rep() end), "vm.lua:107: bad argument #1 to 'rep' (string expected, got no value)", "host argument")
+eq(select(2, xpcall(function() error("x", 0) end, function(e) return e .. "!" end)), "x!", "xpcall handler")
+
+-- string library
+eq(("hello"):upper(), "HELLO", "string methods")
+eq(string.format("%5.2f|%d|%s|%q", 3.14159, 42, "s", "a\n"), " 3.14|42|s|\"a\\\n\"", "format")
+eq(string.gsub("hello world", "o", "0"), "hell0 w0rld", "gsub")
+eq(string.match("key=value", "(%w+)=(%w+)"), "key", "match")
+eq(select(2, string.find("abc", "b", 1, true)), 2, "plain find")
+local words = {}
+for word in string.gmatch("one two three", "%a+") do words[#words + 1] = word end
+eq(table.concat(words, ","), "one,two,three", "gmatch")
+local sorted = {5, 2, 8, 1}
+table.sort(sorted, function(x, y) return x > y end)
+eq(table.concat(sorted, " "), "8 5 2 1", "sort")
+
+return "ok"